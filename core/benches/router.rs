@@ -0,0 +1,53 @@
+//! Request dispatch through the whole engine, without sockets.
+//!
+//! ```text
+//! cargo bench -p s_web --features tower --bench router
+//! ```
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use http_body_util::Empty;
+use hyper::body::Bytes;
+use s_web::{Engine, EngineService, RequestCtx};
+use tower_service::Service;
+
+fn request(path: &str) -> hyper::Request<Empty<Bytes>> {
+    hyper::Request::get(path).body(Empty::new()).unwrap()
+}
+
+/// Time one request from `service.call` to the finished response
+fn bench_path(c: &mut Criterion, name: &str, service: &mut EngineService, path: &str) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    c.bench_function(name, |b| {
+        b.iter(|| runtime.block_on(service.call(request(path))).unwrap());
+    });
+}
+
+fn dispatch(c: &mut Criterion) {
+    let mut app = Engine::new();
+    app.get("/", |_| async { "ok" });
+    app.get("/users/:id/posts/:post", |ctx: RequestCtx| async move {
+        let id = ctx.get_param("id").unwrap_or_default().len();
+        let post = ctx.get_param("post").unwrap_or_default().len();
+        (id + post).to_string()
+    });
+    let mut service = app.build();
+
+    bench_path(c, "dispatch/static", &mut service, "/");
+    bench_path(c, "dispatch/two_params", &mut service, "/users/42/posts/hello-world");
+}
+
+/// Lookup among 10,000 routes, which the trie indexes by static segment
+fn large_route_table(c: &mut Criterion) {
+    let mut app = Engine::new();
+    for i in 0..10_000 {
+        app.get(&format!("/api/res{i}/:id"), |_| async { "" });
+    }
+    let mut service = app.build();
+
+    bench_path(c, "large_table/first", &mut service, "/api/res0/7");
+    bench_path(c, "large_table/middle", &mut service, "/api/res5000/7");
+    bench_path(c, "large_table/last", &mut service, "/api/res9999/7");
+}
+
+criterion_group!(benches, dispatch, large_route_table);
+criterion_main!(benches);
@@ -0,0 +1,229 @@
+//! Per-request allocation tracking for performance work (debug aid).
+//!
+//! Install the counting allocator in the binary and turn tracking on:
+//!
+//! ```rust,ignore
+//! use s_web::alloc_tracking::CountingAllocator;
+//!
+//! #[global_allocator]
+//! static ALLOC: CountingAllocator = CountingAllocator::system();
+//!
+//! app.track_allocations();
+//! ```
+//!
+//! Every poll of a request future is metered on the thread that runs it, so
+//! the numbers cover routing, the middleware chain and the handler, but not
+//! tasks the handler spawns. Requests allocating far more than their route
+//! usually does are logged, and [`AllocTracker::report`] summarizes all routes.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use crate::{MatchedRoute, Response, log};
+
+/// Samples a route needs before outliers are reported
+const WARMUP_SAMPLES: u64 = 20;
+/// A request is an outlier when it allocates this many times the route mean...
+const OUTLIER_FACTOR: u64 = 4;
+/// ...and at least this many bytes, so tiny routes don't flood the log
+const OUTLIER_MIN_BYTES: u64 = 64 * 1024;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    static ALLOCATED_BYTES: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Global allocator wrapper counting allocations per thread.
+pub struct CountingAllocator<A = System> {
+    inner: A,
+}
+
+impl CountingAllocator<System> {
+    /// Count allocations made through the system allocator.
+    pub const fn system() -> Self {
+        Self { inner: System }
+    }
+}
+
+impl<A> CountingAllocator<A> {
+    /// Count allocations made through `inner` (jemalloc, mimalloc, ...).
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+fn count(bytes: usize) {
+    // `try_with` because allocations also happen while thread-locals are torn down
+    let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+    let _ = ALLOCATED_BYTES.try_with(|n| n.set(n.get() + bytes as u64));
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        unsafe { self.inner.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        unsafe { self.inner.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size.saturating_sub(layout.size()));
+        unsafe { self.inner.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+}
+
+/// Allocations and bytes counted on the current thread so far
+pub(crate) fn snapshot() -> (u64, u64) {
+    (
+        ALLOCATIONS.try_with(Cell::get).unwrap_or(0),
+        ALLOCATED_BYTES.try_with(Cell::get).unwrap_or(0),
+    )
+}
+
+/// Whether a [`CountingAllocator`] is installed as the global allocator
+pub(crate) fn counting_installed() -> bool {
+    let before = snapshot().0;
+    drop(std::hint::black_box(Box::new(0u64)));
+    snapshot().0 > before
+}
+
+/// Allocation statistics of one route.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteAllocStats {
+    pub requests: u64,
+    pub allocations: u64,
+    pub bytes: u64,
+    pub max_bytes: u64,
+    pub outliers: u64,
+}
+
+impl RouteAllocStats {
+    /// Mean bytes allocated per request.
+    pub fn mean_bytes(&self) -> u64 {
+        self.bytes / self.requests.max(1)
+    }
+
+    /// Mean allocation count per request.
+    pub fn mean_allocations(&self) -> u64 {
+        self.allocations / self.requests.max(1)
+    }
+}
+
+/// Per-route allocation statistics collected by
+/// [`Engine::track_allocations`](crate::Engine::track_allocations).
+#[derive(Default)]
+pub struct AllocTracker {
+    routes: Mutex<HashMap<String, RouteAllocStats>>,
+}
+
+impl AllocTracker {
+    pub(crate) fn new() -> Arc<Self> {
+        if !counting_installed() {
+            log::warn!(
+                "allocation tracking: CountingAllocator is not the #[global_allocator]; all counts will be zero"
+            );
+        }
+        Arc::new(Self::default())
+    }
+
+    /// Statistics for every route seen so far, sorted by mean bytes (largest first).
+    pub fn report(&self) -> Vec<(String, RouteAllocStats)> {
+        let routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+        let mut report: Vec<_> = routes
+            .iter()
+            .map(|(route, stats)| (route.clone(), stats.clone()))
+            .collect();
+        report.sort_by(|a, b| b.1.mean_bytes().cmp(&a.1.mean_bytes()).then(a.0.cmp(&b.0)));
+        report
+    }
+
+    /// Record a finished request; returns the route mean when it is an outlier
+    fn record(&self, route: &str, allocations: u64, bytes: u64) -> Option<u64> {
+        let mut routes = self.routes.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = routes.entry(route.to_string()).or_default();
+        let mean = stats.mean_bytes();
+        let outlier = stats.requests >= WARMUP_SAMPLES
+            && bytes >= OUTLIER_MIN_BYTES
+            && bytes > mean.saturating_mul(OUTLIER_FACTOR);
+        stats.requests += 1;
+        stats.allocations += allocations;
+        stats.bytes += bytes;
+        stats.max_bytes = stats.max_bytes.max(bytes);
+        stats.outliers += u64::from(outlier);
+        outlier.then_some(mean)
+    }
+}
+
+/// Meters the allocations made while polling a request future
+pub(crate) struct AllocMeter<F: ?Sized> {
+    pub(crate) inner: Pin<Box<F>>,
+    pub(crate) tracker: Arc<AllocTracker>,
+    pub(crate) method: hyper::Method,
+    pub(crate) path: String,
+    pub(crate) allocations: u64,
+    pub(crate) bytes: u64,
+}
+
+impl<F: Future<Output = Response> + ?Sized> Future for AllocMeter<F> {
+    type Output = Response;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Response> {
+        let (allocations, bytes) = snapshot();
+        let result = self.inner.as_mut().poll(cx);
+        let (allocations_after, bytes_after) = snapshot();
+        self.allocations += allocations_after - allocations;
+        self.bytes += bytes_after - bytes;
+        if let Poll::Ready(response) = &result {
+            let route = response
+                .extensions()
+                .get::<MatchedRoute>()
+                .map(|r| &*r.0)
+                .unwrap_or("<unmatched>");
+            if let Some(mean) = self.tracker.record(route, self.allocations, self.bytes) {
+                log::warn!(
+                    "allocation outlier: {} {} (route {route}) allocated {} bytes in {} allocations, route mean is {mean} bytes",
+                    self.method, self.path, self.bytes, self.allocations
+                );
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outliers_after_warmup() {
+        let tracker = AllocTracker::default();
+        assert_eq!(tracker.record("/big", 1, 1 << 20), None);
+        for _ in 1..WARMUP_SAMPLES {
+            assert_eq!(tracker.record("/a", 10, 1_000), None);
+        }
+        assert_eq!(tracker.record("/a", 10, 2_000), None);
+        assert_eq!(tracker.record("/a", 10, 3_000), None, "below the absolute minimum");
+        assert!(tracker.record("/a", 500, 1 << 20).is_some());
+
+        let report = tracker.report();
+        assert_eq!(report[0].0, "/big");
+        let (_, a) = &report[1];
+        assert_eq!(a.requests, WARMUP_SAMPLES + 2);
+        assert_eq!(a.outliers, 1);
+        assert_eq!(a.max_bytes, 1 << 20);
+    }
+}
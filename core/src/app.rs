@@ -0,0 +1,210 @@
+//! Self-contained route sets that are built on their own and mounted on an
+//! engine (or another app) under a prefix.
+
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use crate::{
+    Handler, Middleware, Next, RequestCtx, Response, RouteBuilder, RouteTarget, Router,
+    health::HealthRegistry, router::ANY_METHOD,
+};
+
+/// Routes plus the middleware that guards them, built independently of an
+/// [`Engine`](crate::Engine) so modules can export them instead of
+/// registering into a shared engine.
+///
+/// Patterns are relative to wherever the app is mounted. The app's
+/// middleware only runs for the app's routes, after global middleware and
+/// before route-level middleware.
+///
+/// ```rust,ignore
+/// // posts.rs
+/// pub fn routes(repo: Repo) -> App {
+///     let mut app = App::new();
+///     app.use_middleware(require_login);
+///     app.get("/", list_posts).get("/:id", get_post);
+///     app
+/// }
+///
+/// // main.rs
+/// app.mount("/api/posts", posts::routes(repo));
+/// ```
+#[derive(Default)]
+pub struct App {
+    router: Router,
+    middlewares: Vec<Middleware>,
+    health: HealthRegistry,
+    hidden: bool,
+}
+
+impl RouteTarget for App {
+    fn router_mut(&mut self) -> &mut Router {
+        &mut self.router
+    }
+
+    fn health_registry(&self) -> HealthRegistry {
+        self.health.clone()
+    }
+}
+
+impl App {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Probes registered here join the engine's registry when the app is mounted.
+    pub fn health(&self) -> &HealthRegistry {
+        &self.health
+    }
+
+    /// Add a route; `pattern` is relative to the mount prefix
+    pub fn add_route(&mut self, method: &str, pattern: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.router.add_handler(method, pattern, handler);
+        RouteBuilder::new(self, method, pattern.to_string())
+    }
+
+    /// Add a GET route
+    pub fn get(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("GET", path, handler)
+    }
+
+    /// Add a POST route
+    pub fn post(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("POST", path, handler)
+    }
+
+    /// Add a PUT route
+    pub fn put(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("PUT", path, handler)
+    }
+
+    /// Add a PATCH route
+    pub fn patch(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("PATCH", path, handler)
+    }
+
+    /// Add a DELETE route
+    pub fn delete(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("DELETE", path, handler)
+    }
+
+    /// Add an OPTIONS route, replacing the automatic `204` answer
+    pub fn options(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("OPTIONS", path, handler)
+    }
+
+    /// Add a HEAD route, replacing the fallback to the GET handler
+    pub fn head(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("HEAD", path, handler)
+    }
+
+    /// Add a TRACE route
+    pub fn trace(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("TRACE", path, handler)
+    }
+
+    /// Add a route for any method name, including extension methods (`PROPFIND`, ...)
+    pub fn route(&mut self, method: &str, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route(&method.to_ascii_uppercase(), path, handler)
+    }
+
+    /// Add a route matching every method not registered explicitly on the same path
+    pub fn any(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route(ANY_METHOD, path, handler)
+    }
+
+    /// Add middleware to every route of this app, including mounted sub-apps
+    pub fn use_middleware<F, Fut>(&mut self, middleware: F) -> &mut Self
+    where
+        F: Fn(RequestCtx, Next) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        let wrapped = move |ctx, next| {
+            let fut = middleware(ctx, next);
+            Box::pin(fut) as Pin<Box<dyn Future<Output = Response> + Send>>
+        };
+        self.middlewares.push(Arc::new(wrapped));
+        self
+    }
+
+    /// Answer unmatched paths under the mount prefix with `handler`, unless
+    /// the group it is mounted into already has its own.
+    pub fn not_found(&mut self, handler: impl Handler) -> &mut Self {
+        self.router.set_not_found(Arc::new(handler));
+        self
+    }
+
+    /// Keep every route of this app out of the OpenAPI document
+    pub fn hide_from_docs(&mut self) -> &mut Self {
+        self.hidden = true;
+        self
+    }
+
+    /// Nest another app under `prefix`; its middleware runs after this app's.
+    pub fn mount(&mut self, prefix: &str, app: App) -> &mut Self {
+        app.merge_into(&normalize_prefix(prefix), &mut self.router, &self.health);
+        self
+    }
+
+    /// Move every route into `router` under `prefix`, wrapped in this app's middleware
+    pub(crate) fn merge_into(self, prefix: &str, router: &mut Router, health: &HealthRegistry) {
+        self.health.merge_into(health);
+        if router.not_found_handler().is_none()
+            && let Some(handler) = self.router.not_found_handler()
+        {
+            router.set_not_found(handler);
+        }
+        for (method, pattern, mut route) in self.router.into_routes() {
+            route.wrap_middlewares(&self.middlewares);
+            if self.hidden {
+                route.hide_from_docs();
+            }
+            router.insert_route(&method, &format!("{prefix}{pattern}"), route);
+        }
+    }
+}
+
+/// `/api/` and `api` both become `/api`; the root becomes an empty prefix
+pub(crate) fn normalize_prefix(prefix: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("/{prefix}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResponseBuilder;
+
+    #[test]
+    fn test_nested_mount_prefixes_routes() {
+        let mut comments = App::new();
+        comments.get("/:cid", |_| async { ResponseBuilder::new().build("comment") });
+        comments.hide_from_docs();
+
+        let mut posts = App::new();
+        posts.get("/", |_| async { ResponseBuilder::new().build("posts") });
+        posts.post("/:id", |_| async { ResponseBuilder::new().build("created") });
+        posts.mount("/:id/comments/", comments);
+
+        let mut router = Router::new();
+        posts.merge_into(&normalize_prefix("api/posts/"), &mut router, &HealthRegistry::new());
+        let mut routes: Vec<_> = router
+            .routes()
+            .into_iter()
+            .map(|r| (r.method, r.pattern, r.hidden))
+            .collect();
+        routes.sort();
+        assert_eq!(
+            routes,
+            [
+                ("GET".into(), "/api/posts/".into(), false),
+                ("GET".into(), "/api/posts/:id/comments/:cid".into(), true),
+                ("POST".into(), "/api/posts/:id".into(), false),
+            ]
+        );
+        assert_eq!(normalize_prefix("/"), "");
+    }
+}
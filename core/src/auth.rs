@@ -0,0 +1,69 @@
+//! Authentication helpers (`auth` feature).
+//!
+//! Password hashing uses argon2id with the crate's recommended parameters and
+//! always runs on the blocking thread pool, so a login burst can't stall the
+//! async executor.
+//!
+//! ```rust,ignore
+//! use s_web::auth::{hash_password, verify_password};
+//!
+//! let hash = hash_password(&form.password).await?;   // store this
+//! if !verify_password(&form.password, &user.password_hash).await? {
+//!     return (StatusCode::UNAUTHORIZED, "invalid credentials").into_response();
+//! }
+//! ```
+//!
+//! The `totp` feature adds [`totp`] for two-factor codes.
+
+#[cfg(feature = "totp")]
+pub mod totp;
+
+use argon2::{
+    Argon2,
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
+};
+
+use crate::BoxError;
+
+/// Hash a password into a self-describing PHC string (`$argon2id$v=19$...`).
+pub async fn hash_password(password: &str) -> Result<String, BoxError> {
+    let password = password.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| BoxError::from(e.to_string()))
+    })
+    .await?
+}
+
+/// Check a password against a hash produced by [`hash_password`].
+///
+/// Returns `Ok(false)` for a wrong password and an error only if `hash` is
+/// not a valid PHC string.
+pub async fn verify_password(password: &str, hash: &str) -> Result<bool, BoxError> {
+    let password = password.to_owned();
+    let hash = hash.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let parsed = PasswordHash::new(&hash).map_err(|e| BoxError::from(e.to_string()))?;
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok())
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_hash_and_verify_roundtrip() {
+        let hash = hash_password("hunter2").await.unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("hunter2", &hash).await.unwrap());
+        assert!(!verify_password("hunter3", &hash).await.unwrap());
+        assert!(verify_password("hunter2", "not-a-hash").await.is_err());
+    }
+}
@@ -0,0 +1,121 @@
+//! Running blocking work (password hashing, image processing, sync IO) off the async executor.
+//!
+//! ```rust,ignore
+//! app.post("/login", |mut ctx: RequestCtx| async move {
+//!     let form: Login = ctx.json().await?;
+//!     let ok = ctx.blocking(move || bcrypt::verify(&form.password, &HASH)).await??;
+//!     // ...
+//! });
+//! ```
+//!
+//! The request's `X-Request-Id` is available inside the closure through
+//! [`current_request_id`], and time spent waiting for a free blocking thread
+//! is tracked in [`stats`] so an exhausted pool shows up in metrics.
+
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{BoxError, RequestCtx};
+
+thread_local! {
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+static TASKS: AtomicU64 = AtomicU64::new(0);
+static QUEUE_MICROS_TOTAL: AtomicU64 = AtomicU64::new(0);
+static QUEUE_MICROS_MAX: AtomicU64 = AtomicU64::new(0);
+
+/// Queue-time figures for work submitted through `ctx.blocking`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockingStats {
+    /// Closures that have started running.
+    pub tasks: u64,
+    /// Total time closures spent waiting for a blocking thread.
+    pub total_queue_time: Duration,
+    /// Longest single wait.
+    pub max_queue_time: Duration,
+}
+
+impl BlockingStats {
+    /// Mean wait per task.
+    pub fn mean_queue_time(&self) -> Duration {
+        let micros = self.total_queue_time.as_micros() as u64;
+        Duration::from_micros(micros.checked_div(self.tasks).unwrap_or(0))
+    }
+}
+
+/// Snapshot of the process-wide blocking work counters.
+pub fn stats() -> BlockingStats {
+    BlockingStats {
+        tasks: TASKS.load(Ordering::Relaxed),
+        total_queue_time: Duration::from_micros(QUEUE_MICROS_TOTAL.load(Ordering::Relaxed)),
+        max_queue_time: Duration::from_micros(QUEUE_MICROS_MAX.load(Ordering::Relaxed)),
+    }
+}
+
+/// `X-Request-Id` of the request that submitted the closure currently running
+/// on this thread, for log lines emitted from blocking code.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.with(|id| id.borrow().clone())
+}
+
+impl RequestCtx {
+    /// Run `f` on tokio's blocking thread pool and await its result.
+    /// Fails only if `f` panicked.
+    pub async fn blocking<F, R>(&self, f: F) -> Result<R, BoxError>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let request_id = self.header("x-request-id").map(str::to_owned);
+        run_blocking(request_id, f).await
+    }
+}
+
+async fn run_blocking<F, R>(request_id: Option<String>, f: F) -> Result<R, BoxError>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let queued = Instant::now();
+    let result = tokio::task::spawn_blocking(move || {
+        let waited = queued.elapsed().as_micros().min(u64::MAX as u128) as u64;
+        TASKS.fetch_add(1, Ordering::Relaxed);
+        QUEUE_MICROS_TOTAL.fetch_add(waited, Ordering::Relaxed);
+        QUEUE_MICROS_MAX.fetch_max(waited, Ordering::Relaxed);
+
+        REQUEST_ID.with(|id| *id.borrow_mut() = request_id);
+        let _reset = ResetRequestId;
+        f()
+    })
+    .await?;
+    Ok(result)
+}
+
+/// Blocking threads are reused; clear the id even if the closure panics
+struct ResetRequestId;
+
+impl Drop for ResetRequestId {
+    fn drop(&mut self) {
+        REQUEST_ID.with(|id| *id.borrow_mut() = None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_id_visible_and_counted() {
+        let before = stats().tasks;
+        let id = run_blocking(Some("req-42".into()), current_request_id)
+            .await
+            .unwrap();
+        assert_eq!(id.as_deref(), Some("req-42"));
+        assert!(stats().tasks > before);
+        assert_eq!(run_blocking(None, current_request_id).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,692 @@
+//! Response caching for read-heavy endpoints.
+//!
+//! [`ResponseCache`] stores `GET` and `HEAD` responses and answers repeats
+//! without running the handler. Entries are keyed by method, path, query and
+//! the request headers listed with [`ResponseCache::vary`], and live for the
+//! response's `s-maxage`/`max-age` or the configured TTL:
+//!
+//! ```rust,ignore
+//! use s_web::cache::{CacheControl, MemoryCacheStore, ResponseCache};
+//! use std::time::Duration;
+//!
+//! app.group("/api/catalog").use_middleware(
+//!     ResponseCache::new(MemoryCacheStore::new(64 * 1024 * 1024))
+//!         .ttl(Duration::from_secs(30))
+//!         .vary(["accept-language"])
+//!         .middleware(),
+//! );
+//!
+//! app.get("/api/catalog/featured", |_| async {
+//!     let cache = CacheControl::new().public().max_age(Duration::from_secs(300));
+//!     ResponseBuilder::new().cache_control(&cache).try_body(featured_json().await)
+//! });
+//! ```
+//!
+//! Only responses that are safe to share are stored: requests carrying
+//! `Authorization` or `Cookie` bypass the cache unless a custom
+//! [`ResponseCache::key`] tells users apart, and responses with `Set-Cookie`,
+//! `no-store`, `no-cache`, `private`, `Vary: *` or a body of unknown size are
+//! passed through untouched. Hits carry `Age` and `X-Cache: HIT`.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use http_body_util::BodyExt;
+use hyper::{
+    HeaderMap, Method, StatusCode,
+    body::{Body as _, Bytes},
+    header::{
+        AUTHORIZATION, CACHE_CONTROL, COOKIE, HeaderName, HeaderValue, RANGE, SET_COOKIE, VARY,
+    },
+};
+
+use crate::{
+    Next, RequestCtx, Response, ResponseBuilder, log,
+    response::full,
+    store::{self, KvStore},
+};
+
+/// A `Cache-Control` header value, built or parsed.
+///
+/// ```rust,ignore
+/// let cache = CacheControl::new().public().max_age(Duration::from_secs(60));
+/// assert_eq!(cache.to_string(), "public, max-age=60");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    public: bool,
+    private: bool,
+    no_cache: bool,
+    no_store: bool,
+    must_revalidate: bool,
+    immutable: bool,
+    max_age: Option<Duration>,
+    s_maxage: Option<Duration>,
+    stale_while_revalidate: Option<Duration>,
+}
+
+impl CacheControl {
+    /// No directives.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read directives from a header value; unknown ones are ignored.
+    pub fn parse(value: &str) -> Self {
+        let mut cache = Self::new();
+        for directive in value.split(',') {
+            let (name, arg) = match directive.split_once('=') {
+                Some((name, arg)) => (name.trim(), Some(arg.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let secs = || arg?.parse().ok().map(Duration::from_secs);
+            match name.to_ascii_lowercase().as_str() {
+                "public" => cache.public = true,
+                "private" => cache.private = true,
+                "no-cache" => cache.no_cache = true,
+                "no-store" => cache.no_store = true,
+                "must-revalidate" => cache.must_revalidate = true,
+                "immutable" => cache.immutable = true,
+                "max-age" => cache.max_age = secs(),
+                "s-maxage" => cache.s_maxage = secs(),
+                "stale-while-revalidate" => cache.stale_while_revalidate = secs(),
+                _ => {}
+            }
+        }
+        cache
+    }
+
+    /// Any cache may store the response.
+    pub fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+
+    /// Only the user's own browser may store the response.
+    pub fn private(mut self) -> Self {
+        self.private = true;
+        self
+    }
+
+    /// Caches must revalidate before every reuse.
+    pub fn no_cache(mut self) -> Self {
+        self.no_cache = true;
+        self
+    }
+
+    /// Nothing may store the response.
+    pub fn no_store(mut self) -> Self {
+        self.no_store = true;
+        self
+    }
+
+    /// Stale copies must not be served without revalidation.
+    pub fn must_revalidate(mut self) -> Self {
+        self.must_revalidate = true;
+        self
+    }
+
+    /// The content never changes while fresh (fingerprinted assets).
+    pub fn immutable(mut self) -> Self {
+        self.immutable = true;
+        self
+    }
+
+    /// How long the response stays fresh, rounded down to seconds.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Freshness for shared caches such as [`ResponseCache`], overriding `max-age`.
+    pub fn s_maxage(mut self, s_maxage: Duration) -> Self {
+        self.s_maxage = Some(s_maxage);
+        self
+    }
+
+    /// How long a stale copy may still be served while it is refreshed.
+    pub fn stale_while_revalidate(mut self, window: Duration) -> Self {
+        self.stale_while_revalidate = Some(window);
+        self
+    }
+
+    /// Whether a shared cache may keep the response at all
+    fn storable(&self) -> bool {
+        !(self.no_store || self.no_cache)
+    }
+
+    /// Freshness for a shared cache, if the directives set one
+    fn shared_max_age(&self) -> Option<Duration> {
+        self.s_maxage.or(self.max_age)
+    }
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+            (self.must_revalidate, "must-revalidate"),
+            (self.immutable, "immutable"),
+        ];
+        let durations = [
+            ("max-age", self.max_age),
+            ("s-maxage", self.s_maxage),
+            ("stale-while-revalidate", self.stale_while_revalidate),
+        ];
+        let directives = flags
+            .into_iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, name)| name.to_string())
+            .chain(
+                durations
+                    .into_iter()
+                    .filter_map(|(name, value)| Some(format!("{name}={}", value?.as_secs()))),
+            )
+            .collect::<Vec<_>>();
+        f.write_str(&directives.join(", "))
+    }
+}
+
+/// A stored response: status, headers and the whole body.
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored_ms: u64,
+}
+
+impl CachedResponse {
+    /// Encode for a byte-oriented backend.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = format!("{} {}\r\n", self.status.as_u16(), self.stored_ms).into_bytes();
+        for (name, value) in &self.headers {
+            out.extend_from_slice(name.as_str().as_bytes());
+            out.extend_from_slice(b": ");
+            out.extend_from_slice(value.as_bytes());
+            out.extend_from_slice(b"\r\n");
+        }
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(&self.body);
+        out
+    }
+
+    /// Decode what [`to_bytes`](Self::to_bytes) produced.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let split = bytes.windows(4).position(|w| w == b"\r\n\r\n")?;
+        let head = std::str::from_utf8(&bytes[..split]).ok()?;
+        let mut lines = head.split("\r\n");
+        let (status, stored_ms) = lines.next()?.split_once(' ')?;
+        let mut headers = HeaderMap::new();
+        for line in lines {
+            let (name, value) = line.split_once(": ")?;
+            headers.append(HeaderName::from_bytes(name.as_bytes()).ok()?, HeaderValue::from_str(value).ok()?);
+        }
+        Some(Self {
+            status: StatusCode::from_u16(status.parse().ok()?).ok()?,
+            headers,
+            body: Bytes::copy_from_slice(&bytes[split + 4..]),
+            stored_ms: stored_ms.parse().ok()?,
+        })
+    }
+
+    /// Rough memory footprint, for size-bounded stores
+    fn size(&self) -> usize {
+        let headers: usize = self.headers.iter().map(|(k, v)| k.as_str().len() + v.len()).sum();
+        self.body.len() + headers + 64
+    }
+
+    /// Answer a repeat from this entry
+    fn hit(&self) -> Response {
+        let age = store::unix_millis().saturating_sub(self.stored_ms) / 1000;
+        let mut response = hyper::Response::new(full(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response.headers_mut().insert("age", HeaderValue::from(age));
+        response.headers_mut().insert("x-cache", HeaderValue::from_static("HIT"));
+        response
+    }
+}
+
+/// Backend holding cached responses.
+pub trait CacheStore: Send + Sync + 'static {
+    /// The live entry under `key`.
+    fn get(&self, key: &str) -> Pin<Box<dyn Future<Output = Option<CachedResponse>> + Send + '_>>;
+
+    /// Store `response` under `key` for `ttl`.
+    fn put(&self, key: &str, response: CachedResponse, ttl: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+}
+
+/// In-process cache store holding at most `max_bytes`, evicting the least
+/// recently used entries first.
+pub struct MemoryCacheStore {
+    max_bytes: usize,
+    state: Mutex<MemoryCache>,
+}
+
+#[derive(Default)]
+struct MemoryCache {
+    entries: HashMap<String, MemoryEntry>,
+    bytes: usize,
+    /// Bumped on every access, so entries can be ordered by last use
+    tick: u64,
+}
+
+struct MemoryEntry {
+    response: CachedResponse,
+    expires: Instant,
+    used: u64,
+    size: usize,
+}
+
+impl MemoryCache {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.bytes -= entry.size;
+        }
+    }
+
+    /// Drop expired entries, then the least recently used, until `max_bytes` fit
+    fn evict(&mut self, max_bytes: usize, now: Instant) {
+        self.entries.retain(|_, entry| entry.expires > now);
+        self.bytes = self.entries.values().map(|entry| entry.size).sum();
+        if self.bytes <= max_bytes {
+            return;
+        }
+        let mut by_use: Vec<(u64, String)> = self.entries.iter().map(|(key, entry)| (entry.used, key.clone())).collect();
+        by_use.sort_unstable();
+        for (_, key) in by_use {
+            if self.bytes <= max_bytes {
+                break;
+            }
+            self.remove(&key);
+        }
+    }
+}
+
+impl MemoryCacheStore {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            state: Mutex::new(MemoryCache::default()),
+        }
+    }
+}
+
+impl CacheStore for MemoryCacheStore {
+    fn get(&self, key: &str) -> Pin<Box<dyn Future<Output = Option<CachedResponse>> + Send + '_>> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.tick += 1;
+        let tick = state.tick;
+        let now = Instant::now();
+        let found = match state.entries.get_mut(key) {
+            Some(entry) if entry.expires > now => {
+                entry.used = tick;
+                Some(entry.response.clone())
+            }
+            Some(_) => {
+                state.remove(key);
+                None
+            }
+            None => None,
+        };
+        Box::pin(async move { found })
+    }
+
+    fn put(&self, key: &str, response: CachedResponse, ttl: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        let size = response.size() + key.len();
+        if size <= self.max_bytes {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            state.tick += 1;
+            let used = state.tick;
+            state.remove(key);
+            state.entries.insert(key.to_string(), MemoryEntry { response, expires: now + ttl, used, size });
+            state.bytes += size;
+            if state.bytes > self.max_bytes {
+                state.evict(self.max_bytes, now);
+            }
+        }
+        Box::pin(async {})
+    }
+}
+
+/// Responses under `cache:` keys of a shared [`KvStore`]; if the store is
+/// unreachable every request goes to the handler.
+impl CacheStore for Arc<dyn KvStore> {
+    fn get(&self, key: &str) -> Pin<Box<dyn Future<Output = Option<CachedResponse>> + Send + '_>> {
+        let key = format!("cache:{key}");
+        Box::pin(async move {
+            let bytes = store::or_log(KvStore::get(self.as_ref(), &key).await, None)?;
+            CachedResponse::from_bytes(&bytes)
+        })
+    }
+
+    fn put(&self, key: &str, response: CachedResponse, ttl: Duration) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        let key = format!("cache:{key}");
+        Box::pin(async move {
+            store::or_log(self.set(&key, response.to_bytes(), Some(ttl)).await, ());
+        })
+    }
+}
+
+type KeyFn = Arc<dyn Fn(&RequestCtx) -> Option<String> + Send + Sync>;
+
+/// Response cache middleware configuration.
+#[derive(Clone)]
+pub struct ResponseCache {
+    store: Arc<dyn CacheStore>,
+    ttl: Duration,
+    vary: Vec<HeaderName>,
+    max_body: usize,
+    key: Option<KeyFn>,
+}
+
+impl ResponseCache {
+    /// Cache for 60 seconds unless the response says otherwise; bodies up to 1 MiB.
+    pub fn new(store: impl CacheStore) -> Self {
+        Self {
+            store: Arc::new(store),
+            ttl: Duration::from_secs(60),
+            vary: Vec::new(),
+            max_body: 1024 * 1024,
+            key: None,
+        }
+    }
+
+    /// Lifetime of responses without `s-maxage` or `max-age`.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Keep separate entries per value of these request headers (such as
+    /// `Accept-Language`). Invalid names are logged and ignored.
+    pub fn vary<I, S>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for header in headers {
+            match HeaderName::from_bytes(header.as_ref().as_bytes()) {
+                Ok(name) => self.vary.push(name),
+                Err(_) => log::warn!("invalid header name {:?} in ResponseCache::vary; ignoring it", header.as_ref()),
+            }
+        }
+        self
+    }
+
+    /// Largest body that is buffered and stored.
+    pub fn max_body(mut self, bytes: usize) -> Self {
+        self.max_body = bytes;
+        self
+    }
+
+    /// Key entries with `f` instead of the path and query, e.g. per user:
+    /// `.key(|ctx| Some(format!("{}:{}", ctx.get::<AuthUser>()?.id, ctx.request.uri())))`.
+    /// Requests with credentials are cached too, and so are `private`
+    /// responses; requests without a key are not cached.
+    pub fn key<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&RequestCtx) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Some(Arc::new(f));
+        self
+    }
+
+    /// Build the middleware function for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let cache = Arc::new(self);
+        move |ctx, next| {
+            let cache = cache.clone();
+            Box::pin(async move { cache.handle(ctx, next).await })
+        }
+    }
+
+    async fn handle(&self, ctx: RequestCtx, next: Next) -> Response {
+        let Some(key) = self.key_for(&ctx) else {
+            return next(ctx).await;
+        };
+        // A hard reload asks for a fresh copy; it still refreshes the entry
+        let refresh = ctx
+            .header("cache-control")
+            .is_some_and(|value| !CacheControl::parse(value).storable());
+        if !refresh && let Some(cached) = self.store.get(&key).await {
+            return cached.hit();
+        }
+
+        let response = next(ctx).await;
+        let Some(ttl) = self.ttl_for(&response) else {
+            return response;
+        };
+        let (mut parts, body) = response.into_parts();
+        let body = match body.collect().await {
+            Ok(collected) => collected.to_bytes(),
+            Err(e) => {
+                log::warn!("failed to buffer response for caching: {e}");
+                return ResponseBuilder::internal_error();
+            }
+        };
+        let cached = CachedResponse {
+            status: parts.status,
+            headers: parts.headers.clone(),
+            body: body.clone(),
+            stored_ms: store::unix_millis(),
+        };
+        self.store.put(&key, cached, ttl).await;
+        parts.headers.insert("x-cache", HeaderValue::from_static("MISS"));
+        Response::from_parts(parts, full(body))
+    }
+
+    /// Cache key, or `None` if the request must not be served from the cache
+    fn key_for(&self, ctx: &RequestCtx) -> Option<String> {
+        let request = &ctx.request;
+        let method = request.method();
+        if !(method == Method::GET || method == Method::HEAD) || request.headers().contains_key(RANGE) {
+            return None;
+        }
+        if let Some(key) = &self.key {
+            return Some(format!("{method} {}", key(ctx)?));
+        }
+        // Responses for one user must not reach another
+        if request.headers().contains_key(AUTHORIZATION) || request.headers().contains_key(COOKIE) {
+            return None;
+        }
+        let mut key = format!("{method} {}", request.uri());
+        for name in &self.vary {
+            key.push('\n');
+            key.push_str(name.as_str());
+            key.push(':');
+            for value in request.headers().get_all(name) {
+                key.push_str(&String::from_utf8_lossy(value.as_bytes()));
+            }
+        }
+        Some(key)
+    }
+
+    /// How long `response` may be cached, or `None` if it must not be
+    fn ttl_for(&self, response: &Response) -> Option<Duration> {
+        let cacheable_status = matches!(
+            response.status(),
+            StatusCode::OK
+                | StatusCode::NON_AUTHORITATIVE_INFORMATION
+                | StatusCode::NO_CONTENT
+                | StatusCode::MOVED_PERMANENTLY
+                | StatusCode::PERMANENT_REDIRECT
+        );
+        let headers = response.headers();
+        if !cacheable_status || headers.contains_key(SET_COOKIE) {
+            return None;
+        }
+        let size = response.body().size_hint().exact()?;
+        if size > self.max_body as u64 {
+            return None;
+        }
+        let directives = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        let directives = CacheControl::parse(&directives);
+        if !directives.storable() || (directives.private && self.key.is_none()) {
+            return None;
+        }
+        if !self.varies_on_key(headers) {
+            return None;
+        }
+        let ttl = directives.shared_max_age().unwrap_or(self.ttl);
+        (!ttl.is_zero()).then_some(ttl)
+    }
+
+    /// Whether every header the response varies on is part of the key
+    fn varies_on_key(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get_all(VARY)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .all(|name| name != "*" && (self.key.is_some() || self.vary.iter().any(|v| v.as_str().eq_ignore_ascii_case(name))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn ctx(uri: &str, headers: &[(&str, &str)]) -> RequestCtx {
+        let mut request = hyper::Request::get(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        RequestCtx::from_parts(request.body(()).unwrap().into_parts().0)
+    }
+
+    async fn body(response: Response) -> Bytes {
+        response.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    #[test]
+    fn test_cache_control_roundtrip() {
+        let cache = CacheControl::new().public().max_age(Duration::from_secs(60)).s_maxage(Duration::from_secs(300));
+        assert_eq!(cache.to_string(), "public, max-age=60, s-maxage=300");
+        assert_eq!(CacheControl::parse("Public, MAX-AGE=60, s-maxage=\"300\", foo=bar"), cache);
+        assert!(!CacheControl::parse("no-store").storable());
+        assert_eq!(CacheControl::parse("max-age=10").shared_max_age(), Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_cached_response_encoding() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+        headers.append("x-tag", HeaderValue::from_static("a"));
+        headers.append("x-tag", HeaderValue::from_static("b"));
+        let cached = CachedResponse {
+            status: StatusCode::OK,
+            headers,
+            body: Bytes::from_static(b"{\"ok\":true}\r\n\r\nmore"),
+            stored_ms: 42,
+        };
+        let decoded = CachedResponse::from_bytes(&cached.to_bytes()).unwrap();
+        assert_eq!(decoded.status, StatusCode::OK);
+        assert_eq!(decoded.headers, cached.headers);
+        assert_eq!(decoded.body, cached.body);
+        assert_eq!(decoded.stored_ms, 42);
+        assert!(CachedResponse::from_bytes(b"garbage").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_evicts_least_recently_used() {
+        let store = MemoryCacheStore::new(400);
+        let entry = |body: String| CachedResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from(body),
+            stored_ms: 0,
+        };
+        let ttl = Duration::from_secs(60);
+        store.put("a", entry("a".repeat(100)), ttl).await;
+        store.put("b", entry("b".repeat(100)), ttl).await;
+        assert!(store.get("a").await.is_some());
+        store.put("c", entry("c".repeat(100)), ttl).await;
+        assert!(store.get("a").await.is_some() && store.get("c").await.is_some());
+        assert!(store.get("b").await.is_none());
+
+        store.put("short", entry("x".to_string()), Duration::ZERO).await;
+        assert!(store.get("short").await.is_none());
+        store.put("huge", entry("h".repeat(1000)), ttl).await;
+        assert!(store.get("huge").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_caches_shareable_responses_only() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let next: Next = {
+            let calls = calls.clone();
+            Arc::new(move |ctx| {
+                let n = calls.fetch_add(1, Ordering::SeqCst);
+                let path = ctx.request.uri().path().to_string();
+                Box::pin(async move {
+                    let builder = ResponseBuilder::new();
+                    match path.as_str() {
+                        "/cookie" => builder.with_header("Set-Cookie", "a=b").build(n.to_string()),
+                        "/private" => builder.with_header("Cache-Control", "private").build(n.to_string()),
+                        "/vary" => builder.with_header("Vary", "Accept-Language").build(n.to_string()),
+                        _ => builder.build(n.to_string()),
+                    }
+                })
+            })
+        };
+        let mw = ResponseCache::new(MemoryCacheStore::new(1 << 20)).middleware();
+
+        let first = mw(ctx("/items?page=1", &[]), next.clone()).await;
+        assert_eq!(first.headers()["x-cache"], "MISS");
+        assert_eq!(body(first).await, "0");
+        let hit = mw(ctx("/items?page=1", &[]), next.clone()).await;
+        assert_eq!(hit.headers()["x-cache"], "HIT");
+        assert_eq!(hit.headers()["age"], "0");
+        assert_eq!(body(hit).await, "0");
+        assert_eq!(body(mw(ctx("/items?page=2", &[]), next.clone()).await).await, "1");
+        assert_eq!(body(mw(ctx("/items?page=1", &[("cache-control", "no-cache")]), next.clone()).await).await, "2");
+        assert_eq!(body(mw(ctx("/items?page=1", &[]), next.clone()).await).await, "2");
+
+        let before = calls.load(Ordering::SeqCst);
+        for (uri, headers) in [
+            ("/items?page=1", &[("authorization", "Bearer t")][..]),
+            ("/cookie", &[][..]),
+            ("/private", &[][..]),
+            ("/vary", &[][..]),
+        ] {
+            mw(ctx(uri, headers), next.clone()).await;
+            mw(ctx(uri, headers), next.clone()).await;
+        }
+        assert_eq!(calls.load(Ordering::SeqCst) - before, 8);
+
+        // Per-user keys may cache private responses for each user
+        let per_user = ResponseCache::new(MemoryCacheStore::new(1 << 20))
+            .key(|ctx| Some(format!("{}:{}", ctx.header("authorization")?, ctx.request.uri())))
+            .middleware();
+        let alice = [("authorization", "Bearer alice")];
+        let a = body(per_user(ctx("/private", &alice), next.clone()).await).await;
+        assert_eq!(body(per_user(ctx("/private", &alice), next.clone()).await).await, a);
+        assert_ne!(body(per_user(ctx("/private", &[("authorization", "Bearer bob")]), next.clone()).await).await, a);
+    }
+}
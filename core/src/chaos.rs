@@ -0,0 +1,256 @@
+//! Fault injection for resilience testing in development and staging.
+//!
+//! [`Chaos`] delays, fails or drops a share of requests so client timeouts,
+//! retries and circuit breakers can be exercised against a real backend.
+//! Faults apply to everything behind the middleware: register it globally,
+//! on a group, or on a single route with `with_middleware`.
+//!
+//! ```rust,ignore
+//! use s_web::chaos::Chaos;
+//! use std::time::Duration;
+//!
+//! app.use_middleware(
+//!     Chaos::new()
+//!         .enabled(std::env::var("CHAOS").is_ok())
+//!         .traffic(0.2)
+//!         .latency(Duration::from_millis(50), Duration::from_millis(500))
+//!         .error_rate(0.1)
+//!         .drop_rate(0.02)
+//!         .middleware(),
+//! );
+//! ```
+//!
+//! With [`Chaos::trigger_header`], only requests carrying the header are
+//! affected, and the header value can ask for a specific fault:
+//! `X-Chaos: latency=300`, `X-Chaos: error=503`, `X-Chaos: drop`
+//! (directives combine with `,`; an empty value applies the configured faults).
+
+use std::{
+    future::Future,
+    hash::BuildHasher,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::{Next, RequestCtx, Response, ResponseBuilder, StatusCode, log};
+
+/// Response extension that makes the server close the connection without
+/// sending the response, as if the backend crashed mid-request.
+#[derive(Debug, Clone, Copy)]
+pub struct DropConnection;
+
+/// Fault injection settings. Nothing is injected until faults are configured.
+#[derive(Debug, Clone)]
+pub struct Chaos {
+    enabled: bool,
+    traffic: f64,
+    latency: Option<(Duration, Duration)>,
+    error_rate: f64,
+    error_status: StatusCode,
+    drop_rate: f64,
+    trigger_header: Option<String>,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Faults chosen for one request
+#[derive(Debug, Default, PartialEq)]
+struct Faults {
+    delay: Option<Duration>,
+    error: Option<StatusCode>,
+    drop: bool,
+}
+
+impl Chaos {
+    /// Enabled, applying to all traffic, with no faults configured.
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            traffic: 1.0,
+            latency: None,
+            error_rate: 0.0,
+            error_status: StatusCode::INTERNAL_SERVER_ERROR,
+            drop_rate: 0.0,
+            trigger_header: None,
+        }
+    }
+
+    /// Master switch, typically fed from configuration or an environment
+    /// variable; a disabled middleware passes every request straight through.
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Share of requests (0.0 to 1.0) that faults are applied to.
+    pub fn traffic(mut self, share: f64) -> Self {
+        self.traffic = share.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Delay affected requests by a random duration between `min` and `max`.
+    pub fn latency(mut self, min: Duration, max: Duration) -> Self {
+        self.latency = Some((min, max.max(min)));
+        self
+    }
+
+    /// Share of affected requests answered with the error status instead of
+    /// reaching the handler.
+    pub fn error_rate(mut self, rate: f64) -> Self {
+        self.error_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Status of injected errors (default `500`).
+    pub fn error_status(mut self, status: StatusCode) -> Self {
+        self.error_status = status;
+        self
+    }
+
+    /// Share of affected requests whose connection is closed without a response.
+    pub fn drop_rate(mut self, rate: f64) -> Self {
+        self.drop_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Only affect requests carrying `name`; its value may pick the faults.
+    pub fn trigger_header(mut self, name: &str) -> Self {
+        self.trigger_header = Some(name.to_string());
+        self
+    }
+
+    /// Build the middleware for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let chaos = Arc::new(self);
+        move |ctx, next| {
+            let chaos = chaos.clone();
+            Box::pin(async move {
+                let faults = chaos.faults_for(&ctx);
+                if let Some(delay) = faults.delay {
+                    tokio::time::sleep(delay).await;
+                }
+                if faults.drop {
+                    let mut response = injected(StatusCode::SERVICE_UNAVAILABLE, "drop");
+                    response.extensions_mut().insert(DropConnection);
+                    return response;
+                }
+                if let Some(status) = faults.error {
+                    return injected(status, "error");
+                }
+                next(ctx).await
+            })
+        }
+    }
+
+    fn faults_for(&self, ctx: &RequestCtx) -> Faults {
+        if !self.enabled {
+            return Faults::default();
+        }
+        match &self.trigger_header {
+            Some(name) => match ctx.request.headers().get(name.as_str()) {
+                Some(value) => self.directed(value.to_str().unwrap_or_default()),
+                None => Faults::default(),
+            },
+            None if roll() < self.traffic => self.sampled(),
+            None => Faults::default(),
+        }
+    }
+
+    /// Draw the configured faults
+    fn sampled(&self) -> Faults {
+        Faults {
+            delay: self.latency.map(|(min, max)| min + (max - min).mul_f64(roll())),
+            error: (roll() < self.error_rate).then_some(self.error_status),
+            drop: roll() < self.drop_rate,
+        }
+    }
+
+    /// Faults named in a trigger header value, or the configured ones when it names none
+    fn directed(&self, value: &str) -> Faults {
+        let mut faults = Faults::default();
+        let mut named = false;
+        for directive in value.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            named = true;
+            match directive.split_once('=') {
+                Some(("latency", ms)) => faults.delay = ms.trim().parse().ok().map(Duration::from_millis),
+                Some(("error", status)) => {
+                    faults.error = status.trim().parse().ok().and_then(|s| StatusCode::from_u16(s).ok())
+                }
+                None if directive == "error" => faults.error = Some(self.error_status),
+                None if directive == "drop" => faults.drop = true,
+                _ => log::warn!("chaos: ignoring unknown directive {directive:?}"),
+            }
+        }
+        if named { faults } else { self.sampled() }
+    }
+}
+
+fn injected(status: StatusCode, fault: &str) -> Response {
+    ResponseBuilder::new()
+        .status(status)
+        .content_type("text/plain; charset=utf-8")
+        .with_header("X-Chaos-Injected", fault)
+        .build(format!(
+            "{} {}: injected fault",
+            status.as_u16(),
+            status.canonical_reason().unwrap_or("")
+        ))
+}
+
+/// Uniform draw from `[0, 1)`; not cryptographic, just unpredictable enough
+fn roll() -> f64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let bits = std::collections::hash_map::RandomState::new().hash_one(COUNTER.fetch_add(1, Ordering::Relaxed));
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_directives() {
+        let chaos = Chaos::new().error_status(StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            chaos.directed("latency=250, error"),
+            Faults {
+                delay: Some(Duration::from_millis(250)),
+                error: Some(StatusCode::BAD_GATEWAY),
+                drop: false,
+            }
+        );
+        assert_eq!(chaos.directed("error=503").error, Some(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(chaos.directed("drop").drop);
+        // No directive: the configured faults, of which there are none
+        assert_eq!(chaos.directed(""), Faults::default());
+    }
+
+    #[test]
+    fn test_sampled_rates() {
+        let chaos = Chaos::new()
+            .latency(Duration::from_millis(10), Duration::from_millis(20))
+            .error_rate(1.0);
+        for _ in 0..100 {
+            let faults = chaos.sampled();
+            let delay = faults.delay.unwrap();
+            assert!(delay >= Duration::from_millis(10) && delay <= Duration::from_millis(20));
+            assert_eq!(faults.error, Some(StatusCode::INTERNAL_SERVER_ERROR));
+            assert!(!faults.drop);
+        }
+        let hits = (0..10_000).filter(|_| roll() < 0.25).count();
+        assert!((2000..3000).contains(&hits), "{hits}");
+    }
+}
@@ -0,0 +1,587 @@
+//! HTTP client for calling other services from handlers.
+//!
+//! [`Client`] wraps hyper's pooled client: connections are kept alive and
+//! reused per host, every request has a timeout, and
+//! [`RequestBuilder::propagate`] forwards the incoming request's
+//! `X-Request-Id` and trace context so logs and traces line up across
+//! services. With the `otel` feature the trace context is that of the current
+//! span. HTTPS needs the `tls` feature.
+//!
+//! ```rust,ignore
+//! use s_web::client::Client;
+//!
+//! // One client per process: clones share the connection pool
+//! let app = Engine::new().with_state(Client::builder().timeout(Duration::from_secs(5)).build());
+//!
+//! app.get("/profile/:id", |ctx: RequestCtx| async move {
+//!     let client = ctx.state::<Client>().unwrap();
+//!     let url = format!("http://users.internal/users/{}", ctx.get_param("id").unwrap());
+//!     let user: User = client.get(&url).propagate(&ctx).send().await?.error_for_status()?.json().await?;
+//!     Ok::<_, s_web::Error>(Json(user))
+//! });
+//! ```
+//!
+//! [`ClientError`] answers `502 Bad Gateway` (`504` for timeouts) when
+//! propagated with `?`.
+
+use std::{fmt, time::Duration};
+
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    HeaderMap, Method, StatusCode, Uri,
+    body::{Bytes, Incoming},
+    header::{CONTENT_TYPE, HeaderName, HeaderValue, USER_AGENT},
+};
+use hyper_util::{
+    client::legacy::{self, connect::HttpConnector},
+    rt::{TokioExecutor, TokioTimer},
+};
+
+use crate::{HttpError, RequestCtx};
+
+#[cfg(feature = "tls")]
+type Connector = tls::HttpsConnector;
+#[cfg(not(feature = "tls"))]
+type Connector = HttpConnector;
+
+/// Headers forwarded by [`RequestBuilder::propagate`]
+const PROPAGATED: [&str; 8] = [
+    "x-request-id",
+    "traceparent",
+    "tracestate",
+    "b3",
+    "x-b3-traceid",
+    "x-b3-spanid",
+    "x-b3-parentspanid",
+    "x-b3-sampled",
+];
+
+/// Pooled HTTP client. Cheap to clone; clones share connections.
+#[derive(Clone)]
+pub struct Client {
+    inner: legacy::Client<Connector, Full<Bytes>>,
+    timeout: Duration,
+    user_agent: HeaderValue,
+}
+
+impl Client {
+    /// A client with the default settings of [`ClientBuilder`].
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.request(Method::GET, url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.request(Method::POST, url)
+    }
+
+    pub fn put(&self, url: &str) -> RequestBuilder {
+        self.request(Method::PUT, url)
+    }
+
+    pub fn patch(&self, url: &str) -> RequestBuilder {
+        self.request(Method::PATCH, url)
+    }
+
+    pub fn delete(&self, url: &str) -> RequestBuilder {
+        self.request(Method::DELETE, url)
+    }
+
+    /// Start a request with any method.
+    pub fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, self.user_agent.clone());
+        RequestBuilder {
+            client: self.clone(),
+            method,
+            uri: url.parse::<Uri>().map_err(|e| format!("invalid URL {url:?}: {e}")),
+            headers,
+            body: Bytes::new(),
+            timeout: self.timeout,
+            error: None,
+        }
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// [`Client`] settings.
+pub struct ClientBuilder {
+    timeout: Duration,
+    connect_timeout: Duration,
+    pool_idle_timeout: Duration,
+    pool_max_idle_per_host: usize,
+    user_agent: String,
+    #[cfg(feature = "tls")]
+    tls: Option<rustls::ClientConfig>,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(10),
+            pool_idle_timeout: Duration::from_secs(90),
+            pool_max_idle_per_host: 32,
+            user_agent: concat!("s_web/", env!("CARGO_PKG_VERSION")).to_string(),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+impl ClientBuilder {
+    /// Time allowed until the response headers arrive, including connecting
+    /// (default 30s). Reading the body is not limited.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Time allowed to open a TCP connection (default 10s).
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// How long an unused pooled connection is kept open (default 90s).
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = timeout;
+        self
+    }
+
+    /// Idle connections kept per host (default 32); 0 disables pooling.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = max;
+        self
+    }
+
+    /// `User-Agent` sent with every request (default `s_web/<version>`).
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// TLS settings for `https` URLs, e.g. to trust an internal CA (default:
+    /// the Mozilla root certificates).
+    #[cfg(feature = "tls")]
+    pub fn tls_config(mut self, config: rustls::ClientConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    pub fn build(self) -> Client {
+        let mut http = HttpConnector::new();
+        http.set_connect_timeout(Some(self.connect_timeout));
+        http.set_nodelay(true);
+        http.enforce_http(false);
+        #[cfg(feature = "tls")]
+        let connector = tls::HttpsConnector::new(http, self.tls);
+        #[cfg(not(feature = "tls"))]
+        let connector = http;
+        let inner = legacy::Client::builder(TokioExecutor::new())
+            .pool_timer(TokioTimer::new())
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .build(connector);
+        let user_agent = HeaderValue::from_str(&self.user_agent).unwrap_or_else(|_| {
+            crate::log::warn!("invalid user agent {:?}; using the default", self.user_agent);
+            HeaderValue::from_static(concat!("s_web/", env!("CARGO_PKG_VERSION")))
+        });
+        Client {
+            inner,
+            timeout: self.timeout,
+            user_agent,
+        }
+    }
+}
+
+/// A request being built; nothing is sent until [`send`](Self::send).
+pub struct RequestBuilder {
+    client: Client,
+    method: Method,
+    uri: Result<Uri, String>,
+    headers: HeaderMap,
+    body: Bytes,
+    timeout: Duration,
+    /// First invalid header or body, reported by `send`
+    error: Option<String>,
+}
+
+impl RequestBuilder {
+    /// Add a header; an invalid name or value makes `send` fail.
+    pub fn header<V: AsRef<str>>(mut self, key: &str, value: V) -> Self {
+        match (HeaderName::from_bytes(key.as_bytes()), HeaderValue::from_str(value.as_ref())) {
+            (Ok(name), Ok(value)) => {
+                self.headers.append(name, value);
+            }
+            _ => {
+                self.error.get_or_insert_with(|| format!("invalid header {key:?}"));
+            }
+        }
+        self
+    }
+
+    /// `Authorization: Bearer <token>`.
+    pub fn bearer_auth(self, token: &str) -> Self {
+        self.header("Authorization", format!("Bearer {token}"))
+    }
+
+    /// Send `body` as is.
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Send `value` as JSON.
+    pub fn json<T: serde::Serialize + ?Sized>(mut self, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => {
+                self.body = body.into();
+                self.headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            }
+            Err(e) => {
+                self.error.get_or_insert_with(|| format!("failed to serialize JSON body: {e}"));
+            }
+        }
+        self
+    }
+
+    /// Override the client's timeout for this request.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Forward `X-Request-Id` and the trace context of the request being
+    /// handled, so the callee's logs and spans join the same trace.
+    pub fn propagate(mut self, ctx: &RequestCtx) -> Self {
+        #[cfg(feature = "otel")]
+        {
+            let propagation = ctx
+                .request
+                .extensions()
+                .get::<crate::otel::RemoteContext>()
+                .map_or(crate::otel::Propagation::TraceContext, |remote| remote.propagation);
+            crate::otel::inject(&mut self.headers, propagation);
+        }
+        // A span of our own, injected above, replaces the caller's context
+        let injected = self.headers.contains_key("traceparent") || self.headers.contains_key("b3");
+        for name in PROPAGATED {
+            if self.headers.contains_key(name) || (injected && name != "x-request-id") {
+                continue;
+            }
+            for value in ctx.request.headers().get_all(name) {
+                self.headers.append(name, value.clone());
+            }
+        }
+        self
+    }
+
+    /// Send the request and wait for the response headers.
+    pub async fn send(self) -> Result<ClientResponse, ClientError> {
+        if let Some(error) = self.error {
+            return Err(ClientError::InvalidRequest(error));
+        }
+        let uri = self.uri.map_err(ClientError::InvalidRequest)?;
+        let mut request = hyper::Request::new(Full::new(self.body));
+        *request.method_mut() = self.method;
+        *request.uri_mut() = uri;
+        *request.headers_mut() = self.headers;
+        match tokio::time::timeout(self.timeout, self.client.inner.request(request)).await {
+            Ok(Ok(response)) => Ok(ClientResponse { inner: response }),
+            Ok(Err(e)) => Err(ClientError::Request(e)),
+            Err(_) => Err(ClientError::Timeout(self.timeout)),
+        }
+    }
+}
+
+/// Response of a [`Client`] request; the body is read on demand.
+#[derive(Debug)]
+pub struct ClientResponse {
+    inner: hyper::Response<Incoming>,
+}
+
+impl ClientResponse {
+    pub fn status(&self) -> StatusCode {
+        self.inner.status()
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        self.inner.headers()
+    }
+
+    /// `Err(ClientError::Status)` unless the status is 2xx.
+    pub fn error_for_status(self) -> Result<Self, ClientError> {
+        match self.status() {
+            status if status.is_success() => Ok(self),
+            status => Err(ClientError::Status(status)),
+        }
+    }
+
+    /// Read the whole body.
+    pub async fn bytes(self) -> Result<Bytes, ClientError> {
+        Ok(self.inner.into_body().collect().await.map_err(ClientError::Body)?.to_bytes())
+    }
+
+    /// Read the body as UTF-8, replacing invalid sequences.
+    pub async fn text(self) -> Result<String, ClientError> {
+        Ok(String::from_utf8_lossy(&self.bytes().await?).into_owned())
+    }
+
+    /// Read the body as JSON.
+    pub async fn json<T: serde::de::DeserializeOwned>(self) -> Result<T, ClientError> {
+        serde_json::from_slice(&self.bytes().await?).map_err(ClientError::Json)
+    }
+
+    /// The underlying hyper response, to stream the body.
+    pub fn into_inner(self) -> hyper::Response<Incoming> {
+        self.inner
+    }
+}
+
+/// Why a [`Client`] request failed.
+#[derive(Debug)]
+pub enum ClientError {
+    /// Bad URL, header or body; nothing was sent
+    InvalidRequest(String),
+    /// No response headers within the timeout
+    Timeout(Duration),
+    /// Connecting or exchanging the request failed
+    Request(legacy::Error),
+    /// Reading the response body failed
+    Body(hyper::Error),
+    /// The response body was not the expected JSON
+    Json(serde_json::Error),
+    /// Non-2xx status, from [`ClientResponse::error_for_status`]
+    Status(StatusCode),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::InvalidRequest(e) => write!(f, "invalid request: {e}"),
+            ClientError::Timeout(timeout) => write!(f, "no response within {timeout:?}"),
+            ClientError::Request(e) => match std::error::Error::source(e) {
+                Some(source) => write!(f, "request failed: {e}: {source}"),
+                None => write!(f, "request failed: {e}"),
+            },
+            ClientError::Body(e) => write!(f, "reading response body failed: {e}"),
+            ClientError::Json(e) => write!(f, "invalid JSON response: {e}"),
+            ClientError::Status(status) => write!(f, "upstream answered {status}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Request(e) => Some(e),
+            ClientError::Body(e) => Some(e),
+            ClientError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// An upstream failure is the gateway's problem to report, not the caller's
+impl HttpError for ClientError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ClientError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+mod tls {
+    use std::{
+        future::Future,
+        io,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+    };
+
+    use hyper::{
+        Uri,
+        rt::{Read, ReadBufCursor, Write},
+    };
+    use hyper_util::{
+        client::legacy::connect::{Connected, Connection, HttpConnector},
+        rt::TokioIo,
+    };
+    use rustls::pki_types::ServerName;
+    use tokio::net::TcpStream;
+    use tokio_rustls::{TlsConnector, client::TlsStream};
+    use tower_service::Service;
+
+    use crate::BoxError;
+
+    /// Plain TCP for `http`, rustls on top for `https`
+    #[derive(Clone)]
+    pub(super) struct HttpsConnector {
+        http: HttpConnector,
+        tls: TlsConnector,
+    }
+
+    impl HttpsConnector {
+        pub(super) fn new(http: HttpConnector, config: Option<rustls::ClientConfig>) -> Self {
+            let config = config.unwrap_or_else(|| {
+                let roots = rustls::RootCertStore {
+                    roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+                };
+                rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                    .with_safe_default_protocol_versions()
+                    .expect("ring supports the default protocol versions")
+                    .with_root_certificates(roots)
+                    .with_no_client_auth()
+            });
+            Self {
+                http,
+                tls: TlsConnector::from(Arc::new(config)),
+            }
+        }
+    }
+
+    impl Service<Uri> for HttpsConnector {
+        type Response = MaybeTls;
+        type Error = BoxError;
+        type Future = Pin<Box<dyn Future<Output = Result<MaybeTls, BoxError>> + Send>>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+            self.http.poll_ready(cx).map_err(Into::into)
+        }
+
+        fn call(&mut self, uri: Uri) -> Self::Future {
+            let https = uri.scheme_str() == Some("https");
+            let host = uri.host().unwrap_or_default().trim_matches(['[', ']']).to_string();
+            let connecting = self.http.call(uri);
+            let tls = self.tls.clone();
+            Box::pin(async move {
+                let tcp = connecting.await?;
+                if !https {
+                    return Ok(MaybeTls::Plain(tcp));
+                }
+                let name = ServerName::try_from(host)?;
+                let stream = tls.connect(name, tcp.into_inner()).await?;
+                Ok(MaybeTls::Tls(Box::new(TokioIo::new(stream))))
+            })
+        }
+    }
+
+    pub(super) enum MaybeTls {
+        Plain(TokioIo<TcpStream>),
+        Tls(Box<TokioIo<TlsStream<TcpStream>>>),
+    }
+
+    impl Connection for MaybeTls {
+        fn connected(&self) -> Connected {
+            match self {
+                MaybeTls::Plain(tcp) => tcp.inner().connected(),
+                MaybeTls::Tls(tls) => tls.inner().get_ref().0.connected(),
+            }
+        }
+    }
+
+    impl Read for MaybeTls {
+        fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: ReadBufCursor<'_>) -> Poll<io::Result<()>> {
+            match self.get_mut() {
+                MaybeTls::Plain(tcp) => Pin::new(tcp).poll_read(cx, buf),
+                MaybeTls::Tls(tls) => Pin::new(tls.as_mut()).poll_read(cx, buf),
+            }
+        }
+    }
+
+    impl Write for MaybeTls {
+        fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            match self.get_mut() {
+                MaybeTls::Plain(tcp) => Pin::new(tcp).poll_write(cx, buf),
+                MaybeTls::Tls(tls) => Pin::new(tls.as_mut()).poll_write(cx, buf),
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            match self.get_mut() {
+                MaybeTls::Plain(tcp) => Pin::new(tcp).poll_flush(cx),
+                MaybeTls::Tls(tls) => Pin::new(tls.as_mut()).poll_flush(cx),
+            }
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            match self.get_mut() {
+                MaybeTls::Plain(tcp) => Pin::new(tcp).poll_shutdown(cx),
+                MaybeTls::Tls(tls) => Pin::new(tls.as_mut()).poll_shutdown(cx),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper_util::rt::TokioIo;
+
+    use crate::IntoResponse;
+
+    #[tokio::test]
+    async fn test_round_trip_with_propagation() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let service = hyper::service::service_fn(|request: hyper::Request<Incoming>| async move {
+                    let (parts, body) = request.into_parts();
+                    let echoed = format!(
+                        "{} {} {}",
+                        parts.method,
+                        parts.headers["x-request-id"].to_str().unwrap(),
+                        String::from_utf8_lossy(&body.collect().await?.to_bytes()),
+                    );
+                    Ok::<_, hyper::Error>(hyper::Response::new(Full::new(Bytes::from(echoed))))
+                });
+                tokio::spawn(hyper::server::conn::http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+
+        let incoming = hyper::Request::get("/").header("x-request-id", "req-7").body(()).unwrap();
+        let ctx = RequestCtx::from_parts(incoming.into_parts().0);
+        let client = Client::new();
+        let response = client
+            .post(&format!("http://{addr}/echo"))
+            .propagate(&ctx)
+            .json(&serde_json::json!({ "a": 1 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), r#"POST req-7 {"a":1}"#);
+    }
+
+    #[tokio::test]
+    async fn test_errors_map_to_gateway_statuses() {
+        let invalid = Client::new().get("not a url").send().await.unwrap_err();
+        assert!(matches!(invalid, ClientError::InvalidRequest(_)));
+        let bad_header = Client::new().get("http://127.0.0.1/").header("bad header", "x").send().await;
+        assert!(matches!(bad_header, Err(ClientError::InvalidRequest(_))));
+
+        // Nothing listens on the discard port
+        let refused = Client::new().get("http://127.0.0.1:9/").send().await.unwrap_err();
+        assert_eq!(refused.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(ClientError::Timeout(Duration::from_secs(1)).status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(crate::Error::from(refused).into_response().status(), StatusCode::BAD_GATEWAY);
+    }
+}
@@ -0,0 +1,404 @@
+//! Response compression (gzip, deflate, brotli) negotiated via `Accept-Encoding`.
+//!
+//! ```rust,ignore
+//! use s_web::compression::Compression;
+//!
+//! app.use_middleware(Compression::new().min_size(1024).middleware());
+//! ```
+//!
+//! Bodies are compressed frame by frame while they stream, and each frame is
+//! flushed so streamed output reaches the client without waiting for the
+//! compressor's buffer to fill. Responses that are already encoded, partial
+//! (`206`), marked `Cache-Control: no-transform`, below the size threshold or
+//! of a content type that doesn't compress well are passed through untouched.
+
+use std::{
+    future::Future,
+    io::{self, Write},
+    pin::Pin,
+    sync::Arc,
+};
+
+use futures_util::{StreamExt, stream};
+use http_body_util::{BodyExt, BodyStream, StreamBody};
+use hyper::{
+    StatusCode,
+    body::{Body as _, Bytes, Frame},
+    header::{
+        ACCEPT_ENCODING, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH,
+        CONTENT_RANGE, CONTENT_TYPE, ETAG, HeaderValue, VARY,
+    },
+};
+
+use crate::{Next, RequestCtx, Response, log, response::Body};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// Server preference when the client rates several encodings equally
+    const PREFERENCE: [Encoding; 3] = [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+
+    fn token(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    fn encoder(self) -> Encoder {
+        match self {
+            Encoding::Brotli => {
+                // Quality 4 keeps CPU cost close to gzip for dynamic responses
+                Encoder::Brotli(Box::new(brotli::CompressorWriter::new(Vec::new(), 4096, 4, 22)))
+            }
+            Encoding::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+            Encoding::Deflate => Encoder::Deflate(flate2::write::ZlibEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+        }
+    }
+}
+
+enum Encoder {
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    // HTTP's "deflate" is the zlib format (RFC 9110 §8.4.1.2)
+    Deflate(flate2::write::ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    /// Compress a chunk and return everything the encoder has produced so far
+    fn write(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        let out = match self {
+            Encoder::Brotli(w) => {
+                w.write_all(data)?;
+                w.flush()?;
+                w.get_mut()
+            }
+            Encoder::Gzip(w) => {
+                w.write_all(data)?;
+                w.flush()?;
+                w.get_mut()
+            }
+            Encoder::Deflate(w) => {
+                w.write_all(data)?;
+                w.flush()?;
+                w.get_mut()
+            }
+        };
+        Ok(Bytes::from(std::mem::take(out)))
+    }
+
+    /// Finish the stream and return the remaining output
+    fn finish(self) -> io::Result<Bytes> {
+        let out = match self {
+            Encoder::Brotli(w) => w.into_inner(),
+            Encoder::Gzip(w) => w.finish()?,
+            Encoder::Deflate(w) => w.finish()?,
+        };
+        Ok(Bytes::from(out))
+    }
+}
+
+type ContentTypeFilter = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Compression middleware configuration.
+#[derive(Clone)]
+pub struct Compression {
+    min_size: u64,
+    brotli: bool,
+    gzip: bool,
+    deflate: bool,
+    content_types: ContentTypeFilter,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compression {
+    /// Brotli, gzip and deflate for text-like responses of 1 KiB or more.
+    pub fn new() -> Self {
+        Self {
+            min_size: 1024,
+            brotli: true,
+            gzip: true,
+            deflate: true,
+            content_types: Arc::new(is_compressible),
+        }
+    }
+
+    /// Leave responses smaller than this uncompressed. Streaming bodies of
+    /// unknown length are always compressed.
+    pub fn min_size(mut self, bytes: u64) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    pub fn brotli(mut self, enabled: bool) -> Self {
+        self.brotli = enabled;
+        self
+    }
+
+    pub fn gzip(mut self, enabled: bool) -> Self {
+        self.gzip = enabled;
+        self
+    }
+
+    pub fn deflate(mut self, enabled: bool) -> Self {
+        self.deflate = enabled;
+        self
+    }
+
+    /// Decide which content types to compress (default: text, JSON,
+    /// JavaScript, XML and SVG; not `text/event-stream`).
+    pub fn content_types<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.content_types = Arc::new(filter);
+        self
+    }
+
+    /// Build the middleware function for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let compression = Arc::new(self);
+        move |ctx, next| {
+            let compression = compression.clone();
+            Box::pin(async move {
+                let accept = ctx.header(ACCEPT_ENCODING.as_str()).map(str::to_owned);
+                let head = ctx.request.method() == hyper::Method::HEAD;
+                let resp = next(ctx).await;
+                compression.apply(resp, accept.as_deref(), head)
+            })
+        }
+    }
+
+    fn apply(&self, mut resp: Response, accept: Option<&str>, head: bool) -> Response {
+        if !self.eligible(&resp) {
+            return resp;
+        }
+        // Caches must key on Accept-Encoding whether or not this client got a compressed body
+        add_vary(&mut resp);
+
+        let Some(encoding) = accept.and_then(|a| self.negotiate(a)) else {
+            return resp;
+        };
+        let len = if head {
+            resp.headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok()?.parse::<u64>().ok())
+        } else {
+            resp.body().size_hint().exact()
+        };
+        if len.is_some_and(|len| len < self.min_size) {
+            return resp;
+        }
+
+        let headers = resp.headers_mut();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.token()));
+        headers.remove(CONTENT_LENGTH);
+        headers.remove(ACCEPT_RANGES);
+        // The compressed representation is no longer byte-identical to the strong ETag
+        if let Some(etag) = headers.get(ETAG).and_then(|v| v.to_str().ok())
+            && !etag.starts_with("W/")
+            && let Ok(weak) = HeaderValue::from_str(&format!("W/{etag}"))
+        {
+            headers.insert(ETAG, weak);
+        }
+        if head {
+            return resp;
+        }
+        let (parts, body) = resp.into_parts();
+        Response::from_parts(parts, compress(body, encoding.encoder()))
+    }
+
+    fn eligible(&self, resp: &Response) -> bool {
+        let status = resp.status();
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+            || status == StatusCode::PARTIAL_CONTENT
+        {
+            return false;
+        }
+        let headers = resp.headers();
+        if headers.contains_key(CONTENT_ENCODING) || headers.contains_key(CONTENT_RANGE) {
+            return false;
+        }
+        let no_transform = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .any(|v| v.to_ascii_lowercase().contains("no-transform"));
+        !no_transform
+            && headers
+                .get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|ct| (self.content_types)(ct))
+    }
+
+    /// Pick the enabled encoding with the highest q-value (RFC 9110 §12.5.3)
+    fn negotiate(&self, accept: &str) -> Option<Encoding> {
+        let mut explicit: [Option<f32>; 3] = [None; 3];
+        let mut wildcard = None;
+        for item in accept.split(',') {
+            let mut params = item.split(';');
+            let name = params.next().unwrap_or("").trim().to_ascii_lowercase();
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            match name.as_str() {
+                "br" => explicit[0] = Some(q),
+                "gzip" | "x-gzip" => explicit[1] = Some(q),
+                "deflate" => explicit[2] = Some(q),
+                "*" => wildcard = Some(q),
+                _ => {}
+            }
+        }
+        let enabled = [self.brotli, self.gzip, self.deflate];
+        let mut best: Option<(Encoding, f32)> = None;
+        for (i, encoding) in Encoding::PREFERENCE.into_iter().enumerate() {
+            let q = explicit[i].or(wildcard).unwrap_or(0.0);
+            if enabled[i] && q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((encoding, q));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+}
+
+/// Default filter: formats that are usually text and compress well
+fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    (mime.starts_with("text/") && mime != "text/event-stream")
+        || mime == "application/json"
+        || mime.ends_with("+json")
+        || mime == "application/javascript"
+        || mime == "application/xml"
+        || mime.ends_with("+xml")
+        || mime == "application/wasm"
+}
+
+fn add_vary(resp: &mut Response) {
+    let vary = resp
+        .headers()
+        .get_all(VARY)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .any(|v| v == "*" || v.eq_ignore_ascii_case("accept-encoding"));
+    if !vary {
+        resp.headers_mut()
+            .append(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+}
+
+/// Compress data frames as they arrive; trailers are dropped
+fn compress(body: Body, encoder: Encoder) -> Body {
+    let frames = BodyStream::new(body);
+    let compressed = stream::unfold(Some((frames, encoder)), |state| async move {
+        let (mut frames, mut encoder) = state?;
+        loop {
+            let written = match frames.next().await {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => encoder.write(&data),
+                    Err(_) => continue,
+                },
+                Some(Err(e)) => return Some((Err(e), None)),
+                None => {
+                    return match encoder.finish() {
+                        Ok(tail) => Some((Ok(Frame::data(tail)), None)),
+                        Err(e) => {
+                            log::warn!("compression failed: {e}");
+                            None
+                        }
+                    };
+                }
+            };
+            match written {
+                Ok(out) if out.is_empty() => continue,
+                Ok(out) => return Some((Ok(Frame::data(out)), Some((frames, encoder)))),
+                Err(e) => {
+                    log::warn!("compression failed: {e}");
+                    return None;
+                }
+            }
+        }
+    });
+    BodyExt::boxed(StreamBody::new(compressed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResponseBuilder;
+    use std::io::Read;
+
+    #[test]
+    fn test_negotiate() {
+        let c = Compression::new();
+        assert_eq!(c.negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(c.negotiate("gzip;q=1.0, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(c.negotiate("deflate, *;q=0.1"), Some(Encoding::Deflate));
+        assert_eq!(c.negotiate("*"), Some(Encoding::Brotli));
+        assert_eq!(c.negotiate("br;q=0, identity"), None);
+        assert_eq!(c.negotiate(""), None);
+        assert_eq!(c.brotli(false).negotiate("br, gzip;q=0.8"), Some(Encoding::Gzip));
+    }
+
+    #[tokio::test]
+    async fn test_compresses_eligible_responses() {
+        let text = "hello compression ".repeat(200);
+        let c = Compression::new();
+        let plain = || ResponseBuilder::new().content_type("text/plain; charset=utf-8");
+
+        let resp = c.apply(plain().build(text.clone()), Some("gzip"), false);
+        assert_eq!(resp.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(resp.headers()[VARY], "accept-encoding");
+        assert!(!resp.headers().contains_key(CONTENT_LENGTH));
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&body[..]).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, text);
+
+        let resp = c.apply(plain().build(text.clone()), Some("br"), false);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let mut decoded = String::new();
+        brotli::Decompressor::new(&body[..], 4096).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, text);
+
+        let small = c.apply(plain().build("tiny"), Some("gzip"), false);
+        assert!(!small.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(small.headers()[VARY], "accept-encoding");
+
+        let binary = ResponseBuilder::new().content_type("image/png").build(vec![0u8; 4096]);
+        let binary = c.apply(binary, Some("gzip"), false);
+        assert!(!binary.headers().contains_key(CONTENT_ENCODING));
+        assert!(!binary.headers().contains_key(VARY));
+    }
+}
@@ -0,0 +1,143 @@
+//! Load shedding: a cap on requests in flight.
+//!
+//! [`ConcurrencyLimit`] lets at most `max` requests through the part of the
+//! chain it wraps at once; the rest are answered `503` with `Retry-After`
+//! right away (or after waiting up to [`ConcurrencyLimit::queue_timeout`] for
+//! a slot), so one slow downstream can't pile up unbounded tasks.
+//!
+//! ```rust,ignore
+//! use s_web::concurrency::ConcurrencyLimit;
+//! use std::time::Duration;
+//!
+//! // At most 1024 requests in the whole server
+//! app.use_middleware(ConcurrencyLimit::new(1024).middleware());
+//! // Reports talk to a slow warehouse; give them 8 slots and a short queue
+//! app.group("/reports").use_middleware(
+//!     ConcurrencyLimit::new(8)
+//!         .queue_timeout(Duration::from_millis(200))
+//!         .middleware(),
+//! );
+//! ```
+//!
+//! Clones share their slots, so one limit can cover several routes. For
+//! limits per class of request (health, admin, batch) see
+//! [`crate::lanes`].
+
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{Next, RequestCtx, Response, ResponseBuilder, StatusCode};
+
+/// Concurrency limit middleware configuration. Clones share the same slots.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimit {
+    max: usize,
+    permits: Arc<Semaphore>,
+    queue_timeout: Duration,
+    retry_after: Duration,
+}
+
+impl ConcurrencyLimit {
+    /// Allow at most `max` requests in flight; shed the rest immediately.
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            permits: Arc::new(Semaphore::new(max)),
+            queue_timeout: Duration::ZERO,
+            retry_after: Duration::from_secs(1),
+        }
+    }
+
+    /// Let a request wait this long for a free slot before it is shed
+    /// (default: not at all).
+    pub fn queue_timeout(mut self, timeout: Duration) -> Self {
+        self.queue_timeout = timeout;
+        self
+    }
+
+    /// `Retry-After` sent with the 503 (default 1s, rounded up to seconds).
+    pub fn retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Requests currently holding a slot.
+    pub fn in_flight(&self) -> usize {
+        self.max - self.permits.available_permits()
+    }
+
+    /// Build the middleware function for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let limit = Arc::new(self);
+        move |ctx, next| {
+            let limit = limit.clone();
+            Box::pin(async move {
+                let Some(permit) = limit.acquire().await else {
+                    return limit.saturated();
+                };
+                let response = next(ctx).await;
+                drop(permit);
+                response
+            })
+        }
+    }
+
+    /// A slot, or `None` if none freed up within the queue timeout
+    async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Some(permit);
+        }
+        if self.queue_timeout.is_zero() {
+            return None;
+        }
+        tokio::time::timeout(self.queue_timeout, self.permits.clone().acquire_owned())
+            .await
+            .ok()?
+            .ok()
+    }
+
+    fn saturated(&self) -> Response {
+        let secs = self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0);
+        ResponseBuilder::new()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .content_type("text/plain; charset=utf-8")
+            .with_header("Retry-After", secs.max(1).to_string())
+            .build("503 Service Unavailable: too many requests in flight")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sheds_when_saturated_and_queues_briefly() {
+        let limit = ConcurrencyLimit::new(1).queue_timeout(Duration::from_millis(50));
+        let mw = limit.clone().middleware();
+        let slow: Next = Arc::new(|_| {
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                ResponseBuilder::new().build("slow")
+            })
+        });
+        let fast: Next = Arc::new(|_| Box::pin(async { ResponseBuilder::new().build("fast") }));
+        let ctx = || RequestCtx::from_parts(hyper::Request::get("/").body(()).unwrap().into_parts().0);
+
+        let running = tokio::spawn(mw(ctx(), slow));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(limit.in_flight(), 1);
+        let shed = mw(ctx(), fast.clone()).await;
+        assert_eq!(shed.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(shed.headers()["retry-after"], "1");
+
+        assert_eq!(running.await.unwrap().status(), StatusCode::OK);
+        assert_eq!(limit.in_flight(), 0);
+        assert_eq!(mw(ctx(), fast).await.status(), StatusCode::OK);
+    }
+}
@@ -1,44 +1,296 @@
 //! Context for handling HTTP requests in a web application.
 
-use http_body_util::BodyExt;
+use http_body_util::{BodyExt, Full, Limited, LengthLimitError, combinators::BoxBody};
 use hyper::body::Bytes;
-use std::net::SocketAddr;
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{IntoResponse, Params, Response, ipfilter::Cidr, log, state::StateMap};
 
 /// Type alias for the raw incoming hyper request
 pub type HyperRequest = hyper::Request<hyper::body::Incoming>;
 
+/// Largest body `body_bytes` buffers unless the engine or route says otherwise
+pub const DEFAULT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Returned by [`RequestCtx::body_bytes`] (and everything built on it) when the
+/// body exceeds the body limit. It answers `413 Payload Too Large` when a
+/// handler propagates it with `?` into [`Error`](crate::Error).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadTooLarge {
+    pub limit: usize,
+}
+
+impl fmt::Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request body exceeds the {} byte limit", self.limit)
+    }
+}
+
+impl std::error::Error for PayloadTooLarge {}
+
+/// Returned by [`RequestCtx::body_bytes`] when the body has not fully arrived
+/// within the server's body read timeout (see
+/// [`ServerConfig::body_read_timeout`](crate::ServerConfig::body_read_timeout)).
+/// It answers `408 Request Timeout` when a handler propagates it with `?` into
+/// [`Error`](crate::Error).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyTimeout {
+    pub timeout: Duration,
+}
+
+impl fmt::Display for BodyTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request body not received within {:?}", self.timeout)
+    }
+}
+
+impl std::error::Error for BodyTimeout {}
+
+/// Returned by [`RequestCtx::param`] when a path parameter is missing or does
+/// not parse. It answers `400 Bad Request`, also when a handler propagates it
+/// with `?` into [`Error`](crate::Error).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamError {
+    /// The route has no parameter of that name
+    Missing { name: String },
+    /// The segment did not parse into the requested type
+    Invalid { name: String, value: String, reason: String },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Missing { name } => write!(f, "missing path parameter `{name}`"),
+            ParamError::Invalid { name, value, reason } => {
+                write!(f, "invalid path parameter `{name}` ({value:?}): {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+impl IntoResponse for ParamError {
+    fn into_response(self) -> Response {
+        crate::Error::from(self).into_response()
+    }
+}
+
+/// Returned by [`RequestCtx::query`] and [`RequestCtx::form`] when the query
+/// string or form body does not deserialize into the requested type (`400 Bad
+/// Request`), or the body is not a form (`415 Unsupported Media Type`). It
+/// answers that status also when a handler propagates it with `?` into
+/// [`Error`](crate::Error).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UrlEncodedError {
+    /// The query string or form body did not deserialize
+    Invalid { what: &'static str, reason: String },
+    /// The body's `Content-Type` is not `application/x-www-form-urlencoded`
+    WrongContentType { content_type: Option<String> },
+}
+
+impl fmt::Display for UrlEncodedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlEncodedError::Invalid { what, reason } => write!(f, "invalid {what}: {reason}"),
+            UrlEncodedError::WrongContentType { content_type: Some(found) } => {
+                write!(f, "expected an application/x-www-form-urlencoded body, got {found}")
+            }
+            UrlEncodedError::WrongContentType { content_type: None } => {
+                write!(f, "expected an application/x-www-form-urlencoded body, got no content type")
+            }
+        }
+    }
+}
+
+impl std::error::Error for UrlEncodedError {}
+
 pub struct RequestCtx {
     pub request: hyper::Request<()>, // Request without body
-    pub params: std::collections::HashMap<String, String>,
+    pub params: Params,
     body: Option<Bytes>,                      // Cached body
     body_stream: Option<hyper::body::Incoming>, // Original body stream
     pub remote_addr: Option<SocketAddr>,      // Remote address
+    client_ip: Option<IpAddr>,                // Caller behind trusted proxies
+    state: Option<Arc<StateMap>>,             // Shared application state
+    body_limit: usize,
+    body_deadline: Option<(tokio::time::Instant, Duration)>,
+}
+
+/// Rewrite an absolute-form target (`GET http://host/path`, sent to proxies and
+/// by some old HTTP/1.0 clients) to origin form so handlers see the same
+/// `uri()` either way. The URI's authority replaces the `Host` header, as
+/// RFC 9112 §3.2.2 requires.
+fn to_origin_form(parts: &mut hyper::http::request::Parts) {
+    // CONNECT targets are authority-form (`host:port`) and must stay that way
+    if parts.method == hyper::Method::CONNECT {
+        return;
+    }
+    let Some(authority) = parts.uri.authority() else {
+        return;
+    };
+    if let Ok(host) = hyper::header::HeaderValue::from_str(authority.as_str()) {
+        parts.headers.insert(hyper::header::HOST, host);
+    }
+    let target = parts.uri.path_and_query().map_or("/", |pq| pq.as_str());
+    if let Ok(uri) = target.parse() {
+        parts.uri = uri;
+    }
 }
 
 impl RequestCtx {
     /// Create a new RequestCtx from a hyper request (infallible, body is lazy-loaded)
     pub fn new(request: HyperRequest) -> Self {
-        let (parts, body) = request.into_parts();
+        let (mut parts, body) = request.into_parts();
+        to_origin_form(&mut parts);
         RequestCtx {
             request: hyper::Request::from_parts(parts, ()),
-            params: std::collections::HashMap::new(),
+            params: Params::default(),
             body: None,
             body_stream: Some(body),
             remote_addr: None,
+            client_ip: None,
+            state: None,
+            body_limit: DEFAULT_BODY_LIMIT,
+            body_deadline: None,
+        }
+    }
+
+    /// Context for a request whose body isn't a hyper stream (tower adapters)
+    #[cfg(any(feature = "tower", test))]
+    pub(crate) fn from_parts(mut parts: hyper::http::request::Parts) -> Self {
+        to_origin_form(&mut parts);
+        RequestCtx {
+            request: hyper::Request::from_parts(parts, ()),
+            params: Params::default(),
+            body: None,
+            body_stream: None,
+            remote_addr: None,
+            client_ip: None,
+            state: None,
+            body_limit: DEFAULT_BODY_LIMIT,
+            body_deadline: None,
         }
     }
 
+    /// Take the body in whatever form it is in, to hand it back with
+    /// [`restore_body`](Self::restore_body) (tower adapters)
+    #[cfg(feature = "tower")]
+    pub(crate) fn take_raw_body(&mut self) -> (Option<Bytes>, Option<hyper::body::Incoming>) {
+        (self.body.take(), self.body_stream.take())
+    }
+
+    #[cfg(feature = "tower")]
+    pub(crate) fn restore_body(&mut self, body: Option<Bytes>, stream: Option<hyper::body::Incoming>) {
+        self.body = body;
+        self.body_stream = stream;
+    }
+
     /// Attach the remote address (called by the engine after construction)
     pub fn with_remote_addr(mut self, addr: SocketAddr) -> Self {
         self.remote_addr = Some(addr);
         self
     }
 
+    /// Socket address of the connection, if the request came through the
+    /// server (unset for contexts built by hand or by `MiddlewareLayer`).
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// Address of the caller: the remote address, or the one a trusted proxy
+    /// forwarded (see `Engine::trust_proxies`).
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip.or_else(|| self.remote_addr.map(|addr| addr.ip()))
+    }
+
+    /// Read the forwarding headers if the connection comes from a trusted proxy
+    pub(crate) fn resolve_client_ip(&mut self, trusted: &[Cidr]) {
+        if let Some(remote) = self.remote_addr {
+            self.client_ip = Some(crate::ipfilter::client_ip(remote.ip(), self.request.headers(), trusted));
+        }
+    }
+
+    /// Attach the shared application state (called by the engine per request)
+    pub(crate) fn set_state(&mut self, state: Arc<StateMap>) {
+        self.state = Some(state);
+    }
+
+    /// Maximum number of body bytes `body_bytes` will buffer
+    pub fn body_limit(&self) -> usize {
+        self.body_limit
+    }
+
+    /// Change the body limit for this request; only affects bodies not read yet.
+    pub fn set_body_limit(&mut self, limit: usize) {
+        self.body_limit = limit;
+    }
+
+    /// Fail buffered body reads that haven't finished `timeout` from now
+    pub(crate) fn set_body_read_timeout(&mut self, timeout: Duration) {
+        self.body_deadline = Some((tokio::time::Instant::now() + timeout, timeout));
+    }
+
+    /// Borrow shared application state registered on the engine or by a startup hook
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.state.as_ref()?.get::<T>()
+    }
+
+    /// Store a value for the middleware and handlers that run next, replacing
+    /// (and returning) any earlier value of the same type. Keyed by type, so
+    /// wrap plain strings or ids in a type of your own (`AuthUser(42)`).
+    pub fn insert<T: Clone + Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.request.extensions_mut().insert(value)
+    }
+
+    /// Borrow a value an earlier middleware stored with [`insert`](Self::insert)
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.request.extensions().get::<T>()
+    }
+
+    /// Mutably borrow a value stored with [`insert`](Self::insert)
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.request.extensions_mut().get_mut::<T>()
+    }
+
+    /// Take a value stored with [`insert`](Self::insert) out of the request
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.request.extensions_mut().remove::<T>()
+    }
+
     /// Get a path parameter by key
-    pub fn get_param(&self, key: &str) -> Option<&String> {
+    pub fn get_param(&self, key: &str) -> Option<&str> {
         self.params.get(key)
     }
 
+    /// Parse a path parameter, e.g. `let id: u64 = ctx.param("id")?;`
+    pub fn param<T>(&self, key: &str) -> Result<T, ParamError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match self.params.get(key) {
+            None => Err(ParamError::Missing { name: key.to_string() }),
+            Some(value) => value.parse().map_err(|e: T::Err| ParamError::Invalid {
+                name: key.to_string(),
+                value: value.to_string(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    /// Path of the route named `name` with its parameters filled in, for
+    /// redirects and links; see `Engine::url_for`
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Option<String> {
+        self.state::<crate::router::RouteUrls>()?.url_for(name, params)
+    }
+
     /// Add a parameter to the context
     pub fn add_param(&mut self, key: String, value: String) {
         self.params.insert(key, value);
@@ -51,7 +303,7 @@ impl RequestCtx {
 
     /// Check if a path parameter exists
     pub fn has_param(&self, key: &str) -> bool {
-        self.params.contains_key(key)
+        self.params.contains(key)
     }
 
     /// Get a URL query parameter by key (e.g. `?foo=bar`).
@@ -63,6 +315,23 @@ impl RequestCtx {
             .map(|(_, v)| v.into_owned())
     }
 
+    /// Deserialize the whole URL query string into `T`.
+    /// A missing query string is treated as empty, so all-optional structs still parse.
+    /// Fails with [`UrlEncodedError`] (400) when it does not deserialize.
+    pub fn query<T>(&self) -> Result<T, Box<dyn std::error::Error + Send + Sync>>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let query = self.request.uri().query().unwrap_or("");
+        serde_urlencoded::from_str(query).map_err(|e| {
+            UrlEncodedError::Invalid {
+                what: "query string",
+                reason: e.to_string(),
+            }
+            .into()
+        })
+    }
+
     /// Get a request header value by name (case-insensitive)
     pub fn header(&self, key: &str) -> Option<&str> {
         self.request
@@ -71,7 +340,77 @@ impl RequestCtx {
             .and_then(|v| v.to_str().ok())
     }
 
-    /// Get the request body as bytes (lazy loading)
+    /// Replace a request header for the middleware and handlers that run next
+    /// (normalizing values, injecting identity after authentication).
+    pub fn set_header<V>(&mut self, key: &str, value: V) -> Result<(), hyper::http::Error>
+    where
+        V: AsRef<str>,
+    {
+        let name = hyper::header::HeaderName::from_bytes(key.as_bytes())?;
+        let value = hyper::header::HeaderValue::from_str(value.as_ref())?;
+        self.request.headers_mut().insert(name, value);
+        Ok(())
+    }
+
+    /// Add a request header value, keeping values already set under the same name
+    pub fn append_header<V>(&mut self, key: &str, value: V) -> Result<(), hyper::http::Error>
+    where
+        V: AsRef<str>,
+    {
+        let name = hyper::header::HeaderName::from_bytes(key.as_bytes())?;
+        let value = hyper::header::HeaderValue::from_str(value.as_ref())?;
+        self.request.headers_mut().append(name, value);
+        Ok(())
+    }
+
+    /// Remove every value of a request header (e.g. client-supplied identity
+    /// headers before trusted middleware sets them), returning the first one.
+    pub fn remove_header(&mut self, key: &str) -> Option<hyper::header::HeaderValue> {
+        self.request.headers_mut().remove(key)
+    }
+
+    /// Whether the client asked to switch protocols (`Connection: upgrade`) or
+    /// to open a tunnel (`CONNECT`).
+    pub fn is_upgrade_request(&self) -> bool {
+        self.request.method() == hyper::Method::CONNECT
+            || self
+                .request
+                .headers()
+                .get_all(hyper::header::CONNECTION)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(','))
+                .any(|v| v.trim().eq_ignore_ascii_case("upgrade"))
+    }
+
+    /// Take over the connection for a custom protocol or a CONNECT tunnel.
+    ///
+    /// Return `101 Switching Protocols` (or a 2xx for `CONNECT`) from the
+    /// handler and await the returned future in a spawned task; it resolves
+    /// with the raw connection once hyper has sent the response. Any other
+    /// response cancels the upgrade and the future fails.
+    ///
+    /// ```rust,ignore
+    /// app.get("/raw", |mut ctx: RequestCtx| async move {
+    ///     let on_upgrade = ctx.upgrade();
+    ///     tokio::spawn(async move {
+    ///         let io = TokioIo::new(on_upgrade.await?);
+    ///         run_protocol(io).await
+    ///     });
+    ///     ResponseBuilder::switching_protocols("my-proto/1")
+    /// });
+    /// ```
+    ///
+    /// Upgraded connections no longer belong to the server: graceful shutdown
+    /// doesn't wait for them.
+    pub fn upgrade(&mut self) -> hyper::upgrade::OnUpgrade {
+        hyper::upgrade::on(&mut self.request)
+    }
+
+    /// Get the request body as bytes (lazy loading).
+    /// Fails with [`PayloadTooLarge`] once the body exceeds [`body_limit`](Self::body_limit)
+    /// and with [`BodyTimeout`] when it arrives too slowly;
+    /// use [`take_body_stream`](Self::take_body_stream) to process larger bodies in chunks.
     pub async fn body_bytes(
         &mut self,
     ) -> Result<Option<&Bytes>, Box<dyn std::error::Error + Send + Sync>> {
@@ -80,7 +419,31 @@ impl RequestCtx {
         }
 
         if let Some(body) = self.body_stream.take() {
-            let bytes = body.collect().await?.to_bytes();
+            let too_large = PayloadTooLarge {
+                limit: self.body_limit,
+            };
+            // Refuse declared oversize bodies without reading a byte
+            let declared = self
+                .header("content-length")
+                .and_then(|v| v.parse::<u64>().ok());
+            if declared.is_some_and(|len| len > self.body_limit as u64) {
+                return Err(too_large.into());
+            }
+            let collect = Limited::new(body, self.body_limit).collect();
+            let collected = match self.body_deadline {
+                Some((deadline, timeout)) => match tokio::time::timeout_at(deadline, collect).await {
+                    Ok(collected) => collected,
+                    Err(_) => return Err(BodyTimeout { timeout }.into()),
+                },
+                None => collect.await,
+            };
+            let bytes = match collected {
+                Ok(collected) => collected.to_bytes(),
+                Err(e) if e.is::<LengthLimitError>() => {
+                    return Err(too_large.into());
+                }
+                Err(e) => return Err(e),
+            };
             if !bytes.is_empty() {
                 self.body = Some(bytes);
             }
@@ -137,9 +500,272 @@ impl RequestCtx {
         }
     }
 
+    /// Parse an RFC 6902 JSON Patch (`application/json-patch+json`) body; see [`crate::patch`].
+    pub async fn json_patch(
+        &mut self,
+    ) -> Result<crate::patch::JsonPatch, Box<dyn std::error::Error + Send + Sync>> {
+        self.json().await
+    }
+
+    /// Parse an RFC 7396 merge patch (`application/merge-patch+json`) body; see [`crate::patch`].
+    pub async fn merge_patch(
+        &mut self,
+    ) -> Result<crate::patch::MergePatch, Box<dyn std::error::Error + Send + Sync>> {
+        self.json().await
+    }
+
+    /// Parse an `application/x-www-form-urlencoded` body, returning an error if body is missing.
+    /// Values are percent-decoded and `+` is read as a space. Fails with
+    /// [`UrlEncodedError`] when the request declares another content type (415)
+    /// or the body does not deserialize (400).
+    pub async fn form<T>(&mut self) -> Result<T, Box<dyn std::error::Error + Send + Sync>>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let content_type = self.header("content-type");
+        let is_form = content_type.is_some_and(|value| {
+            let essence = value.split(';').next().unwrap_or("").trim();
+            essence.eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        });
+        if !is_form {
+            return Err(UrlEncodedError::WrongContentType {
+                content_type: content_type.map(str::to_owned),
+            }
+            .into());
+        }
+        match self.body_bytes().await? {
+            Some(bytes) => serde_urlencoded::from_bytes(bytes).map_err(|e| {
+                UrlEncodedError::Invalid {
+                    what: "form body",
+                    reason: e.to_string(),
+                }
+                .into()
+            }),
+            None => Err("Request body is required".into()),
+        }
+    }
+
+    /// Whether the body has already been read into memory (by a handler or a middleware).
+    pub fn is_body_buffered(&self) -> bool {
+        self.body_stream.is_none() && self.body.is_some()
+    }
+
+    /// Take the body for forwarding (proxies, uploads) without collecting it.
+    /// Streams the original bytes when nothing has read the body yet, and falls
+    /// back to the buffered copy when an earlier middleware already did.
+    /// The stream fails with [`PayloadTooLarge`] once it passes
+    /// [`body_limit`](Self::body_limit).
+    /// Subsequent calls to take_body/body_bytes/json will return None.
+    pub fn take_body(&mut self) -> Option<BoxBody<Bytes, Box<dyn std::error::Error + Send + Sync>>> {
+        if let Some(stream) = self.body_stream.take() {
+            let limit = self.body_limit;
+            let limited = Limited::new(stream, limit).map_err(move |e| {
+                if e.is::<LengthLimitError>() { PayloadTooLarge { limit }.into() } else { e }
+            });
+            return Some(limited.boxed());
+        }
+        self.body
+            .take()
+            .map(|bytes| Full::new(bytes).map_err(|never| match never {}).boxed())
+    }
+
     /// Take the raw body stream (for large file / streaming handling).
     /// Note: This consumes the body; subsequent calls to body_bytes/json will return None.
+    /// Returns None once the body has been buffered; use [`take_body`](Self::take_body)
+    /// to accept either form.
     pub fn take_body_stream(&mut self) -> Option<hyper::body::Incoming> {
+        if self.body_stream.is_none() && self.body.is_some() {
+            log::warn!("request body was already buffered; use take_body() to forward it");
+        }
         self.body_stream.take()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(uri: &str, host: Option<&str>) -> hyper::http::request::Parts {
+        let mut builder = hyper::Request::builder().uri(uri);
+        if let Some(host) = host {
+            builder = builder.header("host", host);
+        }
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn test_absolute_form_target() {
+        let mut p = parts("http://Example.com:8080/users/7?tab=posts", Some("proxy.local"));
+        to_origin_form(&mut p);
+        assert_eq!(p.uri, "/users/7?tab=posts");
+        assert_eq!(p.uri.path(), "/users/7");
+        assert_eq!(p.headers["host"], "Example.com:8080");
+
+        let mut p = parts("http://example.com", None);
+        to_origin_form(&mut p);
+        assert_eq!(p.uri, "/");
+        assert_eq!(p.headers["host"], "example.com");
+
+        let mut p = hyper::Request::builder()
+            .method("CONNECT")
+            .uri("example.com:443")
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0;
+        to_origin_form(&mut p);
+        assert_eq!(p.uri, "example.com:443");
+
+        let mut p = parts("/plain?x=1", Some("example.com"));
+        to_origin_form(&mut p);
+        assert_eq!(p.uri, "/plain?x=1");
+        assert_eq!(p.headers["host"], "example.com");
+    }
+
+    #[test]
+    fn test_typed_query() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Paging {
+            page: Option<u32>,
+            tag: Option<String>,
+        }
+
+        let ctx = RequestCtx::from_parts(parts("/items?page=2&tag=rust%20lang+web", None));
+        let paging = Paging { page: Some(2), tag: Some("rust lang web".into()) };
+        assert_eq!(ctx.query::<Paging>().unwrap(), paging);
+        assert_eq!(ctx.query_param("tag").as_deref(), Some("rust lang web"));
+        // A missing query string is an empty one
+        let ctx = RequestCtx::from_parts(parts("/items", None));
+        assert_eq!(ctx.query::<Paging>().unwrap(), Paging { page: None, tag: None });
+
+        let ctx = RequestCtx::from_parts(parts("/items?page=two", None));
+        let err = ctx.query::<Paging>().unwrap_err();
+        assert!(err.is::<UrlEncodedError>(), "{err}");
+        let response = Err::<&str, _>(err).into_response();
+        assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_take_body_once_within_the_limit() {
+        let mut app = crate::Engine::new();
+        app.post("/forward", |mut ctx: RequestCtx| async move {
+            let body = ctx.take_body().unwrap();
+            assert!(ctx.take_body().is_none());
+            assert!(ctx.body_bytes().await.unwrap().is_none());
+            let bytes = body.collect().await?.to_bytes();
+            Ok::<_, crate::Error>(String::from_utf8_lossy(&bytes).into_owned())
+        })
+        .body_limit(8);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/forward", listener.local_addr().unwrap());
+        let server = tokio::spawn(app.run_with_listener(listener));
+
+        let client = crate::client::Client::new();
+        let response = client.post(&url).body("8 bytes!").send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "8 bytes!");
+        let response = client.post(&url).body("nine byte").send().await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::PAYLOAD_TOO_LARGE);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_form_bodies() {
+        #[derive(serde::Deserialize)]
+        struct Login {
+            username: String,
+            password: String,
+        }
+
+        let mut app = crate::Engine::new();
+        app.post("/login", |mut ctx: RequestCtx| async move {
+            let form: Login = ctx.form().await?;
+            Ok::<_, crate::Error>(format!("{}:{}", form.username, form.password))
+        })
+        .body_limit(64);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/login", listener.local_addr().unwrap());
+        let server = tokio::spawn(app.run_with_listener(listener));
+        let client = crate::client::Client::new();
+        let post = |content_type: &'static str, body: String| {
+            client.post(&url).header("content-type", content_type).body(body).send()
+        };
+        const FORM: &str = "application/x-www-form-urlencoded";
+
+        // The media type is case-insensitive and may carry parameters
+        let mixed_case = "Application/X-WWW-Form-Urlencoded; charset=UTF-8";
+        let response = post(mixed_case, "username=ada+l&password=p%26ss".into()).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "ada l:p&ss");
+        let response = post("application/json", r#"{"username":"ada","password":"x"}"#.into()).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let response = post(FORM, "username=ada".into()).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::BAD_REQUEST);
+        assert!(response.text().await.unwrap().contains("invalid form body"));
+        let response = post(FORM, format!("username=ada&password={}", "x".repeat(64))).await.unwrap();
+        assert_eq!(response.status(), hyper::StatusCode::PAYLOAD_TOO_LARGE);
+        server.abort();
+    }
+
+    #[test]
+    fn test_header_mutation() {
+        let mut p = parts("/", None);
+        p.headers.append("x-user", "mallory".parse().unwrap());
+        p.headers.append("x-user", "eve".parse().unwrap());
+        let mut ctx = RequestCtx::from_parts(p);
+
+        // Removing drops every value and returns the first
+        assert_eq!(ctx.remove_header("X-User").unwrap(), "mallory");
+        assert_eq!(ctx.header("x-user"), None);
+        assert_eq!(ctx.remove_header("x-user"), None);
+
+        ctx.set_header("x-user", "alice").unwrap();
+        ctx.set_header("X-User", "bob").unwrap();
+        assert_eq!(ctx.request.headers().get_all("x-user").iter().collect::<Vec<_>>(), ["bob"]);
+        ctx.append_header("x-role", "reader").unwrap();
+        ctx.append_header("x-role", "writer").unwrap();
+        assert_eq!(ctx.request.headers().get_all("x-role").iter().collect::<Vec<_>>(), ["reader", "writer"]);
+
+        // Invalid names and values are refused and leave the headers alone
+        assert!(ctx.set_header("bad name", "x").is_err());
+        assert!(ctx.append_header("x-user", "line\nbreak").is_err());
+        assert_eq!(ctx.header("x-user"), Some("bob"));
+    }
+
+    #[test]
+    fn test_typed_extensions() {
+        #[derive(Clone, Debug, PartialEq)]
+        struct AuthUser {
+            id: u64,
+        }
+
+        let mut ctx = RequestCtx::from_parts(parts("/", None));
+        assert_eq!(ctx.get::<AuthUser>(), None);
+        assert_eq!(ctx.insert(AuthUser { id: 1 }), None);
+        assert_eq!(ctx.insert(AuthUser { id: 7 }), Some(AuthUser { id: 1 }));
+        ctx.insert(3u32);
+        *ctx.get_mut::<u32>().unwrap() += 1;
+        assert_eq!(ctx.get::<AuthUser>().map(|u| u.id), Some(7));
+        assert_eq!(ctx.remove::<u32>(), Some(4));
+        assert_eq!(ctx.get::<u32>(), None);
+        // Values travel with the request, e.g. through tower layers
+        assert_eq!(ctx.request.extensions().get::<AuthUser>(), Some(&AuthUser { id: 7 }));
+    }
+
+    #[test]
+    fn test_client_ip_from_trusted_proxy() {
+        let forwarded = || {
+            let mut p = parts("/", None);
+            p.headers.insert("x-forwarded-for", "203.0.113.9".parse().unwrap());
+            RequestCtx::from_parts(p).with_remote_addr("10.0.0.1:4000".parse().unwrap())
+        };
+        assert_eq!(RequestCtx::from_parts(parts("/", None)).client_ip(), None);
+
+        let ctx = forwarded();
+        assert_eq!(ctx.remote_addr(), Some("10.0.0.1:4000".parse().unwrap()));
+        assert_eq!(ctx.client_ip(), Some("10.0.0.1".parse().unwrap()));
+
+        let mut ctx = forwarded();
+        ctx.resolve_client_ip(&crate::ipfilter::parse_ranges(["10.0.0.0/8"]));
+        assert_eq!(ctx.client_ip(), Some("203.0.113.9".parse().unwrap()));
+        assert_eq!(ctx.remote_addr(), Some("10.0.0.1:4000".parse().unwrap()));
+    }
+}
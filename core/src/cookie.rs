@@ -0,0 +1,426 @@
+//! Reading request cookies and setting response cookies.
+//!
+//! ```rust,ignore
+//! use s_web::cookie::{Cookie, SameSite};
+//!
+//! app.get("/", |ctx: RequestCtx| async move {
+//!     let theme = ctx.cookies().get("theme").unwrap_or("light").to_string();
+//!     ResponseBuilder::new()
+//!         .cookie(Cookie::new("seen", "1").http_only(true).same_site(SameSite::Lax))
+//!         .try_body(theme)
+//! });
+//! ```
+//!
+//! With the `cookie-jar` feature, a [`Key`] derived from a secret signs or
+//! encrypts values so clients can't forge or read them:
+//!
+//! ```rust,ignore
+//! let key = Key::from_secret(std::env::var("COOKIE_SECRET")?.as_bytes())?;
+//! let user = ctx.cookies().signed(&key).get("user");
+//! ResponseBuilder::new().cookie(key.sign(Cookie::new("user", "42")))
+//! ```
+
+use std::{fmt, time::Duration};
+
+use crate::{RequestCtx, ResponseBuilder};
+
+/// `SameSite` cookie attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Browsers only accept this together with `Secure`.
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        })
+    }
+}
+
+/// A cookie to send with `Set-Cookie`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    http_only: bool,
+    secure: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// Session cookie scoped to `/`.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            path: Some("/".to_string()),
+            domain: None,
+            max_age: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        }
+    }
+
+    /// Cookie that makes the browser delete `name` (empty value, `Max-Age=0`).
+    /// Path and domain must match the ones the cookie was set with.
+    pub fn remove(name: impl Into<String>) -> Self {
+        Self::new(name, "").max_age(Duration::ZERO)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Lifetime in whole seconds; without it the cookie ends with the browser session.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Hide the cookie from JavaScript.
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Only send the cookie over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+/// Renders the `Set-Cookie` header value.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={same_site}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Cookies sent with a request, in header order.
+#[derive(Debug, Clone, Default)]
+pub struct Cookies {
+    pairs: Vec<(String, String)>,
+}
+
+impl Cookies {
+    /// Parse one or more `Cookie` header values. Malformed pairs are skipped.
+    pub fn parse<'a>(headers: impl IntoIterator<Item = &'a str>) -> Self {
+        let pairs = headers
+            .into_iter()
+            .flat_map(|header| header.split(';'))
+            .filter_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                let name = name.trim();
+                if name.is_empty() {
+                    return None;
+                }
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect();
+        Self { pairs }
+    }
+
+    /// Value of the first cookie called `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+impl RequestCtx {
+    /// Parse the request's `Cookie` headers.
+    pub fn cookies(&self) -> Cookies {
+        Cookies::parse(
+            self.request
+                .headers()
+                .get_all(hyper::header::COOKIE)
+                .iter()
+                .filter_map(|v| v.to_str().ok()),
+        )
+    }
+}
+
+impl ResponseBuilder {
+    /// Append a `Set-Cookie` header; call repeatedly to set several cookies.
+    pub fn cookie(self, cookie: Cookie) -> Self {
+        self.with_header("Set-Cookie", cookie.to_string())
+    }
+}
+
+#[cfg(feature = "cookie-jar")]
+pub use jar::{Key, PrivateJar, SignedJar};
+
+#[cfg(feature = "cookie-jar")]
+mod jar {
+    use aes_gcm::{
+        Aes256Gcm, KeyInit, Nonce,
+        aead::{Aead, AeadCore, OsRng, Payload},
+    };
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    use super::{Cookie, Cookies};
+    use crate::BoxError;
+
+    type HmacSha256 = Hmac<Sha256>;
+
+    const MIN_SECRET_LEN: usize = 32;
+    const MAC_LEN: usize = 32;
+    const NONCE_LEN: usize = 12;
+
+    /// Signing and encryption keys derived from one application secret.
+    #[derive(Clone)]
+    pub struct Key {
+        signing: [u8; 32],
+        encryption: [u8; 32],
+    }
+
+    impl Key {
+        /// Derive both keys from `secret`, which must be at least 32 bytes.
+        /// Rotating the secret invalidates every signed and encrypted cookie.
+        pub fn from_secret(secret: &[u8]) -> Result<Self, BoxError> {
+            if secret.len() < MIN_SECRET_LEN {
+                return Err(format!("cookie secret must be at least {MIN_SECRET_LEN} bytes").into());
+            }
+            Ok(Self {
+                signing: derive(secret, b"s_web cookie signing"),
+                encryption: derive(secret, b"s_web cookie encryption"),
+            })
+        }
+
+        /// Append an HMAC-SHA256 tag to the value. Readable by clients, but
+        /// tampering is detected by [`SignedJar::get`].
+        pub fn sign(&self, mut cookie: Cookie) -> Cookie {
+            let tag = self.tag(&cookie.name, &cookie.value);
+            cookie.value = format!("{}.{}", URL_SAFE_NO_PAD.encode(tag), cookie.value);
+            cookie
+        }
+
+        /// Encrypt the value with AES-256-GCM so clients can neither read nor alter it.
+        pub fn encrypt(&self, mut cookie: Cookie) -> Cookie {
+            let cipher = self.cipher();
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let payload = Payload {
+                msg: cookie.value.as_bytes(),
+                aad: cookie.name.as_bytes(),
+            };
+            let Ok(sealed) = cipher.encrypt(&nonce, payload) else {
+                unreachable!("AES-GCM encryption only fails for oversized input");
+            };
+            let mut data = nonce.to_vec();
+            data.extend_from_slice(&sealed);
+            cookie.value = URL_SAFE_NO_PAD.encode(data);
+            cookie
+        }
+
+        fn tag(&self, name: &str, value: &str) -> [u8; MAC_LEN] {
+            let mut mac = self.mac();
+            mac.update(name.as_bytes());
+            mac.update(b"=");
+            mac.update(value.as_bytes());
+            mac.finalize().into_bytes().into()
+        }
+
+        fn mac(&self) -> HmacSha256 {
+            let Ok(mac) = <HmacSha256 as Mac>::new_from_slice(&self.signing) else {
+                unreachable!("HMAC accepts keys of any length");
+            };
+            mac
+        }
+
+        fn cipher(&self) -> Aes256Gcm {
+            let Ok(cipher) = Aes256Gcm::new_from_slice(&self.encryption) else {
+                unreachable!("encryption key is 32 bytes");
+            };
+            cipher
+        }
+
+        fn verify(&self, name: &str, value: &str) -> Option<String> {
+            let (tag, value) = value.split_once('.')?;
+            let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+            let mut mac = self.mac();
+            mac.update(name.as_bytes());
+            mac.update(b"=");
+            mac.update(value.as_bytes());
+            mac.verify_slice(&tag).ok()?;
+            Some(value.to_string())
+        }
+
+        pub(crate) fn decrypt(&self, name: &str, value: &str) -> Option<String> {
+            let data = URL_SAFE_NO_PAD.decode(value).ok()?;
+            if data.len() < NONCE_LEN {
+                return None;
+            }
+            let (nonce, sealed) = data.split_at(NONCE_LEN);
+            let payload = Payload {
+                msg: sealed,
+                aad: name.as_bytes(),
+            };
+            let plain = self
+                .cipher()
+                .decrypt(Nonce::from_slice(nonce), payload)
+                .ok()?;
+            String::from_utf8(plain).ok()
+        }
+    }
+
+    fn derive(secret: &[u8], label: &[u8]) -> [u8; 32] {
+        let Ok(mut mac) = <HmacSha256 as Mac>::new_from_slice(secret) else {
+            unreachable!("HMAC accepts keys of any length");
+        };
+        mac.update(label);
+        mac.finalize().into_bytes().into()
+    }
+
+    /// Request cookies verified with [`Key::sign`].
+    pub struct SignedJar<'a> {
+        cookies: &'a Cookies,
+        key: &'a Key,
+    }
+
+    impl SignedJar<'_> {
+        /// Original value of `name`, or `None` if it is missing or was tampered with.
+        pub fn get(&self, name: &str) -> Option<String> {
+            self.key.verify(name, self.cookies.get(name)?)
+        }
+    }
+
+    /// Request cookies encrypted with [`Key::encrypt`].
+    pub struct PrivateJar<'a> {
+        cookies: &'a Cookies,
+        key: &'a Key,
+    }
+
+    impl PrivateJar<'_> {
+        /// Decrypted value of `name`, or `None` if it is missing or can't be decrypted.
+        pub fn get(&self, name: &str) -> Option<String> {
+            self.key.decrypt(name, self.cookies.get(name)?)
+        }
+    }
+
+    impl Cookies {
+        pub fn signed<'a>(&'a self, key: &'a Key) -> SignedJar<'a> {
+            SignedJar { cookies: self, key }
+        }
+
+        pub fn private<'a>(&'a self, key: &'a Key) -> PrivateJar<'a> {
+            PrivateJar { cookies: self, key }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_render() {
+        let cookies = Cookies::parse(["a=1; b=\"two\"", "junk; c = 3"]);
+        assert_eq!(cookies.get("a"), Some("1"));
+        assert_eq!(cookies.get("b"), Some("two"));
+        assert_eq!(cookies.get("c"), Some("3"));
+        assert_eq!(cookies.len(), 3);
+
+        let cookie = Cookie::new("sid", "abc")
+            .max_age(Duration::from_secs(60))
+            .http_only(true)
+            .secure(true)
+            .same_site(SameSite::Strict);
+        assert_eq!(
+            cookie.to_string(),
+            "sid=abc; Path=/; Max-Age=60; HttpOnly; Secure; SameSite=Strict"
+        );
+        assert_eq!(Cookie::remove("sid").to_string(), "sid=; Path=/; Max-Age=0");
+    }
+
+    #[cfg(feature = "cookie-jar")]
+    #[test]
+    fn test_signed_and_private_jars() {
+        assert!(Key::from_secret(b"short").is_err());
+        let key = Key::from_secret(&[7; 32]).unwrap();
+
+        let signed = key.sign(Cookie::new("user", "42"));
+        let encrypted = key.encrypt(Cookie::new("token", "secret"));
+        assert!(!encrypted.value().contains("secret"));
+
+        let header = format!("user={}; token={}", signed.value(), encrypted.value());
+        let cookies = Cookies::parse([header.as_str()]);
+        assert_eq!(cookies.signed(&key).get("user").as_deref(), Some("42"));
+        assert_eq!(cookies.private(&key).get("token").as_deref(), Some("secret"));
+
+        // Swapping names or editing values fails verification
+        let forged = format!("user={}", signed.value().replace(".42", ".43"));
+        assert_eq!(Cookies::parse([forged.as_str()]).signed(&key).get("user"), None);
+        let moved = format!("other={}", encrypted.value());
+        assert_eq!(Cookies::parse([moved.as_str()]).private(&key).get("other"), None);
+    }
+}
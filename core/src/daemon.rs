@@ -0,0 +1,284 @@
+//! Running outside containers: pidfiles, working directory, detaching from
+//! the terminal and service manager notifications (`daemon` feature).
+//!
+//! Detaching forks the process, so [`Daemon::start`] must run before the
+//! tokio runtime (and any other thread) is started:
+//!
+//! ```rust,ignore
+//! fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let _daemon = Daemon::new()
+//!         .detach(true)
+//!         .working_dir("/srv/app")
+//!         .pid_file("/run/app.pid")
+//!         .log_file("/var/log/app.log")
+//!         .start()?;
+//!     tokio::runtime::Runtime::new()?.block_on(async {
+//!         let app = build_app();
+//!         Ok(app.run("0.0.0.0:8080").await?)
+//!     })
+//! }
+//! ```
+//!
+//! Under systemd with `Type=notify`, the server reports `READY=1` once it is
+//! serving and `STOPPING=1` when shutdown begins. SIGTERM, and console close
+//! or system shutdown events on Windows, trigger the same graceful shutdown
+//! as Ctrl-C. Status reporting to the Windows service control manager is not
+//! provided; run the binary under a service host such as WinSW or NSSM.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::log;
+
+/// Process setup for running as a daemon or service.
+#[derive(Debug, Clone, Default)]
+pub struct Daemon {
+    detach: bool,
+    working_dir: Option<PathBuf>,
+    pid_file: Option<PathBuf>,
+    log_file: Option<PathBuf>,
+}
+
+impl Daemon {
+    /// Stay in the foreground, keep the working directory, write no pidfile.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fork into the background and start a new session, detached from the
+    /// terminal (unix only). Not needed under systemd, launchd or supervisord.
+    #[cfg(unix)]
+    pub fn detach(mut self, detach: bool) -> Self {
+        self.detach = detach;
+        self
+    }
+
+    /// Change into `dir`, so relative paths (static files, SQLite databases)
+    /// don't depend on where the process was started from.
+    pub fn working_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.working_dir = Some(dir.into());
+        self
+    }
+
+    /// Write the process id to `path`, refusing to start while the process
+    /// named in an existing pidfile is still alive. The file is removed when
+    /// the returned guard is dropped.
+    pub fn pid_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pid_file = Some(path.into());
+        self
+    }
+
+    /// Append stdout and stderr to `path` (default after detaching: `/dev/null`).
+    pub fn log_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.log_file = Some(path.into());
+        self
+    }
+
+    /// Apply the setup. Call from a synchronous `main`, before starting the runtime.
+    pub fn start(self) -> io::Result<DaemonGuard> {
+        // Resolve relative paths before the working directory changes
+        let absolute = |path: PathBuf| std::path::absolute(&path).unwrap_or(path);
+        let pid_file = self.pid_file.map(absolute);
+        let log_file = self.log_file.map(absolute);
+
+        if let Some(path) = &pid_file {
+            check_pid_file(path)?;
+        }
+        #[cfg(unix)]
+        if self.detach {
+            unix::detach()?;
+        }
+        if let Some(path) = &log_file {
+            redirect_output(Some(path))?;
+        } else if self.detach {
+            redirect_output(None)?;
+        }
+        if let Some(dir) = &self.working_dir {
+            std::env::set_current_dir(dir)
+                .map_err(|e| io::Error::new(e.kind(), format!("changing into {}: {e}", dir.display())))?;
+        }
+        if let Some(path) = &pid_file {
+            fs::write(path, format!("{}\n", std::process::id()))
+                .map_err(|e| io::Error::new(e.kind(), format!("writing {}: {e}", path.display())))?;
+        }
+        Ok(DaemonGuard { pid_file })
+    }
+}
+
+/// Keeps the pidfile in place; removes it when dropped at the end of `main`.
+#[derive(Debug)]
+pub struct DaemonGuard {
+    pid_file: Option<PathBuf>,
+}
+
+impl Drop for DaemonGuard {
+    fn drop(&mut self) {
+        if let Some(path) = &self.pid_file {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Fail if `path` names a process that is still running; stale files are ignored
+fn check_pid_file(path: &Path) -> io::Result<()> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return Ok(());
+    };
+    match contents.trim().parse::<u32>() {
+        Ok(pid) if pid != std::process::id() && process_alive(pid) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("already running with pid {pid} (pidfile {})", path.display()),
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks that the process exists; EPERM means it belongs to another user
+    unsafe { libc::kill(pid, 0) == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM) }
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    // Without a portable liveness check an existing pidfile is treated as stale
+    false
+}
+
+/// Point stdout and stderr at `path` (appending), or at the null device
+fn redirect_output(path: Option<&Path>) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let file = match path {
+            Some(path) => fs::OpenOptions::new().create(true).append(true).open(path),
+            None => fs::OpenOptions::new().write(true).open("/dev/null"),
+        }?;
+        unix::redirect_output(&file)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        log::warn!("daemon: log_file is only supported on unix; output is not redirected");
+        Ok(())
+    }
+}
+
+/// Send a state line such as `READY=1` or `STATUS=warming caches` to the
+/// service manager named by `NOTIFY_SOCKET` (systemd `Type=notify`).
+/// Returns `Ok(false)` when not running under one.
+#[cfg(unix)]
+pub fn notify(state: &str) -> io::Result<bool> {
+    match std::env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => unix::notify_to(&socket, state).map(|()| true),
+        None => Ok(false),
+    }
+}
+
+/// Lifecycle notifications sent by `Engine::run`; failures are only logged
+pub(crate) fn notify_lifecycle(state: &str) {
+    #[cfg(unix)]
+    if let Err(e) = notify(state) {
+        log::warn!("service manager notification {state:?} failed: {e}");
+    }
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        ffi::OsStr,
+        io,
+        os::{fd::AsRawFd, unix::net::UnixDatagram},
+    };
+
+    fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result)
+        }
+    }
+
+    /// Classic double fork: the first child leads a new session, the grandchild
+    /// can never reacquire a controlling terminal. Parents exit immediately.
+    pub(super) fn detach() -> io::Result<()> {
+        unsafe {
+            if check(libc::fork())? > 0 {
+                libc::_exit(0);
+            }
+            check(libc::setsid())?;
+            if check(libc::fork())? > 0 {
+                libc::_exit(0);
+            }
+            libc::umask(0o027);
+        }
+        Ok(())
+    }
+
+    pub(super) fn redirect_output(file: &std::fs::File) -> io::Result<()> {
+        unsafe {
+            check(libc::dup2(file.as_raw_fd(), libc::STDOUT_FILENO))?;
+            check(libc::dup2(file.as_raw_fd(), libc::STDERR_FILENO))?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn notify_to(socket: &OsStr, state: &str) -> io::Result<()> {
+        let datagram = UnixDatagram::unbound()?;
+        let bytes = socket.as_encoded_bytes();
+        // `@name` addresses a socket in the Linux abstract namespace
+        #[cfg(target_os = "linux")]
+        if let Some(name) = bytes.strip_prefix(b"@") {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(state.as_bytes(), &addr)?;
+            return Ok(());
+        }
+        let _ = bytes;
+        datagram.send_to(state.as_bytes(), socket)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file_refuses_live_process() {
+        let dir = std::env::temp_dir().join(format!("s_web-daemon-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.pid");
+
+        // pid 1 is always alive; a huge pid never is
+        fs::write(&path, "1\n").unwrap();
+        assert_eq!(check_pid_file(&path).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        fs::write(&path, "999999999\n").unwrap();
+        assert!(check_pid_file(&path).is_ok());
+
+        let guard = Daemon::new().pid_file(&path).start().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", std::process::id()));
+        drop(guard);
+        assert!(!path.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_notify_socket() {
+        let path = std::env::temp_dir().join(format!("s_web-notify-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        unix::notify_to(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0u8; 32];
+        let n = listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,215 @@
+//! Idempotent webhook receivers: answer redeliveries without running the handler again.
+//!
+//! Providers redeliver webhooks whenever they are unsure the first attempt
+//! arrived. [`Dedup`] remembers every delivery for a window and answers a
+//! repeat with `200 OK` straight away. By default a delivery is identified
+//! by a hash of its method, path and body; providers that send a delivery
+//! id header are better keyed on that:
+//!
+//! ```rust,ignore
+//! use s_web::dedup::{Dedup, MemoryDedupStore};
+//! use std::time::Duration;
+//!
+//! app.post("/webhooks/github", on_push).with_middleware(
+//!     Dedup::new(MemoryDedupStore::new())
+//!         .window(Duration::from_secs(24 * 3600))
+//!         .key(|ctx| ctx.header("x-github-delivery").map(str::to_string))
+//!         .middleware(),
+//! );
+//! ```
+//!
+//! A delivery whose handler did not answer 2xx is forgotten again, so the
+//! provider's retry is processed. A duplicate arriving while the first copy
+//! is still being handled is answered `200` as well.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::{DefaultHasher, Hash, Hasher},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Next, RequestCtx, Response, ResponseBuilder,
+    store::{self, KvStore},
+};
+
+/// Backend remembering which deliveries were seen.
+pub trait DedupStore: Send + Sync + 'static {
+    /// Atomically record `key` for `window`; `false` if it was already
+    /// recorded and has not expired.
+    fn first_seen(&self, key: &str, window: Duration) -> Pin<Box<dyn Future<Output = bool> + Send + '_>>;
+
+    /// Forget `key`, so the next delivery with it is processed.
+    fn forget(&self, key: &str) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+}
+
+/// In-process dedup store.
+#[derive(Default)]
+pub struct MemoryDedupStore {
+    seen: Mutex<HashMap<String, Instant>>,
+}
+
+impl MemoryDedupStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl DedupStore for MemoryDedupStore {
+    fn first_seen(&self, key: &str, window: Duration) -> Pin<Box<dyn Future<Output = bool> + Send + '_>> {
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        // Drop expired entries so the map only holds the current window
+        seen.retain(|_, expires| *expires > now);
+        let first = !seen.contains_key(key);
+        if first {
+            seen.insert(key.to_string(), now + window);
+        }
+        Box::pin(async move { first })
+    }
+
+    fn forget(&self, key: &str) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        self.seen.lock().unwrap_or_else(|e| e.into_inner()).remove(key);
+        Box::pin(async {})
+    }
+}
+
+/// Deliveries under `dedup:` keys of a shared [`KvStore`]; if the store is
+/// unreachable every delivery is processed.
+impl DedupStore for Arc<dyn KvStore> {
+    fn first_seen(&self, key: &str, window: Duration) -> Pin<Box<dyn Future<Output = bool> + Send + '_>> {
+        let key = format!("dedup:{key}");
+        Box::pin(async move { store::or_log(self.cas(&key, None, b"1".to_vec(), Some(window)).await, true) })
+    }
+
+    fn forget(&self, key: &str) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        let key = format!("dedup:{key}");
+        Box::pin(async move {
+            store::or_log(self.delete(&key).await, false);
+        })
+    }
+}
+
+type KeyFn = Arc<dyn Fn(&RequestCtx) -> Option<String> + Send + Sync>;
+
+/// Dedup middleware configuration.
+#[derive(Clone)]
+pub struct Dedup {
+    store: Arc<dyn DedupStore>,
+    key: Option<KeyFn>,
+    window: Duration,
+}
+
+impl Dedup {
+    /// Deduplicate by content hash over a one hour window.
+    pub fn new(store: impl DedupStore) -> Self {
+        Self {
+            store: Arc::new(store),
+            key: None,
+            window: Duration::from_secs(3600),
+        }
+    }
+
+    /// How long a delivery is remembered.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Identify deliveries by e.g. a delivery id header instead of the body
+    /// hash. Requests without a key are not deduplicated.
+    pub fn key<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&RequestCtx) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Some(Arc::new(f));
+        self
+    }
+
+    /// Build the middleware for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let dedup = Arc::new(self);
+        move |ctx, next| {
+            let dedup = dedup.clone();
+            Box::pin(async move { dedup.handle(ctx, next).await })
+        }
+    }
+
+    async fn handle(&self, mut ctx: RequestCtx, next: Next) -> Response {
+        let key = match &self.key {
+            Some(key) => key(&ctx),
+            None => content_key(&mut ctx).await,
+        };
+        let Some(key) = key else {
+            return next(ctx).await;
+        };
+        if !self.store.first_seen(&key, self.window).await {
+            return ResponseBuilder::new()
+                .content_type("text/plain; charset=utf-8")
+                .with_header("X-Duplicate-Delivery", "true")
+                .build("duplicate delivery ignored");
+        }
+        let response = next(ctx).await;
+        if !response.status().is_success() {
+            self.store.forget(&key).await;
+        }
+        response
+    }
+}
+
+/// Hash of method, path and body; `None` when the body can't be read (too
+/// large), leaving the error to the handler.
+async fn content_key(ctx: &mut RequestCtx) -> Option<String> {
+    let method = ctx.request.method().clone();
+    let path = ctx.request.uri().path().to_string();
+    let body = ctx.body_bytes().await.ok()?.cloned().unwrap_or_default();
+    Some(content_hash(method.as_str(), &path, &body))
+}
+
+/// 128-bit hex digest from two differently seeded SipHash runs; enough to
+/// tell deliveries apart, but not collision-resistant against crafted input
+fn content_hash(method: &str, path: &str, body: &[u8]) -> String {
+    let digest = |seed: u8| {
+        let mut hasher = DefaultHasher::new();
+        (seed, method, path, body).hash(&mut hasher);
+        hasher.finish()
+    };
+    format!("{:016x}{:016x}", digest(0), digest(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_store_window_and_forget() {
+        let store = MemoryDedupStore::new();
+        let window = Duration::from_secs(60);
+        assert!(store.first_seen("a", window).await);
+        assert!(!store.first_seen("a", window).await);
+        assert!(store.first_seen("b", window).await);
+
+        store.forget("a").await;
+        assert!(store.first_seen("a", window).await);
+
+        assert!(store.first_seen("short", Duration::ZERO).await);
+        assert!(store.first_seen("short", Duration::ZERO).await);
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let hash = content_hash("POST", "/hook", b"{\"id\":1}");
+        assert_eq!(hash.len(), 32);
+        assert_eq!(hash, content_hash("POST", "/hook", b"{\"id\":1}"));
+        assert_ne!(hash, content_hash("POST", "/hook", b"{\"id\":2}"));
+        assert_ne!(hash, content_hash("POST", "/other", b"{\"id\":1}"));
+    }
+}
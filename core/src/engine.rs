@@ -1,28 +1,80 @@
 //! Main HTTP engine and router group implementations.
 
 use std::{
-    collections::HashMap, convert::Infallible, future::Future, net::SocketAddr, pin::Pin, sync::Arc,
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, atomic::AtomicBool},
+    time::Duration,
 };
 
-use hyper::{server::conn::http1, service::service_fn};
-use hyper_util::{rt::TokioIo, server::graceful::GracefulShutdown};
+use tokio::{net::TcpListener, sync::watch};
 
 use crate::{
-    Handler, Middleware, Next, RequestCtx, Response, Router, execute_chain, middleware::IntoNext,
+    EngineService, Handler, Middleware, Next, RequestCtx, Response, RouteInfo, Router, compose,
+    health::HealthRegistry,
+    ipfilter::Cidr,
+    RunError,
+    error::ErrorHandler,
+    lifecycle::{HookOutput, LifecycleCtx, ShutdownToken, run_hook},
+    log,
+    mail::{Mailer, MailerRef},
+    ratelimit::{Limiter, Rate},
+    route::{RouteBuilder, RouteTarget},
+    router::{ANY_METHOD, RouteUrls, RoutesHandle, WildcardLimit},
+    run_error::HookFailure,
+    server::{Acceptor, PreprocessedGroup, ServerConfig, ServerContext, accept_loop},
+    shutdown::Subsystem,
+    state::StateMap,
     swagger::SwaggerInfo,
+    watermark::Watermark,
 };
 
 /// Type alias for lifecycle hooks
-type LifecycleHook = Box<dyn Fn() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+type LifecycleHook =
+    Box<dyn Fn(LifecycleCtx) -> Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send>> + Send + Sync>;
 
-/// Pre-processed server data ready for the accept loop
-type PreprocessedGroup = (String, Arc<RouterGroup>, Arc<Vec<Middleware>>);
+/// Error type returned by preflight checks
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
-struct ServerContext {
-    router: Arc<Router>,
-    groups: Arc<Vec<PreprocessedGroup>>,
-    global_middlewares: Arc<Vec<Middleware>>,
-    has_global_middleware: bool,
+/// Type alias for named preflight checks
+type PreflightCheck = (
+    String,
+    Box<dyn Fn() -> Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send>> + Send + Sync>,
+);
+
+/// A preflight check failed, so the server refused to start
+#[derive(Debug)]
+pub struct PreflightError {
+    pub check: String,
+    pub source: BoxError,
+}
+
+impl std::fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "preflight check `{}` failed: {}", self.check, self.source)
+    }
+}
+
+impl std::error::Error for PreflightError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+/// When the listener opens relative to preflight checks and startup hooks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StartupMode {
+    /// Bind only once preflight checks and startup hooks have passed, so a
+    /// port that accepts connections means the app is ready. Suits TCP
+    /// readiness probes and load balancers that health-check the port.
+    #[default]
+    BindWhenReady,
+    /// Bind immediately and answer every request with `503` and `Retry-After`
+    /// until startup completes. Suits platforms that require the port to open
+    /// quickly and probe an HTTP endpoint for readiness.
+    ServeUnavailable,
 }
 
 /// A group of routes with shared prefix and middleware
@@ -30,51 +82,146 @@ pub struct RouterGroup {
     prefix: String,
     router: Router,
     middlewares: Vec<Middleware>,
+    health: HealthRegistry,
+    hidden: bool,
+    swagger_info: HashMap<String, SwaggerInfo>,
+    swagger_defaults: Option<SwaggerInfo>,
+}
+
+impl RouteTarget for RouterGroup {
+    fn router_mut(&mut self) -> &mut Router {
+        &mut self.router
+    }
+
+    fn health_registry(&self) -> HealthRegistry {
+        self.health.clone()
+    }
 }
 
 impl RouterGroup {
-    fn new(prefix: String) -> Self {
+    pub(crate) fn new(prefix: String, health: HealthRegistry) -> Self {
         Self {
             prefix,
             router: Router::new(),
             middlewares: Vec::new(),
+            health,
+            hidden: false,
+            swagger_info: HashMap::new(),
+            swagger_defaults: None,
         }
     }
 
+    /// Keep every route of this group, including ones added later, out of the
+    /// OpenAPI document (e.g. `/debug` or `/admin` groups)
+    pub fn hide_from_docs(&mut self) -> &mut Self {
+        self.hidden = true;
+        self
+    }
+
     /// Add a route to this group
-    pub fn add_route(&mut self, method: &str, pattern: &str, handler: impl Handler) {
-        let handler = Box::new(handler);
+    pub fn add_route(&mut self, method: &str, pattern: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
         let full_pattern = format!("{}{}", self.prefix, pattern);
-        self.router.add_route(method, &full_pattern, handler);
+        self.router.add_handler(method, &full_pattern, handler);
+        RouteBuilder::new(self, method, full_pattern)
     }
 
     /// Add a GET route to this group
-    pub fn get(&mut self, path: &str, handler: impl Handler) -> &mut Self {
-        self.add_route("GET", path, handler);
-        self
+    pub fn get(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("GET", path, handler)
+    }
+
+    /// Add a GET route with swagger info to this group
+    pub fn get_with_swagger(&mut self, path: &str, handler: impl Handler, swagger_info: SwaggerInfo) -> RouteBuilder<'_, Self> {
+        self.swagger_for_route("GET", path, swagger_info);
+        self.add_route("GET", path, handler)
     }
 
     /// Add a POST route to this group
-    pub fn post(&mut self, path: &str, handler: impl Handler) -> &mut Self {
-        self.add_route("POST", path, handler);
-        self
+    pub fn post(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("POST", path, handler)
+    }
+
+    /// Add a POST route with swagger info to this group
+    pub fn post_with_swagger(&mut self, path: &str, handler: impl Handler, swagger_info: SwaggerInfo) -> RouteBuilder<'_, Self> {
+        self.swagger_for_route("POST", path, swagger_info);
+        self.add_route("POST", path, handler)
     }
 
     /// Add a PUT route to this group
-    pub fn put(&mut self, path: &str, handler: impl Handler) -> &mut Self {
-        self.add_route("PUT", path, handler);
-        self
+    pub fn put(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("PUT", path, handler)
+    }
+
+    /// Add a PUT route with swagger info to this group
+    pub fn put_with_swagger(&mut self, path: &str, handler: impl Handler, swagger_info: SwaggerInfo) -> RouteBuilder<'_, Self> {
+        self.swagger_for_route("PUT", path, swagger_info);
+        self.add_route("PUT", path, handler)
     }
 
     /// Add a PATCH route to this group.
-    pub fn patch(&mut self, path: &str, handler: impl Handler) -> &mut Self {
-        self.add_route("PATCH", path, handler);
-        self
+    pub fn patch(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("PATCH", path, handler)
     }
 
     /// Add a DELETE route to this group
-    pub fn delete(&mut self, path: &str, handler: impl Handler) -> &mut Self {
-        self.add_route("DELETE", path, handler);
+    pub fn delete(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("DELETE", path, handler)
+    }
+
+    /// Add a DELETE route with swagger info to this group
+    pub fn delete_with_swagger(&mut self, path: &str, handler: impl Handler, swagger_info: SwaggerInfo) -> RouteBuilder<'_, Self> {
+        self.swagger_for_route("DELETE", path, swagger_info);
+        self.add_route("DELETE", path, handler)
+    }
+
+    /// Add an OPTIONS route to this group, replacing the automatic `204` answer
+    pub fn options(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("OPTIONS", path, handler)
+    }
+
+    /// Add a HEAD route to this group, replacing the fallback to the GET handler
+    pub fn head(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("HEAD", path, handler)
+    }
+
+    /// Add a TRACE route to this group
+    pub fn trace(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("TRACE", path, handler)
+    }
+
+    /// Add a route for any method name, including extension methods (`PROPFIND`, ...)
+    pub fn route(&mut self, method: &str, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route(&method.to_ascii_uppercase(), path, handler)
+    }
+
+    /// Add a route matching every method not registered explicitly on the same path
+    pub fn any(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route(ANY_METHOD, path, handler)
+    }
+
+    /// Set swagger info for a route of this group; `path` is relative to the prefix
+    pub fn swagger_for_route(&mut self, method: &str, path: &str, swagger_info: SwaggerInfo) -> &mut Self {
+        let route_key = crate::swagger::route_key(method, &format!("{}{}", self.prefix, path));
+        self.swagger_info.insert(route_key, swagger_info);
+        self
+    }
+
+    /// Document every route of this group, including ones added later, with
+    /// the tags, security requirements and responses of `defaults`, on top of
+    /// what each route documents itself:
+    ///
+    /// ```rust,ignore
+    /// app.group("/admin").swagger_defaults(swagger().tag("admin").bearer_auth().build());
+    /// ```
+    pub fn swagger_defaults(&mut self, defaults: SwaggerInfo) -> &mut Self {
+        self.swagger_defaults = Some(defaults);
+        self
+    }
+
+    /// Answer unmatched paths under this prefix with `handler` instead of the
+    /// engine-wide fallback set by [`Engine::not_found`].
+    pub fn not_found(&mut self, handler: impl Handler) -> &mut Self {
+        self.router.set_not_found(Arc::new(handler));
         self
     }
 
@@ -92,6 +239,11 @@ impl RouterGroup {
         self
     }
 
+    /// Whether one of this group's routes matches `path` (under any method)
+    pub(crate) fn matches_path(&self, path: &str) -> bool {
+        self.router.matches_path(path)
+    }
+
     /// Handle a request using this group's router
     pub async fn handle_request(&self, ctx: RequestCtx) -> Response {
         self.router.handle_request(ctx).await
@@ -99,16 +251,51 @@ impl RouterGroup {
 }
 
 /// Main HTTP engine for building web applications
-#[derive(Default)]
 pub struct Engine {
     router: Router,
     groups: HashMap<String, RouterGroup>,
     middlewares: Vec<Middleware>,
+    preflight_checks: Vec<PreflightCheck>,
     startup_hooks: Vec<LifecycleHook>,
     shutdown_hooks: Vec<LifecycleHook>,
+    subsystems: Vec<Subsystem>,
     swagger_info: HashMap<String, SwaggerInfo>,
     /// Whether to expose Swagger UI at /docs/
     swagger_enabled: bool,
+    health: HealthRegistry,
+    state: StateMap,
+    blocking_threshold: Option<Duration>,
+    body_limit: usize,
+    request_timeout: Option<crate::timeout::Timeout>,
+    connect: Option<Arc<dyn Handler>>,
+    watermark: Option<Watermark>,
+    #[cfg(feature = "alloc-tracking")]
+    alloc_tracker: Option<Arc<crate::alloc_tracking::AllocTracker>>,
+    startup_mode: StartupMode,
+    hook_failures: HookFailure,
+    wildcard_limit: Option<WildcardLimit>,
+    rate_limit: Option<Arc<Limiter>>,
+    error_handler: Option<ErrorHandler>,
+    trusted_proxies: Vec<Cidr>,
+    server_config: ServerConfig,
+    routes_handle: RoutesHandle,
+    workers: usize,
+}
+
+impl RouteTarget for Engine {
+    fn router_mut(&mut self) -> &mut Router {
+        &mut self.router
+    }
+
+    fn health_registry(&self) -> HealthRegistry {
+        self.health.clone()
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Engine {
@@ -118,19 +305,231 @@ impl Engine {
             router: Router::new(),
             groups: HashMap::new(),
             middlewares: Vec::new(),
+            preflight_checks: Vec::new(),
             startup_hooks: Vec::new(),
             shutdown_hooks: Vec::new(),
+            subsystems: Vec::new(),
             swagger_info: HashMap::new(),
             swagger_enabled: false,
+            health: HealthRegistry::new(),
+            state: StateMap::new(),
+            blocking_threshold: None,
+            body_limit: crate::DEFAULT_BODY_LIMIT,
+            request_timeout: None,
+            connect: None,
+            watermark: None,
+            #[cfg(feature = "alloc-tracking")]
+            alloc_tracker: None,
+            startup_mode: StartupMode::BindWhenReady,
+            hook_failures: HookFailure::Fatal,
+            wildcard_limit: None,
+            rate_limit: None,
+            error_handler: None,
+            trusted_proxies: Vec::new(),
+            server_config: ServerConfig::default(),
+            routes_handle: RoutesHandle::default(),
+            workers: 1,
         }
     }
 
+    /// Register shared application state (DB pools, config, clients).
+    /// Handlers read it with `ctx.state::<T>()`; one value is kept per type.
+    pub fn with_state<T: Send + Sync + 'static>(mut self, state: T) -> Self {
+        self.state.insert(state);
+        self
+    }
+
+    /// Register the mailer handlers reach through `ctx.mailer()`.
+    pub fn with_mailer(self, mailer: impl Mailer) -> Self {
+        self.with_state(MailerRef(Arc::new(mailer)))
+    }
+
+    /// Registry of dependency health probes shared by all routes and groups.
+    pub fn health(&self) -> &HealthRegistry {
+        &self.health
+    }
+
+    /// Serve the health report under `path`: `GET {path}` and `{path}/ready`
+    /// answer the probes' aggregated JSON (503 while unhealthy), `{path}/live`
+    /// answers 200 as long as the server is up.
+    ///
+    /// ```rust,ignore
+    /// app.health().critical("db", move || {
+    ///     let pool = pool.clone();
+    ///     async move { sqlx::query("SELECT 1").execute(&pool).await.into() }
+    /// });
+    /// app.health_check("/healthz");
+    /// ```
+    pub fn health_check(&mut self, path: &str) -> &mut Self {
+        let path = path.trim_end_matches('/');
+        let base = if path.is_empty() { "/" } else { path };
+        self.get(base, self.health.handler());
+        self.get(&format!("{path}/ready"), self.health.handler());
+        self.get(&format!("{path}/live"), crate::health::alive);
+        self
+    }
+
     /// Enable the built-in Swagger UI at `/docs/` and `/docs/swagger.json`.
     pub fn enable_swagger(&mut self) -> &mut Self {
         self.swagger_enabled = true;
         self
     }
 
+    /// Shorthand for [`ServerConfig::keep_alive_timeout`].
+    pub fn keep_alive_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.server_config.keep_alive_timeout(timeout);
+        self
+    }
+
+    /// HTTP connection options: keep-alive and its idle timeout, requests per
+    /// connection, header and body read timeouts, per-IP connection caps,
+    /// header and buffer size limits, half-close and `TCP_NODELAY`.
+    pub fn server_config(&mut self) -> &mut ServerConfig {
+        &mut self.server_config
+    }
+
+    /// Accept connections on `n` tasks instead of one (default: 1), so accepting
+    /// and spreading new connections over the runtime's threads scales with
+    /// cores under heavy connection churn. On Linux each task gets its own
+    /// `SO_REUSEPORT` socket and the kernel balances connections between them;
+    /// elsewhere, and for `run_with_listener`, the tasks share the listener.
+    ///
+    /// `SO_REUSEPORT` would also let a second copy of the server, run by the
+    /// same user, bind the port and quietly take half the connections. Binding
+    /// fails instead when the port is already in use, but a copy started in
+    /// the moment between that check and the workers' binds is not caught.
+    pub fn workers(&mut self, n: usize) -> &mut Self {
+        if n == 0 {
+            log::warn!("workers(0) ignored, keeping {}", self.workers);
+        } else {
+            self.workers = n;
+        }
+        self
+    }
+
+    /// Shorthand for [`ServerConfig::max_requests_per_connection`].
+    pub fn max_requests_per_connection(&mut self, max: usize) -> &mut Self {
+        self.server_config.max_requests_per_connection(max);
+        self
+    }
+
+    /// Largest request body `ctx.body_bytes()` (and `json`, `form`, ...) will
+    /// buffer and `ctx.take_body()` will stream (default 2 MiB). Bigger bodies
+    /// fail with [`PayloadTooLarge`](crate::PayloadTooLarge),
+    /// which answers 413 Payload Too Large; raise the limit for single routes
+    /// with `.body_limit(..)` on the route.
+    pub fn body_limit(&mut self, bytes: usize) -> &mut Self {
+        self.body_limit = bytes;
+        self
+    }
+
+    /// Answer `414 URI Too Long` when a route's `*wildcard` would capture more
+    /// than `max_segments` segments or `max_bytes` bytes (default: no limit),
+    /// protecting file-serving and proxy routes from crafted paths. Routes can
+    /// set their own with `.wildcard_limit(..)`.
+    pub fn wildcard_limit(&mut self, max_segments: usize, max_bytes: usize) -> &mut Self {
+        self.wildcard_limit = Some(WildcardLimit { max_segments, max_bytes });
+        self
+    }
+
+    /// Per-client request budget shared by every route (default: unlimited);
+    /// over-budget requests get `429` with `Retry-After`. Routes can set their
+    /// own with `.rate_limit(..)`. See [`crate::ratelimit`].
+    pub fn rate_limit(&mut self, rate: Rate) -> &mut Self {
+        self.rate_limit = Some(Arc::new(Limiter::new(rate)));
+        self
+    }
+
+    /// Believe `Forwarded`/`X-Forwarded-For` on connections from these
+    /// addresses or ranges (`"10.0.0.0/8"`, `"::1"`), so `ctx.client_ip()`
+    /// is the caller rather than the load balancer. Invalid entries are
+    /// logged and ignored. See [`crate::ipfilter`].
+    pub fn trust_proxies<I, S>(&mut self, proxies: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.trusted_proxies.extend(crate::ipfilter::parse_ranges(proxies));
+        self
+    }
+
+    /// Handle `CONNECT` requests (forward proxies, tunnels). They target
+    /// `host:port` instead of a path, so they bypass routing and groups and
+    /// only run through global middleware. Answer with a 2xx and use
+    /// `ctx.upgrade()` to get the tunnel's connection.
+    pub fn connect(&mut self, handler: impl Handler) -> &mut Self {
+        self.connect = Some(Arc::new(handler));
+        self
+    }
+
+    /// Answer 504 Gateway Timeout when a request (middleware and handler) takes
+    /// longer than `timeout` (default: no limit). Routes can set their own
+    /// deadline with `s_web::timeout::Timeout`.
+    pub fn request_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.request_timeout = Some(crate::timeout::Timeout::new(timeout));
+        self
+    }
+
+    /// Development aid: log requests whose handler or middleware blocks the
+    /// executor for longer than `threshold` (e.g. 100ms) in a single poll,
+    /// which points at `std::thread::sleep`, sync IO or heavy CPU work in async code.
+    pub fn detect_blocking(&mut self, threshold: Duration) -> &mut Self {
+        self.blocking_threshold = Some(threshold);
+        self
+    }
+
+    /// Development aid: count the allocations each request makes (requires
+    /// `CountingAllocator` as the `#[global_allocator]`) and log requests that
+    /// allocate far more than their route's average. The per-route statistics
+    /// are registered as state: `ctx.state::<Arc<AllocTracker>>()`.
+    #[cfg(feature = "alloc-tracking")]
+    pub fn track_allocations(&mut self) -> &mut Self {
+        if self.alloc_tracker.is_none() {
+            let tracker = crate::alloc_tracking::AllocTracker::new();
+            self.state.insert(tracker.clone());
+            self.alloc_tracker = Some(tracker);
+        }
+        self
+    }
+
+    /// Choose whether to bind before or after startup completes (default
+    /// [`StartupMode::BindWhenReady`]).
+    pub fn startup_mode(&mut self, mode: StartupMode) -> &mut Self {
+        self.startup_mode = mode;
+        self
+    }
+
+    /// Choose whether a startup or shutdown hook returning `Err` (or panicking)
+    /// makes `run` fail (default [`HookFailure::Fatal`]) or is only logged.
+    pub fn hook_failures(&mut self, policy: HookFailure) -> &mut Self {
+        self.hook_failures = policy;
+        self
+    }
+
+    /// Tag every response with a per-deployment identifier (instance, version)
+    /// to trace which server answered during incident triage.
+    pub fn watermark(&mut self, watermark: Watermark) -> &mut Self {
+        self.watermark = Some(watermark);
+        self
+    }
+
+    /// Render every [`Error`](crate::Error) answered by a handler, including
+    /// errors propagated with `?` and `ParamError`s, e.g. as JSON:
+    ///
+    /// ```rust,ignore
+    /// app.error_handler(|err| {
+    ///     let body = json!({ "error": err.message().unwrap_or("internal error") });
+    ///     (err.status(), body).into_response()
+    /// });
+    /// ```
+    pub fn error_handler<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(&crate::Error) -> Response + Send + Sync + 'static,
+    {
+        self.error_handler = Some(Arc::new(handler));
+        self
+    }
+
     /// Add global middleware
     pub fn use_middleware<F, Fut>(&mut self, middleware: F) -> &mut Self
     where
@@ -145,121 +544,327 @@ impl Engine {
         self
     }
 
-    /// Add a startup hook that will be executed when the server starts
-    pub fn on_startup<F, Fut>(mut self, f: F) -> Self 
+    /// Add a preflight check that runs before startup hooks and before the
+    /// listener is bound. If any check fails, `run` returns [`RunError::Preflight`]
+    /// instead of serving traffic.
+    pub fn preflight<F, Fut, E>(mut self, name: &str, f: F) -> Self
     where
         F: Fn() -> Fut + Send + Sync + 'static,
-        Fut: Future<Output = ()> + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Into<BoxError>,
     {
         let wrapped = move || {
             let fut = f();
-            Box::pin(fut) as Pin<Box<dyn Future<Output = ()> + Send>>
+            Box::pin(async move { fut.await.map_err(Into::into) })
+                as Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send>>
+        };
+        self.preflight_checks.push((name.to_string(), Box::new(wrapped)));
+        self
+    }
+
+    /// Run sqlx migrations as a preflight step and publish the applied
+    /// versions in the health report.
+    #[cfg(feature = "migrate")]
+    pub fn migrate<DB>(self, migrations: crate::migrate::Migrations, pool: sqlx::Pool<DB>) -> Self
+    where
+        DB: sqlx::Database,
+        DB::Connection: sqlx::migrate::Migrate,
+    {
+        let health = self.health.clone();
+        self.preflight("migrations", move || {
+            let migrations = migrations.clone();
+            let pool = pool.clone();
+            let health = health.clone();
+            async move {
+                let applied = migrations.run(&pool).await?;
+                log::info!("✔ Database migrations applied: {applied:?}");
+                health.set_info("migrations", serde_json::json!({ "applied": applied }));
+                Ok::<_, sqlx::migrate::MigrateError>(())
+            }
+        })
+    }
+
+    /// Add a startup hook that will be executed when the server starts.
+    /// Hooks may return `()` or a `Result`; see [`hook_failures`](Self::hook_failures).
+    pub fn on_startup<F, Fut>(self, f: F) -> Self 
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: HookOutput,
+    {
+        self.on_startup_with(move |_| f())
+    }
+
+    /// Like `on_startup`, but the hook receives a [`LifecycleCtx`] to register
+    /// shared state (read in handlers via `ctx.state::<T>()`) and to obtain a
+    /// [`ShutdownToken`] for background tasks it spawns.
+    pub fn on_startup_with<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(LifecycleCtx) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: HookOutput,
+    {
+        let wrapped = move |ctx| {
+            let fut = f(ctx);
+            Box::pin(async move { fut.await.into_result() })
+                as Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send>>
         };
         self.startup_hooks.push(Box::new(wrapped));
         self
     }
 
     /// Add a shutdown hook that will be executed during graceful shutdown
-    pub fn on_shutdown<F, Fut>(mut self, f: F) -> Self 
+    pub fn on_shutdown<F, Fut>(self, f: F) -> Self 
     where
         F: Fn() -> Fut + Send + Sync + 'static,
-        Fut: Future<Output = ()> + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: HookOutput,
     {
-        let wrapped = move || {
-            let fut = f();
-            Box::pin(fut) as Pin<Box<dyn Future<Output = ()> + Send>>
+        self.on_shutdown_with(move |_| f())
+    }
+
+    /// Like `on_shutdown`, but the hook receives a [`LifecycleCtx`] with access
+    /// to the shared state registered during startup.
+    pub fn on_shutdown_with<F, Fut>(mut self, f: F) -> Self
+    where
+        F: Fn(LifecycleCtx) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: HookOutput,
+    {
+        let wrapped = move |ctx| {
+            let fut = f(ctx);
+            Box::pin(async move { fut.await.into_result() })
+                as Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send>>
         };
         self.shutdown_hooks.push(Box::new(wrapped));
         self
     }
 
+    /// Register a background subsystem to stop, in dependency order, once
+    /// connections have drained. If any fails or times out, `run` returns
+    /// [`RunError::Shutdown`] naming them.
+    pub fn subsystem(mut self, subsystem: Subsystem) -> Self {
+        if self.subsystems.iter().any(|s| s.name() == subsystem.name()) {
+            log::warn!("subsystem `{}` registered twice; keeping the last one", subsystem.name());
+            self.subsystems.retain(|s| s.name() != subsystem.name());
+        }
+        self.subsystems.push(subsystem);
+        self
+    }
+
     /// Create (or retrieve) a route group with the given prefix.
     /// Calling `group()` with the same prefix twice returns the existing group
-    /// rather than silently discarding previously registered routes.
+    /// rather than silently discarding previously registered routes, so several
+    /// modules can each add their routes to `/api`. `/api`, `/api/` and `api`
+    /// name the same group.
     pub fn group(&mut self, prefix: &str) -> &mut RouterGroup {
+        let prefix = crate::app::normalize_prefix(prefix);
         self.groups
-            .entry(prefix.to_string())
-            .or_insert_with(|| RouterGroup::new(prefix.to_string()))
+            .entry(prefix.clone())
+            .or_insert_with(|| RouterGroup::new(prefix, self.health.clone()))
+    }
+
+    /// Mount a self-contained [`App`](crate::App) under `prefix` (`"/"` for
+    /// the root). Its routes join the group for that prefix, each wrapped in
+    /// the app's middleware, so apps never see each other's middleware.
+    pub fn mount(&mut self, prefix: &str, app: crate::App) -> &mut Self {
+        let prefix = crate::app::normalize_prefix(prefix);
+        let health = self.health.clone();
+        if prefix.is_empty() {
+            app.merge_into("", &mut self.router, &health);
+        } else {
+            let group = self.group(&prefix);
+            app.merge_into(&prefix, &mut group.router, &health);
+        }
+        self
+    }
+
+    /// Handle for adding and removing main-router routes after the server
+    /// has started; see [`RoutesHandle`]. Routes added before `run` are
+    /// merged with the engine's own.
+    pub fn routes_handle(&self) -> RoutesHandle {
+        self.routes_handle.clone()
     }
 
     /// Add a route to the main router
-    pub fn add_route(&mut self, method: &str, pattern: &str, handler: impl Handler) {
-        let handler = Box::new(handler);
-        self.router.add_route(method, pattern, handler);
+    pub fn add_route(&mut self, method: &str, pattern: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.router.add_handler(method, pattern, handler);
+        RouteBuilder::new(self, method, pattern.to_string())
     }
 
     /// Add a GET route
-    pub fn get(&mut self, path: &str, handler: impl Handler) -> &mut Self {
-        self.add_route("GET", path, handler);
-        self
+    pub fn get(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("GET", path, handler)
     }
 
     /// Add a GET route with swagger info
-    pub fn get_with_swagger(&mut self, path: &str, handler: impl Handler, swagger_info: SwaggerInfo) -> &mut Self {
-        self.add_route("GET", path, handler);
+    pub fn get_with_swagger(&mut self, path: &str, handler: impl Handler, swagger_info: SwaggerInfo) -> RouteBuilder<'_, Self> {
         self.swagger_for_route("GET", path, swagger_info);
-        self
+        self.add_route("GET", path, handler)
     }
 
     /// Add a POST route
-    pub fn post(&mut self, path: &str, handler: impl Handler) -> &mut Self {
-        self.add_route("POST", path, handler);
-        self
+    pub fn post(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("POST", path, handler)
     }
 
     /// Add a POST route with swagger info
-    pub fn post_with_swagger(&mut self, path: &str, handler: impl Handler, swagger_info: SwaggerInfo) -> &mut Self {
-        self.add_route("POST", path, handler);
+    pub fn post_with_swagger(&mut self, path: &str, handler: impl Handler, swagger_info: SwaggerInfo) -> RouteBuilder<'_, Self> {
         self.swagger_for_route("POST", path, swagger_info);
-        self
+        self.add_route("POST", path, handler)
     }
 
     /// Add a PUT route
-    pub fn put(&mut self, path: &str, handler: impl Handler) -> &mut Self {
-        self.add_route("PUT", path, handler);
-        self
+    pub fn put(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("PUT", path, handler)
     }
 
     /// Add a PATCH route.
-    pub fn patch(&mut self, path: &str, handler: impl Handler) -> &mut Self {
-        self.add_route("PATCH", path, handler);
-        self
+    pub fn patch(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("PATCH", path, handler)
     }
 
     /// Add a PUT route with swagger info
-    pub fn put_with_swagger(&mut self, path: &str, handler: impl Handler, swagger_info: SwaggerInfo) -> &mut Self {
-        self.add_route("PUT", path, handler);
+    pub fn put_with_swagger(&mut self, path: &str, handler: impl Handler, swagger_info: SwaggerInfo) -> RouteBuilder<'_, Self> {
         self.swagger_for_route("PUT", path, swagger_info);
-        self
+        self.add_route("PUT", path, handler)
     }
 
     /// Add a DELETE route
-    pub fn delete(&mut self, path: &str, handler: impl Handler) -> &mut Self {
-        self.add_route("DELETE", path, handler);
-        self
+    pub fn delete(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("DELETE", path, handler)
     }
 
     /// Add a DELETE route with swagger info
-    pub fn delete_with_swagger(&mut self, path: &str, handler: impl Handler, swagger_info: SwaggerInfo) -> &mut Self {
-        self.add_route("DELETE", path, handler);
+    pub fn delete_with_swagger(&mut self, path: &str, handler: impl Handler, swagger_info: SwaggerInfo) -> RouteBuilder<'_, Self> {
         self.swagger_for_route("DELETE", path, swagger_info);
-        self
+        self.add_route("DELETE", path, handler)
+    }
+
+    /// Add an OPTIONS route, replacing the automatic `204` answer for its path
+    pub fn options(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("OPTIONS", path, handler)
+    }
+
+    /// Add a HEAD route, replacing the fallback to the GET handler for its path
+    pub fn head(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("HEAD", path, handler)
+    }
+
+    /// Add a TRACE route
+    pub fn trace(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route("TRACE", path, handler)
+    }
+
+    /// Add a route for any method name, including extension methods (`PROPFIND`, ...)
+    pub fn route(&mut self, method: &str, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route(&method.to_ascii_uppercase(), path, handler)
+    }
+
+    /// Add a route matching every method not registered explicitly on the same path
+    pub fn any(&mut self, path: &str, handler: impl Handler) -> RouteBuilder<'_, Self> {
+        self.add_route(ANY_METHOD, path, handler)
     }
 
     /// Set swagger info for a specific route
     pub fn swagger_for_route(&mut self, method: &str, path: &str, swagger_info: SwaggerInfo) -> &mut Self {
-        let route_key = format!("{}-{}", method.to_uppercase(), path);
+        let route_key = crate::swagger::route_key(method, path);
         self.swagger_info.insert(route_key, swagger_info);
         self
     }
 
-    fn add_swagger_endpoints(&mut self) {
-        let mut all_routes = Vec::new();
-        all_routes.extend(self.router.get_all_routes());
+    /// Answer requests no route matches with `handler` (branded HTML or JSON
+    /// error pages) instead of the plain-text `404 Not Found`. Groups use it
+    /// too unless they set their own with [`RouterGroup::not_found`].
+    pub fn not_found(&mut self, handler: impl Handler) -> &mut Self {
+        self.router.set_not_found(Arc::new(handler));
+        self
+    }
 
+    /// Customize the response for a path that exists under other methods only
+    /// (default: plain-text `405`). The handler finds the supported methods in
+    /// [`AllowedMethods`](crate::AllowedMethods) in the request extensions, and
+    /// an `Allow` header is added unless it sets one itself.
+    pub fn method_not_allowed(&mut self, handler: impl Handler) -> &mut Self {
+        self.router.set_method_not_allowed(Arc::new(handler));
+        self
+    }
+
+    /// List every registered route, including group routes, sorted by pattern
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        let mut routes = self.router.routes();
         for group in self.groups.values() {
-            all_routes.extend(group.router.get_all_routes());
+            routes.extend(group.router.routes().into_iter().map(|mut route| {
+                route.hidden |= group.hidden;
+                route
+            }));
         }
+        routes.sort_by(|a, b| (&a.pattern, &a.method).cmp(&(&b.pattern, &b.method)));
+        routes
+    }
+
+    /// Print every registered route as a table: method, full pattern, handler
+    /// and the group, name and options attached to it. Call it before `run`.
+    pub fn print_routes(&self) {
+        println!("{}", self.route_table());
+    }
+
+    fn route_table(&self) -> String {
+        let mut rows = Vec::new();
+        let mut groups: Vec<(&str, &Router, bool)> = vec![("", &self.router, false)];
+        groups.extend(self.groups.iter().map(|(prefix, g)| (prefix.as_str(), &g.router, g.hidden)));
+        for (prefix, router, group_hidden) in groups {
+            for route in router.routes() {
+                let mut notes = Vec::new();
+                if !prefix.is_empty() {
+                    notes.push(format!("group {prefix}"));
+                }
+                if let Some(name) = &route.name {
+                    notes.push(format!("name {name}"));
+                }
+                notes.extend(route.security.iter().map(|s| format!("auth {}", s.swagger_name())));
+                if let Some(date) = &route.sunset {
+                    notes.push(format!("sunset {date}"));
+                }
+                if route.hidden || group_hidden {
+                    notes.push("hidden".to_string());
+                }
+                let handler = match route.handler {
+                    "" => "-".to_string(),
+                    name => name.replace("::{{closure}}", " (closure)"),
+                };
+                rows.push([route.method, route.pattern, handler, notes.join(", ")]);
+            }
+        }
+        rows.sort_by(|a, b| (&a[1], &a[0]).cmp(&(&b[1], &b[0])));
+
+        let header = ["METHOD", "PATTERN", "HANDLER", ""].map(String::from);
+        let mut widths = [0; 3];
+        for row in std::iter::once(&header).chain(&rows) {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        std::iter::once(&header)
+            .chain(&rows)
+            .map(|[method, pattern, handler, notes]| {
+                let line = format!("{method:<w0$}  {pattern:<w1$}  {handler:<w2$}  {notes}", w0 = widths[0], w1 = widths[1], w2 = widths[2]);
+                line.trim_end().to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Path of the route named `name` (see [`RouteBuilder::name`]) with its
+    /// parameters filled in and percent-encoded, e.g.
+    /// `url_for("user_detail", &[("id", "42")])` gives `/users/42`.
+    /// `None` if no route has that name or a parameter is missing.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Option<String> {
+        RouteUrls::new(&self.routes()).url_for(name, params)
+    }
+
+    fn add_swagger_endpoints(&mut self) {
+        let all_routes = self.routes();
 
         if all_routes.is_empty() {
             return;
@@ -267,20 +872,31 @@ impl Engine {
 
         let json_path = "/docs/swagger.json";
         let ui_path = "/docs/";
-        let swagger_info = self.swagger_info.clone();
+        let mut swagger_info = self.swagger_info.clone();
+        let mut group_defaults = HashMap::new();
+        for group in self.groups.values() {
+            swagger_info.extend(group.swagger_info.clone());
+            if let Some(defaults) = &group.swagger_defaults {
+                for route in group.router.routes() {
+                    let route_key = crate::swagger::route_key(&route.method, &route.pattern);
+                    group_defaults.insert(route_key, defaults.clone());
+                }
+            }
+        }
 
         self.get(json_path, move |_ctx: RequestCtx| {
             let routes = all_routes.clone();
             let swagger_info = swagger_info.clone();
+            let group_defaults = group_defaults.clone();
             async move {
                 use crate::response::ResponseBuilder;
                 use crate::swagger::generate_enhanced_swagger_json;
 
-                let json = generate_enhanced_swagger_json(&routes, &swagger_info);
+                let json = generate_enhanced_swagger_json(&routes, &swagger_info, &group_defaults);
                 ResponseBuilder::new()
                     .status(hyper::StatusCode::OK)
-                    .header("Content-Type", "application/json")
-                    .body(json)
+                    .with_header("Content-Type", "application/json")
+                    .build(json)
             }
         });
 
@@ -291,150 +907,825 @@ impl Engine {
             let html = generate_swagger_ui("/docs/swagger.json");
             ResponseBuilder::new()
                 .status(hyper::StatusCode::OK)
-                .header("Content-Type", "text/html")
-                .body(html)
+                .with_header("Content-Type", "text/html")
+                .build(html)
         });
     }
 
-    /// Start the HTTP server
-    pub async fn run(mut self, addr: &str) -> Result<(), Box<dyn std::error::Error>> {
-        for hook in &self.startup_hooks {
-            hook().await;
+    /// Finish setup and return the request handling as a service, without
+    /// binding a port: for custom accept loops, serverless adapters, tower
+    /// stacks and tests. Preflight checks, lifecycle hooks and subsystems
+    /// only run with [`run`](Self::run) and friends.
+    pub fn build(mut self) -> EngineService {
+        if self.swagger_enabled {
+            self.add_swagger_endpoints();
+        }
+        EngineService::new(self.build_server_context())
+    }
+
+    /// Start the HTTP server and serve until Ctrl-C or SIGTERM. Errors say why the server
+    /// could not start or stopped abnormally; see [`RunError::exit_code`].
+    pub async fn run(self, addr: &str) -> Result<(), RunError> {
+        self.serve(Listen::Addrs(vec![parse_addr(addr)?]), Acceptor::Plain).await
+    }
+
+    /// Like [`run`](Self::run), listening on every address at once, e.g.
+    /// `&["0.0.0.0:80", "[::]:80"]`. IPv6 sockets accept IPv6 only here, so a
+    /// wildcard IPv6 address can sit next to its IPv4 counterpart.
+    pub async fn run_many(self, addrs: &[&str]) -> Result<(), RunError> {
+        if addrs.is_empty() {
+            return Err(RunError::Config("run_many needs at least one address".to_string()));
         }
+        let addrs = addrs.iter().map(|addr| parse_addr(addr)).collect::<Result<_, _>>()?;
+        self.serve(Listen::Addrs(addrs), Acceptor::Plain).await
+    }
+
+    /// Like [`run`](Self::run) on a socket bound by the caller, e.g. a test
+    /// binding port 0 and reading the port back with `local_addr`. Startup
+    /// hooks still run first; connections wait in the backlog meanwhile.
+    pub async fn run_with_listener(self, listener: tokio::net::TcpListener) -> Result<(), RunError> {
+        self.serve(Listen::Bound(vec![listener]), Acceptor::Plain).await
+    }
 
-        let addr = addr.parse::<SocketAddr>()?;
-        println!("🚀 Server running on http://{addr}");
-        let listener = tokio::net::TcpListener::bind(addr).await?;
+    /// Start an HTTPS server terminating TLS with rustls.
+    /// HTTP/2 is served when the client negotiates it through ALPN.
+    #[cfg(feature = "tls")]
+    pub async fn run_tls(
+        self,
+        addr: &str,
+        tls: crate::tls::TlsConfig,
+    ) -> Result<(), RunError> {
+        self.serve(Listen::Addrs(vec![parse_addr(addr)?]), Acceptor::Tls(tls.acceptor())).await
+    }
+
+    async fn serve(mut self, listen: Listen, acceptor: Acceptor) -> Result<(), RunError> {
+        let scheme = match acceptor {
+            Acceptor::Plain => "http",
+            #[cfg(feature = "tls")]
+            Acceptor::Tls(_) => "https",
+        };
+        let subsystem_stages = crate::shutdown::plan(&self.subsystems).map_err(RunError::Config)?;
+        let (shutdown_tx, shutdown_token) = ShutdownToken::new();
+        let lifecycle = LifecycleCtx::new(std::mem::take(&mut self.state), shutdown_token);
+        let (drain_tx, drain_rx) = watch::channel(false);
+
+        let listeners = match self.startup_mode {
+            StartupMode::BindWhenReady => {
+                self.start(&lifecycle).await?;
+                listen.bind(self.workers).await?
+            }
+            StartupMode::ServeUnavailable => {
+                let listeners = listen.bind(self.workers).await?;
+                log::info!(
+                    "⏳ Listening on {}, answering 503 until startup completes",
+                    urls(scheme, &listeners[0])
+                );
+                let starting = ServerContext::starting(self.watermark.clone(), self.server_config.clone());
+                tokio::select! {
+                    started = self.start(&lifecycle) => started?,
+                    // Ctrl-C before startup finished
+                    stopped = accept_loop(listeners.clone(), starting, acceptor.clone(), drain_rx.clone()) => {
+                        return stopped.map_err(RunError::Accept);
+                    }
+                }
+                listeners
+            }
+        };
+        self.state = lifecycle.state();
+        log::info!("🚀 Server running on {}", urls(scheme, &listeners[0]));
+        #[cfg(feature = "daemon")]
+        crate::daemon::notify_lifecycle("READY=1");
 
         if self.swagger_enabled {
             self.add_swagger_endpoints();
-            println!("📖 Swagger UI available at http://{addr}/docs/");
+            if let Some(addr) = listeners[0].first().and_then(|listener| listener.local_addr().ok()) {
+                log::info!("📖 Swagger UI available at {scheme}://{addr}/docs/");
+            }
         }
 
         let shutdown_hooks = std::mem::take(&mut self.shutdown_hooks);
+        let hook_failures = self.hook_failures;
+        let subsystems = std::mem::take(&mut self.subsystems);
         let server_ctx = self.build_server_context();
-        let graceful = GracefulShutdown::new();
 
-        accept_loop(listener, server_ctx, &graceful).await;
+        let accepted = accept_loop(listeners.clone(), server_ctx, acceptor, drain_rx).await;
+        drop(listeners);
+        let _ = shutdown_tx.send(true);
+        #[cfg(feature = "daemon")]
+        crate::daemon::notify_lifecycle("STOPPING=1");
 
-        for hook in &shutdown_hooks {
-            hook().await;
+        // Finish the shutdown sequence even after a failure, reporting the first one
+        let mut failure = accepted.err().map(RunError::Accept);
+        if let Err(e) = run_shutdown_hooks(&shutdown_hooks, hook_failures, &lifecycle).await {
+            failure.get_or_insert(e);
         }
 
+        // Every connection task holds a receiver; `closed` resolves once they have all finished
+        let _ = drain_tx.send(true);
         tokio::select! {
-            _ = graceful.shutdown() => {
-                eprintln!("✅ All connections gracefully closed");
+            _ = drain_tx.closed() => {
+                log::info!(stderr; "✅ All connections gracefully closed");
             },
             _ = tokio::time::sleep(std::time::Duration::from_secs(10)) => {
-                eprintln!("⏰ Timed out waiting for all connections to close");
+                log::error!("⏰ Timed out waiting for all connections to close");
             }
         }
 
+        if let Err(e) = crate::shutdown::stop_all(&subsystems, &subsystem_stages, &lifecycle).await {
+            failure.get_or_insert(e.into());
+        }
+        failure.map_or(Ok(()), Err)
+    }
+
+    /// Preflight checks, then startup hooks
+    async fn start(&self, lifecycle: &LifecycleCtx) -> Result<(), RunError> {
+        self.run_preflight().await?;
+        for hook in &self.startup_hooks {
+            check_hook(self.hook_failures, "startup", hook(lifecycle.clone())).await?;
+        }
+        Ok(())
+    }
+
+    /// Run all preflight checks in registration order, stopping at the first failure
+    async fn run_preflight(&self) -> Result<(), PreflightError> {
+        for (name, check) in &self.preflight_checks {
+            if let Err(source) = check().await {
+                log::error!("❌ Preflight check `{name}` failed: {source}");
+                return Err(PreflightError {
+                    check: name.clone(),
+                    source,
+                });
+            }
+            log::info!("✔ Preflight check `{name}` passed");
+        }
         Ok(())
     }
 
     /// Pre-process groups and middleware for the request handling path
-    fn build_server_context(self) -> ServerContext {
-        let global_middlewares = Arc::new(self.middlewares);
+    pub(crate) fn build_server_context(mut self) -> ServerContext {
+        let route_urls = RouteUrls::new(&self.routes());
+        if let Some(limit) = self.wildcard_limit {
+            self.router.set_wildcard_limit(limit);
+            for group in self.groups.values_mut() {
+                group.router.set_wildcard_limit(limit);
+            }
+        }
+        if let Some(limiter) = &self.rate_limit {
+            self.router.set_rate_limit(limiter.clone());
+            for group in self.groups.values_mut() {
+                group.router.set_rate_limit(limiter.clone());
+            }
+        }
+        let global_middlewares = self.middlewares;
+        let not_found = self.router.not_found_handler();
+        let method_not_allowed = self.router.method_not_allowed_handler();
 
-        let mut group_data: Vec<(String, Arc<RouterGroup>, Arc<Vec<Middleware>>)> = self
+        let mut group_data: Vec<PreprocessedGroup> = self
             .groups
             .into_iter()
-            .map(|(prefix, group)| {
+            .map(|(prefix, mut group)| {
+                if let Some(handler) = &not_found
+                    && group.router.not_found_handler().is_none()
+                {
+                    group.router.set_not_found(handler.clone());
+                }
+                if let Some(handler) = &method_not_allowed
+                    && group.router.method_not_allowed_handler().is_none()
+                {
+                    group.router.set_method_not_allowed(handler.clone());
+                }
                 let mut combined =
                     Vec::with_capacity(global_middlewares.len() + group.middlewares.len());
                 combined.extend(global_middlewares.iter().cloned());
                 combined.extend(group.middlewares.iter().cloned());
-                (prefix, Arc::new(group), Arc::new(combined))
+                let group = Arc::new(group);
+                let pipeline = (!combined.is_empty()).then(|| {
+                    let group = group.clone();
+                    let endpoint: Next = Arc::new(move |ctx| {
+                        let group = group.clone();
+                        Box::pin(async move { group.handle_request(ctx).await })
+                    });
+                    compose(&combined, endpoint)
+                });
+                (prefix, group, pipeline)
             })
             .collect();
 
-        group_data.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        group_data.sort_by_key(|g| std::cmp::Reverse(g.0.len()));
 
-        let has_global_middleware = !global_middlewares.is_empty();
+        let mut state = self.state;
+        state.insert(route_urls);
+        let state = Arc::new(state);
+
+        self.routes_handle.install(self.router);
+        let pipeline = (!global_middlewares.is_empty()).then(|| {
+            let routes = self.routes_handle.clone();
+            let endpoint: Next = Arc::new(move |ctx| {
+                let router = routes.current();
+                Box::pin(async move { router.handle_request(ctx).await })
+            });
+            compose(&global_middlewares, endpoint)
+        });
+        let connect = self.connect.map(|handler| {
+            let endpoint: Next = Arc::new(move |ctx| handler.handle(ctx));
+            compose(&global_middlewares, endpoint)
+        });
 
         ServerContext {
-            router: Arc::new(self.router),
+            router: self.routes_handle,
             groups: Arc::new(group_data),
-            global_middlewares,
-            has_global_middleware,
+            pipeline,
+            state,
+            draining: Arc::new(AtomicBool::new(false)),
+            blocking_threshold: self.blocking_threshold,
+            body_limit: self.body_limit,
+            request_timeout: self.request_timeout,
+            connect,
+            watermark: self.watermark,
+            error_handler: self.error_handler,
+            trusted_proxies: Arc::new(self.trusted_proxies),
+            config: self.server_config,
+            #[cfg(feature = "alloc-tracking")]
+            alloc_tracker: self.alloc_tracker,
+            starting: false,
         }
     }
 }
 
-/// Accept and handle incoming connections
-async fn accept_loop(
-    listener: tokio::net::TcpListener,
-    ctx: ServerContext,
-    graceful: &GracefulShutdown,
-) {
-    loop {
-        tokio::select! {
-            Ok((stream, remote_addr)) = listener.accept() => {
-                let io = TokioIo::new(stream);
-                let router = ctx.router.clone();
-                let groups = ctx.groups.clone();
-                let global_middlewares = ctx.global_middlewares.clone();
-                let has_global_middleware = ctx.has_global_middleware;
-
-                let conn = http1::Builder::new()
-                    .serve_connection(io, service_fn(move |req| {
-                        let router = router.clone();
-                        let groups = groups.clone();
-                        let global_middlewares = global_middlewares.clone();
-
-                        async move {
-                            let path = req.uri().path().to_owned();
-
-                            let matched_group = groups
-                                .iter()
-                                .find(|(prefix, _, _)| {
-                                    path.starts_with(prefix.as_str())
-                                        && (path.len() == prefix.len()
-                                            || path.as_bytes().get(prefix.len()) == Some(&b'/'))
-                                })
-                                .map(|(_, group, middlewares)| (group.clone(), middlewares.clone()));
-
-                            let ctx = RequestCtx::new(req).with_remote_addr(remote_addr);
-
-                            let response = if let Some((group, combined_middlewares)) = matched_group {
-                                if combined_middlewares.is_empty() {
-                                    group.handle_request(ctx).await
-                                } else {
-                                    let endpoint = (move |ctx| {
-                                        let group = group.clone();
-                                        async move { group.handle_request(ctx).await }
-                                    })
-                                    .into_next();
-                                    execute_chain(combined_middlewares, endpoint, ctx).await
-                                }
-                            } else if !has_global_middleware {
-                                router.handle_request(ctx).await
-                            } else {
-                                let endpoint = (move |ctx| {
-                                    let router = router.clone();
-                                    async move { router.handle_request(ctx).await }
-                                })
-                                .into_next();
-                                execute_chain(global_middlewares, endpoint, ctx).await
-                            };
-
-                            Ok::<_, Infallible>(response)
-                        }
-                    }));
-
-                let fut = graceful.watch(conn);
-                tokio::spawn(async move {
-                    if let Err(err) = fut.await {
-                        eprintln!("Connection error {remote_addr}: {err:?}");
-                    }
-                });
+/// Where [`Engine::serve`] accepts connections
+enum Listen {
+    /// Addresses to bind when the startup mode says so
+    Addrs(Vec<SocketAddr>),
+    /// Sockets bound by the caller
+    Bound(Vec<TcpListener>),
+}
+
+impl Listen {
+    /// Bind the addresses, returning the listeners each of `workers` acceptor
+    /// tasks polls
+    async fn bind(self, workers: usize) -> Result<Arc<[Vec<TcpListener>]>, RunError> {
+        let listeners = match self {
+            Listen::Bound(listeners) => listeners,
+            Listen::Addrs(addrs) => {
+                let only_v6 = addrs.len() > 1;
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                if workers > 1 {
+                    return bind_reuse_port(&addrs, only_v6, workers);
+                }
+                let mut listeners = Vec::with_capacity(addrs.len());
+                for addr in addrs {
+                    listeners.push(bind(addr, only_v6).await?);
+                }
+                listeners
+            }
+        };
+        let mut sets = Vec::with_capacity(workers);
+        for _ in 1..workers {
+            sets.push(listeners.iter().map(share).collect::<Result<_, _>>()?);
+        }
+        sets.insert(0, listeners);
+        Ok(sets.into())
+    }
+}
+
+fn parse_addr(addr: &str) -> Result<SocketAddr, RunError> {
+    addr.parse::<SocketAddr>().map_err(|source| RunError::InvalidAddr {
+        addr: addr.to_string(),
+        source,
+    })
+}
+
+async fn bind(addr: SocketAddr, only_v6: bool) -> Result<TcpListener, RunError> {
+    let bound = if only_v6 && addr.is_ipv6() {
+        bind_v6_only(addr)
+    } else {
+        TcpListener::bind(addr).await
+    };
+    bound.map_err(|source| RunError::Bind { addr, source })
+}
+
+/// Bind an IPv6 socket that leaves IPv4 to another listener on the same port
+fn bind_v6_only(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    bind_socket(addr, |socket| socket.set_only_v6(true))
+}
+
+/// One `SO_REUSEPORT` socket per address for each worker, all on the same
+/// ports; the kernel spreads incoming connections over them
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_reuse_port(
+    addrs: &[SocketAddr],
+    only_v6: bool,
+    workers: usize,
+) -> Result<Arc<[Vec<TcpListener>]>, RunError> {
+    let mut addrs = addrs.to_vec();
+    // A plain bind fails if anything already listens on the port, which the
+    // workers' SO_REUSEPORT sockets would otherwise join
+    for addr in &mut addrs {
+        let probe = bind_socket(*addr, |socket| {
+            if only_v6 && addr.is_ipv6() {
+                socket.set_only_v6(true)?;
+            }
+            Ok(())
+        })
+        .map_err(|source| RunError::Bind { addr: *addr, source })?;
+        // Port 0: every worker uses the port the probe was given
+        *addr = probe.local_addr().unwrap_or(*addr);
+    }
+    let mut sets = Vec::with_capacity(workers);
+    for _ in 0..workers {
+        let mut listeners = Vec::with_capacity(addrs.len());
+        for addr in &mut addrs {
+            let listener = bind_socket(*addr, |socket| {
+                if only_v6 && addr.is_ipv6() {
+                    socket.set_only_v6(true)?;
+                }
+                socket.set_reuse_port(true)
+            })
+            .map_err(|source| RunError::Bind { addr: *addr, source })?;
+            listeners.push(listener);
+        }
+        sets.push(listeners);
+    }
+    Ok(sets.into())
+}
+
+/// Bind a listening socket with options `TcpListener::bind` doesn't offer
+fn bind_socket(
+    addr: SocketAddr,
+    configure: impl FnOnce(&socket2::Socket) -> std::io::Result<()>,
+) -> std::io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    configure(&socket)?;
+    // Same as `TcpListener::bind`: restarting must not wait out TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Another handle on the same listening socket, for an extra acceptor task
+fn share(listener: &TcpListener) -> Result<TcpListener, RunError> {
+    let addr = listener.local_addr().map_err(RunError::Accept)?;
+    socket2::SockRef::from(listener)
+        .try_clone()
+        .and_then(|socket| TcpListener::from_std(socket.into()))
+        .map_err(|source| RunError::Bind { addr, source })
+}
+
+/// `http://a, http://b` for the startup log
+fn urls(scheme: &str, listeners: &[TcpListener]) -> String {
+    listeners
+        .iter()
+        .filter_map(|listener| listener.local_addr().ok())
+        .map(|addr| format!("{scheme}://{addr}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Run a lifecycle hook and apply the hook failure policy to its outcome
+async fn check_hook(
+    policy: HookFailure,
+    phase: &'static str,
+    hook: Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send>>,
+) -> Result<(), RunError> {
+    let Err(source) = run_hook(hook).await else {
+        return Ok(());
+    };
+    log::error!("❌ {phase} hook failed: {source}");
+    match policy {
+        HookFailure::Fatal => Err(RunError::Hook { phase, source }),
+        HookFailure::Log => Ok(()),
+    }
+}
+
+/// Run every shutdown hook in registration order, even after one fails, reporting the first failure
+async fn run_shutdown_hooks(
+    hooks: &[LifecycleHook],
+    policy: HookFailure,
+    lifecycle: &LifecycleCtx,
+) -> Result<(), RunError> {
+    let mut failure = None;
+    for hook in hooks {
+        if let Err(e) = check_hook(policy, "shutdown", hook(lifecycle.clone())).await {
+            failure.get_or_insert(e);
+        }
+    }
+    failure.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ResponseBuilder, SecurityScheme, StatusCode};
+
+    async fn ok(_ctx: RequestCtx) -> Response {
+        ResponseBuilder::new().build("ok")
+    }
+
+    /// Serve `app` on a free port, returning its base URL
+    async fn spawn(app: Engine) -> (String, tokio::task::JoinHandle<Result<(), RunError>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        (base, tokio::spawn(app.run_with_listener(listener)))
+    }
+
+    // routes/users.rs and routes/posts.rs each register into the same group
+    fn users_routes(app: &mut Engine) {
+        let api = app.group("/api");
+        api.use_middleware(|ctx: RequestCtx, next: Next| async move { next(ctx).await });
+        api.get("/users", ok).get("/users/:id", ok);
+    }
+
+    fn posts_routes(app: &mut Engine) {
+        app.group("api/").get("/posts", ok).hide_from_docs();
+        app.group("/api").not_found(ok);
+    }
+
+    #[test]
+    fn test_repeated_group_calls_merge() {
+        let mut app = Engine::new();
+        users_routes(&mut app);
+        posts_routes(&mut app);
+        app.group("/admin").get("/", ok);
+
+        let routes: Vec<_> = app.routes().into_iter().map(|r| (r.pattern, r.hidden)).collect();
+        assert_eq!(
+            routes,
+            [
+                ("/admin/".to_string(), false),
+                ("/api/posts".to_string(), true),
+                ("/api/users".to_string(), false),
+                ("/api/users/:id".to_string(), false),
+            ]
+        );
+        assert_eq!(app.groups.len(), 2);
+        let api = &app.groups["/api"];
+        assert_eq!(api.middlewares.len(), 1);
+        assert!(api.router.not_found_handler().is_some());
+    }
+
+    #[test]
+    fn test_route_table() {
+        let mut app = Engine::new();
+        app.get("/", ok);
+        app.any("/echo", |_| async { "echo" }).name("echo");
+        app.group("/api").delete("/users/:id", ok).secured(SecurityScheme::Bearer).hide_from_docs();
+        let table = app.route_table();
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines.len(), 4, "{table}");
+        assert_eq!(lines[0], "METHOD  PATTERN         HANDLER");
+        assert!(lines[1].starts_with("GET     /               s_web::engine::tests::ok"), "{table}");
+        assert!(lines[2].starts_with("DELETE  /api/users/:id"), "{table}");
+        assert!(lines[2].ends_with("  group /api, auth bearerAuth, hidden"), "{table}");
+        assert!(lines[3].ends_with("test_route_table (closure)  name echo"), "{table}");
+    }
+
+    #[tokio::test]
+    async fn test_run_with_listener_and_run_many() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut app = Engine::new();
+        app.get("/", ok);
+        let server = tokio::spawn(app.run_with_listener(listener));
+        let response = crate::client::Client::new().get(&format!("http://{addr}/")).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+        server.abort();
+
+        assert!(matches!(Engine::new().run_many(&[]).await, Err(RunError::Config(_))));
+        assert!(matches!(
+            Engine::new().run_many(&["127.0.0.1:0", "nope"]).await,
+            Err(RunError::InvalidAddr { .. })
+        ));
+        // The port is taken, so nothing starts serving
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = taken.local_addr().unwrap();
+        let bind = Engine::new().run_many(&["127.0.0.1:0", &addr.to_string()]).await;
+        assert!(matches!(bind, Err(RunError::Bind { addr: failed, .. }) if failed == addr), "{bind:?}");
+    }
+
+    #[tokio::test]
+    async fn test_body_limits() {
+        let app = Engine::default();
+        assert_eq!((app.body_limit, app.workers), (crate::DEFAULT_BODY_LIMIT, 1));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let echo = |mut ctx: RequestCtx| async move { Ok::<_, crate::Error>(ctx.body_bytes().await?.map_or(0, |b| b.len()).to_string()) };
+        let mut app = Engine::default();
+        app.body_limit(16);
+        app.post("/small", echo);
+        app.post("/large", echo).body_limit(1024);
+        let server = tokio::spawn(app.run_with_listener(listener));
+
+        let client = crate::client::Client::new();
+        let post = |path: &str, len: usize| client.post(&format!("{base}{path}")).body(vec![b'x'; len]).send();
+        let response = post("/small", 16).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "16");
+        assert_eq!(post("/small", 17).await.unwrap().status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(post("/large", 1000).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(post("/large", 1025).await.unwrap().status(), StatusCode::PAYLOAD_TOO_LARGE);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_method_not_allowed() {
+        let mut app = Engine::new();
+        app.get("/users/:id", ok).delete("/users/:id", ok);
+        app.group("/admin").post("/jobs", ok);
+        let (base, server) = spawn(app).await;
+        let client = crate::client::Client::new();
+
+        let response = client.post(&format!("{base}/users/7")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()["allow"], "DELETE, GET, HEAD, OPTIONS");
+        let response = client.get(&format!("{base}/admin/jobs")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()["allow"], "OPTIONS, POST");
+        server.abort();
+
+        // A custom hook sees the supported methods and serves groups too
+        let mut app = Engine::new();
+        app.get("/users/:id", ok);
+        app.group("/admin").post("/jobs", ok);
+        app.method_not_allowed(|ctx: RequestCtx| async move {
+            let allowed = ctx.get::<crate::AllowedMethods>().unwrap().0.join(" ");
+            ResponseBuilder::new().status(StatusCode::METHOD_NOT_ALLOWED).build(format!("try {allowed}"))
+        });
+        let (base, server) = spawn(app).await;
+
+        let response = client.delete(&format!("{base}/users/7")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()["allow"], "GET, HEAD, OPTIONS");
+        assert_eq!(response.text().await.unwrap(), "try GET HEAD OPTIONS");
+        let response = client.get(&format!("{base}/admin/jobs")).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "try OPTIONS POST");
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_not_found_handlers() {
+        let mut app = Engine::new();
+        app.get("/", ok);
+        app.not_found(|ctx: RequestCtx| async move {
+            let path = ctx.request.uri().path().to_string();
+            (StatusCode::NOT_FOUND, crate::Json(serde_json::json!({ "missing": path })))
+        });
+        app.group("/api")
+            .get("/users", ok)
+            .not_found(|_| async { (StatusCode::NOT_FOUND, "no such endpoint") });
+        app.group("/admin").get("/", ok);
+        let (base, server) = spawn(app).await;
+        let client = crate::client::Client::new();
+
+        let response = client.get(&format!("{base}/nope")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["missing"], "/nope");
+        // Groups fall back to the engine's handler unless they set their own
+        let response = client.get(&format!("{base}/admin/nope")).send().await.unwrap();
+        assert_eq!(response.json::<serde_json::Value>().await.unwrap()["missing"], "/admin/nope");
+        let response = client.get(&format!("{base}/api/nope")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.text().await.unwrap(), "no such endpoint");
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_preflight_checks() {
+        let ran = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let record = |name: &'static str, pass: bool| {
+            let ran = ran.clone();
+            move || {
+                ran.lock().unwrap().push(name);
+                async move { if pass { Ok(()) } else { Err("unreachable database") } }
             }
-            _ = tokio::signal::ctrl_c() => {
-                drop(listener);
-                eprintln!("\n🛑 Graceful shutdown signal received");
-                return;
+        };
+
+        // A failing check stops startup before later checks and startup hooks, and before
+        // the bind, which would fail on this taken port with RunError::Bind instead
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = taken.local_addr().unwrap().to_string();
+        let app = Engine::new()
+            .preflight("config", record("config", true))
+            .preflight("database", record("database", false))
+            .preflight("cache", record("cache", true))
+            .on_startup(record("startup", true));
+        let Err(RunError::Preflight(err)) = app.run(&addr).await else {
+            panic!("preflight failure should stop run");
+        };
+        assert_eq!(err.check, "database");
+        assert_eq!(err.to_string(), "preflight check `database` failed: unreachable database");
+        assert_eq!(*ran.lock().unwrap(), ["config", "database"]);
+
+        ran.lock().unwrap().clear();
+        let mut app = Engine::new()
+            .preflight("config", record("config", true))
+            .on_startup(record("startup", true));
+        app.get("/", ok);
+        let (base, server) = spawn(app).await;
+        let response = crate::client::Client::new().get(&format!("{base}/")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*ran.lock().unwrap(), ["config", "startup"]);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_hooks_run_in_order() {
+        let ran = Arc::new(std::sync::Mutex::new(Vec::new()));
+        // Each hook records what it sees; the first registers the state the others read
+        let hook = |name: &'static str, fail: bool| {
+            let ran = ran.clone();
+            move |ctx: LifecycleCtx| {
+                let state = ctx.get::<&'static str>().unwrap_or("-");
+                let stopping = ctx.shutdown_token().is_shutdown();
+                ran.lock().unwrap().push(format!("{name}: state {state}, stopping {stopping}"));
+                if name == "startup 1" {
+                    ctx.insert("pool");
+                }
+                async move { if fail { Err("close failed") } else { Ok(()) } }
             }
+        };
+        let app = Engine::new()
+            .on_startup_with(hook("startup 1", false))
+            .on_startup_with(hook("startup 2", false))
+            .on_shutdown_with(hook("shutdown 1", true))
+            .on_shutdown_with(hook("shutdown 2", false));
+
+        let (shutdown_tx, token) = ShutdownToken::new();
+        let lifecycle = LifecycleCtx::new(StateMap::new(), token);
+        app.start(&lifecycle).await.unwrap();
+        let _ = shutdown_tx.send(true);
+        // A failed shutdown hook doesn't stop the ones after it, but is reported
+        let stopped = run_shutdown_hooks(&app.shutdown_hooks, app.hook_failures, &lifecycle).await;
+        assert!(matches!(stopped, Err(RunError::Hook { phase: "shutdown", .. })), "{stopped:?}");
+        assert_eq!(
+            *ran.lock().unwrap(),
+            [
+                "startup 1: state -, stopping false",
+                "startup 2: state pool, stopping false",
+                "shutdown 1: state pool, stopping true",
+                "shutdown 2: state pool, stopping true",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_startup_modes() {
+        // Startup hooks that wait until the test lets them finish
+        let gated = |mode: StartupMode| {
+            let (release, gate) = tokio::sync::watch::channel(false);
+            let mut app = Engine::new().on_startup(move || {
+                let mut gate = gate.clone();
+                async move {
+                    let _ = gate.wait_for(|open| *open).await;
+                }
+            });
+            app.get("/", ok).startup_mode(mode);
+            (app, release)
+        };
+        let client = crate::client::Client::new();
+        // Startup finishes in the background; poll until the route answers 200
+        async fn ready(client: &crate::client::Client, url: &str) {
+            let poll = async {
+                loop {
+                    match client.get(url).send().await {
+                        Ok(response) if response.status() == StatusCode::OK => break,
+                        _ => tokio::time::sleep(Duration::from_millis(10)).await,
+                    }
+                }
+            };
+            tokio::time::timeout(Duration::from_secs(5), poll).await.unwrap();
         }
+
+        // Nothing listens on the port until startup completes
+        let free = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let (app, release) = gated(StartupMode::BindWhenReady);
+        let server = tokio::spawn(async move { app.run(&free.to_string()).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(tokio::net::TcpStream::connect(free).await.is_err());
+        release.send(true).unwrap();
+        ready(&client, &format!("http://{free}/")).await;
+        server.abort();
+
+        // The port answers at once, with 503 until startup completes
+        let (app, release) = gated(StartupMode::ServeUnavailable);
+        let (base, server) = spawn(app).await;
+        let response = client.get(&format!("{base}/")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], "5");
+        assert_eq!(response.headers()["connection"], "close");
+        release.send(true).unwrap();
+        ready(&client, &format!("{base}/")).await;
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_workers() {
+        // Every worker listens on the port the first one was given
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let workers = Listen::Addrs(vec![addr]).bind(3).await.unwrap();
+        let port = workers[0][0].local_addr().unwrap().port();
+        assert_eq!(workers.len(), 3);
+        assert!(workers.iter().all(|set| set[0].local_addr().unwrap().port() == port));
+
+        // Another process's SO_REUSEPORT socket on the port is not joined
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            let other = bind_socket(addr, |socket| socket.set_reuse_port(true)).unwrap();
+            let taken = other.local_addr().unwrap();
+            let bind = Listen::Addrs(vec![taken]).bind(2).await;
+            assert!(matches!(bind, Err(RunError::Bind { addr, .. }) if addr == taken), "{bind:?}");
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let mut app = Engine::new();
+        app.get("/", ok).workers(4);
+        let server = tokio::spawn(app.run_with_listener(listener));
+        let client = crate::client::Client::new();
+        let requests = (0..32).map(|_| {
+            let request = client.get(&url);
+            tokio::spawn(async move { request.send().await.unwrap().text().await.unwrap() })
+        });
+        for response in futures_util::future::join_all(requests).await {
+            assert_eq!(response.unwrap(), "ok");
+        }
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_routes_handle_at_runtime() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let mut app = Engine::new();
+        app.get("/", ok);
+        let routes = app.routes_handle();
+        routes.add("GET", "/early", ok);
+        let server = tokio::spawn(app.run_with_listener(listener));
+
+        let client = crate::client::Client::new();
+        let status = |path: &'static str| {
+            let request = client.get(&format!("{base}{path}"));
+            async move { request.send().await.unwrap().status() }
+        };
+        assert_eq!(status("/early").await, StatusCode::OK);
+        assert_eq!(status("/plugin/1").await, StatusCode::NOT_FOUND);
+
+        routes.add("GET", "/plugin/:id", ok);
+        assert_eq!(status("/plugin/1").await, StatusCode::OK);
+        assert!(routes.routes().iter().any(|r| r.pattern == "/plugin/:id"));
+
+        assert!(routes.remove("GET", "/plugin/:id"));
+        assert!(!routes.remove("GET", "/plugin/:id"));
+        assert!(routes.remove("GET", "/"));
+        assert_eq!(status("/plugin/1").await, StatusCode::NOT_FOUND);
+        assert_eq!(status("/").await, StatusCode::NOT_FOUND);
+        assert_eq!(status("/early").await, StatusCode::OK);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_group_swagger_defaults() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/docs/swagger.json", listener.local_addr().unwrap());
+        let mut app = Engine::new();
+        app.enable_swagger();
+        let admin = app.group("/admin");
+        admin.swagger_defaults(crate::swagger().tag("admin").bearer_auth().build());
+        admin.get_with_swagger("/users", ok, crate::swagger().summary("List users").tag("users").build());
+        admin.delete("/users/:id", ok);
+        app.get("/public", ok);
+        let server = tokio::spawn(app.run_with_listener(listener));
+
+        let doc: serde_json::Value = crate::client::Client::new().get(&url).send().await.unwrap().json().await.unwrap();
+        let list = &doc["paths"]["/admin/users"]["get"];
+        assert_eq!(list["summary"], "List users");
+        assert_eq!(list["tags"], serde_json::json!(["admin", "users"]));
+        assert_eq!(list["security"], serde_json::json!([{ "bearerAuth": [] }]));
+        assert!(list["responses"]["401"].is_object());
+        let delete = &doc["paths"]["/admin/users/{id}"]["delete"];
+        assert_eq!(delete["tags"], serde_json::json!(["admin"]));
+        assert_eq!(delete["security"], serde_json::json!([{ "bearerAuth": [] }]));
+        assert!(doc["paths"]["/public"]["get"].get("security").is_none());
+        server.abort();
+    }
+
+    #[test]
+    fn test_url_for_named_routes() {
+        let mut app = Engine::new();
+        app.get("/users/:id", ok).name("user_detail");
+        app.group("/api").get("/posts/:post/comments/:id", ok).name("comment");
+        assert_eq!(app.url_for("user_detail", &[("id", "42")]).as_deref(), Some("/users/42"));
+        assert_eq!(
+            app.url_for("comment", &[("id", "3"), ("post", "hello world")]).as_deref(),
+            Some("/api/posts/hello%20world/comments/3")
+        );
+        assert_eq!(app.url_for("user_detail", &[]), None);
+        assert_eq!(app.url_for("missing", &[]), None);
     }
 }
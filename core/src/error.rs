@@ -0,0 +1,324 @@
+//! Handler error type rendered as an HTTP response.
+
+use std::{convert::Infallible, fmt, sync::Arc};
+
+use crate::{
+    BodyTimeout, BoxError, IntoResponse, ParamError, PayloadTooLarge, Response, ResponseBuilder, StatusCode,
+    UrlEncodedError, log,
+    patch::PatchError,
+};
+
+/// An error answered with a status and an optional message for the client.
+///
+/// The source of a `5xx`, if any, is logged but never sent. Errors
+/// implementing [`HttpError`] keep their status (`ParamError` answers 400, a
+/// too large body 413); a `BoxError` or common library errors answer a plain
+/// `500 Internal Server Error`. `Engine::error_handler` can render every
+/// `Error` differently, e.g. as JSON.
+///
+/// ```rust,ignore
+/// async fn get_user(mut ctx: RequestCtx) -> Result<Json<User>, s_web::Error> {
+///     let id: u64 = ctx.param("id")?;
+///     let user = db::find(id).await.map_err(s_web::Error::internal)?;
+///     user.map(Json).ok_or_else(|| s_web::Error::new(StatusCode::NOT_FOUND, "no such user"))
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Error {
+    status: StatusCode,
+    message: Option<String>,
+    source: Option<BoxError>,
+}
+
+impl Error {
+    /// Answer `status` with `message` shown to the client.
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: Some(message.into()),
+            source: None,
+        }
+    }
+
+    /// Answer `status` with only its reason phrase.
+    pub fn from_status(status: StatusCode) -> Self {
+        Self {
+            status,
+            message: None,
+            source: None,
+        }
+    }
+
+    /// `500 Internal Server Error` caused by `source`, which is logged but not sent.
+    pub fn internal(source: impl Into<BoxError>) -> Self {
+        Self::from_status(StatusCode::INTERNAL_SERVER_ERROR).with_source(source)
+    }
+
+    /// Attach the underlying cause, for logging and `error_handler`.
+    pub fn with_source(mut self, source: impl Into<BoxError>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Message shown to the client, if any.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    pub fn source(&self) -> Option<&(dyn std::error::Error + Send + Sync + 'static)> {
+        self.source.as_deref()
+    }
+}
+
+/// An error type that knows the status it should be answered with.
+///
+/// Handlers returning `Result<T, E>` for such an `E` (directly or converted
+/// into [`Error`] with `?`) answer with that status instead of `500`:
+///
+/// ```rust,ignore
+/// #[derive(Debug)]
+/// enum ArticleError { NotFound, Conflict(String), Db(sqlx::Error) }
+/// // impl Display and std::error::Error ...
+///
+/// impl HttpError for ArticleError {
+///     fn status(&self) -> StatusCode {
+///         match self {
+///             Self::NotFound => StatusCode::NOT_FOUND,
+///             Self::Conflict(_) => StatusCode::CONFLICT,
+///             Self::Db(_) => StatusCode::INTERNAL_SERVER_ERROR,
+///         }
+///     }
+/// }
+///
+/// async fn get_article(ctx: RequestCtx) -> Result<Json<Article>, ArticleError> { ... }
+/// ```
+///
+/// The status is lost once the error is boxed into a `BoxError`.
+pub trait HttpError: std::error::Error + Send + Sync + 'static {
+    /// Status to answer with (default `500`).
+    fn status(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    /// Message sent to the client: by default the `Display` text for client
+    /// errors and nothing for server errors, which are only logged.
+    fn message(&self) -> Option<String> {
+        (!self.status().is_server_error()).then(|| self.to_string())
+    }
+}
+
+impl<E: HttpError> From<E> for Error {
+    fn from(err: E) -> Self {
+        Self {
+            status: err.status(),
+            message: err.message(),
+            source: Some(Box::new(err)),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status.as_u16(), self.status.canonical_reason().unwrap_or(""))?;
+        if let Some(message) = &self.message {
+            write!(f, ": {message}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the status of the framework's own errors; anything else is a 500
+impl From<BoxError> for Error {
+    fn from(err: BoxError) -> Self {
+        let err = match err.downcast::<ParamError>() {
+            Ok(err) => return (*err).into(),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<PayloadTooLarge>() {
+            Ok(err) => return (*err).into(),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<BodyTimeout>() {
+            Ok(err) => return (*err).into(),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<UrlEncodedError>() {
+            Ok(err) => return (*err).into(),
+            Err(err) => err,
+        };
+        match err.downcast::<PatchError>() {
+            Ok(err) => (*err).into(),
+            Err(err) => Self::internal(err),
+        }
+    }
+}
+
+/// Errors of common libraries, answered with a plain 500
+macro_rules! internal_errors {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl From<$ty> for Error {
+                fn from(err: $ty) -> Self {
+                    Self::internal(err)
+                }
+            }
+        )+
+    };
+}
+
+internal_errors!(
+    &'static str,
+    String,
+    std::io::Error,
+    std::fmt::Error,
+    serde_json::Error,
+    hyper::Error,
+    hyper::http::Error,
+);
+
+impl From<Infallible> for Error {
+    fn from(err: Infallible) -> Self {
+        match err {}
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        BoxError::from(err).into()
+    }
+}
+
+impl HttpError for ParamError {
+    fn status(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+impl HttpError for UrlEncodedError {
+    fn status(&self) -> StatusCode {
+        match self {
+            UrlEncodedError::Invalid { .. } => StatusCode::BAD_REQUEST,
+            UrlEncodedError::WrongContentType { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        }
+    }
+}
+
+impl HttpError for BodyTimeout {
+    fn status(&self) -> StatusCode {
+        StatusCode::REQUEST_TIMEOUT
+    }
+}
+
+impl HttpError for PayloadTooLarge {
+    fn status(&self) -> StatusCode {
+        StatusCode::PAYLOAD_TOO_LARGE
+    }
+
+    fn message(&self) -> Option<String> {
+        None
+    }
+}
+
+impl HttpError for PatchError {
+    fn status(&self) -> StatusCode {
+        match self {
+            PatchError::TestFailed { .. } => StatusCode::CONFLICT,
+            _ => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+/// The error behind a response, kept in its extensions for `Engine::error_handler`
+#[derive(Debug, Clone)]
+pub(crate) struct ErrorReport(pub(crate) Arc<Error>);
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        if self.status.is_server_error()
+            && let Some(source) = &self.source
+        {
+            log::warn!("handler error: {source:?}");
+        }
+        let mut response = ResponseBuilder::new()
+            .status(self.status)
+            .content_type("text/plain; charset=utf-8")
+            .build(self.to_string());
+        response.extensions_mut().insert(ErrorReport(Arc::new(self)));
+        response
+    }
+}
+
+/// Renders handler errors for `Engine::error_handler`
+pub(crate) type ErrorHandler = Arc<dyn Fn(&Error) -> Response + Send + Sync>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    enum ArticleError {
+        NotFound,
+        Db,
+    }
+
+    impl fmt::Display for ArticleError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::NotFound => write!(f, "no such article"),
+                Self::Db => write!(f, "connection refused"),
+            }
+        }
+    }
+
+    impl std::error::Error for ArticleError {}
+
+    impl HttpError for ArticleError {
+        fn status(&self) -> StatusCode {
+            match self {
+                Self::NotFound => StatusCode::NOT_FOUND,
+                Self::Db => StatusCode::SERVICE_UNAVAILABLE,
+            }
+        }
+    }
+
+    #[test]
+    fn test_conversions_keep_status_and_hide_sources() {
+        let err = Error::from(std::io::Error::other("disk on fire"));
+        assert_eq!(err.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(err.to_string(), "500 Internal Server Error");
+        assert!(err.source().is_some());
+
+        let boxed: BoxError = ParamError::Missing { name: "id".into() }.into();
+        assert_eq!(Error::from(boxed).status(), StatusCode::BAD_REQUEST);
+
+        let boxed: BoxError = PayloadTooLarge { limit: 10 }.into();
+        assert_eq!(Error::from(boxed).to_string(), "413 Payload Too Large");
+
+        let err = Error::new(StatusCode::NOT_FOUND, "no such user");
+        assert_eq!(err.to_string(), "404 Not Found: no such user");
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(response.extensions().get::<ErrorReport>().is_some());
+    }
+
+    #[test]
+    fn test_http_errors_carry_their_status() {
+        let response = Err::<&str, _>(ArticleError::NotFound).into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let report = &response.extensions().get::<ErrorReport>().unwrap().0;
+        assert_eq!(report.to_string(), "404 Not Found: no such article");
+
+        // Server errors keep their status but not their message
+        let err = Error::from(ArticleError::Db);
+        assert_eq!(err.to_string(), "503 Service Unavailable");
+        assert_eq!(err.source().unwrap().to_string(), "connection refused");
+
+        let response = Err::<&str, _>("plain failure").into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -21,4 +21,17 @@ where
         let fut = (self)(ctx);
         Box::pin(async move { fut.await.into_response() })
     }
-}
\ No newline at end of file
+}
+
+/// Handlers built at runtime, e.g. by a plugin system, register like any other.
+impl Handler for Box<dyn Handler> {
+    fn handle(&self, ctx: RequestCtx) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+        (**self).handle(ctx)
+    }
+}
+
+impl Handler for std::sync::Arc<dyn Handler> {
+    fn handle(&self, ctx: RequestCtx) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+        (**self).handle(ctx)
+    }
+}
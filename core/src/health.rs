@@ -0,0 +1,380 @@
+//! Dependency health checks with degradation levels.
+//!
+//! Probes are registered as either critical or soft dependencies. A failing
+//! soft dependency only degrades the service, while a failing critical one
+//! makes it unhealthy; routes can refuse traffic up front with
+//! `.requires_dependency("db")` instead of erroring deep inside the handler.
+//!
+//! ```rust,ignore
+//! app.health().critical("db", move || {
+//!     let pool = pool.clone();
+//!     async move {
+//!         match sqlx::query("SELECT 1").execute(&pool).await {
+//!             Ok(_) => HealthStatus::Healthy,
+//!             Err(e) => HealthStatus::Unhealthy(e.to_string()),
+//!         }
+//!     }
+//! });
+//! app.get("/orders", list_orders).requires_dependency("db");
+//! ```
+//!
+//! `Engine::health_check("/healthz")` serves the aggregated report as JSON:
+//! `/healthz` and `/healthz/ready` answer 503 while a critical dependency is
+//! down, `/healthz/live` only says the process is answering requests.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use futures_util::future::join_all;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{Handler, IntoResponse, Json, Next, RequestCtx, Response, ResponseBuilder, StatusCode};
+
+/// Result of a single probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthStatus {
+    Healthy,
+    /// Working, but with reduced capacity or features.
+    Degraded(String),
+    /// Not usable.
+    Unhealthy(String),
+}
+
+impl HealthStatus {
+    fn level(&self) -> u8 {
+        match self {
+            HealthStatus::Healthy => 0,
+            HealthStatus::Degraded(_) => 1,
+            HealthStatus::Unhealthy(_) => 2,
+        }
+    }
+
+    /// Short label used in reports: `healthy`, `degraded` or `unhealthy`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HealthStatus::Healthy => "healthy",
+            HealthStatus::Degraded(_) => "degraded",
+            HealthStatus::Unhealthy(_) => "unhealthy",
+        }
+    }
+
+    fn message(&self) -> Option<&str> {
+        match self {
+            HealthStatus::Healthy => None,
+            HealthStatus::Degraded(msg) | HealthStatus::Unhealthy(msg) => Some(msg),
+        }
+    }
+}
+
+/// `Ok` is healthy, `Err` unhealthy with the error as message, so probes can
+/// end in `.await.into()`.
+impl<T, E: std::fmt::Display> From<Result<T, E>> for HealthStatus {
+    fn from(result: Result<T, E>) -> Self {
+        match result {
+            Ok(_) => HealthStatus::Healthy,
+            Err(e) => HealthStatus::Unhealthy(e.to_string()),
+        }
+    }
+}
+
+type ProbeFn = Box<dyn Fn() -> Pin<Box<dyn Future<Output = HealthStatus> + Send>> + Send + Sync>;
+
+struct Probe {
+    name: String,
+    critical: bool,
+    probe: ProbeFn,
+    last: Mutex<Option<(Instant, HealthStatus)>>,
+}
+
+/// Status of one dependency inside a [`HealthReport`].
+#[derive(Debug, Clone, Serialize)]
+pub struct DependencyReport {
+    pub name: String,
+    pub critical: bool,
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// Aggregated result of all probes.
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub status: &'static str,
+    pub dependencies: Vec<DependencyReport>,
+    /// Extra facts published by subsystems (e.g. applied migrations).
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub info: Map<String, Value>,
+}
+
+/// Shared registry of named dependency probes.
+///
+/// Cloning is cheap; all clones see the same probes. Results are cached for a
+/// short time so per-request guards don't hammer the dependencies.
+#[derive(Clone)]
+pub struct HealthRegistry {
+    probes: Arc<RwLock<Vec<Arc<Probe>>>>,
+    cache_ttl: Arc<RwLock<Duration>>,
+    probe_timeout: Arc<RwLock<Duration>>,
+    info: Arc<RwLock<Map<String, Value>>>,
+    /// Registry of the engine an `App` was mounted on; checks are answered there
+    merged_into: Arc<RwLock<Option<HealthRegistry>>>,
+}
+
+impl Default for HealthRegistry {
+    fn default() -> Self {
+        Self {
+            probes: Arc::new(RwLock::new(Vec::new())),
+            cache_ttl: Arc::new(RwLock::new(Duration::from_secs(1))),
+            probe_timeout: Arc::new(RwLock::new(Duration::from_secs(5))),
+            info: Arc::new(RwLock::new(Map::new())),
+            merged_into: Arc::new(RwLock::new(None)),
+        }
+    }
+}
+
+impl HealthRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a dependency whose failure makes the whole service unhealthy.
+    pub fn critical<F, Fut>(&self, name: &str, probe: F) -> &Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HealthStatus> + Send + 'static,
+    {
+        self.register(name, true, probe)
+    }
+
+    /// Register a dependency whose failure only degrades the service.
+    pub fn soft<F, Fut>(&self, name: &str, probe: F) -> &Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HealthStatus> + Send + 'static,
+    {
+        self.register(name, false, probe)
+    }
+
+    /// How long a probe result is reused before the probe runs again (default 1s).
+    pub fn cache_for(&self, ttl: Duration) -> &Self {
+        *self.cache_ttl.write().unwrap_or_else(|e| e.into_inner()) = ttl;
+        self
+    }
+
+    /// Probes still running after this long count as unhealthy (default 5s).
+    pub fn probe_timeout(&self, timeout: Duration) -> &Self {
+        *self.probe_timeout.write().unwrap_or_else(|e| e.into_inner()) = timeout;
+        self
+    }
+
+    /// Publish an informational entry in the health report.
+    pub fn set_info(&self, key: &str, value: Value) -> &Self {
+        self.info
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key.to_string(), value);
+        self
+    }
+
+    fn register<F, Fut>(&self, name: &str, critical: bool, probe: F) -> &Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HealthStatus> + Send + 'static,
+    {
+        let probe = Probe {
+            name: name.to_string(),
+            critical,
+            probe: Box::new(move || Box::pin(probe())),
+            last: Mutex::new(None),
+        };
+        let mut probes = self.probes.write().unwrap_or_else(|e| e.into_inner());
+        probes.retain(|p| p.name != name);
+        probes.push(Arc::new(probe));
+        self
+    }
+
+    fn find(&self, name: &str) -> Option<Arc<Probe>> {
+        self.probes
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|p| p.name == name)
+            .cloned()
+    }
+
+    async fn run(&self, probe: &Probe) -> HealthStatus {
+        let ttl = *self.cache_ttl.read().unwrap_or_else(|e| e.into_inner());
+        if let Some((at, status)) = probe.last.lock().unwrap_or_else(|e| e.into_inner()).as_ref()
+            && at.elapsed() < ttl
+        {
+            return status.clone();
+        }
+        let timeout = *self.probe_timeout.read().unwrap_or_else(|e| e.into_inner());
+        let status = tokio::time::timeout(timeout, (probe.probe)())
+            .await
+            .unwrap_or_else(|_| HealthStatus::Unhealthy(format!("probe timed out after {timeout:?}")));
+        *probe.last.lock().unwrap_or_else(|e| e.into_inner()) = Some((Instant::now(), status.clone()));
+        status
+    }
+
+    /// Move probes and info into `target` and answer later checks from it, so
+    /// guards registered on a mounted `App` see the engine's dependencies.
+    pub(crate) fn merge_into(&self, target: &HealthRegistry) {
+        let probes = std::mem::take(&mut *self.probes.write().unwrap_or_else(|e| e.into_inner()));
+        {
+            let mut target_probes = target.probes.write().unwrap_or_else(|e| e.into_inner());
+            for probe in probes {
+                target_probes.retain(|p| p.name != probe.name);
+                target_probes.push(probe);
+            }
+        }
+        let info = std::mem::take(&mut *self.info.write().unwrap_or_else(|e| e.into_inner()));
+        target.info.write().unwrap_or_else(|e| e.into_inner()).extend(info);
+        *self.merged_into.write().unwrap_or_else(|e| e.into_inner()) = Some(target.clone());
+    }
+
+    /// Check a single dependency; `None` if no probe has that name.
+    pub async fn check(&self, name: &str) -> Option<HealthStatus> {
+        let merged_into = self.merged_into.read().unwrap_or_else(|e| e.into_inner()).clone();
+        if let Some(target) = merged_into {
+            return Box::pin(target.check(name)).await;
+        }
+        let probe = self.find(name)?;
+        Some(self.run(&probe).await)
+    }
+
+    /// Run every probe concurrently and aggregate the overall status.
+    pub async fn report(&self) -> HealthReport {
+        let probes: Vec<_> = self
+            .probes
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let statuses = join_all(probes.iter().map(|probe| self.run(probe))).await;
+        let mut overall = HealthStatus::Healthy;
+        let mut dependencies = Vec::with_capacity(probes.len());
+        for (probe, status) in probes.iter().zip(statuses) {
+            // A failing soft dependency can degrade the service but never takes it down.
+            let effective = match (&status, probe.critical) {
+                (HealthStatus::Unhealthy(msg), false) => HealthStatus::Degraded(msg.clone()),
+                _ => status.clone(),
+            };
+            if effective.level() > overall.level() {
+                overall = effective;
+            }
+            dependencies.push(DependencyReport {
+                name: probe.name.clone(),
+                critical: probe.critical,
+                status: status.as_str(),
+                message: status.message().map(str::to_owned),
+            });
+        }
+        HealthReport {
+            status: overall.as_str(),
+            dependencies,
+            info: self.info.read().unwrap_or_else(|e| e.into_inner()).clone(),
+        }
+    }
+
+    /// Handler answering the [`report`](Self::report) as JSON, with 503 while
+    /// the service is unhealthy (degraded still answers 200).
+    pub fn handler(&self) -> impl Handler {
+        let registry = self.clone();
+        move |_ctx: RequestCtx| {
+            let registry = registry.clone();
+            async move {
+                let report = registry.report().await;
+                let status = match report.status {
+                    "unhealthy" => StatusCode::SERVICE_UNAVAILABLE,
+                    _ => StatusCode::OK,
+                };
+                let mut response = (status, Json(report)).into_response();
+                response
+                    .headers_mut()
+                    .insert(hyper::header::CACHE_CONTROL, hyper::header::HeaderValue::from_static("no-store"));
+                response
+            }
+        }
+    }
+}
+
+/// Liveness handler: the process is up and answering, whatever its dependencies say
+pub(crate) async fn alive(_ctx: RequestCtx) -> Response {
+    let mut response = Json(serde_json::json!({ "status": "alive" })).into_response();
+    response
+        .headers_mut()
+        .insert(hyper::header::CACHE_CONTROL, hyper::header::HeaderValue::from_static("no-store"));
+    response
+}
+
+/// Middleware returning 503 while the named dependency is unhealthy.
+///
+/// Degraded dependencies still let requests through. Unknown names are ignored.
+pub fn requires_dependency(
+    registry: HealthRegistry,
+    name: &str,
+) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>> + Send + Sync + 'static
+{
+    let name: Arc<str> = Arc::from(name);
+    move |ctx, next| {
+        let registry = registry.clone();
+        let name = name.clone();
+        Box::pin(async move {
+            match registry.check(&name).await {
+                Some(HealthStatus::Unhealthy(_)) => ResponseBuilder::new()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .content_type("text/plain; charset=utf-8")
+                    .with_header("Retry-After", "5")
+                    .build(format!("503 Service Unavailable: dependency `{name}` is down")),
+                _ => next(ctx).await,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_soft_failure_only_degrades() {
+        let registry = HealthRegistry::new();
+        registry
+            .critical("db", || async { HealthStatus::Healthy })
+            .soft("cache", || async { HealthStatus::Unhealthy("timeout".into()) });
+
+        let report = registry.report().await;
+        assert_eq!(report.status, "degraded");
+        assert_eq!(report.dependencies[1].status, "unhealthy");
+
+        registry.critical("db", || async { HealthStatus::Unhealthy("down".into()) });
+        assert_eq!(registry.report().await.status, "unhealthy");
+    }
+
+    #[tokio::test]
+    async fn test_handler_reports_hung_probe_as_unhealthy() {
+        use http_body_util::BodyExt;
+
+        let registry = HealthRegistry::new();
+        registry
+            .probe_timeout(Duration::from_millis(20))
+            .soft("cache", || async { Ok::<_, std::io::Error>(()).into() })
+            .critical("db", || async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                HealthStatus::Healthy
+            });
+
+        let ctx = RequestCtx::from_parts(hyper::Request::get("/healthz").body(()).unwrap().into_parts().0);
+        let response = registry.handler().handle(ctx).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let report: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["dependencies"][0]["status"], "healthy");
+        assert_eq!(report["dependencies"][1]["message"], "probe timed out after 20ms");
+    }
+}
@@ -0,0 +1,453 @@
+//! Resized/cropped image serving with cache headers and signed URLs (`image` feature).
+//!
+//! ```rust,ignore
+//! use s_web::image::{FsStorage, ImageServer};
+//!
+//! let images = ImageServer::new(FsStorage::new("./uploads")).secret("change-me");
+//! // GET /images/cat.png?w=320&h=240&fit=crop&s=<signature>
+//! app.get("/images/*path", images);
+//! ```
+
+use std::{
+    future::Future,
+    io::Cursor,
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+};
+
+use ::image::{DynamicImage, ImageFormat, ImageReader, Limits, imageops::FilterType};
+use hmac::{Hmac, Mac};
+use hyper::body::Bytes;
+use sha2::{Digest, Sha256};
+
+use crate::{Handler, RequestCtx, Response, ResponseBuilder, StatusCode};
+
+/// Source of original images, keyed by the wildcard path of the route.
+pub trait ImageStorage: Send + Sync + 'static {
+    fn load(&self, key: &str) -> Pin<Box<dyn Future<Output = Option<Bytes>> + Send + '_>>;
+}
+
+/// Loads images from a directory on the local filesystem.
+pub struct FsStorage {
+    root: PathBuf,
+}
+
+impl FsStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl ImageStorage for FsStorage {
+    fn load(&self, key: &str) -> Pin<Box<dyn Future<Output = Option<Bytes>> + Send + '_>> {
+        // Only plain relative segments are allowed so `../` can't escape the root.
+        let relative = Path::new(key);
+        let safe = relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+        let path = self.root.join(relative);
+        Box::pin(async move {
+            if !safe {
+                return None;
+            }
+            tokio::fs::read(path).await.ok().map(Bytes::from)
+        })
+    }
+}
+
+/// How the image is fitted into the requested box.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fit {
+    /// Scale to fit inside the box, preserving aspect ratio.
+    #[default]
+    Contain,
+    /// Scale to cover the box and crop the overflow.
+    Crop,
+}
+
+/// Transform requested through the query string (`w`, `h`, `fit`, `fmt`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transform {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Fit,
+    pub format: Option<ImageFormat>,
+}
+
+impl Transform {
+    /// Parse a transform from the request's query string.
+    pub fn from_ctx(ctx: &RequestCtx) -> Result<Self, String> {
+        let dimension = |key: &str| -> Result<Option<u32>, String> {
+            match ctx.query_param(key) {
+                Some(v) => v
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .map(Some)
+                    .ok_or_else(|| format!("invalid `{key}` parameter")),
+                None => Ok(None),
+            }
+        };
+        let fit = match ctx.query_param("fit").as_deref() {
+            None | Some("contain") => Fit::Contain,
+            Some("crop") => Fit::Crop,
+            Some(other) => return Err(format!("unsupported fit `{other}`")),
+        };
+        let format = match ctx.query_param("fmt") {
+            Some(ext) => Some(
+                ImageFormat::from_extension(&ext)
+                    .ok_or_else(|| format!("unsupported format `{ext}`"))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            width: dimension("w")?,
+            height: dimension("h")?,
+            fit,
+            format,
+        })
+    }
+
+    /// Canonical form used for URL signing and ETags.
+    fn canonical(&self, key: &str) -> String {
+        let mut out = format!("{key}?");
+        if let Some(w) = self.width {
+            out.push_str(&format!("w={w}&"));
+        }
+        if let Some(h) = self.height {
+            out.push_str(&format!("h={h}&"));
+        }
+        if self.fit == Fit::Crop {
+            out.push_str("fit=crop&");
+        }
+        if let Some(ext) = self.format.and_then(|f| f.extensions_str().first()) {
+            out.push_str(&format!("fmt={ext}&"));
+        }
+        out.pop();
+        out
+    }
+
+    fn is_identity(&self) -> bool {
+        self.width.is_none() && self.height.is_none() && self.format.is_none()
+    }
+
+    fn apply(&self, img: DynamicImage) -> DynamicImage {
+        let (w, h) = match (self.width, self.height) {
+            (None, None) => return img,
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, u32::MAX),
+            (None, Some(h)) => (u32::MAX, h),
+        };
+        match self.fit {
+            Fit::Crop if w != u32::MAX && h != u32::MAX => {
+                img.resize_to_fill(w, h, FilterType::Lanczos3)
+            }
+            _ => img.resize(w, h, FilterType::Lanczos3),
+        }
+    }
+}
+
+/// Handler serving transformed images from an [`ImageStorage`].
+#[derive(Clone)]
+pub struct ImageServer {
+    storage: Arc<dyn ImageStorage>,
+    secret: Option<Arc<[u8]>>,
+    param: String,
+    max_dimension: u32,
+    max_source_dimension: u32,
+    max_age: u64,
+}
+
+impl ImageServer {
+    pub fn new(storage: impl ImageStorage) -> Self {
+        Self {
+            storage: Arc::new(storage),
+            secret: None,
+            param: "path".to_string(),
+            max_dimension: 4096,
+            max_source_dimension: 8192,
+            max_age: 86_400,
+        }
+    }
+
+    /// Require transforms to carry an HMAC-SHA256 signature in the `s` query parameter.
+    pub fn secret(mut self, secret: impl AsRef<[u8]>) -> Self {
+        self.secret = Some(Arc::from(secret.as_ref()));
+        self
+    }
+
+    /// Name of the route parameter holding the image key (default `path`).
+    pub fn param(mut self, name: &str) -> Self {
+        self.param = name.to_string();
+        self
+    }
+
+    /// Largest width/height a client may request (default 4096).
+    pub fn max_dimension(mut self, max: u32) -> Self {
+        self.max_dimension = max;
+        self
+    }
+
+    /// Largest width/height of a stored image that gets decoded (default 8192).
+    /// Bigger sources, including decompression bombs that only claim such a
+    /// size, answer 422; decoder memory is capped at 8 bytes per pixel of a
+    /// `max` × `max` image.
+    pub fn max_source_dimension(mut self, max: u32) -> Self {
+        self.max_source_dimension = max;
+        self
+    }
+
+    /// `Cache-Control: max-age` in seconds for successful responses (default one day).
+    pub fn max_age(mut self, seconds: u64) -> Self {
+        self.max_age = seconds;
+        self
+    }
+
+    /// Build a signed query string (`w=..&h=..&s=..`) for the given key and transform.
+    pub fn sign(&self, key: &str, transform: &Transform) -> String {
+        let canonical = transform.canonical(key);
+        let query = canonical.split_once('?').map(|(_, q)| q).unwrap_or("");
+        match self.signature(&canonical) {
+            Some(sig) if query.is_empty() => format!("s={sig}"),
+            Some(sig) => format!("{query}&s={sig}"),
+            None => query.to_string(),
+        }
+    }
+
+    fn signature(&self, canonical: &str) -> Option<String> {
+        let secret = self.secret.as_ref()?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).ok()?;
+        mac.update(canonical.as_bytes());
+        Some(hex(&mac.finalize().into_bytes()))
+    }
+
+    fn limits(&self) -> Limits {
+        let side = u64::from(self.max_source_dimension);
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(self.max_source_dimension);
+        limits.max_image_height = Some(self.max_source_dimension);
+        limits.max_alloc = Some(side * side * 8);
+        limits
+    }
+
+    fn verify(&self, canonical: &str, sig: Option<&str>) -> bool {
+        let Some(secret) = self.secret.as_ref() else {
+            return true;
+        };
+        let Some(sig) = sig.and_then(unhex) else {
+            return false;
+        };
+        let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret) else {
+            return false;
+        };
+        mac.update(canonical.as_bytes());
+        mac.verify_slice(&sig).is_ok()
+    }
+
+    async fn serve(&self, ctx: RequestCtx) -> Response {
+        let Some(key) = ctx.get_param(&self.param).map(str::to_owned) else {
+            return ResponseBuilder::not_found();
+        };
+        let transform = match Transform::from_ctx(&ctx) {
+            Ok(t) => t,
+            Err(msg) => return error(StatusCode::BAD_REQUEST, msg),
+        };
+        if transform.width.unwrap_or(0) > self.max_dimension
+            || transform.height.unwrap_or(0) > self.max_dimension
+        {
+            return error(StatusCode::BAD_REQUEST, "requested size is too large");
+        }
+
+        let canonical = transform.canonical(&key);
+        if !self.verify(&canonical, ctx.query_param("s").as_deref()) {
+            return error(StatusCode::FORBIDDEN, "invalid image signature");
+        }
+
+        let Some(source) = self.storage.load(&key).await else {
+            return ResponseBuilder::not_found();
+        };
+
+        let etag = {
+            let mut hasher = Sha256::new();
+            hasher.update(canonical.as_bytes());
+            hasher.update(&source);
+            format!("\"{}\"", &hex(&hasher.finalize())[..32])
+        };
+        let cache_control = format!("public, max-age={}", self.max_age);
+        if ctx.header("if-none-match") == Some(etag.as_str()) {
+            return ResponseBuilder::new()
+                .status(StatusCode::NOT_MODIFIED)
+                .with_header("ETag", &etag)
+                .with_header("Cache-Control", &cache_control)
+                .empty_body();
+        }
+
+        let limits = self.limits();
+        let rendered = tokio::task::spawn_blocking(move || render(&source, &transform, limits)).await;
+        match rendered {
+            Ok(Ok((bytes, format))) => ResponseBuilder::new()
+                .status(StatusCode::OK)
+                .content_type(format.to_mime_type())
+                .with_header("ETag", &etag)
+                .with_header("Cache-Control", &cache_control)
+                .build(bytes),
+            Ok(Err(msg)) => error(StatusCode::UNPROCESSABLE_ENTITY, msg),
+            Err(_) => ResponseBuilder::internal_error(),
+        }
+    }
+}
+
+impl Handler for ImageServer {
+    fn handle(&self, ctx: RequestCtx) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+        let this = self.clone();
+        Box::pin(async move { this.serve(ctx).await })
+    }
+}
+
+/// Decode, transform and re-encode an image (runs on the blocking pool).
+fn render(source: &Bytes, transform: &Transform, limits: Limits) -> Result<(Vec<u8>, ImageFormat), String> {
+    let input_format = ::image::guess_format(source).map_err(|e| e.to_string())?;
+    let output_format = transform.format.unwrap_or(input_format);
+    if transform.is_identity() {
+        return Ok((source.to_vec(), output_format));
+    }
+    let mut reader = ImageReader::with_format(Cursor::new(source), input_format);
+    reader.limits(limits);
+    let img = reader.decode().map_err(|e| e.to_string())?;
+    let mut out = Cursor::new(Vec::new());
+    transform
+        .apply(img)
+        .write_to(&mut out, output_format)
+        .map_err(|e| e.to_string())?;
+    Ok((out.into_inner(), output_format))
+}
+
+fn error(status: StatusCode, msg: impl Into<String>) -> Response {
+    ResponseBuilder::new()
+        .status(status)
+        .content_type("text/plain; charset=utf-8")
+        .build(msg.into())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    struct MemoryStorage(HashMap<String, Bytes>);
+
+    impl ImageStorage for MemoryStorage {
+        fn load(&self, key: &str) -> Pin<Box<dyn Future<Output = Option<Bytes>> + Send + '_>> {
+            let image = self.0.get(key).cloned();
+            Box::pin(async move { image })
+        }
+    }
+
+    fn png(width: u32, height: u32) -> Bytes {
+        let mut out = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(width, height).write_to(&mut out, ImageFormat::Png).unwrap();
+        out.into_inner().into()
+    }
+
+    fn server() -> ImageServer {
+        let images = HashMap::from([("cat.png".to_string(), png(64, 32))]);
+        ImageServer::new(MemoryStorage(images)).secret("test-secret")
+    }
+
+    fn ctx(target: &str) -> RequestCtx {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let uri = format!("/images/{path}?{query}");
+        let parts = hyper::Request::get(uri).body(()).unwrap().into_parts().0;
+        let mut ctx = RequestCtx::from_parts(parts);
+        ctx.add_param("path".to_string(), path.to_string());
+        ctx
+    }
+
+    #[tokio::test]
+    async fn test_signature_required() {
+        let images = server();
+        let transform = Transform { width: Some(16), ..Transform::default() };
+        let query = images.sign("cat.png", &transform);
+        assert!(query.starts_with("w=16&s="));
+        assert_eq!(images.serve(ctx(&format!("cat.png?{query}"))).await.status(), StatusCode::OK);
+
+        let tampered = query.replace("w=16", "w=32");
+        assert_eq!(images.serve(ctx(&format!("cat.png?{tampered}"))).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(images.serve(ctx("cat.png?w=16")).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(images.serve(ctx("cat.png?w=16&s=zz")).await.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_transform_rejects_bad_queries() {
+        for query in ["w=0", "w=abc", "h=-1", "fit=stretch", "fmt=nope"] {
+            assert!(Transform::from_ctx(&ctx(&format!("cat.png?{query}"))).is_err(), "{query}");
+        }
+        let transform = Transform::from_ctx(&ctx("cat.png?w=10&h=20&fit=crop&fmt=webp")).unwrap();
+        assert_eq!(
+            transform,
+            Transform { width: Some(10), height: Some(20), fit: Fit::Crop, format: Some(ImageFormat::WebP) }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fs_storage_stays_inside_root() {
+        let dir = std::env::temp_dir().join(format!("s_web_image_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("public")).unwrap();
+        std::fs::write(dir.join("public/a.png"), b"inside").unwrap();
+        std::fs::write(dir.join("secret.txt"), b"outside").unwrap();
+
+        let storage = FsStorage::new(dir.join("public"));
+        assert_eq!(storage.load("a.png").await.as_deref(), Some(&b"inside"[..]));
+        assert_eq!(storage.load("../secret.txt").await, None);
+        assert_eq!(storage.load("x/../../secret.txt").await, None);
+        assert_eq!(storage.load(dir.join("secret.txt").to_str().unwrap()).await, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resize_and_revalidate() {
+        let images = server();
+        let transform = Transform { width: Some(16), fit: Fit::Contain, ..Transform::default() };
+        let target = format!("cat.png?{}", images.sign("cat.png", &transform));
+
+        let response = images.serve(ctx(&target)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "image/png");
+        let etag = response.headers()["etag"].to_str().unwrap().to_string();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let resized = ::image::load_from_memory(&body).unwrap();
+        assert_eq!((resized.width(), resized.height()), (16, 8));
+
+        let mut revalidate = ctx(&target);
+        revalidate.request.headers_mut().insert("if-none-match", etag.parse().unwrap());
+        let response = images.serve(revalidate).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()["etag"], etag.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_sources_are_not_decoded() {
+        let images = server().max_source_dimension(32);
+        let transform = Transform { width: Some(16), ..Transform::default() };
+        let target = format!("cat.png?{}", images.sign("cat.png", &transform));
+        assert_eq!(images.serve(ctx(&target)).await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
@@ -0,0 +1,290 @@
+//! Client IP allow/deny lists and trusted proxies.
+//!
+//! Behind a load balancer every connection comes from the balancer, and the
+//! caller's address travels in `Forwarded` or `X-Forwarded-For`. Those
+//! headers are only believed when the connection comes from an address
+//! listed with `Engine::trust_proxies`; `ctx.client_ip()` then walks the
+//! chain from the right, skipping trusted hops, to the first address that
+//! isn't one of them.
+//!
+//! [`IpFilter`] answers `403 Forbidden` to clients outside its allow list or
+//! inside its deny list:
+//!
+//! ```rust,ignore
+//! use s_web::ipfilter::IpFilter;
+//!
+//! app.trust_proxies(["10.0.0.0/8", "127.0.0.1"]);
+//! app.group("/admin").use_middleware(
+//!     IpFilter::new()
+//!         .allow("192.168.0.0/16")
+//!         .allow("2001:db8::/32")
+//!         .deny("192.168.13.7")
+//!         .middleware(),
+//! );
+//! ```
+
+use std::{
+    fmt,
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+};
+
+use hyper::HeaderMap;
+
+use crate::{Next, RequestCtx, Response, ResponseBuilder, StatusCode, log};
+
+/// An address range such as `10.0.0.0/8` or `2001:db8::/32`; a bare address
+/// is a range of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Whether `ip` lies in the range. IPv4-mapped IPv6 addresses
+    /// (`::ffff:10.1.2.3`) match IPv4 ranges.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix)).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Error parsing a [`Cidr`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCidr(String);
+
+impl fmt::Display for InvalidCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid address range {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidCidr {}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidCidr(s.to_string());
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let network = addr.parse::<IpAddr>().map_err(|_| invalid())?.to_canonical();
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok().filter(|p| *p <= max).ok_or_else(invalid)?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// Parse configured ranges, logging and skipping invalid ones
+pub(crate) fn parse_ranges<I, S>(ranges: I) -> Vec<Cidr>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    ranges
+        .into_iter()
+        .filter_map(|range| match range.as_ref().parse() {
+            Ok(cidr) => Some(cidr),
+            Err(e) => {
+                log::warn!("{e}; ignoring it");
+                None
+            }
+        })
+        .collect()
+}
+
+/// The caller's address: `remote` itself unless it is a trusted proxy, in
+/// which case the forwarding chain is walked from the right
+pub(crate) fn client_ip(remote: IpAddr, headers: &HeaderMap, trusted: &[Cidr]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|cidr| cidr.contains(ip));
+    if !is_trusted(remote) {
+        return remote;
+    }
+    let chain = forwarded_for(headers);
+    let mut client = remote;
+    for hop in chain.iter().rev() {
+        // An unparsable hop can't be checked, so trust stops there
+        let Some(ip) = *hop else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+    client
+}
+
+/// Addresses in `Forwarded: for=` or, without it, `X-Forwarded-For`, oldest
+/// first; `None` for obfuscated or malformed entries
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+    };
+    if headers.contains_key("forwarded") {
+        values("forwarded")
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (key, value) = pair.trim().split_once('=')?;
+                    key.eq_ignore_ascii_case("for").then(|| parse_node(value))
+                })
+            })
+            .collect()
+    } else {
+        values("x-forwarded-for").map(parse_node).collect()
+    }
+}
+
+/// `1.2.3.4`, `1.2.3.4:80`, `"[2001:db8::1]:80"` or `2001:db8::1`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    node.parse()
+        .ok()
+        .or_else(|| node.rsplit_once(':')?.0.parse::<std::net::Ipv4Addr>().ok().map(IpAddr::V4))
+}
+
+/// IP filter middleware configuration.
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    /// Let every client through until ranges are added.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only let clients in the allowed ranges through. Invalid ranges are
+    /// logged and ignored.
+    pub fn allow(mut self, range: &str) -> Self {
+        self.allow.extend(parse_ranges([range]));
+        self
+    }
+
+    /// Refuse clients in this range, even if an allowed range contains them.
+    pub fn deny(mut self, range: &str) -> Self {
+        self.deny.extend(parse_ranges([range]));
+        self
+    }
+
+    /// Whether a client may pass; without a known address only a filter
+    /// with no allow list lets it through
+    pub fn permits(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => {
+                !self.deny.iter().any(|cidr| cidr.contains(ip))
+                    && (self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip)))
+            }
+            None => self.allow.is_empty(),
+        }
+    }
+
+    /// Build the middleware function for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let filter = Arc::new(self);
+        move |ctx, next| {
+            let filter = filter.clone();
+            Box::pin(async move {
+                if !filter.permits(ctx.client_ip()) {
+                    return ResponseBuilder::new()
+                        .status(StatusCode::FORBIDDEN)
+                        .content_type("text/plain; charset=utf-8")
+                        .build("403 Forbidden");
+                }
+                next(ctx).await
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr_parsing_and_matching() {
+        let net: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(net.contains(ip("10.255.1.2")) && !net.contains(ip("11.0.0.1")));
+        assert!(net.contains(ip("::ffff:10.1.2.3")));
+        assert!(!net.contains(ip("2001:db8::1")));
+
+        let v6: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(ip("2001:db8:ffff::1")) && !v6.contains(ip("2001:db9::1")));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("8.8.8.8")));
+        assert_eq!("127.0.0.1".parse::<Cidr>().unwrap().to_string(), "127.0.0.1/32");
+        for bad in ["10.0.0.0/33", "10.0.0/8", "localhost", "::/129"] {
+            assert!(bad.parse::<Cidr>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_client_ip_only_believes_trusted_proxies() {
+        let trusted = parse_ranges(["10.0.0.0/8"]);
+        let headers: HeaderMap = [("x-forwarded-for", "6.6.6.6, 1.2.3.4, 10.0.0.2")]
+            .into_iter()
+            .map(|(k, v)| (k.parse().unwrap(), v.parse().unwrap()))
+            .collect();
+        // The spoofable leftmost entry is never reached
+        assert_eq!(client_ip(ip("10.0.0.1"), &headers, &trusted), ip("1.2.3.4"));
+        assert_eq!(client_ip(ip("5.5.5.5"), &headers, &trusted), ip("5.5.5.5"));
+
+        let headers: HeaderMap = [("forwarded", r#"for="[2001:db8::7]:4711";proto=https, for=10.0.0.3"#)]
+            .into_iter()
+            .map(|(k, v)| (k.parse().unwrap(), v.parse().unwrap()))
+            .collect();
+        assert_eq!(client_ip(ip("10.0.0.1"), &headers, &trusted), ip("2001:db8::7"));
+    }
+
+    #[test]
+    fn test_filter_deny_beats_allow() {
+        let filter = IpFilter::new().allow("192.168.0.0/16").deny("192.168.13.7");
+        assert!(filter.permits(Some(ip("192.168.1.1"))));
+        assert!(!filter.permits(Some(ip("192.168.13.7"))));
+        assert!(!filter.permits(Some(ip("8.8.8.8"))));
+        assert!(!filter.permits(None));
+        assert!(IpFilter::new().deny("8.8.8.8").permits(None));
+    }
+}
@@ -0,0 +1,196 @@
+//! Priority lanes: separate concurrency limits per class of request.
+//!
+//! Requests are classified into [`Lane`]s and each lane has its own limit,
+//! so a flood of user or batch traffic can saturate its lane without taking
+//! health checks or the admin UI down with it. A request arriving at a full
+//! lane is answered `503` with `Retry-After` right away.
+//!
+//! ```rust,ignore
+//! use s_web::lanes::{Lane, PriorityLanes};
+//!
+//! let lanes = PriorityLanes::new()
+//!     .limit(Lane::Normal, 512)
+//!     .limit(Lane::Batch, 16)
+//!     .classify(|ctx| match ctx.request.uri().path() {
+//!         p if p.starts_with("/exports") => Lane::Batch,
+//!         p => PriorityLanes::default_lane(p),
+//!     });
+//! // Register first, so rejected requests skip the rest of the chain
+//! app.use_middleware(lanes.middleware());
+//! ```
+
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{Next, RequestCtx, Response, ResponseBuilder, StatusCode};
+
+/// Class of request, from most to least important.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lane {
+    /// Liveness and readiness probes
+    Health,
+    /// Operator and back-office endpoints
+    Admin,
+    /// Regular user traffic
+    Normal,
+    /// Exports, imports and other heavy, deferrable work
+    Batch,
+}
+
+impl Lane {
+    const ALL: [Lane; 4] = [Lane::Health, Lane::Admin, Lane::Normal, Lane::Batch];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Lane::Health => "health",
+            Lane::Admin => "admin",
+            Lane::Normal => "normal",
+            Lane::Batch => "batch",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+type Classifier = Arc<dyn Fn(&RequestCtx) -> Lane + Send + Sync>;
+
+/// Per-lane concurrency limits. Clones share the same counters.
+#[derive(Clone)]
+pub struct PriorityLanes {
+    /// `(limit, permits)` per lane, indexed by [`Lane::index`]; `None` is unlimited
+    lanes: [Option<(usize, Arc<Semaphore>)>; 4],
+    classify: Classifier,
+}
+
+impl Default for PriorityLanes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PriorityLanes {
+    /// All lanes unlimited, classified by [`default_lane`](Self::default_lane).
+    pub fn new() -> Self {
+        Self {
+            lanes: [None, None, None, None],
+            classify: Arc::new(|ctx| Self::default_lane(ctx.request.uri().path())),
+        }
+    }
+
+    /// Allow at most `max` requests of `lane` in flight at once.
+    pub fn limit(mut self, lane: Lane, max: usize) -> Self {
+        self.lanes[lane.index()] = Some((max, Arc::new(Semaphore::new(max))));
+        self
+    }
+
+    /// Replace the classifier.
+    pub fn classify<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&RequestCtx) -> Lane + Send + Sync + 'static,
+    {
+        self.classify = Arc::new(f);
+        self
+    }
+
+    /// `/health*`, `/healthz`, `/ready*` and `/live*` go to [`Lane::Health`],
+    /// paths under `/admin` to [`Lane::Admin`], everything else to [`Lane::Normal`].
+    pub fn default_lane(path: &str) -> Lane {
+        let first = path.trim_start_matches('/').split('/').next().unwrap_or("");
+        if ["health", "ready", "live"].iter().any(|p| first.starts_with(p)) {
+            Lane::Health
+        } else if first == "admin" {
+            Lane::Admin
+        } else {
+            Lane::Normal
+        }
+    }
+
+    /// Requests of `lane` currently being served (0 for unlimited lanes).
+    pub fn in_flight(&self, lane: Lane) -> usize {
+        self.lanes[lane.index()]
+            .as_ref()
+            .map_or(0, |(max, permits)| max - permits.available_permits())
+    }
+
+    /// Build the middleware for `use_middleware`. The lane is stored in the
+    /// request extensions for later middleware and handlers.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let lanes = Arc::new(self);
+        move |mut ctx, next| {
+            let lanes = lanes.clone();
+            Box::pin(async move {
+                let lane = (lanes.classify)(&ctx);
+                let Ok(permit) = lanes.admit(lane) else {
+                    return at_capacity(lane);
+                };
+                ctx.request.extensions_mut().insert(lane);
+                let response = next(ctx).await;
+                drop(permit);
+                response
+            })
+        }
+    }
+
+    /// Take a slot in `lane`; `Ok(None)` for unlimited lanes, `Err` when full
+    fn admit(&self, lane: Lane) -> Result<Option<OwnedSemaphorePermit>, ()> {
+        match &self.lanes[lane.index()] {
+            None => Ok(None),
+            Some((_, permits)) => permits.clone().try_acquire_owned().map(Some).map_err(|_| ()),
+        }
+    }
+}
+
+impl std::fmt::Debug for PriorityLanes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut map = f.debug_map();
+        for lane in Lane::ALL {
+            map.entry(&lane.as_str(), &self.lanes[lane.index()].as_ref().map(|(max, _)| max));
+        }
+        map.finish()
+    }
+}
+
+fn at_capacity(lane: Lane) -> Response {
+    ResponseBuilder::new()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .content_type("text/plain; charset=utf-8")
+        .with_header("Retry-After", "1")
+        .build(format!("503 Service Unavailable: {} lane is at capacity", lane.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_lanes() {
+        assert_eq!(PriorityLanes::default_lane("/healthz"), Lane::Health);
+        assert_eq!(PriorityLanes::default_lane("/ready"), Lane::Health);
+        assert_eq!(PriorityLanes::default_lane("/admin/users"), Lane::Admin);
+        assert_eq!(PriorityLanes::default_lane("/administrators"), Lane::Normal);
+        assert_eq!(PriorityLanes::default_lane("/api/health"), Lane::Normal);
+    }
+
+    #[test]
+    fn test_full_lane_does_not_block_others() {
+        let lanes = PriorityLanes::new().limit(Lane::Normal, 2).limit(Lane::Health, 1);
+        let a = lanes.admit(Lane::Normal).unwrap();
+        let _b = lanes.admit(Lane::Normal).unwrap();
+        assert!(lanes.admit(Lane::Normal).is_err());
+        assert_eq!(lanes.in_flight(Lane::Normal), 2);
+
+        assert!(lanes.admit(Lane::Health).unwrap().is_some());
+        assert!(lanes.admit(Lane::Batch).unwrap().is_none(), "unlimited");
+
+        drop(a);
+        assert!(lanes.admit(Lane::Normal).is_ok());
+    }
+}
@@ -1,21 +1,105 @@
+// Lets `#[derive(ToSchema)]` output, which names `::s_web`, compile in this crate
+extern crate self as s_web;
+
+mod app;
+pub mod blocking;
+pub mod cache;
+pub mod chaos;
+pub mod client;
+pub mod concurrency;
 mod context;
+pub mod dedup;
+pub mod cookie;
 mod engine;
+mod error;
 mod handler;
+pub mod health;
+pub mod ipfilter;
+pub mod lanes;
+mod lifecycle;
+pub mod loadgen;
+mod macros;
+pub mod lockout;
+mod log;
+pub mod mail;
+pub mod metrics;
 mod middleware;
+mod params;
+pub mod patch;
+pub mod queue;
+pub mod quota;
+pub mod range;
+pub mod ratelimit;
 mod response;
+mod route;
 mod router;
+mod run_error;
+pub mod scan;
+mod security;
+mod server;
+mod service;
+mod shutdown;
+mod state;
+pub mod store;
+mod sunset;
 mod swagger;
+#[cfg(test)]
+mod test_alloc;
+pub mod timeout;
 mod trie;
+pub mod usage;
+mod watermark;
+
+#[cfg(feature = "alloc-tracking")]
+pub mod alloc_tracking;
+#[cfg(feature = "auth")]
+pub mod auth;
+#[cfg(feature = "compression")]
+pub mod compression;
+#[cfg(feature = "daemon")]
+pub mod daemon;
+#[cfg(feature = "image")]
+pub mod image;
+#[cfg(feature = "migrate")]
+pub mod migrate;
+#[cfg(feature = "otel")]
+pub mod otel;
+#[cfg(feature = "policy")]
+pub mod policy;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "tracing")]
+pub mod trace;
+#[cfg(feature = "session")]
+pub mod session;
+#[cfg(feature = "sqlx")]
+pub mod tx;
 
-pub(crate) use middleware::{execute_chain, Middleware};
+pub(crate) use middleware::{compose, Middleware};
 use router::Router;
 
-pub use context::RequestCtx;
-pub use engine::Engine;
+pub use app::App;
+pub use context::{BodyTimeout, DEFAULT_BODY_LIMIT, ParamError, PayloadTooLarge, RequestCtx, UrlEncodedError};
+pub use engine::{BoxError, Engine, PreflightError, RouterGroup, StartupMode};
+pub use error::{Error, HttpError};
+pub use shutdown::{ShutdownError, StopFailure, Subsystem};
 pub use handler::Handler;
-pub use response::{IntoResponse, Response, ResponseBuilder};
+pub use lifecycle::{HookOutput, LifecycleCtx, ShutdownToken};
+pub use response::{Chunked, Event, Html, IntoResponse, Json, Multipart, Response, ResponseBuilder, Sse, Text};
 pub use middleware::{IntoNext, Next};
-pub use swagger::{SwaggerInfo, SwaggerBuilder, swagger};
+pub use params::Params;
+pub use route::{RouteBuilder, RouteTarget};
+pub use router::{AllowedMethods, MatchedRoute, RouteInfo, RoutesHandle};
+pub use run_error::{HookFailure, RunError};
+pub use security::{BasicCredentials, RequireAuth, SecurityScheme};
+pub use server::ServerConfig;
+pub use service::EngineService;
+pub use swagger::{Schema, SwaggerInfo, SwaggerBuilder, ToSchema, swagger};
+/// Derive [`ToSchema`] for request and response types
+pub use s_web_macros::ToSchema;
+pub use watermark::Watermark;
 
 /// HTTP status codes for convenience
 pub use hyper::StatusCode;
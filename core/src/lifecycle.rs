@@ -0,0 +1,118 @@
+//! Handles passed to lifecycle hooks.
+
+use std::{
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::FutureExt;
+use tokio::sync::watch;
+
+use crate::{BoxError, state::StateMap};
+
+/// Cloneable signal that flips once the server starts shutting down.
+///
+/// Hand it to background tasks spawned from startup hooks so they can stop
+/// cleanly instead of being killed mid-work.
+#[derive(Clone)]
+pub struct ShutdownToken {
+    rx: watch::Receiver<bool>,
+}
+
+impl ShutdownToken {
+    /// Create a token and the sender that triggers it.
+    pub(crate) fn new() -> (watch::Sender<bool>, Self) {
+        let (tx, rx) = watch::channel(false);
+        (tx, Self { rx })
+    }
+
+    /// Whether shutdown has already been signalled.
+    pub fn is_shutdown(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Resolve once shutdown is signalled (or the server is gone).
+    pub async fn cancelled(&self) {
+        let mut rx = self.rx.clone();
+        let _ = rx.wait_for(|stopping| *stopping).await;
+    }
+}
+
+/// Context handed to `on_startup_with` / `on_shutdown_with` hooks.
+#[derive(Clone)]
+pub struct LifecycleCtx {
+    state: Arc<Mutex<StateMap>>,
+    shutdown: ShutdownToken,
+}
+
+impl LifecycleCtx {
+    pub(crate) fn new(state: StateMap, shutdown: ShutdownToken) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(state)),
+            shutdown,
+        }
+    }
+
+    /// Register a shared resource (DB pool, cache, config) for handlers to
+    /// read through `ctx.state::<T>()`.
+    pub fn insert<T: Send + Sync + 'static>(&self, value: T) {
+        self.lock().insert(value);
+    }
+
+    /// Clone out a previously registered resource.
+    pub fn get<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.lock().get::<T>().cloned()
+    }
+
+    /// Token that resolves when the server begins shutting down.
+    pub fn shutdown_token(&self) -> ShutdownToken {
+        self.shutdown.clone()
+    }
+
+    /// Snapshot of the state registered so far.
+    pub(crate) fn state(&self) -> StateMap {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, StateMap> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Return type of lifecycle hooks: `()` for hooks that cannot fail, or a
+/// `Result` whose error is handled according to
+/// [`Engine::hook_failures`](crate::Engine::hook_failures).
+pub trait HookOutput: Send + 'static {
+    fn into_result(self) -> Result<(), BoxError>;
+}
+
+impl HookOutput for () {
+    fn into_result(self) -> Result<(), BoxError> {
+        Ok(())
+    }
+}
+
+impl<E: Into<BoxError> + Send + 'static> HookOutput for Result<(), E> {
+    fn into_result(self) -> Result<(), BoxError> {
+        self.map_err(Into::into)
+    }
+}
+
+/// Run a hook, turning a panic into an error so the failure policy applies to it too
+pub(crate) async fn run_hook<F>(hook: F) -> Result<(), BoxError>
+where
+    F: Future<Output = Result<(), BoxError>>,
+{
+    match AssertUnwindSafe(hook).catch_unwind().await {
+        Ok(result) => result,
+        Err(panic) => {
+            let msg = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(format!("panicked: {msg}").into())
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Soak and regression testing without external tools.
+//!
+//! [`LoadGen`] replays recorded requests at a fixed rate against an engine
+//! started in the same process (on a free loopback port) or against a
+//! running server, and reports status counts and latency percentiles:
+//!
+//! ```rust,ignore
+//! use s_web::loadgen::{LoadGen, RecordedRequest};
+//! use std::time::Duration;
+//!
+//! #[tokio::test]
+//! async fn list_users_stays_fast() {
+//!     let requests = RecordedRequest::parse_jsonl(include_str!("traffic.jsonl")).unwrap();
+//!     let report = LoadGen::new(requests)
+//!         .rps(500)
+//!         .duration(Duration::from_secs(10))
+//!         .run(build_app())
+//!         .await
+//!         .unwrap();
+//!     println!("{report}");
+//!     assert_eq!(report.errors, 0);
+//!     assert!(report.percentile(99.0) < Duration::from_millis(50));
+//! }
+//! ```
+//!
+//! Requests are sent on schedule whether or not earlier ones have finished,
+//! and latency is measured from the scheduled send time, so a stalled server
+//! shows up as high latency instead of a politely lowered request rate.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Bytes, client::conn::http1::SendRequest};
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use tokio::sync::{Mutex, mpsc};
+
+use crate::{Engine, RunError};
+
+/// One request to replay.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// Path and query, e.g. `/users?page=2`
+    pub path: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn new(method: &str, path: &str) -> Self {
+        Self {
+            method: method.to_ascii_uppercase(),
+            path: path.to_string(),
+            headers: HashMap::new(),
+            body: String::new(),
+        }
+    }
+
+    pub fn get(path: &str) -> Self {
+        Self::new("GET", path)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<String>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Parse one JSON object per line, e.g.
+    /// `{"method": "POST", "path": "/users", "headers": {"content-type": "application/json"}, "body": "{}"}`.
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn parse_jsonl(text: &str) -> Result<Vec<Self>, serde_json::Error> {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(serde_json::from_str)
+            .collect()
+    }
+
+    fn to_hyper(&self, addr: SocketAddr) -> Result<hyper::Request<Full<Bytes>>, hyper::http::Error> {
+        let mut builder = hyper::Request::builder()
+            .method(self.method.as_str())
+            .uri(self.path.as_str())
+            .header(hyper::header::HOST, addr.to_string());
+        for (name, value) in &self.headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        builder.body(Full::new(Bytes::from(self.body.clone())))
+    }
+}
+
+/// Fixed-rate replay of recorded requests, cycling through them in order.
+#[derive(Debug, Clone)]
+pub struct LoadGen {
+    requests: Arc<Vec<RecordedRequest>>,
+    rps: u32,
+    duration: Duration,
+    connections: usize,
+}
+
+impl LoadGen {
+    /// 100 requests per second for 10 seconds over 4 keep-alive connections.
+    pub fn new(requests: Vec<RecordedRequest>) -> Self {
+        Self {
+            requests: Arc::new(requests),
+            rps: 100,
+            duration: Duration::from_secs(10),
+            connections: 4,
+        }
+    }
+
+    /// Target request rate.
+    pub fn rps(mut self, rps: u32) -> Self {
+        self.rps = rps.max(1);
+        self
+    }
+
+    /// How long to send for; in-flight requests are awaited afterwards.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Concurrent keep-alive connections; raise it when one request takes
+    /// longer than `connections / rps` seconds.
+    pub fn connections(mut self, connections: usize) -> Self {
+        self.connections = connections.max(1);
+        self
+    }
+
+    /// Serve `engine` on a free loopback port, replay against it, then stop it.
+    pub async fn run(self, engine: Engine) -> Result<LoadReport, RunError> {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .map_err(|source| RunError::Bind {
+                addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+                source,
+            })?;
+        let mut server = tokio::spawn(async move { engine.run(&addr.to_string()).await });
+        // Wait until the engine accepts connections (startup hooks may take a while)
+        loop {
+            tokio::select! {
+                result = &mut server => {
+                    return match result {
+                        Ok(Err(e)) => Err(e),
+                        Err(join) if join.is_panic() => std::panic::resume_unwind(join.into_panic()),
+                        _ => Err(RunError::Accept(io::Error::other("server stopped before the load test"))),
+                    };
+                }
+                connected = tokio::net::TcpStream::connect(addr) => {
+                    if connected.is_ok() {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+        }
+        let report = self.run_against(addr).await;
+        server.abort();
+        Ok(report)
+    }
+
+    /// Replay against a server that is already listening on `addr`.
+    pub async fn run_against(self, addr: SocketAddr) -> LoadReport {
+        let (tx, rx) = mpsc::unbounded_channel::<(usize, Instant)>();
+        let rx = Arc::new(Mutex::new(rx));
+        let workers: Vec<_> = (0..self.connections)
+            .map(|_| tokio::spawn(worker(addr, self.requests.clone(), rx.clone())))
+            .collect();
+
+        let started = Instant::now();
+        let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / f64::from(self.rps)));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Burst);
+        let mut sent = 0;
+        if !self.requests.is_empty() {
+            while started.elapsed() < self.duration {
+                let scheduled = ticker.tick().await;
+                if tx.send((sent % self.requests.len(), scheduled.into_std())).is_err() {
+                    break;
+                }
+                sent += 1;
+            }
+        }
+        drop(tx);
+
+        let mut report = LoadReport {
+            sent,
+            ..LoadReport::default()
+        };
+        for worker in workers {
+            if let Ok(results) = worker.await {
+                report.merge(results);
+            }
+        }
+        report.elapsed = started.elapsed();
+        report.latencies.sort();
+        report
+    }
+}
+
+/// Results of one connection worker
+#[derive(Default)]
+struct WorkerResults {
+    statuses: BTreeMap<u16, usize>,
+    errors: usize,
+    latencies: Vec<Duration>,
+}
+
+async fn worker(
+    addr: SocketAddr,
+    requests: Arc<Vec<RecordedRequest>>,
+    queue: Arc<Mutex<mpsc::UnboundedReceiver<(usize, Instant)>>>,
+) -> WorkerResults {
+    let mut results = WorkerResults::default();
+    let mut conn: Option<SendRequest<Full<Bytes>>> = None;
+    loop {
+        let Some((index, scheduled)) = queue.lock().await.recv().await else {
+            return results;
+        };
+        match send(addr, &mut conn, &requests[index]).await {
+            Ok(status) => {
+                *results.statuses.entry(status).or_default() += 1;
+                results.latencies.push(scheduled.elapsed());
+            }
+            Err(_) => {
+                // Reconnect for the next request
+                conn = None;
+                results.errors += 1;
+            }
+        }
+    }
+}
+
+async fn send(
+    addr: SocketAddr,
+    conn: &mut Option<SendRequest<Full<Bytes>>>,
+    request: &RecordedRequest,
+) -> Result<u16, crate::BoxError> {
+    if conn.as_ref().is_none_or(|c| c.is_closed()) {
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        let (sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(connection);
+        *conn = Some(sender);
+    }
+    let Some(sender) = conn.as_mut() else {
+        unreachable!("connection was just established");
+    };
+    sender.ready().await?;
+    let response = sender.send_request(request.to_hyper(addr)?).await?;
+    let status = response.status().as_u16();
+    // Read the whole body so the connection can be reused
+    response.into_body().collect().await?;
+    Ok(status)
+}
+
+/// Outcome of a load run.
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    /// Requests scheduled
+    pub sent: usize,
+    /// Responses by status code
+    pub statuses: BTreeMap<u16, usize>,
+    /// Requests that got no response (connection refused, reset, ...)
+    pub errors: usize,
+    /// Wall time from the first send until the last response
+    pub elapsed: Duration,
+    /// Sorted latencies of answered requests
+    pub latencies: Vec<Duration>,
+}
+
+impl LoadReport {
+    fn merge(&mut self, results: WorkerResults) {
+        for (status, count) in results.statuses {
+            *self.statuses.entry(status).or_default() += count;
+        }
+        self.errors += results.errors;
+        self.latencies.extend(results.latencies);
+    }
+
+    /// Responses with a 2xx or 3xx status.
+    pub fn succeeded(&self) -> usize {
+        self.statuses.range(200..400).map(|(_, n)| n).sum()
+    }
+
+    /// Responses with a 4xx or 5xx status.
+    pub fn failed(&self) -> usize {
+        self.statuses.range(400..).map(|(_, n)| n).sum()
+    }
+
+    /// Latency at percentile `p` (0 to 100) of answered requests.
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (p.clamp(0.0, 100.0) / 100.0 * (self.latencies.len() - 1) as f64).round();
+        self.latencies[rank as usize]
+    }
+
+    /// Responses per second actually achieved.
+    pub fn throughput(&self) -> f64 {
+        (self.succeeded() + self.failed()) as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} sent in {:.1?} ({:.0} req/s): {} ok, {} failed, {} errors",
+            self.sent,
+            self.elapsed,
+            self.throughput(),
+            self.succeeded(),
+            self.failed(),
+            self.errors
+        )?;
+        let statuses: Vec<String> = self.statuses.iter().map(|(s, n)| format!("{s}×{n}")).collect();
+        writeln!(f, "statuses: {}", statuses.join(" "))?;
+        write!(
+            f,
+            "latency: p50 {:.1?}, p90 {:.1?}, p99 {:.1?}, max {:.1?}",
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            self.percentile(100.0)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResponseBuilder;
+
+    #[tokio::test]
+    async fn test_replays_against_in_process_engine() {
+        let mut app = Engine::new();
+        app.get("/ok", |_| async { ResponseBuilder::new().build("ok") });
+        let requests = RecordedRequest::parse_jsonl(
+            "# recorded traffic\n{\"method\": \"GET\", \"path\": \"/ok\"}\n\n{\"method\": \"GET\", \"path\": \"/missing\"}\n",
+        )
+        .unwrap();
+
+        let report = LoadGen::new(requests)
+            .rps(200)
+            .duration(Duration::from_millis(250))
+            .connections(2)
+            .run(app)
+            .await
+            .unwrap();
+        assert!(report.sent >= 40, "{report}");
+        assert_eq!(report.errors, 0, "{report}");
+        assert_eq!(report.succeeded() + report.failed(), report.sent);
+        assert_eq!(report.statuses[&200], report.sent.div_ceil(2));
+        assert_eq!(report.statuses[&404], report.sent / 2);
+        assert!(report.percentile(50.0) <= report.percentile(100.0));
+    }
+}
@@ -0,0 +1,374 @@
+//! Brute-force protection: temporary lockouts after repeated auth failures.
+//!
+//! The middleware watches responses from everything behind it; a `401` — as
+//! returned by `.secured(..)` guards, `TotpGuard`, `Policy` or your own login
+//! handler — counts as a failed attempt, and a 2xx clears the count. Once a
+//! key reaches the limit within the window, its requests are rejected with
+//! `429` (or `423 Locked`) and a `Retry-After` header until the lockout ends.
+//!
+//! ```rust,ignore
+//! use s_web::lockout::{Lockout, MemoryLockoutStore};
+//! use std::time::Duration;
+//!
+//! app.post("/login", login).with_middleware(
+//!     Lockout::new(MemoryLockoutStore::new())
+//!         .max_failures(5)
+//!         .lockout(Duration::from_secs(15 * 60))
+//!         .key(|ctx| ctx.query_param("user"))
+//!         .middleware(),
+//! );
+//! ```
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hyper::{StatusCode, header::HeaderValue};
+
+use crate::{
+    Next, RequestCtx, Response, ResponseBuilder,
+    log,
+    store::{self, KvStore},
+};
+
+/// Backend persisting failure counters and lockouts.
+///
+/// `record_failure` must atomically count the failure and start the lockout
+/// once `max_failures` is reached inside `window`.
+pub trait LockoutStore: Send + Sync + 'static {
+    /// Remaining lockout time for `key`, if it is locked.
+    fn locked_for(&self, key: &str) -> Pin<Box<dyn Future<Output = Option<Duration>> + Send + '_>>;
+
+    /// Count a failure; returns the lockout duration if this failure triggered one.
+    fn record_failure(
+        &self,
+        key: &str,
+        max_failures: u32,
+        window: Duration,
+        lockout: Duration,
+    ) -> Pin<Box<dyn Future<Output = Option<Duration>> + Send + '_>>;
+
+    /// Forget failures for `key` after a successful attempt.
+    fn reset(&self, key: &str) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Attempts {
+    window_start: Instant,
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+/// In-process lockout store with fixed windows.
+#[derive(Default)]
+pub struct MemoryLockoutStore {
+    attempts: Mutex<Table>,
+}
+
+#[derive(Default)]
+struct Table {
+    attempts: HashMap<String, Attempts>,
+    /// Size at which the next failure sweeps out stale entries
+    sweep_at: usize,
+}
+
+impl MemoryLockoutStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl LockoutStore for MemoryLockoutStore {
+    fn locked_for(&self, key: &str) -> Pin<Box<dyn Future<Output = Option<Duration>> + Send + '_>> {
+        let now = Instant::now();
+        let remaining = self
+            .attempts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .attempts
+            .get(key)
+            .and_then(|a| a.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| until - now);
+        Box::pin(async move { remaining })
+    }
+
+    fn record_failure(
+        &self,
+        key: &str,
+        max_failures: u32,
+        window: Duration,
+        lockout: Duration,
+    ) -> Pin<Box<dyn Future<Output = Option<Duration>> + Send + '_>> {
+        let now = Instant::now();
+        let mut table = self.attempts.lock().unwrap_or_else(|e| e.into_inner());
+        // Drop stale entries so one-off failures don't accumulate forever, sweeping
+        // only as the map doubles so sprayed keys don't make every failure O(n)
+        if table.attempts.len() >= table.sweep_at {
+            table.attempts.retain(|_, a| {
+                now.duration_since(a.window_start) < window || a.locked_until.is_some_and(|u| u > now)
+            });
+            table.sweep_at = (table.attempts.len() * 2).max(1024);
+        }
+        let entry = table.attempts.entry(key.to_string()).or_insert(Attempts {
+            window_start: now,
+            failures: 0,
+            locked_until: None,
+        });
+        if now.duration_since(entry.window_start) >= window {
+            entry.window_start = now;
+            entry.failures = 0;
+        }
+        entry.failures += 1;
+        let triggered = entry.failures >= max_failures;
+        if triggered {
+            entry.locked_until = Some(now + lockout);
+            entry.window_start = now;
+            entry.failures = 0;
+        }
+        Box::pin(async move { triggered.then_some(lockout) })
+    }
+
+    fn reset(&self, key: &str) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        self.attempts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .attempts
+            .remove(key);
+        Box::pin(async {})
+    }
+}
+
+/// Failure counters and lockouts under `lockout:` keys of a shared
+/// [`KvStore`]; if the store is unreachable nobody is locked out.
+impl LockoutStore for Arc<dyn KvStore> {
+    fn locked_for(&self, key: &str) -> Pin<Box<dyn Future<Output = Option<Duration>> + Send + '_>> {
+        let key = format!("lockout:{key}:locked");
+        Box::pin(async move { store::or_log(self.ttl(&key).await, None) })
+    }
+
+    fn record_failure(
+        &self,
+        key: &str,
+        max_failures: u32,
+        window: Duration,
+        lockout: Duration,
+    ) -> Pin<Box<dyn Future<Output = Option<Duration>> + Send + '_>> {
+        let failures = format!("lockout:{key}:failures");
+        let locked = format!("lockout:{key}:locked");
+        Box::pin(async move {
+            let result = async {
+                // The counter expires `window` after the first failure (fixed windows)
+                if self.incr(&failures, 1, Some(window)).await? < i64::from(max_failures) {
+                    return Ok(None);
+                }
+                self.set(&locked, b"1".to_vec(), Some(lockout)).await?;
+                self.delete(&failures).await?;
+                Ok(Some(lockout))
+            };
+            store::or_log(result.await, None)
+        })
+    }
+
+    fn reset(&self, key: &str) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        let failures = format!("lockout:{key}:failures");
+        Box::pin(async move {
+            store::or_log(self.delete(&failures).await, false);
+        })
+    }
+}
+
+type KeyFn = Arc<dyn Fn(&RequestCtx) -> Option<String> + Send + Sync>;
+type FailureFn = Arc<dyn Fn(&Response) -> bool + Send + Sync>;
+
+/// Lockout middleware configuration.
+#[derive(Clone)]
+pub struct Lockout {
+    store: Arc<dyn LockoutStore>,
+    key: KeyFn,
+    is_failure: FailureFn,
+    max_failures: u32,
+    window: Duration,
+    lockout: Duration,
+    locked_status: StatusCode,
+}
+
+impl Lockout {
+    /// Create a lockout backed by `store`, keyed by client IP: 5 failures
+    /// within 15 minutes lock the key for 15 minutes.
+    pub fn new(store: impl LockoutStore) -> Self {
+        Self {
+            store: Arc::new(store),
+            key: Arc::new(|ctx| ctx.client_ip().map(|ip| ip.to_string())),
+            is_failure: Arc::new(|resp| resp.status() == StatusCode::UNAUTHORIZED),
+            max_failures: 5,
+            window: Duration::from_secs(15 * 60),
+            lockout: Duration::from_secs(15 * 60),
+            locked_status: StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    /// Extract the principal (username, account id) or IP to track.
+    /// Requests without a key are not tracked.
+    pub fn key<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&RequestCtx) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(f);
+        self
+    }
+
+    /// Decide which responses count as a failed attempt (default: status 401).
+    pub fn is_failure<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Response) -> bool + Send + Sync + 'static,
+    {
+        self.is_failure = Arc::new(f);
+        self
+    }
+
+    /// Failures allowed inside the window before the key is locked.
+    pub fn max_failures(mut self, max_failures: u32) -> Self {
+        self.max_failures = max_failures.max(1);
+        self
+    }
+
+    /// Window in which failures are counted.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// How long a key stays locked.
+    pub fn lockout(mut self, lockout: Duration) -> Self {
+        self.lockout = lockout;
+        self
+    }
+
+    /// Status for locked requests: 429 (default) or 423 when locking an account.
+    pub fn locked_status(mut self, status: StatusCode) -> Self {
+        self.locked_status = status;
+        self
+    }
+
+    /// Build the middleware function for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let lockout = Arc::new(self);
+        move |ctx, next| {
+            let lockout = lockout.clone();
+            Box::pin(async move { lockout.handle(ctx, next).await })
+        }
+    }
+
+    async fn handle(&self, ctx: RequestCtx, next: Next) -> Response {
+        let Some(key) = (self.key)(&ctx) else {
+            return next(ctx).await;
+        };
+        if let Some(remaining) = self.store.locked_for(&key).await {
+            return self.locked(remaining);
+        }
+
+        let resp = next(ctx).await;
+        if (self.is_failure)(&resp) {
+            if let Some(lockout) = self
+                .store
+                .record_failure(&key, self.max_failures, self.window, self.lockout)
+                .await
+            {
+                log::warn!("lockout: {key} locked for {}s after repeated failures", lockout.as_secs());
+            }
+        } else if resp.status().is_success() {
+            self.store.reset(&key).await;
+        }
+        resp
+    }
+
+    fn locked(&self, remaining: Duration) -> Response {
+        // Round up so clients never retry while still locked
+        let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+        let mut resp = ResponseBuilder::new()
+            .status(self.locked_status)
+            .content_type("text/plain; charset=utf-8")
+            .build("Too many failed attempts");
+        resp.headers_mut()
+            .insert(hyper::header::RETRY_AFTER, HeaderValue::from(secs));
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_store_locks_and_resets() {
+        let store = MemoryLockoutStore::new();
+        let window = Duration::from_secs(60);
+        let lockout = Duration::from_secs(30);
+
+        assert_eq!(store.record_failure("ip", 3, window, lockout).await, None);
+        assert_eq!(store.record_failure("ip", 3, window, lockout).await, None);
+        store.reset("ip").await;
+        assert_eq!(store.record_failure("ip", 3, window, lockout).await, None);
+        assert_eq!(store.record_failure("ip", 3, window, lockout).await, None);
+        assert_eq!(store.locked_for("ip").await, None);
+
+        assert_eq!(store.record_failure("ip", 3, window, lockout).await, Some(lockout));
+        let remaining = store.locked_for("ip").await.unwrap();
+        assert!(remaining <= lockout && remaining > Duration::from_secs(29));
+        assert_eq!(store.locked_for("other").await, None);
+
+        assert_eq!(store.record_failure("short", 1, window, Duration::ZERO).await, Some(Duration::ZERO));
+        assert_eq!(store.locked_for("short").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_sweeps_stale_keys() {
+        let store = MemoryLockoutStore::new();
+        for i in 0..5000 {
+            store.record_failure(&format!("sprayed{i}"), 2, Duration::ZERO, Duration::ZERO).await;
+        }
+        assert!(store.attempts.lock().unwrap().attempts.len() <= 1024);
+    }
+
+    #[tokio::test]
+    async fn test_middleware_locks_and_resets() {
+        let lockout = Lockout::new(MemoryLockoutStore::new())
+            .max_failures(2)
+            .lockout(Duration::from_millis(90_500))
+            .locked_status(StatusCode::LOCKED)
+            .key(|ctx| ctx.header("x-user").map(str::to_string))
+            .middleware();
+        let next: Next = Arc::new(|ctx| {
+            let status = match ctx.header("x-password") {
+                Some("right") => StatusCode::OK,
+                _ => StatusCode::UNAUTHORIZED,
+            };
+            Box::pin(async move { ResponseBuilder::new().status(status).build("") })
+        });
+        let attempt = |password: &str| {
+            let request = hyper::Request::post("/login").header("x-user", "alice").header("x-password", password);
+            lockout(RequestCtx::from_parts(request.body(()).unwrap().into_parts().0), next.clone())
+        };
+
+        // A success in between clears the count
+        assert_eq!(attempt("wrong").await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(attempt("right").await.status(), StatusCode::OK);
+        assert_eq!(attempt("wrong").await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(attempt("wrong").await.status(), StatusCode::UNAUTHORIZED);
+
+        // Locked even with the right password; Retry-After rounds 90.5s up
+        let response = attempt("right").await;
+        assert_eq!(response.status(), StatusCode::LOCKED);
+        assert_eq!(response.headers()["retry-after"], "91");
+    }
+}
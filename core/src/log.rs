@@ -0,0 +1,42 @@
+//! Framework log output: `tracing` events with the `tracing` feature, plain
+//! stdout/stderr lines otherwise.
+
+/// Lifecycle messages (server started, subsystem stopped, ...). `stderr;`
+/// keeps messages that always went to stderr there without `tracing`.
+macro_rules! info {
+    (stderr; $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::info!($($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        eprintln!($($arg)+);
+    }};
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::info!($($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        println!($($arg)+);
+    }};
+}
+
+/// Diagnostics about misconfiguration or failures the framework recovered from
+macro_rules! warning {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::warn!($($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        eprintln!("[s_web] {}", format_args!($($arg)+));
+    }};
+}
+
+/// Failures that stop the server or one of its parts
+macro_rules! error {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::error!($($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        eprintln!($($arg)+);
+    }};
+}
+
+// `warn` alone would clash with the built-in lint attribute
+pub(crate) use {error, info, warning as warn};
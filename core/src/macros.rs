@@ -0,0 +1,157 @@
+//! Declarative route definitions and response impls.
+
+/// Build an [`App`](crate::App) from a declarative route table.
+///
+/// Each statement ends with `;`:
+///
+/// - `METHOD "/path" => handler;` registers a route; the method is any name
+///   (`GET`, `POST`, `PROPFIND`, ...) or `ANY` for every method. Route options
+///   follow the handler: `GET "/me" => me, secured(SecurityScheme::Bearer);`
+/// - `middleware expr;` adds middleware to the routes of the enclosing block
+/// - `not_found handler;` answers unmatched paths under the block's prefix
+/// - `group "/prefix" { ... }` nests a block; its middleware runs after the
+///   outer block's
+///
+/// ```rust,ignore
+/// use s_web::routes;
+///
+/// app.mount("/", routes! {
+///     middleware access_log;
+///     GET "/" => index;
+///     group "/api" {
+///         middleware auth;
+///         GET "/users" => list_users;
+///         POST "/users" => create_user, body_limit(64 * 1024);
+///         DELETE "/users/:id" => delete_user, secured(SecurityScheme::Bearer);
+///     }
+/// });
+/// ```
+///
+/// Every statement is one step of macro recursion; blocks with more than
+/// about a hundred statements need a higher `#![recursion_limit]` (or can be
+/// split into groups).
+#[macro_export]
+macro_rules! routes {
+    ($($body:tt)*) => {{
+        let mut app = $crate::App::new();
+        $crate::__routes_body!(app; $($body)*);
+        app
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __routes_body {
+    ($app:ident;) => {};
+    ($app:ident; middleware $middleware:expr; $($rest:tt)*) => {
+        $app.use_middleware($middleware);
+        $crate::__routes_body!($app; $($rest)*);
+    };
+    ($app:ident; not_found $handler:expr; $($rest:tt)*) => {
+        $app.not_found($handler);
+        $crate::__routes_body!($app; $($rest)*);
+    };
+    ($app:ident; group $prefix:literal { $($inner:tt)* } $($rest:tt)*) => {
+        $app.mount($prefix, $crate::routes! { $($inner)* });
+        $crate::__routes_body!($app; $($rest)*);
+    };
+    ($app:ident; ANY $path:literal => $handler:expr $(, $option:ident($($arg:expr),* $(,)?))*; $($rest:tt)*) => {
+        $app.any($path, $handler)$(.$option($($arg),*))*;
+        $crate::__routes_body!($app; $($rest)*);
+    };
+    ($app:ident; $method:ident $path:literal => $handler:expr $(, $option:ident($($arg:expr),* $(,)?))*; $($rest:tt)*) => {
+        $app.route(stringify!($method), $path, $handler)$(.$option($($arg),*))*;
+        $crate::__routes_body!($app; $($rest)*);
+    };
+}
+
+/// Implement [`IntoResponse`](crate::IntoResponse) for `Serialize` types so
+/// handlers can return them directly as JSON (status 200; wrap them in a
+/// tuple to pick another). Equivalent to returning [`Json(value)`](crate::Json).
+///
+/// ```rust,ignore
+/// #[derive(serde::Serialize)]
+/// struct User { id: u32, name: String }
+///
+/// s_web::json_response!(User);
+///
+/// app.get("/me", |_| async { User { id: 1, name: "Alice".into() } });
+/// ```
+#[macro_export]
+macro_rules! json_response {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl $crate::IntoResponse for $ty {
+                fn into_response(self) -> $crate::Response {
+                    $crate::IntoResponse::into_response($crate::Json(self))
+                }
+            }
+        )+
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{IntoResponse, Next, RequestCtx, ResponseBuilder, Router, SecurityScheme, health::HealthRegistry};
+
+    async fn ok(_ctx: RequestCtx) -> crate::Response {
+        ResponseBuilder::new().build("ok")
+    }
+
+    #[test]
+    fn test_routes_macro_expands_to_builder_calls() {
+        let app = crate::routes! {
+            middleware |ctx: RequestCtx, next: Next| async move { next(ctx).await };
+            GET "/" => ok;
+            ANY "/echo" => ok;
+            group "/api" {
+                not_found ok;
+                GET "/users" => ok;
+                POST "/users" => ok, body_limit(1024), hide_from_docs();
+                propfind "/files/*path" => ok, secured(SecurityScheme::Bearer);
+                group "/admin" {
+                    DELETE "/users/:id" => ok;
+                }
+            }
+        };
+
+        let mut router = Router::new();
+        app.merge_into("", &mut router, &HealthRegistry::new());
+        let mut routes: Vec<_> = router
+            .routes()
+            .into_iter()
+            .map(|r| (r.method, r.pattern, r.hidden, r.security.len()))
+            .collect();
+        routes.sort();
+        let expected = [
+            ("*", "/echo", false, 0),
+            ("DELETE", "/api/admin/users/:id", false, 0),
+            ("GET", "/", false, 0),
+            ("GET", "/api/users", false, 0),
+            ("POST", "/api/users", true, 0),
+            ("PROPFIND", "/api/files/*path", false, 1),
+        ]
+        .map(|(m, p, h, s)| (m.to_string(), p.to_string(), h, s));
+        assert_eq!(routes, expected);
+        assert!(router.not_found_handler().is_some());
+    }
+
+    #[derive(serde::Serialize)]
+    struct User {
+        id: u32,
+    }
+
+    #[derive(serde::Serialize)]
+    struct Page(Vec<User>);
+
+    crate::json_response!(User, Page);
+
+    #[test]
+    fn test_json_response_macro() {
+        let response = (hyper::StatusCode::CREATED, User { id: 1 }).into_response();
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+        assert_eq!(response.headers()["content-type"], "application/json; charset=utf-8");
+        let response = Page(vec![User { id: 1 }]).into_response();
+        assert_eq!(response.headers()["content-type"], "application/json; charset=utf-8");
+    }
+}
@@ -0,0 +1,271 @@
+//! Request latency histograms in OpenMetrics format, with trace exemplars.
+//!
+//! ```rust,ignore
+//! use s_web::metrics::Metrics;
+//!
+//! let metrics = Metrics::new();
+//! app.use_middleware(metrics.clone().middleware());
+//! app.get("/metrics", metrics.handler());
+//! ```
+//!
+//! Each bucket carries an exemplar — the latest request that landed in it
+//! together with its trace id — so Grafana can jump from a slow bucket to the
+//! exact trace. The trace id is taken from a [`TraceId`] that tracing
+//! middleware put in the response or request extensions, falling back to the
+//! W3C `traceparent` request header.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Handler, MatchedRoute, Next, RequestCtx, Response, ResponseBuilder};
+
+const METRIC: &str = "http_request_duration_seconds";
+const DEFAULT_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Trace id of the current request, shared between tracing and metrics
+/// middleware through request or response extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceId(pub String);
+
+impl TraceId {
+    /// Trace id from a W3C `traceparent` header (`00-<trace-id>-<span-id>-<flags>`).
+    pub fn from_traceparent(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let _version = parts.next()?;
+        let id = parts.next()?;
+        let valid = id.len() == 32
+            && id.bytes().all(|b| b.is_ascii_hexdigit())
+            && id.bytes().any(|b| b != b'0');
+        valid.then(|| Self(id.to_ascii_lowercase()))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Exemplar {
+    trace_id: String,
+    value: f64,
+    timestamp: f64,
+}
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    counts: Vec<u64>,
+    exemplars: Vec<Option<Exemplar>>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(buckets: usize) -> Self {
+        Self {
+            counts: vec![0; buckets],
+            exemplars: vec![None; buckets + 1],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+}
+
+/// (method, route, status)
+type SeriesKey = (String, String, u16);
+
+/// Request latency histograms shared between the recording middleware and
+/// the exposition handler. Clones share the same data.
+#[derive(Clone)]
+pub struct Metrics {
+    buckets: Arc<Vec<f64>>,
+    series: Arc<Mutex<BTreeMap<SeriesKey, Histogram>>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Histograms with the usual 5ms..10s latency buckets.
+    pub fn new() -> Self {
+        Self {
+            buckets: Arc::new(DEFAULT_BUCKETS.to_vec()),
+            series: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Replace the bucket upper bounds (seconds). Call before recording starts.
+    pub fn buckets(mut self, bounds: &[f64]) -> Self {
+        let mut bounds = bounds.to_vec();
+        bounds.retain(|b| b.is_finite());
+        bounds.sort_by(f64::total_cmp);
+        bounds.dedup();
+        self.buckets = Arc::new(bounds);
+        self
+    }
+
+    /// Build the recording middleware for `use_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let metrics = Arc::new(self);
+        move |ctx, next| {
+            let metrics = metrics.clone();
+            Box::pin(async move {
+                let start = Instant::now();
+                let method = ctx.request.method().as_str().to_string();
+                let trace_id = ctx
+                    .request
+                    .extensions()
+                    .get::<TraceId>()
+                    .cloned()
+                    .or_else(|| ctx.header("traceparent").and_then(TraceId::from_traceparent));
+
+                let response = next(ctx).await;
+
+                let route = response
+                    .extensions()
+                    .get::<MatchedRoute>()
+                    .map_or("unmatched", |route| &*route.0)
+                    .to_string();
+                // Tracing middleware running inside this one reports through the response
+                let trace_id = response.extensions().get::<TraceId>().cloned().or(trace_id);
+                metrics.observe(
+                    (method, route, response.status().as_u16()),
+                    start.elapsed().as_secs_f64(),
+                    trace_id,
+                );
+                response
+            })
+        }
+    }
+
+    /// Handler serving [`render`](Self::render) for a `/metrics` route.
+    pub fn handler(&self) -> impl Handler {
+        let metrics = self.clone();
+        move |_ctx: RequestCtx| {
+            let body = metrics.render();
+            async move {
+                ResponseBuilder::new()
+                    .content_type("application/openmetrics-text; version=1.0.0; charset=utf-8")
+                    .build(body)
+            }
+        }
+    }
+
+    fn observe(&self, key: SeriesKey, seconds: f64, trace_id: Option<TraceId>) {
+        let mut series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+        let histogram = series
+            .entry(key)
+            .or_insert_with(|| Histogram::new(self.buckets.len()));
+        histogram.count += 1;
+        histogram.sum += seconds;
+        for (count, _) in histogram
+            .counts
+            .iter_mut()
+            .zip(self.buckets.iter())
+            .filter(|(_, bound)| seconds <= **bound)
+        {
+            *count += 1;
+        }
+        if let Some(TraceId(trace_id)) = trace_id {
+            // The exemplar belongs to the smallest bucket containing the value (or +Inf)
+            let slot = self
+                .buckets
+                .iter()
+                .position(|bound| seconds <= *bound)
+                .unwrap_or(self.buckets.len());
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64())
+                .unwrap_or(0.0);
+            histogram.exemplars[slot] = Some(Exemplar {
+                trace_id,
+                value: seconds,
+                timestamp,
+            });
+        }
+    }
+
+    /// All histograms in the OpenMetrics text format.
+    pub fn render(&self) -> String {
+        let series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out = String::new();
+        let _ = writeln!(out, "# TYPE {METRIC} histogram");
+        let _ = writeln!(out, "# UNIT {METRIC} seconds");
+        let _ = writeln!(out, "# HELP {METRIC} Time from request to response headers.");
+        for ((method, route, status), histogram) in series.iter() {
+            let labels = format!(
+                "method=\"{}\",route=\"{}\",status=\"{status}\"",
+                escape(method),
+                escape(route)
+            );
+            let bounds = self.buckets.iter().map(|b| b.to_string());
+            let counts = histogram.counts.iter().copied();
+            let buckets = bounds
+                .chain(["+Inf".to_string()])
+                .zip(counts.chain([histogram.count]));
+            for ((le, count), exemplar) in buckets.zip(&histogram.exemplars) {
+                let _ = write!(out, "{METRIC}_bucket{{{labels},le=\"{le}\"}} {count}");
+                if let Some(e) = exemplar {
+                    let _ = write!(
+                        out,
+                        " # {{trace_id=\"{}\"}} {} {:.3}",
+                        escape(&e.trace_id),
+                        e.value,
+                        e.timestamp
+                    );
+                }
+                out.push('\n');
+            }
+            let _ = writeln!(out, "{METRIC}_count{{{labels}}} {}", histogram.count);
+            let _ = writeln!(out, "{METRIC}_sum{{{labels}}} {}", histogram.sum);
+        }
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traceparent_parsing() {
+        let id = TraceId::from_traceparent("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01");
+        assert_eq!(id, Some(TraceId("4bf92f3577b34da6a3ce929d0e0e4736".into())));
+        assert_eq!(TraceId::from_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01"), None);
+        assert_eq!(TraceId::from_traceparent("garbage"), None);
+    }
+
+    #[test]
+    fn test_render_with_exemplars() {
+        let metrics = Metrics::new().buckets(&[0.1, 1.0]);
+        let key = ("GET".to_string(), "/users/:id".to_string(), 200);
+        metrics.observe(key.clone(), 0.05, None);
+        metrics.observe(key.clone(), 0.5, Some(TraceId("abc".into())));
+        metrics.observe(key, 3.0, Some(TraceId("slow".into())));
+
+        let text = metrics.render();
+        let labels = r#"method="GET",route="/users/:id",status="200""#;
+        assert!(text.contains(&format!("{METRIC}_bucket{{{labels},le=\"0.1\"}} 1\n")));
+        assert!(text.contains(&format!("{METRIC}_bucket{{{labels},le=\"1\"}} 2 # {{trace_id=\"abc\"}} 0.5 ")));
+        assert!(text.contains(&format!("{METRIC}_bucket{{{labels},le=\"+Inf\"}} 3 # {{trace_id=\"slow\"}} 3 ")));
+        assert!(text.contains(&format!("{METRIC}_count{{{labels}}} 3\n")));
+        assert!(text.ends_with("# EOF\n"));
+    }
+}
@@ -2,6 +2,11 @@
 //! 
 //! This middleware system allows using async functions directly as middleware,
 //! providing a clean and intuitive API without boilerplate.
+//!
+//! Passing a `RequestCtx` down the chain never touches the request body: it stays
+//! an unread stream until a handler or extractor asks for it, so proxy and upload
+//! handlers can forward it end-to-end with `ctx.take_body()`. A middleware that
+//! reads the body (`body_bytes`, `json`, ...) buffers it for everyone after it.
 
 use std::{sync::Arc, future::Future, pin::Pin};
 use crate::{RequestCtx, Response};
@@ -30,31 +35,55 @@ where
     }
 }
 
-/// Execute a middleware chain.
+/// Fold `middlewares` around `endpoint` into a single `Next`, the first
+/// middleware outermost.
 ///
-/// Takes an `Arc<Vec<Middleware>>` so inner `Next` closures can hold a cheap `Arc` clone
-/// (reference-count bump) instead of a deep `Vec` clone per middleware layer.
-pub async fn execute_chain(middlewares: Arc<Vec<Middleware>>, endpoint: Next, ctx: RequestCtx) -> Response {
-    execute_at(middlewares, 0, endpoint, ctx).await
+/// Chains are composed once, when routes and groups are registered or the
+/// server starts, so running one per request only bumps the reference count
+/// of each layer's `Next` instead of building closures and boxing an executor
+/// future for every layer.
+///
+/// This holds with [`RoutesHandle`](crate::RoutesHandle) swapping route
+/// tables at runtime: a route's chain lives in the route, so copying the
+/// router on a change shares every existing chain and only composes the ones
+/// of routes being added, and the global pipeline looks up the current router
+/// per request instead of capturing one.
+pub(crate) fn compose(middlewares: &[Middleware], endpoint: Next) -> Next {
+    middlewares.iter().rev().fold(endpoint, |next, middleware| {
+        let middleware = middleware.clone();
+        Arc::new(move |ctx| middleware(ctx, next.clone()))
+    })
 }
 
-/// Recursive index-based executor — no Vec allocation per layer.
-fn execute_at(
-    middlewares: Arc<Vec<Middleware>>,
-    index: usize,
-    endpoint: Next,
-    ctx: RequestCtx,
-) -> Pin<Box<dyn Future<Output = Response> + Send>> {
-    Box::pin(async move {
-        if index >= middlewares.len() {
-            return endpoint(ctx).await;
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::ResponseBuilder;
+
+    #[tokio::test]
+    async fn test_compose_runs_first_middleware_outermost() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let layer = |name: &'static str| -> Middleware {
+            let order = order.clone();
+            Arc::new(move |ctx, next: Next| {
+                let order = order.clone();
+                Box::pin(async move {
+                    order.lock().unwrap().push(name);
+                    let response = next(ctx).await;
+                    order.lock().unwrap().push(name);
+                    response
+                })
+            })
+        };
+        let endpoint = (|_ctx| async { ResponseBuilder::new().build("ok") }).into_next();
+        let chain = compose(&[layer("outer"), layer("inner")], endpoint);
+
+        for _ in 0..2 {
+            let request = hyper::Request::get("/").body(()).unwrap();
+            chain(RequestCtx::from_parts(request.into_parts().0)).await;
         }
-        let mw = middlewares[index].clone();
-        let next: Next = Arc::new(move |ctx| {
-            let mws = middlewares.clone();   // cheap Arc clone, not Vec clone
-            let ep = endpoint.clone();
-            execute_at(mws, index + 1, ep, ctx)
-        });
-        mw(ctx, next).await
-    })
+        assert_eq!(*order.lock().unwrap(), ["outer", "inner", "inner", "outer"].repeat(2));
+    }
 }
@@ -0,0 +1,174 @@
+//! sqlx migration runner executed before the server accepts traffic (`migrate` feature).
+//!
+//! ```rust,ignore
+//! use s_web::migrate::Migrations;
+//!
+//! let app = Engine::new().migrate(Migrations::new("./migrations"), pool.clone());
+//! ```
+//!
+//! Migrations run as a preflight check, so a failure aborts `run` before the
+//! listener is bound. Applied versions are published under `migrations` in the
+//! health report.
+
+use std::path::PathBuf;
+
+use sqlx::{
+    Database, Pool,
+    migrate::{Migrate, MigrateError, Migrator},
+};
+
+/// Location and options of a migrations directory.
+#[derive(Debug, Clone)]
+pub struct Migrations {
+    dir: PathBuf,
+    locking: bool,
+    ignore_missing: bool,
+    skip: bool,
+}
+
+impl Migrations {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            locking: true,
+            ignore_missing: false,
+            skip: false,
+        }
+    }
+
+    /// Take a database lock while migrating so concurrent instances don't race (default on).
+    pub fn locking(mut self, locking: bool) -> Self {
+        self.locking = locking;
+        self
+    }
+
+    /// Tolerate applied migrations that are missing from the directory.
+    pub fn ignore_missing(mut self, ignore: bool) -> Self {
+        self.ignore_missing = ignore;
+        self
+    }
+
+    /// Don't apply anything, only report what is already applied.
+    /// Useful when a separate job owns the schema.
+    pub fn skip(mut self, skip: bool) -> Self {
+        self.skip = skip;
+        self
+    }
+
+    /// Apply pending migrations and return the versions now applied.
+    pub async fn run<DB>(&self, pool: &Pool<DB>) -> Result<Vec<i64>, MigrateError>
+    where
+        DB: Database,
+        DB::Connection: Migrate,
+    {
+        if !self.skip {
+            let mut migrator = Migrator::new(self.dir.clone()).await?;
+            migrator.set_locking(self.locking);
+            migrator.set_ignore_missing(self.ignore_missing);
+            migrator.run(pool).await?;
+        }
+
+        let mut conn = pool.acquire().await?;
+        let applied = match conn.list_applied_migrations().await {
+            Ok(applied) => applied,
+            // In skip mode the migrations table may legitimately not exist yet.
+            Err(_) if self.skip => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(applied.into_iter().map(|m| m.version).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use sqlx::{SqlitePool, sqlite::SqlitePoolOptions};
+
+    use super::*;
+
+    /// A migrations directory holding `files`, unique to the calling test
+    fn migrations_dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("s_web-migrate-{}-{test}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, sql) in files {
+            fs::write(dir.join(name), sql).unwrap();
+        }
+        dir
+    }
+
+    /// One connection, so every query sees the same in-memory database
+    async fn pool() -> SqlitePool {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    const CREATE: (&str, &str) = ("1_create_items.sql", "CREATE TABLE items (name TEXT NOT NULL);");
+    const ALTER: (&str, &str) = ("2_add_price.sql", "ALTER TABLE items ADD COLUMN price INTEGER;");
+
+    #[tokio::test]
+    async fn test_applies_in_order_and_skips_applied() {
+        let dir = migrations_dir("order", &[ALTER, CREATE]);
+        let pool = pool().await;
+        // The ALTER fails unless the CREATE ran first
+        assert_eq!(Migrations::new(&dir).run(&pool).await.unwrap(), [1, 2]);
+        sqlx::query("INSERT INTO items (name, price) VALUES ('widget', 3)").execute(&pool).await.unwrap();
+
+        // Re-running the CREATE would fail; only the new version is applied
+        fs::write(dir.join("3_add_stock.sql"), "ALTER TABLE items ADD COLUMN stock INTEGER;").unwrap();
+        assert_eq!(Migrations::new(&dir).locking(false).run(&pool).await.unwrap(), [1, 2, 3]);
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM items").fetch_one(&pool).await.unwrap();
+        assert_eq!(count, 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_skip_only_reports() {
+        let dir = migrations_dir("skip", &[CREATE]);
+        let pool = pool().await;
+        // No migrations table yet: nothing applied, and nothing run
+        assert!(Migrations::new(&dir).skip(true).run(&pool).await.unwrap().is_empty());
+        assert!(sqlx::query("SELECT * FROM items").execute(&pool).await.is_err());
+
+        Migrations::new(&dir).run(&pool).await.unwrap();
+        fs::write(dir.join(ALTER.0), ALTER.1).unwrap();
+        assert_eq!(Migrations::new(&dir).skip(true).run(&pool).await.unwrap(), [1]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_migrations() {
+        let dir = migrations_dir("missing", &[CREATE, ALTER]);
+        let pool = pool().await;
+        Migrations::new(&dir).run(&pool).await.unwrap();
+
+        fs::remove_file(dir.join(ALTER.0)).unwrap();
+        let err = Migrations::new(&dir).run(&pool).await.unwrap_err();
+        assert!(matches!(err, MigrateError::VersionMissing(2)), "{err}");
+        assert_eq!(Migrations::new(&dir).ignore_missing(true).run(&pool).await.unwrap(), [1, 2]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_applied_versions_reach_the_health_report() {
+        let dir = migrations_dir("health", &[CREATE, ALTER]);
+        let mut app = crate::Engine::new().migrate(Migrations::new(&dir), pool().await);
+        app.health_check("/health");
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/health", listener.local_addr().unwrap());
+        let server = tokio::spawn(app.run_with_listener(listener));
+
+        // Migrations run before the listener serves anything
+        let response = crate::client::Client::new().get(&url).send().await.unwrap();
+        let report: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(report["info"]["migrations"]["applied"], serde_json::json!([1, 2]));
+        server.abort();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+//! OpenTelemetry trace propagation.
+//!
+//! With the `otel` feature the engine reads the caller's trace context from
+//! W3C `traceparent`/`tracestate` or Zipkin `b3` (single header or
+//! `X-B3-*`) headers, makes it the parent of the request's `request` span,
+//! runs the matched handler in a `handler` span named after the route, and
+//! answers with the request span's own context in the format the caller
+//! used (`traceparent` when it sent none). Spans reach OpenTelemetry through
+//! the `tracing-opentelemetry` layer, which must be installed:
+//!
+//! ```rust,ignore
+//! use tracing_subscriber::prelude::*;
+//!
+//! let tracer = opentelemetry_otlp::SpanExporter::builder() /* ... */;
+//! tracing_subscriber::registry()
+//!     .with(tracing_opentelemetry::layer().with_tracer(tracer))
+//!     .init();
+//! ```
+//!
+//! Calls to other services continue the trace with [`inject`]:
+//!
+//! ```rust,ignore
+//! let mut headers = HeaderMap::new();
+//! s_web::otel::inject(&mut headers, Propagation::TraceContext);
+//! client.get(url).headers(headers).send().await?;
+//! ```
+//!
+//! Without the layer nothing is exported and no header is added to
+//! responses; the caller's context is still available as
+//! [`RequestCtx::remote_span_context`].
+
+use hyper::{
+    HeaderMap,
+    header::{HeaderName, HeaderValue},
+};
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::{RequestCtx, Response};
+
+const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+const TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+const B3: HeaderName = HeaderName::from_static("b3");
+
+/// Header format of a trace context.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Propagation {
+    /// W3C Trace Context: `traceparent` and `tracestate`
+    TraceContext,
+    /// Zipkin B3; read from `b3` or `X-B3-*`, written as a single `b3` header
+    B3,
+}
+
+/// Trace context sent by the caller, kept in the request extensions
+#[derive(Debug, Clone)]
+pub(crate) struct RemoteContext {
+    pub(crate) span_context: SpanContext,
+    pub(crate) propagation: Propagation,
+}
+
+/// The engine's span for one request, named after the route once it is matched
+#[derive(Debug, Clone)]
+pub(crate) struct RequestSpan(pub(crate) Span);
+
+impl RequestCtx {
+    /// Trace context the caller sent in `traceparent` or `b3` headers, if any.
+    pub fn remote_span_context(&self) -> Option<&SpanContext> {
+        self.request
+            .extensions()
+            .get::<RemoteContext>()
+            .map(|remote| &remote.span_context)
+    }
+}
+
+/// Read the caller's trace context; `traceparent` wins over `b3`
+pub(crate) fn extract(headers: &HeaderMap) -> Option<RemoteContext> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let (span_context, propagation) = if let Some(traceparent) = header("traceparent") {
+        let state = header("tracestate").and_then(|s| s.parse().ok()).unwrap_or_default();
+        (parse_traceparent(traceparent, state)?, Propagation::TraceContext)
+    } else if let Some(b3) = header("b3") {
+        (parse_b3(b3)?, Propagation::B3)
+    } else {
+        let sampled = match (header("x-b3-flags"), header("x-b3-sampled")) {
+            (Some("1"), _) => "d",
+            (_, Some(sampled)) => sampled,
+            _ => "",
+        };
+        let b3 = format!("{}-{}-{sampled}", header("x-b3-traceid")?, header("x-b3-spanid")?);
+        (parse_b3(b3.trim_end_matches('-'))?, Propagation::B3)
+    };
+    Some(RemoteContext {
+        span_context,
+        propagation,
+    })
+}
+
+/// `version-traceid-spanid-flags`; later versions may append fields
+fn parse_traceparent(value: &str, state: TraceState) -> Option<SpanContext> {
+    let mut parts = value.trim().split('-');
+    let (version, trace_id, span_id, flags) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    if !is_hex(version, 2) || version == "ff" || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+    let flags = u8::from_str_radix(flags, 16).ok().filter(|_| is_hex(flags, 2))?;
+    remote(trace_id, span_id, TraceFlags::new(flags) & TraceFlags::SAMPLED, state)
+}
+
+/// `traceid-spanid[-sampled[-parentspanid]]`; a lone `0`/`1` carries no context
+fn parse_b3(value: &str) -> Option<SpanContext> {
+    let mut parts = value.trim().split('-');
+    let (trace_id, span_id) = (parts.next()?, parts.next()?);
+    let flags = match parts.next() {
+        Some("1" | "d" | "true") => TraceFlags::SAMPLED,
+        Some("0" | "false") => TraceFlags::default(),
+        // Deferred: let the local sampler decide, as for an unsampled parent
+        None => TraceFlags::default(),
+        Some(_) => return None,
+    };
+    // 64-bit trace ids are left-padded to 128 bits
+    let trace_id = match trace_id.len() {
+        16 => format!("{trace_id:0>32}"),
+        _ => trace_id.to_owned(),
+    };
+    remote(&trace_id, span_id, flags, TraceState::NONE)
+}
+
+fn remote(trace_id: &str, span_id: &str, flags: TraceFlags, state: TraceState) -> Option<SpanContext> {
+    if !is_hex(trace_id, 32) || !is_hex(span_id, 16) {
+        return None;
+    }
+    let trace_id = TraceId::from_hex(trace_id).ok()?;
+    let span_id = SpanId::from_hex(span_id).ok()?;
+    let context = SpanContext::new(trace_id, span_id, flags, true, state);
+    context.is_valid().then_some(context)
+}
+
+/// Lowercase hex of exactly `len` digits
+fn is_hex(s: &str, len: usize) -> bool {
+    s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Write `span_context` into `headers` in the given format
+fn write(headers: &mut HeaderMap, span_context: &SpanContext, propagation: Propagation) {
+    let sampled = span_context.is_sampled();
+    let value = match propagation {
+        Propagation::TraceContext => format!(
+            "00-{}-{}-{:02x}",
+            span_context.trace_id(),
+            span_context.span_id(),
+            u8::from(sampled)
+        ),
+        Propagation::B3 => format!(
+            "{}-{}-{}",
+            span_context.trace_id(),
+            span_context.span_id(),
+            u8::from(sampled)
+        ),
+    };
+    let Ok(value) = HeaderValue::from_str(&value) else {
+        return;
+    };
+    match propagation {
+        Propagation::TraceContext => {
+            headers.insert(TRACEPARENT, value);
+            let state = span_context.trace_state().header();
+            if let Ok(state) = HeaderValue::from_str(&state)
+                && !state.is_empty()
+            {
+                headers.insert(TRACESTATE, state);
+            }
+        }
+        Propagation::B3 => {
+            headers.insert(B3, value);
+        }
+    }
+}
+
+/// Add the current span's trace context to `headers`, e.g. for a call to
+/// another service. Does nothing outside an OpenTelemetry-backed span.
+pub fn inject(headers: &mut HeaderMap, propagation: Propagation) {
+    let context = Span::current().context();
+    let span = context.span();
+    if span.span_context().is_valid() {
+        write(headers, span.span_context(), propagation);
+    }
+}
+
+/// Make the caller's context the parent of the engine's `request` span
+pub(crate) fn set_parent(span: &Span, remote: Option<&RemoteContext>) {
+    if let Some(remote) = remote {
+        let parent = opentelemetry::Context::new().with_remote_span_context(remote.span_context.clone());
+        // Fails only when no OpenTelemetry layer is installed
+        let _ = span.set_parent(parent);
+    }
+}
+
+/// Answer with the request span's context in the caller's format
+pub(crate) fn inject_response(span: &Span, remote: Option<Propagation>, response: &mut Response) {
+    let context = span.context();
+    let span = context.span();
+    if span.span_context().is_valid() {
+        write(
+            response.headers_mut(),
+            span.span_context(),
+            remote.unwrap_or(Propagation::TraceContext),
+        );
+    }
+}
+
+/// Span the matched handler runs in; also names the request span `METHOD /route/pattern`
+pub(crate) fn handler_span(ctx: &RequestCtx, pattern: &str) -> Span {
+    if let Some(RequestSpan(span)) = ctx.request.extensions().get::<RequestSpan>() {
+        span.record("otel.name", format!("{} {pattern}", ctx.request.method()));
+        span.record("http.route", pattern);
+    }
+    tracing::info_span!("handler", http.route = pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(k, v)| (HeaderName::from_static(k), HeaderValue::from_static(v)))
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_traceparent_and_b3() {
+        let remote = extract(&headers(&[
+            ("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            ("tracestate", "congo=t61rcWkgMzE"),
+            ("b3", "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1"),
+        ]))
+        .unwrap();
+        assert_eq!(remote.propagation, Propagation::TraceContext);
+        let cx = &remote.span_context;
+        assert_eq!(cx.trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(cx.span_id().to_string(), "00f067aa0ba902b7");
+        assert!(cx.is_sampled() && cx.is_remote());
+        assert_eq!(cx.trace_state().get("congo"), Some("t61rcWkgMzE"));
+
+        let remote = extract(&headers(&[("b3", "a3ce929d0e0e4736-00f067aa0ba902b7-0")])).unwrap();
+        assert_eq!(remote.propagation, Propagation::B3);
+        assert_eq!(remote.span_context.trace_id().to_string(), "0000000000000000a3ce929d0e0e4736");
+        assert!(!remote.span_context.is_sampled());
+
+        let remote = extract(&headers(&[
+            ("x-b3-traceid", "80f198ee56343ba864fe8b2a57d3eff7"),
+            ("x-b3-spanid", "e457b5a2e4d86bd1"),
+            ("x-b3-sampled", "1"),
+        ]))
+        .unwrap();
+        assert!(remote.span_context.is_sampled());
+
+        for bad in [
+            ("traceparent", "00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            ("traceparent", "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            ("traceparent", "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01"),
+            ("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra"),
+            ("b3", "0"),
+            ("b3", "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-x"),
+        ] {
+            assert!(extract(&headers(&[bad])).is_none(), "{bad:?}");
+        }
+        // Later versions may add fields
+        assert!(extract(&headers(&[(
+            "traceparent",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra"
+        )]))
+        .is_some());
+    }
+
+    #[test]
+    fn test_write_round_trips() {
+        let remote = extract(&headers(&[
+            ("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            ("tracestate", "congo=t61rcWkgMzE"),
+        ]))
+        .unwrap();
+        let mut out = HeaderMap::new();
+        write(&mut out, &remote.span_context, Propagation::TraceContext);
+        assert_eq!(out["traceparent"], "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+        assert_eq!(out["tracestate"], "congo=t61rcWkgMzE");
+
+        let mut out = HeaderMap::new();
+        write(&mut out, &remote.span_context, Propagation::B3);
+        assert_eq!(out["b3"], "4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1");
+        let b3 = extract(&out).unwrap().span_context;
+        assert_eq!((b3.trace_id(), b3.span_id()), (remote.span_context.trace_id(), remote.span_context.span_id()));
+    }
+}
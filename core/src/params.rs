@@ -0,0 +1,138 @@
+//! Path parameters captured by routing.
+
+use std::{fmt, ops::Range, sync::Arc};
+
+use hyper::http::uri::PathAndQuery;
+use smallvec::SmallVec;
+
+/// Parameters a route match captures: name and byte range into the path
+pub(crate) type Captures = SmallVec<[(Arc<str>, Range<usize>); 4]>;
+
+#[derive(Clone)]
+enum Value {
+    /// Range into the request path
+    Path(Range<usize>),
+    /// Set by middleware with `add_param`
+    Owned(String),
+}
+
+/// Path parameters of a request, read with
+/// [`RequestCtx::get_param`](crate::RequestCtx::get_param) and
+/// [`RequestCtx::param`](crate::RequestCtx::param).
+///
+/// Captured values point into the request path instead of being copied, and
+/// names are shared with the route, so matching a route with a few parameters
+/// allocates nothing.
+#[derive(Clone, Default)]
+pub struct Params {
+    /// Request path the captured ranges point into (a reference-counted handle)
+    path: Option<PathAndQuery>,
+    entries: SmallVec<[(Arc<str>, Value); 4]>,
+}
+
+impl Params {
+    /// Value of the parameter `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| &**key == name)
+            .and_then(|(_, value)| self.resolve(value))
+    }
+
+    /// Whether the parameter `name` is set
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.iter().any(|(key, _)| &**key == name)
+    }
+
+    /// Set the parameter `name`, replacing its previous value
+    pub fn insert(&mut self, name: impl Into<Arc<str>>, value: impl Into<String>) {
+        self.set(name.into(), Value::Owned(value.into()));
+    }
+
+    /// Every parameter as `(name, value)`
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .filter_map(|(key, value)| Some((&**key, self.resolve(value)?)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add the parameters a route captured from `path`, replacing ones with the
+    /// same names
+    pub(crate) fn capture(&mut self, path: &PathAndQuery, captures: Captures) {
+        if self.path.as_ref().is_some_and(|old| old != path) {
+            // Ranges into the previous path would read the wrong bytes
+            for index in 0..self.entries.len() {
+                if let Value::Path(_) = self.entries[index].1 {
+                    let value = self.resolve(&self.entries[index].1).unwrap_or_default().to_string();
+                    self.entries[index].1 = Value::Owned(value);
+                }
+            }
+        }
+        self.path = Some(path.clone());
+        for (name, range) in captures {
+            self.set(name, Value::Path(range));
+        }
+    }
+
+    fn set(&mut self, name: Arc<str>, value: Value) {
+        match self.entries.iter_mut().find(|(key, _)| *key == name) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((name, value)),
+        }
+    }
+
+    fn resolve<'a>(&'a self, value: &'a Value) -> Option<&'a str> {
+        match value {
+            Value::Path(range) => self.path.as_ref()?.path().get(range.clone()),
+            Value::Owned(value) => Some(value),
+        }
+    }
+}
+
+impl Extend<(String, String)> for Params {
+    fn extend<I: IntoIterator<Item = (String, String)>>(&mut self, params: I) {
+        for (name, value) in params {
+            self.insert(name, value);
+        }
+    }
+}
+
+impl fmt::Debug for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_captured_and_inserted() {
+        let mut params = Params::default();
+        params.insert("tenant", "acme");
+        let path = PathAndQuery::from_static("/users/42/posts/7?x=1");
+        params.capture(&path, Captures::from_iter([("id".into(), 7..9), ("post".into(), 16..17)]));
+        assert_eq!(params.get("id"), Some("42"));
+        assert_eq!(params.get("post"), Some("7"));
+        assert_eq!(params.get("tenant"), Some("acme"));
+        assert_eq!(params.get("missing"), None);
+
+        params.insert("id", "43");
+        assert_eq!(params.get("id"), Some("43"));
+        assert_eq!(params.len(), 3);
+
+        // Values captured from an earlier path survive a capture from another
+        params.capture(&PathAndQuery::from_static("/v2"), Captures::from_iter([("v".into(), 1..3)]));
+        assert_eq!(params.get("post"), Some("7"));
+        assert_eq!(params.get("v"), Some("v2"));
+    }
+}
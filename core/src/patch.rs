@@ -0,0 +1,368 @@
+//! Standard bodies for `PATCH` endpoints.
+//!
+//! [`JsonPatch`] is an RFC 6902 list of operations (`application/json-patch+json`),
+//! [`MergePatch`] an RFC 7396 partial document (`application/merge-patch+json`).
+//! Both apply to a `serde_json::Value` or to any entity that round-trips
+//! through JSON:
+//!
+//! ```rust,ignore
+//! app.patch("/users/:id", |mut ctx| async move {
+//!     let patch = match ctx.json_patch().await {
+//!         Ok(patch) => patch,
+//!         Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+//!     };
+//!     let user = load_user(&ctx).await;
+//!     match patch.apply_to(&user) {
+//!         Ok(user) => save_user(user).await.into_response(),
+//!         Err(e) => e.into_response(), // 409 for a failed `test`, 422 otherwise
+//!     }
+//! });
+//! ```
+//!
+//! A patch is applied atomically: when one operation fails, the document is
+//! left unchanged.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+
+use crate::{IntoResponse, Response};
+
+/// One RFC 6902 operation; `path` and `from` are JSON pointers such as `/tags/0`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+impl PatchOperation {
+    fn path(&self) -> &str {
+        match self {
+            Self::Add { path, .. }
+            | Self::Remove { path }
+            | Self::Replace { path, .. }
+            | Self::Move { path, .. }
+            | Self::Copy { path, .. }
+            | Self::Test { path, .. } => path,
+        }
+    }
+}
+
+/// An RFC 6902 JSON Patch document.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct JsonPatch(pub Vec<PatchOperation>);
+
+impl JsonPatch {
+    /// Apply every operation in order, or none of them.
+    pub fn apply(&self, doc: &mut Value) -> Result<(), PatchError> {
+        let mut patched = doc.clone();
+        for (index, operation) in self.0.iter().enumerate() {
+            apply_operation(&mut patched, operation).map_err(|failure| failure.at(index, operation.path()))?;
+        }
+        *doc = patched;
+        Ok(())
+    }
+
+    /// Patch a copy of `entity` through its JSON form.
+    pub fn apply_to<T>(&self, entity: &T) -> Result<T, PatchError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        patch_entity(entity, |doc| self.apply(doc))
+    }
+}
+
+/// An RFC 7396 merge patch: objects merge recursively, `null` removes a
+/// member, anything else replaces the target value.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MergePatch(pub Value);
+
+impl MergePatch {
+    pub fn apply(&self, doc: &mut Value) {
+        merge(doc, &self.0);
+    }
+
+    /// Patch a copy of `entity` through its JSON form.
+    pub fn apply_to<T>(&self, entity: &T) -> Result<T, PatchError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        patch_entity(entity, |doc| {
+            self.apply(doc);
+            Ok(())
+        })
+    }
+}
+
+/// Why a patch could not be applied.
+#[derive(Debug)]
+pub enum PatchError {
+    /// Operation `index` refers to a location that does not exist.
+    NotFound { index: usize, path: String },
+    /// The `test` operation `index` did not match.
+    TestFailed { index: usize, path: String },
+    /// Operation `index` is malformed, e.g. an invalid pointer.
+    Invalid { index: usize, path: String, reason: &'static str },
+    /// The entity could not be converted to or from its patched JSON form.
+    Entity(serde_json::Error),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound { index, path } => write!(f, "operation {index}: no value at {path:?}"),
+            Self::TestFailed { index, path } => write!(f, "operation {index}: test failed at {path:?}"),
+            Self::Invalid { index, path, reason } => write!(f, "operation {index} at {path:?}: {reason}"),
+            Self::Entity(e) => write!(f, "patched document is invalid: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// `409 Conflict` for a failed `test`, `422 Unprocessable Entity` otherwise.
+impl IntoResponse for PatchError {
+    fn into_response(self) -> Response {
+        crate::Error::from(self).into_response()
+    }
+}
+
+/// Failure of one operation, before its position is known
+enum Failure {
+    NotFound,
+    TestFailed,
+    Invalid(&'static str),
+}
+
+impl Failure {
+    fn at(self, index: usize, path: &str) -> PatchError {
+        let path = path.to_string();
+        match self {
+            Self::NotFound => PatchError::NotFound { index, path },
+            Self::TestFailed => PatchError::TestFailed { index, path },
+            Self::Invalid(reason) => PatchError::Invalid { index, path, reason },
+        }
+    }
+}
+
+fn patch_entity<T>(entity: &T, apply: impl FnOnce(&mut Value) -> Result<(), PatchError>) -> Result<T, PatchError>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let mut doc = serde_json::to_value(entity).map_err(PatchError::Entity)?;
+    apply(&mut doc)?;
+    serde_json::from_value(doc).map_err(PatchError::Entity)
+}
+
+fn apply_operation(doc: &mut Value, operation: &PatchOperation) -> Result<(), Failure> {
+    match operation {
+        PatchOperation::Add { path, value } => add(doc, &pointer(path)?, value.clone()),
+        PatchOperation::Remove { path } => remove(doc, &pointer(path)?).map(drop),
+        PatchOperation::Replace { path, value } => {
+            *get_mut(doc, &pointer(path)?).ok_or(Failure::NotFound)? = value.clone();
+            Ok(())
+        }
+        PatchOperation::Move { from, path } => {
+            let (from, to) = (pointer(from)?, pointer(path)?);
+            if from == to {
+                return Ok(());
+            }
+            if to.starts_with(&from) {
+                return Err(Failure::Invalid("cannot move a value into one of its children"));
+            }
+            let value = remove(doc, &from)?;
+            add(doc, &to, value)
+        }
+        PatchOperation::Copy { from, path } => {
+            let value = get(doc, &pointer(from)?).ok_or(Failure::NotFound)?.clone();
+            add(doc, &pointer(path)?, value)
+        }
+        PatchOperation::Test { path, value } => match get(doc, &pointer(path)?) {
+            Some(current) if current == value => Ok(()),
+            _ => Err(Failure::TestFailed),
+        },
+    }
+}
+
+/// Reference tokens of a JSON pointer (RFC 6901)
+fn pointer(pointer: &str) -> Result<Vec<String>, Failure> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rest = pointer
+        .strip_prefix('/')
+        .ok_or(Failure::Invalid("JSON pointers must start with '/'"))?;
+    Ok(rest.split('/').map(|token| token.replace("~1", "/").replace("~0", "~")).collect())
+}
+
+/// Array index token: digits without leading zeros
+fn array_index(token: &str) -> Option<usize> {
+    let canonical = token == "0" || (!token.starts_with('0') && token.bytes().all(|b| b.is_ascii_digit()));
+    if canonical { token.parse().ok() } else { None }
+}
+
+fn get<'a>(doc: &'a Value, tokens: &[String]) -> Option<&'a Value> {
+    tokens.iter().try_fold(doc, |value, token| match value {
+        Value::Object(map) => map.get(token),
+        Value::Array(items) => items.get(array_index(token)?),
+        _ => None,
+    })
+}
+
+fn get_mut<'a>(doc: &'a mut Value, tokens: &[String]) -> Option<&'a mut Value> {
+    tokens.iter().try_fold(doc, |value, token| match value {
+        Value::Object(map) => map.get_mut(token),
+        Value::Array(items) => items.get_mut(array_index(token)?),
+        _ => None,
+    })
+}
+
+fn add(doc: &mut Value, tokens: &[String], value: Value) -> Result<(), Failure> {
+    let Some((last, parent)) = tokens.split_last() else {
+        *doc = value;
+        return Ok(());
+    };
+    match get_mut(doc, parent).ok_or(Failure::NotFound)? {
+        Value::Object(map) => {
+            map.insert(last.clone(), value);
+        }
+        Value::Array(items) if last == "-" => items.push(value),
+        Value::Array(items) => {
+            let index = array_index(last).filter(|i| *i <= items.len()).ok_or(Failure::NotFound)?;
+            items.insert(index, value);
+        }
+        _ => return Err(Failure::NotFound),
+    }
+    Ok(())
+}
+
+fn remove(doc: &mut Value, tokens: &[String]) -> Result<Value, Failure> {
+    let Some((last, parent)) = tokens.split_last() else {
+        return Err(Failure::Invalid("cannot remove the whole document"));
+    };
+    match get_mut(doc, parent).ok_or(Failure::NotFound)? {
+        Value::Object(map) => map.remove(last).ok_or(Failure::NotFound),
+        Value::Array(items) => {
+            let index = array_index(last).filter(|i| *i < items.len()).ok_or(Failure::NotFound)?;
+            Ok(items.remove(index))
+        }
+        _ => Err(Failure::NotFound),
+    }
+}
+
+fn merge(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let Value::Object(map) = target else {
+        unreachable!("target was just made an object");
+    };
+    for (key, value) in patch {
+        if value.is_null() {
+            map.remove(key);
+        } else {
+            merge(map.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StatusCode;
+    use serde_json::json;
+
+    fn patch(operations: Value) -> JsonPatch {
+        serde_json::from_value(operations).unwrap()
+    }
+
+    #[test]
+    fn test_json_patch_operations() {
+        let mut doc = json!({ "foo": ["bar", "baz"], "a/b": { "c": 1 } });
+        patch(json!([
+            { "op": "add", "path": "/foo/1", "value": "qux" },
+            { "op": "add", "path": "/foo/-", "value": "end" },
+            { "op": "remove", "path": "/foo/0" },
+            { "op": "replace", "path": "/a~1b/c", "value": 2 },
+            { "op": "copy", "from": "/a~1b", "path": "/copied" },
+            { "op": "move", "from": "/copied/c", "path": "/moved" },
+            { "op": "test", "path": "/moved", "value": 2 },
+        ]))
+        .apply(&mut doc)
+        .unwrap();
+        assert_eq!(
+            doc,
+            json!({ "foo": ["qux", "baz", "end"], "a/b": { "c": 2 }, "copied": {}, "moved": 2 })
+        );
+    }
+
+    #[test]
+    fn test_failed_patch_leaves_document_unchanged() {
+        let original = json!({ "name": "Alice", "tags": [] });
+        let mut doc = original.clone();
+
+        let err = patch(json!([
+            { "op": "replace", "path": "/name", "value": "Bob" },
+            { "op": "test", "path": "/name", "value": "Alice" },
+        ]))
+        .apply(&mut doc)
+        .unwrap_err();
+        assert!(matches!(err, PatchError::TestFailed { index: 1, .. }), "{err}");
+        assert_eq!(err.into_response().status(), StatusCode::CONFLICT);
+        assert_eq!(doc, original);
+
+        for (operations, expected) in [
+            (json!([{ "op": "remove", "path": "/missing" }]), "operation 0: no value at \"/missing\""),
+            (json!([{ "op": "add", "path": "/tags/01", "value": 1 }]), "operation 0: no value at \"/tags/01\""),
+            (json!([{ "op": "replace", "path": "name", "value": 1 }]), "operation 0 at \"name\": JSON pointers must start with '/'"),
+            (
+                json!([{ "op": "move", "from": "/tags", "path": "/tags/0" }]),
+                "operation 0 at \"/tags/0\": cannot move a value into one of its children",
+            ),
+        ] {
+            let err = patch(operations).apply(&mut doc).unwrap_err();
+            assert_eq!(err.to_string(), expected);
+            assert_eq!(err.into_response().status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        assert_eq!(doc, original);
+    }
+
+    #[test]
+    fn test_merge_patch_and_entities() {
+        // RFC 7396 section 3
+        let mut doc = json!({ "title": "Goodbye!", "author": { "givenName": "John", "familyName": "Doe" }, "tags": ["example", "sample"] });
+        MergePatch(json!({ "title": "Hello!", "author": { "familyName": null }, "tags": ["example"], "phoneNumber": "+01-123-456-7890" }))
+            .apply(&mut doc);
+        assert_eq!(
+            doc,
+            json!({ "title": "Hello!", "author": { "givenName": "John" }, "tags": ["example"], "phoneNumber": "+01-123-456-7890" })
+        );
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct User {
+            name: String,
+            age: u32,
+        }
+        let user = User { name: "Alice".into(), age: 30 };
+        let patched = MergePatch(json!({ "age": 31 })).apply_to(&user).unwrap();
+        assert_eq!(patched, User { name: "Alice".into(), age: 31 });
+        let patched = patch(json!([{ "op": "replace", "path": "/name", "value": "Bob" }])).apply_to(&user).unwrap();
+        assert_eq!(patched.name, "Bob");
+
+        let err = MergePatch(json!({ "age": "old" })).apply_to(&user).unwrap_err();
+        assert!(matches!(err, PatchError::Entity(_)));
+        assert_eq!(err.into_response().status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
@@ -0,0 +1,270 @@
+//! Policy-based authorization (`policy` feature).
+//!
+//! Requests are checked as `(subject, route pattern, method)` against a
+//! [`PolicyEngine`], so permission matrices live in one table instead of in
+//! per-route closures. [`Rbac`] is a built-in role table loadable from JSON;
+//! adapters for engines such as casbin only need to implement the trait.
+//!
+//! ```rust,ignore
+//! use s_web::policy::{Policy, Rbac};
+//!
+//! let rbac = Rbac::from_json(&std::fs::read_to_string("policy.json")?)?;
+//! app.use_middleware(
+//!     Policy::new(rbac)
+//!         .subject(|ctx| ctx.header("x-user").map(str::to_owned))
+//!         .middleware(),
+//! );
+//! ```
+//!
+//! ```json
+//! {
+//!   "roles": {
+//!     "viewer": [{ "path": "/posts/:id", "methods": ["GET"] }],
+//!     "editor": [{ "path": "/posts/*rest", "methods": ["*"] }]
+//!   },
+//!   "inherits": { "editor": ["viewer"] },
+//!   "subjects": { "alice": ["editor"] }
+//! }
+//! ```
+
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
+
+use serde::Deserialize;
+
+use crate::{BoxError, MatchedRoute, Next, RequestCtx, Response, ResponseBuilder, Router, StatusCode, log};
+use crate::trie::{Segment, parse_segment};
+
+/// Decides whether `subject` may perform `action` (HTTP method) on `resource` (route pattern).
+pub trait PolicyEngine: Send + Sync + 'static {
+    fn enforce<'a>(
+        &'a self,
+        subject: &'a str,
+        resource: &'a str,
+        action: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<bool, BoxError>> + Send + 'a>>;
+}
+
+/// One permission: a path pattern (`:param` and `*wildcard` allowed) and the
+/// methods it grants (`*` for all).
+#[derive(Debug, Clone, Deserialize)]
+pub struct Permission {
+    pub path: String,
+    pub methods: Vec<String>,
+}
+
+impl Permission {
+    fn allows(&self, resource: &str, action: &str) -> bool {
+        self.methods
+            .iter()
+            .any(|m| m == "*" || m.eq_ignore_ascii_case(action))
+            && pattern_matches(&self.path, resource)
+    }
+}
+
+/// Built-in role table.
+///
+/// A subject is granted the permissions of the roles assigned to it, of the
+/// role with the same name as the subject, and of every role those inherit.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Rbac {
+    #[serde(default)]
+    roles: HashMap<String, Vec<Permission>>,
+    #[serde(default)]
+    inherits: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    subjects: HashMap<String, Vec<String>>,
+}
+
+impl Rbac {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a role table from its JSON representation.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Grant `methods` on `path` to `role`.
+    pub fn allow(mut self, role: &str, path: &str, methods: &[&str]) -> Self {
+        self.roles.entry(role.to_string()).or_default().push(Permission {
+            path: path.to_string(),
+            methods: methods.iter().map(|m| m.to_string()).collect(),
+        });
+        self
+    }
+
+    /// Make `role` inherit every permission of `parent`.
+    pub fn inherit(mut self, role: &str, parent: &str) -> Self {
+        self.inherits
+            .entry(role.to_string())
+            .or_default()
+            .push(parent.to_string());
+        self
+    }
+
+    /// Assign `role` to `subject`.
+    pub fn assign(mut self, subject: &str, role: &str) -> Self {
+        self.subjects
+            .entry(subject.to_string())
+            .or_default()
+            .push(role.to_string());
+        self
+    }
+
+    /// Synchronous check, also used by the [`PolicyEngine`] impl.
+    pub fn is_allowed(&self, subject: &str, resource: &str, action: &str) -> bool {
+        let mut pending: Vec<&str> = vec![subject];
+        if let Some(assigned) = self.subjects.get(subject) {
+            pending.extend(assigned.iter().map(String::as_str));
+        }
+        let mut seen: Vec<&str> = Vec::new();
+        while let Some(role) = pending.pop() {
+            if seen.contains(&role) {
+                continue;
+            }
+            seen.push(role);
+            if self
+                .roles
+                .get(role)
+                .is_some_and(|perms| perms.iter().any(|p| p.allows(resource, action)))
+            {
+                return true;
+            }
+            if let Some(parents) = self.inherits.get(role) {
+                pending.extend(parents.iter().map(String::as_str));
+            }
+        }
+        false
+    }
+}
+
+impl PolicyEngine for Rbac {
+    fn enforce<'a>(
+        &'a self,
+        subject: &'a str,
+        resource: &'a str,
+        action: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<bool, BoxError>> + Send + 'a>> {
+        let allowed = self.is_allowed(subject, resource, action);
+        Box::pin(async move { Ok(allowed) })
+    }
+}
+
+/// Match a rule pattern against a route pattern or a concrete path.
+fn pattern_matches(rule: &str, resource: &str) -> bool {
+    let rule = Router::parse_pattern(rule);
+    let resource = Router::parse_pattern(resource);
+    for (i, part) in rule.iter().enumerate() {
+        if part.starts_with('*') {
+            return true;
+        }
+        match resource.get(i) {
+            Some(seg) if part == seg || matches!(parse_segment(part), Segment::Param { .. }) => {}
+            _ => return false,
+        }
+    }
+    rule.len() == resource.len()
+}
+
+type SubjectFn = Arc<dyn Fn(&RequestCtx) -> Option<String> + Send + Sync>;
+
+/// Authorization middleware configuration.
+#[derive(Clone)]
+pub struct Policy {
+    engine: Arc<dyn PolicyEngine>,
+    subject: SubjectFn,
+}
+
+impl Policy {
+    /// Create a policy check backed by `engine`. By default the subject is
+    /// read from the `X-User` header.
+    pub fn new(engine: impl PolicyEngine) -> Self {
+        Self {
+            engine: Arc::new(engine),
+            subject: Arc::new(|ctx| ctx.header("x-user").map(str::to_owned)),
+        }
+    }
+
+    /// Extract the subject (user id, role, API key owner). Requests without
+    /// a subject get 401.
+    pub fn subject<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&RequestCtx) -> Option<String> + Send + Sync + 'static,
+    {
+        self.subject = Arc::new(f);
+        self
+    }
+
+    /// Build the middleware function for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let policy = Arc::new(self);
+        move |ctx, next| {
+            let policy = policy.clone();
+            Box::pin(async move { policy.handle(ctx, next).await })
+        }
+    }
+
+    async fn handle(&self, ctx: RequestCtx, next: Next) -> Response {
+        let Some(subject) = (self.subject)(&ctx) else {
+            return deny(StatusCode::UNAUTHORIZED, "401 Unauthorized");
+        };
+        // Route-level middleware sees the matched pattern; global and group
+        // middleware run before routing and fall back to the request path.
+        let resource = ctx
+            .request
+            .extensions()
+            .get::<MatchedRoute>()
+            .map(|route| route.0.to_string())
+            .unwrap_or_else(|| ctx.request.uri().path().to_string());
+        let action = ctx.request.method().as_str().to_string();
+
+        match self.engine.enforce(&subject, &resource, &action).await {
+            Ok(true) => next(ctx).await,
+            Ok(false) => deny(StatusCode::FORBIDDEN, "403 Forbidden"),
+            Err(e) => {
+                log::warn!("policy engine error: {e}");
+                ResponseBuilder::internal_error()
+            }
+        }
+    }
+}
+
+fn deny(status: StatusCode, body: &'static str) -> Response {
+    ResponseBuilder::new()
+        .status(status)
+        .content_type("text/plain; charset=utf-8")
+        .build(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rbac_inheritance_and_patterns() {
+        let rbac = Rbac::from_json(
+            r#"{
+                "roles": {
+                    "viewer": [{ "path": "/posts/:id", "methods": ["GET"] }],
+                    "editor": [{ "path": "/posts/*rest", "methods": ["*"] }]
+                },
+                "inherits": { "editor": ["viewer"] },
+                "subjects": { "alice": ["editor"], "bob": ["viewer"] }
+            }"#,
+        )
+        .unwrap();
+
+        assert!(rbac.is_allowed("bob", "/posts/:id", "GET"));
+        assert!(rbac.is_allowed("bob", "/posts/7", "get"));
+        assert!(!rbac.is_allowed("bob", "/posts/7", "DELETE"));
+        assert!(!rbac.is_allowed("bob", "/posts/7/comments", "GET"));
+        assert!(rbac.is_allowed("alice", "/posts/7/comments", "DELETE"));
+        assert!(!rbac.is_allowed("mallory", "/posts/7", "GET"));
+    }
+}
@@ -0,0 +1,163 @@
+//! Queue-then-serve: absorb bursts instead of letting every request race.
+//!
+//! At most `concurrency` requests run behind the middleware at once; the
+//! next ones wait in a bounded queue, in arrival order. When the queue is
+//! full, or a request has waited longer than [`RequestQueue::max_wait`], it
+//! is answered `503` with `Retry-After` so clients back off instead of
+//! timing out.
+//!
+//! ```rust,ignore
+//! use s_web::queue::RequestQueue;
+//! use std::time::Duration;
+//!
+//! app.use_middleware(
+//!     RequestQueue::new(64)
+//!         .capacity(256)
+//!         .max_wait(Duration::from_secs(2))
+//!         .middleware(),
+//! );
+//! ```
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{Next, RequestCtx, Response, ResponseBuilder, StatusCode};
+
+/// Bounded request queue in front of handler execution. Clones share the
+/// same queue.
+#[derive(Debug, Clone)]
+pub struct RequestQueue {
+    concurrency: usize,
+    capacity: usize,
+    max_wait: Duration,
+    permits: Arc<Semaphore>,
+    waiting: Arc<AtomicUsize>,
+}
+
+impl RequestQueue {
+    /// Serve `concurrency` requests at a time, queueing up to 100 more for
+    /// at most 5 seconds each.
+    pub fn new(concurrency: usize) -> Self {
+        let concurrency = concurrency.max(1);
+        Self {
+            concurrency,
+            capacity: 100,
+            max_wait: Duration::from_secs(5),
+            permits: Arc::new(Semaphore::new(concurrency)),
+            waiting: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// How many requests may wait; `0` rejects as soon as all slots are busy.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// How long a request may wait for a slot before it gets `503`.
+    pub fn max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Requests currently waiting for a slot.
+    pub fn queued(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// Requests currently being served.
+    pub fn in_flight(&self) -> usize {
+        self.concurrency - self.permits.available_permits()
+    }
+
+    /// Build the middleware for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let queue = Arc::new(self);
+        move |ctx, next| {
+            let queue = queue.clone();
+            Box::pin(async move {
+                let permit = match queue.enter().await {
+                    Ok(permit) => permit,
+                    Err(reason) => return rejected(reason),
+                };
+                let response = next(ctx).await;
+                drop(permit);
+                response
+            })
+        }
+    }
+
+    /// Take a slot, waiting in the queue if all are busy
+    async fn enter(&self) -> Result<OwnedSemaphorePermit, &'static str> {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+        // Reserve a place in the queue, or fail fast when it is full
+        let reserved = self
+            .waiting
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.capacity).then_some(n + 1)
+            })
+            .is_ok();
+        if !reserved {
+            return Err("request queue is full");
+        }
+        let acquired = tokio::time::timeout(self.max_wait, self.permits.clone().acquire_owned()).await;
+        self.waiting.fetch_sub(1, Ordering::AcqRel);
+        match acquired {
+            Ok(Ok(permit)) => Ok(permit),
+            Ok(Err(_)) => Err("request queue is closed"),
+            Err(_) => Err("timed out waiting in the request queue"),
+        }
+    }
+}
+
+fn rejected(reason: &str) -> Response {
+    ResponseBuilder::new()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .content_type("text/plain; charset=utf-8")
+        .with_header("Retry-After", "1")
+        .build(format!("503 Service Unavailable: {reason}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_queue_waits_then_rejects() {
+        let queue = RequestQueue::new(1).capacity(1).max_wait(Duration::from_millis(50));
+        let first = queue.enter().await.unwrap();
+        assert_eq!(queue.in_flight(), 1);
+
+        // Waits for the slot to free up
+        let waiter = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.enter().await.map(drop) }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(queue.queued(), 1);
+        assert_eq!(queue.enter().await.unwrap_err(), "request queue is full");
+        drop(first);
+        assert!(waiter.await.unwrap().is_ok());
+
+        // Times out when the slot stays busy
+        let _busy = queue.enter().await.unwrap();
+        assert_eq!(queue.enter().await.unwrap_err(), "timed out waiting in the request queue");
+        assert_eq!(queue.queued(), 0);
+    }
+}
@@ -0,0 +1,438 @@
+//! Per-tenant / per-principal quota accounting.
+//!
+//! Unlike rate limiting, quotas track consumption over long windows (a day, a
+//! billing month) in requests, response bytes or custom units, and report the
+//! balance back to clients through `X-Quota-*` headers.
+//!
+//! ```rust,ignore
+//! use s_web::quota::{MemoryQuotaStore, Quota};
+//! use std::time::Duration;
+//!
+//! app.use_middleware(
+//!     Quota::new(MemoryQuotaStore::new())
+//!         .limit(10_000)
+//!         .per(Duration::from_secs(86_400))
+//!         .key(|ctx| ctx.header("x-api-key").map(str::to_owned))
+//!         .middleware(),
+//! );
+//! ```
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use http_body_util::BodyExt;
+use hyper::{
+    StatusCode,
+    body::Body,
+    header::{HeaderName, HeaderValue},
+};
+
+use crate::{
+    Next, RequestCtx, Response, ResponseBuilder,
+    store::{self, KvStore},
+};
+
+/// Outcome of a [`QuotaStore::consume`] call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUsage {
+    /// Whether the units were accepted (and recorded).
+    pub allowed: bool,
+    /// Units used in the current window after this call.
+    pub used: u64,
+    /// Time until the current window resets.
+    pub reset_after: Duration,
+}
+
+/// Backend persisting quota counters.
+///
+/// `consume` must atomically add `units` to the counter for `key` if the
+/// result stays within `limit`, and otherwise leave it untouched.
+pub trait QuotaStore: Send + Sync + 'static {
+    fn consume(
+        &self,
+        key: &str,
+        units: u64,
+        limit: u64,
+        window: Duration,
+    ) -> Pin<Box<dyn Future<Output = QuotaUsage> + Send + '_>>;
+}
+
+/// In-process quota store with fixed windows.
+#[derive(Default)]
+pub struct MemoryQuotaStore {
+    counters: Mutex<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    /// Window start and units used, per key
+    windows: HashMap<String, (Instant, u64)>,
+    /// Size at which the next call sweeps out expired windows
+    sweep_at: usize,
+}
+
+impl MemoryQuotaStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl QuotaStore for MemoryQuotaStore {
+    fn consume(
+        &self,
+        key: &str,
+        units: u64,
+        limit: u64,
+        window: Duration,
+    ) -> Pin<Box<dyn Future<Output = QuotaUsage> + Send + '_>> {
+        let now = Instant::now();
+        let mut counters = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        if counters.windows.len() >= counters.sweep_at {
+            counters.windows.retain(|_, (started, _)| now.duration_since(*started) < window);
+            counters.sweep_at = (counters.windows.len() * 2).max(1024);
+        }
+        let (started, used) = counters.windows.entry(key.to_string()).or_insert((now, 0));
+        if now.duration_since(*started) >= window {
+            *started = now;
+            *used = 0;
+        }
+        let allowed = used.saturating_add(units) <= limit;
+        if allowed {
+            *used += units;
+        }
+        let usage = QuotaUsage {
+            allowed,
+            used: *used,
+            reset_after: window.saturating_sub(now.duration_since(*started)),
+        };
+        Box::pin(async move { usage })
+    }
+}
+
+/// Counters under `quota:` keys of a shared [`KvStore`], in fixed windows
+/// starting at a key's first use. Rejected units are added and taken back
+/// again, so a request racing one that overshoots may be rejected too. If
+/// the store is unreachable requests are allowed.
+impl QuotaStore for Arc<dyn KvStore> {
+    fn consume(
+        &self,
+        key: &str,
+        units: u64,
+        limit: u64,
+        window: Duration,
+    ) -> Pin<Box<dyn Future<Output = QuotaUsage> + Send + '_>> {
+        let key = format!("quota:{key}");
+        Box::pin(async move {
+            let result = async {
+                let units = i64::try_from(units)?;
+                let mut used = self.incr(&key, units, Some(window)).await?;
+                let allowed = u64::try_from(used).is_ok_and(|used| used <= limit);
+                if !allowed {
+                    used = self.incr(&key, -units, Some(window)).await?;
+                }
+                let reset_after = self.ttl(&key).await?.unwrap_or(window);
+                Ok::<_, crate::BoxError>(QuotaUsage {
+                    allowed,
+                    used: used.max(0) as u64,
+                    reset_after,
+                })
+            };
+            store::or_log(
+                result.await,
+                QuotaUsage {
+                    allowed: true,
+                    used: 0,
+                    reset_after: window,
+                },
+            )
+        })
+    }
+}
+
+/// What a request is charged against the quota.
+#[derive(Clone)]
+pub enum QuotaUnits {
+    /// One unit per request.
+    Requests,
+    /// Size of the response body in bytes, charged after the handler runs.
+    /// Streamed bodies of unknown length are charged once they have been
+    /// sent (or dropped), so their `X-Quota-Remaining` does not include them.
+    ResponseBytes,
+    /// Custom cost computed from the request.
+    Custom(Arc<dyn Fn(&RequestCtx) -> u64 + Send + Sync>),
+}
+
+type KeyFn = Arc<dyn Fn(&RequestCtx) -> Option<String> + Send + Sync>;
+type LimitFn = Arc<dyn Fn(&str) -> u64 + Send + Sync>;
+
+/// Quota middleware configuration.
+#[derive(Clone)]
+pub struct Quota {
+    store: Arc<dyn QuotaStore>,
+    key: KeyFn,
+    limit: LimitFn,
+    window: Duration,
+    units: QuotaUnits,
+    exhausted_status: StatusCode,
+}
+
+impl Quota {
+    /// Create a quota backed by `store`, keyed by client IP, 1000 requests per day.
+    pub fn new(store: impl QuotaStore) -> Self {
+        Self {
+            store: Arc::new(store),
+            key: Arc::new(|ctx| ctx.client_ip().map(|ip| ip.to_string())),
+            limit: Arc::new(|_| 1000),
+            window: Duration::from_secs(86_400),
+            units: QuotaUnits::Requests,
+            exhausted_status: StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    /// Extract the principal/tenant key. Requests without a key are not metered.
+    pub fn key<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&RequestCtx) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(f);
+        self
+    }
+
+    /// Same limit for every principal.
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Arc::new(move |_| limit);
+        self
+    }
+
+    /// Per-principal limit, e.g. looked up from the tenant's plan.
+    pub fn limit_by<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str) -> u64 + Send + Sync + 'static,
+    {
+        self.limit = Arc::new(f);
+        self
+    }
+
+    /// Length of the accounting window.
+    pub fn per(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Unit charged per request (default [`QuotaUnits::Requests`]).
+    pub fn units(mut self, units: QuotaUnits) -> Self {
+        self.units = units;
+        self
+    }
+
+    /// Status returned once the quota is exhausted: 429 (default) or 402 for paid plans.
+    pub fn exhausted_status(mut self, status: StatusCode) -> Self {
+        self.exhausted_status = status;
+        self
+    }
+
+    /// Build the middleware function for `use_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let quota = Arc::new(self);
+        move |ctx, next| {
+            let quota = quota.clone();
+            Box::pin(async move { quota.handle(ctx, next).await })
+        }
+    }
+
+    async fn handle(&self, ctx: RequestCtx, next: Next) -> Response {
+        let Some(key) = (self.key)(&ctx) else {
+            return next(ctx).await;
+        };
+        let limit = (self.limit)(&key);
+
+        // Response bytes are only known afterwards, so the pre-check only
+        // verifies that some balance is left.
+        let cost = match &self.units {
+            QuotaUnits::Requests => 1,
+            QuotaUnits::ResponseBytes => 0,
+            QuotaUnits::Custom(f) => f(&ctx),
+        };
+        let usage = self.store.consume(&key, cost, limit, self.window).await;
+        if !usage.allowed || (cost == 0 && usage.used >= limit) {
+            let mut resp = ResponseBuilder::new()
+                .status(self.exhausted_status)
+                .content_type("text/plain; charset=utf-8")
+                .build("Quota exceeded");
+            set_quota_headers(&mut resp, limit, usage);
+            return resp;
+        }
+
+        let mut resp = next(ctx).await;
+        let usage = match self.units {
+            QuotaUnits::ResponseBytes => match resp.body().size_hint().exact() {
+                Some(bytes) => self.store.consume(&key, bytes, u64::MAX, self.window).await,
+                None => {
+                    let mut charge = StreamCharge {
+                        store: self.store.clone(),
+                        key,
+                        window: self.window,
+                        bytes: 0,
+                    };
+                    resp = resp.map(|body| {
+                        body.map_frame(move |frame| {
+                            charge.count(&frame);
+                            frame
+                        })
+                        .boxed()
+                    });
+                    usage
+                }
+            },
+            _ => usage,
+        };
+        set_quota_headers(&mut resp, limit, usage);
+        resp
+    }
+}
+
+/// Bytes of a streamed response, charged when the body is dropped
+struct StreamCharge {
+    store: Arc<dyn QuotaStore>,
+    key: String,
+    window: Duration,
+    bytes: u64,
+}
+
+impl StreamCharge {
+    fn count(&mut self, frame: &hyper::body::Frame<hyper::body::Bytes>) {
+        if let Some(data) = frame.data_ref() {
+            self.bytes += data.len() as u64;
+        }
+    }
+}
+
+impl Drop for StreamCharge {
+    fn drop(&mut self) {
+        if self.bytes > 0
+            && let Ok(runtime) = tokio::runtime::Handle::try_current()
+        {
+            let (store, key) = (self.store.clone(), std::mem::take(&mut self.key));
+            let (bytes, window) = (self.bytes, self.window);
+            runtime.spawn(async move {
+                store.consume(&key, bytes, u64::MAX, window).await;
+            });
+        }
+    }
+}
+
+fn set_quota_headers(resp: &mut Response, limit: u64, usage: QuotaUsage) {
+    let headers = resp.headers_mut();
+    headers.insert(
+        HeaderName::from_static("x-quota-limit"),
+        HeaderValue::from(limit),
+    );
+    headers.insert(
+        HeaderName::from_static("x-quota-remaining"),
+        HeaderValue::from(limit.saturating_sub(usage.used)),
+    );
+    headers.insert(
+        HeaderName::from_static("x-quota-reset"),
+        HeaderValue::from(usage.reset_after.as_secs()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntoResponse;
+
+    #[tokio::test]
+    async fn test_memory_store_enforces_limit() {
+        let store = MemoryQuotaStore::new();
+        let window = Duration::from_secs(60);
+        assert!(store.consume("t1", 2, 3, window).await.allowed);
+        let usage = store.consume("t1", 2, 3, window).await;
+        assert!(!usage.allowed);
+        assert_eq!(usage.used, 2);
+        assert!(store.consume("t1", 1, 3, window).await.allowed);
+        assert!(store.consume("t2", 3, 3, window).await.allowed);
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_sweeps_expired_windows() {
+        let store = MemoryQuotaStore::new();
+        for i in 0..5000 {
+            store.consume(&format!("client{i}"), 1, 10, Duration::ZERO).await;
+        }
+        assert!(store.counters.lock().unwrap().windows.len() <= 1024);
+    }
+
+    fn request(key: &str) -> RequestCtx {
+        let request = hyper::Request::get("/").header("x-api-key", key);
+        RequestCtx::from_parts(request.body(()).unwrap().into_parts().0)
+    }
+
+    #[tokio::test]
+    async fn test_middleware_meters_requests() {
+        let quota = Quota::new(MemoryQuotaStore::new())
+            .limit(2)
+            .per(Duration::from_secs(60))
+            .key(|ctx| ctx.header("x-api-key").map(str::to_owned))
+            .exhausted_status(StatusCode::PAYMENT_REQUIRED)
+            .middleware();
+        let next: Next = Arc::new(|_| Box::pin(async { ResponseBuilder::new().build("ok") }));
+
+        let response = quota(request("a"), next.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-quota-limit"], "2");
+        assert_eq!(response.headers()["x-quota-remaining"], "1");
+        assert_eq!(response.headers()["x-quota-reset"], "60");
+        assert_eq!(quota(request("a"), next.clone()).await.headers()["x-quota-remaining"], "0");
+
+        let response = quota(request("a"), next.clone()).await;
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(response.headers()["x-quota-remaining"], "0");
+        assert_eq!(quota(request("b"), next).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_middleware_charges_response_bytes() {
+        let quota = Quota::new(MemoryQuotaStore::new())
+            .limit(10)
+            .key(|ctx| ctx.header("x-api-key").map(str::to_owned))
+            .units(QuotaUnits::ResponseBytes)
+            .middleware();
+        let next: Next = Arc::new(|ctx| {
+            Box::pin(async move {
+                match ctx.request.uri().path() {
+                    "/" => ResponseBuilder::new().build("123456"),
+                    _ => {
+                        let chunks = futures_util::stream::iter(["12", "34"]);
+                        crate::Chunked::new(chunks).into_response()
+                    }
+                }
+            })
+        });
+
+        let response = quota(request("a"), next.clone()).await;
+        assert_eq!(response.headers()["x-quota-remaining"], "4");
+
+        // Streamed bodies are charged once sent
+        let mut streamed = request("a");
+        *streamed.request.uri_mut() = "/stream".parse().unwrap();
+        let response = quota(streamed, next.clone()).await;
+        assert_eq!(response.headers()["x-quota-remaining"], "4");
+        assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), "1234");
+        tokio::task::yield_now().await;
+
+        let response = quota(request("a"), next.clone()).await;
+        assert_eq!(response.headers()["x-quota-remaining"], "0");
+        assert_eq!(quota(request("a"), next).await.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
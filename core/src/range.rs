@@ -0,0 +1,482 @@
+//! Range requests: `Accept-Ranges`, `Range`, `If-Range` and `206 Partial Content`.
+//!
+//! Binary responses (`Vec<u8>`, `Bytes`, `&[u8]`) and [`File`] advertise
+//! `Accept-Ranges: bytes`. When a `GET` for them carries `Range`, the router
+//! answers with just the requested bytes: a single `206` with
+//! `Content-Range`, a `multipart/byteranges` body for several ranges, or
+//! `416 Range Not Satisfiable` when none of them lies within the content.
+//! An `If-Range` that no longer matches the `ETag` or `Last-Modified` gets the
+//! whole content again, so resumed downloads never mix two versions.
+//!
+//! ```rust,ignore
+//! use s_web::range::File;
+//!
+//! // Seekable video and resumable downloads
+//! app.get("/intro.mp4", |_| async { File::open("media/intro.mp4").await.ok() });
+//! app.get("/report.pdf", |_| async {
+//!     (StatusCode::OK, "application/pdf", build_report().await)
+//! });
+//! ```
+//!
+//! Middleware that rewrites the body (such as compression) drops
+//! `Accept-Ranges`, and such responses are always sent whole.
+
+use std::{
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use futures_util::{Stream, StreamExt, stream};
+use hyper::{
+    HeaderMap, Method, StatusCode,
+    body::{Body as _, Bytes},
+    header::{
+        ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+        HeaderValue, IF_RANGE, LAST_MODIFIED, RANGE,
+    },
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+
+use crate::{
+    IntoResponse, Response, ResponseBuilder, log,
+    response::{full, stream_body},
+};
+
+/// More ranges than this in one request are ignored and the content is sent
+/// whole, so a single request can't ask for thousands of tiny parts
+const MAX_RANGES: usize = 16;
+
+/// Bytes read from a file per body chunk
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Where the bytes of a range-capable response come from, kept in its
+/// extensions until the router has seen the request's `Range`
+#[derive(Clone)]
+pub(crate) enum RangeSource {
+    Memory(Bytes),
+    File { path: Arc<PathBuf>, len: u64 },
+}
+
+impl RangeSource {
+    fn len(&self) -> u64 {
+        match self {
+            RangeSource::Memory(bytes) => bytes.len() as u64,
+            RangeSource::File { len, .. } => *len,
+        }
+    }
+
+    fn part(&self, range: Range<u64>) -> crate::response::Body {
+        match self {
+            RangeSource::Memory(bytes) => full(bytes.slice(range.start as usize..range.end as usize)),
+            RangeSource::File { path, .. } => stream_body(file_part(path.clone(), range)),
+        }
+    }
+
+    fn parts(self, ranges: Vec<Range<u64>>, head: Vec<String>, closing: String) -> crate::response::Body {
+        let parts = ranges.into_iter().zip(head).map(move |(range, head)| {
+            let data: std::pin::Pin<Box<dyn Stream<Item = _> + Send>> = match &self {
+                RangeSource::Memory(bytes) => Box::pin(stream::iter([Ok(
+                    bytes.slice(range.start as usize..range.end as usize),
+                )])),
+                RangeSource::File { path, .. } => Box::pin(file_part(path.clone(), range)),
+            };
+            stream::iter([Ok(Bytes::from(head))]).chain(data)
+        });
+        stream_body(stream::iter(parts).flatten().chain(stream::iter([Ok(Bytes::from(closing))])))
+    }
+}
+
+/// The `Range` of a `GET`, with its `If-Range` validator
+pub(crate) struct RangeRequest {
+    range: HeaderValue,
+    if_range: Option<HeaderValue>,
+}
+
+impl RangeRequest {
+    /// `None` unless the request is a `GET` with a `Range` header
+    pub(crate) fn from_request<B>(request: &hyper::Request<B>) -> Option<Self> {
+        if request.method() != Method::GET {
+            return None;
+        }
+        Some(Self {
+            range: request.headers().get(RANGE)?.clone(),
+            if_range: request.headers().get(IF_RANGE).cloned(),
+        })
+    }
+
+    /// Cut `response` down to the requested ranges if it supports them
+    pub(crate) fn apply(&self, response: Response) -> Response {
+        let Some(source) = range_source(&response) else {
+            return response;
+        };
+        if !self.validator_matches(response.headers()) {
+            return response;
+        }
+        let len = source.len();
+        match self.range.to_str().ok().map(|header| parse(header, len)) {
+            Some(Parsed::Ranges(ranges)) if ranges.len() == 1 => partial(response, source, ranges[0].clone()),
+            Some(Parsed::Ranges(ranges)) => byteranges(response, source, ranges),
+            Some(Parsed::Unsatisfiable) => ResponseBuilder::new()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .content_type("text/plain; charset=utf-8")
+                .with_header("Content-Range", format!("bytes */{len}"))
+                .build("416 Range Not Satisfiable"),
+            Some(Parsed::Ignore) | None => response,
+        }
+    }
+
+    /// Whether `If-Range`, if sent, still names this version: a strong ETag
+    /// or the exact `Last-Modified` date
+    fn validator_matches(&self, headers: &HeaderMap) -> bool {
+        let Some(if_range) = &self.if_range else {
+            return true;
+        };
+        let validator = if if_range.as_bytes().starts_with(b"\"") {
+            headers.get(ETAG)
+        } else if if_range.as_bytes().starts_with(b"W/") {
+            return false;
+        } else {
+            headers.get(LAST_MODIFIED)
+        };
+        validator == Some(if_range)
+    }
+}
+
+/// The source of a `200` that still holds the bytes it was built from
+fn range_source(response: &Response) -> Option<RangeSource> {
+    let headers = response.headers();
+    if response.status() != StatusCode::OK
+        || headers.contains_key(CONTENT_ENCODING)
+        || headers.get(ACCEPT_RANGES).is_none_or(|v| v != "bytes")
+    {
+        return None;
+    }
+    let source = response.extensions().get::<RangeSource>()?.clone();
+    // Middleware may have swapped the body and kept the extension
+    let current_len = response
+        .body()
+        .size_hint()
+        .exact()
+        .or_else(|| headers.get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok());
+    (current_len == Some(source.len())).then_some(source)
+}
+
+enum Parsed {
+    /// Malformed, another unit or too many ranges: send everything
+    Ignore,
+    Unsatisfiable,
+    Ranges(Vec<Range<u64>>),
+}
+
+/// Parse `bytes=0-499, -500, 9500-` against content of `len` bytes
+fn parse(header: &str, len: u64) -> Parsed {
+    let Some((unit, specs)) = header.split_once('=') else {
+        return Parsed::Ignore;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return Parsed::Ignore;
+    }
+    let mut ranges = Vec::new();
+    for spec in specs.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+        let Some((first, last)) = spec.split_once('-') else {
+            return Parsed::Ignore;
+        };
+        let (first, last) = (first.trim(), last.trim());
+        let range = match (first.parse::<u64>(), last.parse::<u64>()) {
+            (Ok(first), Ok(last)) if first <= last => first..last.saturating_add(1).min(len),
+            (Ok(first), Err(_)) if last.is_empty() => first..len,
+            (Err(_), Ok(suffix)) if first.is_empty() => len.saturating_sub(suffix)..len,
+            _ => return Parsed::Ignore,
+        };
+        if range.start < range.end {
+            ranges.push(range);
+        }
+        if ranges.len() > MAX_RANGES {
+            return Parsed::Ignore;
+        }
+    }
+    if ranges.is_empty() {
+        Parsed::Unsatisfiable
+    } else {
+        Parsed::Ranges(ranges)
+    }
+}
+
+fn partial(response: Response, source: RangeSource, range: Range<u64>) -> Response {
+    let (mut parts, _) = response.into_parts();
+    parts.status = StatusCode::PARTIAL_CONTENT;
+    let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, source.len());
+    parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(range.end - range.start));
+    if let Ok(value) = HeaderValue::from_str(&content_range) {
+        parts.headers.insert(CONTENT_RANGE, value);
+    }
+    Response::from_parts(parts, source.part(range))
+}
+
+fn byteranges(response: Response, source: RangeSource, ranges: Vec<Range<u64>>) -> Response {
+    let (mut parts, _) = response.into_parts();
+    let boundary = {
+        use std::hash::BuildHasher;
+        format!("s_web-{:016x}", std::collections::hash_map::RandomState::new().hash_one(0u8))
+    };
+    let content_type = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let len = source.len();
+    let head: Vec<String> = ranges
+        .iter()
+        .map(|range| {
+            format!(
+                "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {}-{}/{len}\r\n\r\n",
+                range.start,
+                range.end - 1,
+            )
+        })
+        .collect();
+    let closing = format!("\r\n--{boundary}--\r\n");
+    // Known up front, so a file that shrinks mid-read shows as a broken body
+    let total = head.iter().map(|h| h.len() as u64).sum::<u64>()
+        + ranges.iter().map(|r| r.end - r.start).sum::<u64>()
+        + closing.len() as u64;
+
+    parts.status = StatusCode::PARTIAL_CONTENT;
+    parts.headers.remove(CONTENT_RANGE);
+    parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(total));
+    if let Ok(value) = HeaderValue::from_str(&format!("multipart/byteranges; boundary={boundary}")) {
+        parts.headers.insert(CONTENT_TYPE, value);
+    }
+    Response::from_parts(parts, source.parts(ranges, head, closing))
+}
+
+/// `range` of the file at `path`, opened when the body is first polled
+fn file_part(path: Arc<PathBuf>, range: Range<u64>) -> impl Stream<Item = Result<Bytes, hyper::Error>> + Send {
+    stream::once(async move {
+        let mut file = tokio::fs::File::open(path.as_path()).await?;
+        file.seek(io::SeekFrom::Start(range.start)).await?;
+        Ok::<_, io::Error>(file.take(range.end - range.start))
+    })
+    .filter_map(|opened| async move {
+        opened.map_err(|e| log::warn!("failed to read file for a range request: {e}")).ok()
+    })
+    .flat_map(read_chunks)
+}
+
+/// The contents of `reader` in chunks. A read error ends the body early;
+/// the `Content-Length` sent with it tells the client it was cut short.
+fn read_chunks<R>(reader: R) -> impl Stream<Item = Result<Bytes, hyper::Error>> + Send
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    stream::unfold(reader, |mut reader| async move {
+        let mut buf = vec![0; CHUNK_SIZE];
+        match reader.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(Bytes::from(buf)), reader))
+            }
+            Err(e) => {
+                log::warn!("failed to read file: {e}");
+                None
+            }
+        }
+    })
+}
+
+/// A file on disk served with `Content-Length`, `Last-Modified`, an `ETag`
+/// and range support.
+///
+/// `File::open(..).await.ok()` makes a missing file a `404`.
+pub struct File {
+    file: tokio::fs::File,
+    path: PathBuf,
+    len: u64,
+    modified: Option<SystemTime>,
+    content_type: String,
+}
+
+impl File {
+    /// Open a regular file; the content type is guessed from its extension.
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = tokio::fs::File::open(&path).await?;
+        let metadata = file.metadata().await?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "not a regular file"));
+        }
+        Ok(Self {
+            file,
+            content_type: guess_content_type(&path).to_string(),
+            path,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    /// Content type to send instead of the guessed one.
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.content_type = content_type.to_string();
+        self
+    }
+}
+
+impl IntoResponse for File {
+    fn into_response(self) -> Response {
+        let mut builder = ResponseBuilder::new()
+            .content_type(&self.content_type)
+            .with_header("Accept-Ranges", "bytes")
+            .with_header("Content-Length", self.len.to_string());
+        if let Some(modified) = self.modified {
+            let secs = modified.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            builder = builder
+                .with_header("Last-Modified", crate::sunset::http_date(modified))
+                .with_header("ETag", format!("\"{:x}-{secs:x}\"", self.len));
+        }
+        let mut response = builder.streaming(stream_body(read_chunks(self.file.take(self.len))));
+        response.extensions_mut().insert(RangeSource::File {
+            path: Arc::new(self.path),
+            len: self.len,
+        });
+        response
+    }
+}
+
+/// Content type for common web and media file extensions
+fn guess_content_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "wasm" => "application/wasm",
+        "woff2" => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    fn ranges(header: &str, len: u64) -> Option<Vec<(u64, u64)>> {
+        match parse(header, len) {
+            Parsed::Ranges(ranges) => Some(ranges.into_iter().map(|r| (r.start, r.end)).collect()),
+            Parsed::Unsatisfiable => Some(Vec::new()),
+            Parsed::Ignore => None,
+        }
+    }
+
+    fn request(range: &str, if_range: Option<&str>) -> RangeRequest {
+        let mut request = hyper::Request::get("/").header("range", range);
+        if let Some(value) = if_range {
+            request = request.header("if-range", value);
+        }
+        RangeRequest::from_request(&request.body(()).unwrap()).unwrap()
+    }
+
+    async fn body(response: Response) -> Bytes {
+        response.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        assert_eq!(ranges("bytes=0-499", 10_000), Some(vec![(0, 500)]));
+        assert_eq!(ranges("bytes=9500-", 10_000), Some(vec![(9500, 10_000)]));
+        assert_eq!(ranges("bytes=-500", 10_000), Some(vec![(9500, 10_000)]));
+        assert_eq!(ranges("bytes=-500", 100), Some(vec![(0, 100)]));
+        assert_eq!(ranges("Bytes=0-0, 5-999999", 10), Some(vec![(0, 1), (5, 10)]));
+        assert_eq!(ranges("bytes=10-20", 10), Some(vec![]));
+        assert_eq!(ranges("bytes=0-", 0), Some(vec![]));
+        for ignored in ["items=0-1", "bytes=5-1", "bytes=a-b", "bytes=1", "bytes"] {
+            assert_eq!(ranges(ignored, 10), None, "{ignored}");
+        }
+        let many = (0..20).map(|i| format!("{i}-{i}")).collect::<Vec<_>>().join(",");
+        assert_eq!(ranges(&format!("bytes={many}"), 100), None);
+    }
+
+    #[tokio::test]
+    async fn test_byte_responses_serve_ranges() {
+        let content = || Bytes::from_static(b"0123456789").into_response();
+        assert_eq!(content().headers()["accept-ranges"], "bytes");
+
+        let single = request("bytes=2-4", None).apply(content());
+        assert_eq!(single.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(single.headers()["content-range"], "bytes 2-4/10");
+        assert_eq!(single.headers()["content-length"], "3");
+        assert_eq!(body(single).await, "234");
+
+        let multi = request("bytes=0-1,-2", None).apply(content());
+        let content_type = multi.headers()["content-type"].to_str().unwrap().to_string();
+        let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+        let length: usize = multi.headers()["content-length"].to_str().unwrap().parse().unwrap();
+        let text = String::from_utf8(body(multi).await.to_vec()).unwrap();
+        assert_eq!(text.len(), length);
+        assert!(text.contains("Content-Range: bytes 0-1/10\r\n\r\n01\r\n"));
+        assert!(text.contains("Content-Range: bytes 8-9/10\r\n\r\n89\r\n"));
+        assert!(text.ends_with(&format!("--{boundary}--\r\n")));
+
+        let unsatisfiable = request("bytes=20-", None).apply(content());
+        assert_eq!(unsatisfiable.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(unsatisfiable.headers()["content-range"], "bytes */10");
+
+        // Responses without a range source are left alone
+        let text = request("bytes=0-1", None).apply("hello".into_response());
+        assert_eq!(text.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_file_ranges_and_if_range() {
+        let path = std::env::temp_dir().join(format!("s_web-range-{}.txt", std::process::id()));
+        std::fs::write(&path, "hello, ranges").unwrap();
+
+        let file = File::open(&path).await.unwrap().into_response();
+        assert_eq!(file.headers()["content-type"], "text/plain; charset=utf-8");
+        let etag = file.headers()["etag"].to_str().unwrap().to_string();
+        let last_modified = file.headers()["last-modified"].to_str().unwrap().to_string();
+        assert_eq!(body(file).await, "hello, ranges");
+
+        for validator in [etag.as_str(), last_modified.as_str()] {
+            let response = request("bytes=7-", Some(validator)).apply(File::open(&path).await.unwrap().into_response());
+            assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(body(response).await, "ranges");
+        }
+        for stale in ["\"other\"", "W/\"weak\"", "Thu, 01 Jan 1970 00:00:00 GMT"] {
+            let response = request("bytes=7-", Some(stale)).apply(File::open(&path).await.unwrap().into_response());
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(body(response).await, "hello, ranges");
+        }
+
+        let multi = request("bytes=0-4,7-12", None).apply(File::open(&path).await.unwrap().into_response());
+        let text = String::from_utf8(body(multi).await.to_vec()).unwrap();
+        assert!(text.contains("Content-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-4/13\r\n\r\nhello\r\n"));
+        assert!(text.contains("Content-Range: bytes 7-12/13\r\n\r\nranges\r\n"));
+
+        assert_eq!(File::open(std::env::temp_dir()).await.err().map(|e| e.kind()), Some(io::ErrorKind::NotFound));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,527 @@
+//! Per-client request budgets enforced at routing time.
+//!
+//! A [`Rate`] is a token bucket: `Rate::per_second(10).burst(50)` lets a
+//! client send 50 requests at once and then 10 per second, and an optional
+//! sustained bucket caps the long-run volume on top of that. Set a default
+//! for every route with `Engine::rate_limit` and give single routes their
+//! own budget with `.rate_limit(..)` at registration:
+//!
+//! ```rust,ignore
+//! use s_web::ratelimit::Rate;
+//! use std::time::Duration;
+//!
+//! app.rate_limit(Rate::per_second(20).burst(40));
+//! app.get("/search", search).rate_limit(Rate::per_second(100).burst(200));
+//! app.post("/admin/reindex", reindex)
+//!     .rate_limit(Rate::per_minute(2).sustained(10, Duration::from_secs(3600)));
+//! ```
+//!
+//! Clients are told apart by remote IP; requests without one are not limited.
+//! Over-budget requests get `429 Too Many Requests` with `Retry-After`.
+//!
+//! The [`RateLimit`] middleware applies a budget to whatever it wraps, keyed
+//! by IP, a header or a custom function, and reports the balance in
+//! `X-RateLimit-Limit`, `X-RateLimit-Remaining` and `X-RateLimit-Reset`.
+//! Buckets live in the process by default; a shared [`KvStore`] (e.g.
+//! Redis) enforces one budget across instances:
+//!
+//! ```rust,ignore
+//! use s_web::ratelimit::{Rate, RateLimit};
+//!
+//! app.group("/api").use_middleware(
+//!     RateLimit::new(Rate::per_second(5).burst(20))
+//!         .key_by_header("x-api-key")
+//!         .store(kv.clone())
+//!         .middleware(),
+//! );
+//! ```
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hyper::header::{HeaderName, HeaderValue};
+
+use crate::{
+    Next, RequestCtx, Response, ResponseBuilder, StatusCode,
+    store::{self, KvStore},
+};
+
+/// Capacity and refill speed of one bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Bucket {
+    capacity: f64,
+    per_second: f64,
+}
+
+impl Bucket {
+    fn new(limit: u32, per: Duration) -> Self {
+        let limit = f64::from(limit.max(1));
+        Self {
+            capacity: limit,
+            per_second: limit / per.as_secs_f64().max(f64::EPSILON),
+        }
+    }
+}
+
+/// A request budget: a token bucket refilled at a steady rate, plus an
+/// optional slower bucket for the sustained rate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    burst: Bucket,
+    sustained: Option<Bucket>,
+}
+
+impl Rate {
+    /// `limit` requests per `per`; the burst defaults to `limit`.
+    pub fn new(limit: u32, per: Duration) -> Self {
+        Self {
+            burst: Bucket::new(limit, per),
+            sustained: None,
+        }
+    }
+
+    pub fn per_second(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(limit: u32) -> Self {
+        Self::new(limit, Duration::from_secs(60))
+    }
+
+    /// Requests a client may send at once after being idle.
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst.capacity = f64::from(burst.max(1));
+        self
+    }
+
+    /// Additionally allow at most `limit` requests per `per` in the long run,
+    /// e.g. 10 per second with bursts of 50, but no more than 5000 per hour.
+    pub fn sustained(mut self, limit: u32, per: Duration) -> Self {
+        self.sustained = Some(Bucket::new(limit, per));
+        self
+    }
+
+    fn buckets(&self) -> impl Iterator<Item = &Bucket> {
+        std::iter::once(&self.burst).chain(&self.sustained)
+    }
+}
+
+/// Tokens left in each bucket of one client
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Tokens {
+    burst: f64,
+    sustained: f64,
+}
+
+impl Tokens {
+    fn full(rate: &Rate) -> Self {
+        Self {
+            burst: rate.burst.capacity,
+            sustained: rate.sustained.map_or(0.0, |b| b.capacity),
+        }
+    }
+
+    fn refill(&mut self, rate: &Rate, elapsed: Duration) {
+        let elapsed = elapsed.as_secs_f64();
+        self.burst = (self.burst + elapsed * rate.burst.per_second).min(rate.burst.capacity);
+        if let Some(sustained) = &rate.sustained {
+            self.sustained = (self.sustained + elapsed * sustained.per_second).min(sustained.capacity);
+        }
+    }
+
+    fn levels(&self) -> [f64; 2] {
+        [self.burst, self.sustained]
+    }
+
+    fn is_full(&self, rate: &Rate) -> bool {
+        rate.buckets().zip(self.levels()).all(|(bucket, tokens)| tokens >= bucket.capacity)
+    }
+
+    /// Take a token from every bucket, or leave them as they are if one is empty
+    fn take(&mut self, rate: &Rate) -> RateDecision {
+        let wait = rate
+            .buckets()
+            .zip(self.levels())
+            .filter(|(_, tokens)| *tokens < 1.0)
+            .map(|(bucket, tokens)| Duration::from_secs_f64((1.0 - tokens) / bucket.per_second))
+            .max();
+        if wait.is_none() {
+            self.burst -= 1.0;
+            self.sustained -= 1.0;
+        }
+        let remaining = rate
+            .buckets()
+            .zip(self.levels())
+            .map(|(_, tokens)| tokens.max(0.0) as u64)
+            .min()
+            .unwrap_or(0);
+        let reset_after = rate
+            .buckets()
+            .zip(self.levels())
+            .map(|(bucket, tokens)| Duration::from_secs_f64((bucket.capacity - tokens).max(0.0) / bucket.per_second))
+            .max()
+            .unwrap_or_default();
+        RateDecision {
+            limit: rate.burst.capacity as u64,
+            remaining,
+            reset_after,
+            retry_after: wait,
+        }
+    }
+}
+
+/// Outcome of a [`RateLimitStore::acquire`] call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateDecision {
+    /// Requests a client may send at once (the burst).
+    pub limit: u64,
+    /// Requests left right now.
+    pub remaining: u64,
+    /// Time until the buckets are full again.
+    pub reset_after: Duration,
+    /// `Some` when the request was refused: time until a token is available.
+    pub retry_after: Option<Duration>,
+}
+
+impl RateDecision {
+    pub fn allowed(&self) -> bool {
+        self.retry_after.is_none()
+    }
+}
+
+/// Buckets of all clients under one budget
+#[derive(Debug)]
+struct Clients<K> {
+    tokens: HashMap<K, (Tokens, Instant)>,
+    /// Size at which idle (full) entries are swept
+    sweep_at: usize,
+}
+
+impl<K> Default for Clients<K> {
+    fn default() -> Self {
+        Self {
+            tokens: HashMap::new(),
+            sweep_at: 0,
+        }
+    }
+}
+
+impl<K: Hash + Eq> Clients<K> {
+    fn take(&mut self, key: K, rate: &Rate, now: Instant) -> RateDecision {
+        if self.tokens.len() >= self.sweep_at {
+            self.tokens.retain(|_, (tokens, updated)| {
+                tokens.refill(rate, now.saturating_duration_since(*updated));
+                *updated = now;
+                !tokens.is_full(rate)
+            });
+            self.sweep_at = (self.tokens.len() * 2).max(1024);
+        }
+        let (tokens, updated) = self.tokens.entry(key).or_insert_with(|| (Tokens::full(rate), now));
+        tokens.refill(rate, now.saturating_duration_since(*updated));
+        *updated = now;
+        tokens.take(rate)
+    }
+}
+
+/// One budget shared by the requests it applies to, tracked per client
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    rate: Rate,
+    clients: Mutex<Clients<IpAddr>>,
+}
+
+impl Limiter {
+    pub(crate) fn new(rate: Rate) -> Self {
+        Self {
+            rate,
+            clients: Mutex::new(Clients::default()),
+        }
+    }
+
+    /// `None` when the request fits the budget, else the `429` to answer with
+    pub(crate) fn check(&self, ctx: &RequestCtx) -> Option<Response> {
+        let ip = ctx.client_ip()?;
+        let decision = self.clients.lock().unwrap_or_else(|e| e.into_inner()).take(ip, &self.rate, Instant::now());
+        (!decision.allowed()).then(|| too_many_requests(decision))
+    }
+
+    /// Take a token from every bucket, or report how long until that is possible
+    #[cfg(test)]
+    fn take(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let decision = self.clients.lock().unwrap_or_else(|e| e.into_inner()).take(ip, &self.rate, now);
+        decision.retry_after.map_or(Ok(()), Err)
+    }
+}
+
+/// `429` for a refused request
+fn too_many_requests(decision: RateDecision) -> Response {
+    let mut response = ResponseBuilder::new()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .content_type("text/plain; charset=utf-8")
+        .with_header("Retry-After", ceil_secs(decision.retry_after.unwrap_or_default()).to_string())
+        .build("429 Too Many Requests: rate limit exceeded");
+    set_rate_headers(&mut response, decision);
+    response
+}
+
+fn set_rate_headers(response: &mut Response, decision: RateDecision) {
+    let headers = response.headers_mut();
+    headers.insert(HeaderName::from_static("x-ratelimit-limit"), HeaderValue::from(decision.limit));
+    headers.insert(
+        HeaderName::from_static("x-ratelimit-remaining"),
+        HeaderValue::from(decision.remaining),
+    );
+    headers.insert(
+        HeaderName::from_static("x-ratelimit-reset"),
+        HeaderValue::from(ceil_secs(decision.reset_after)),
+    );
+}
+
+/// Round up so clients never retry too early
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// Backend keeping the token buckets of [`RateLimit`].
+///
+/// `acquire` must atomically refill the bucket for `key`, take a token if
+/// there is one and report the balance.
+pub trait RateLimitStore: Send + Sync + 'static {
+    fn acquire(&self, key: &str, rate: &Rate) -> Pin<Box<dyn Future<Output = RateDecision> + Send + '_>>;
+}
+
+/// In-process buckets.
+#[derive(Default)]
+pub struct MemoryRateLimitStore {
+    clients: Mutex<Clients<String>>,
+}
+
+impl MemoryRateLimitStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RateLimitStore for MemoryRateLimitStore {
+    fn acquire(&self, key: &str, rate: &Rate) -> Pin<Box<dyn Future<Output = RateDecision> + Send + '_>> {
+        let decision = self
+            .clients
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take(key.to_string(), rate, Instant::now());
+        Box::pin(async move { decision })
+    }
+}
+
+/// Attempts at a compare-and-swap update before a request is let through
+const CAS_ATTEMPTS: usize = 8;
+
+/// Buckets under `ratelimit:` keys of a shared [`KvStore`], updated with
+/// compare-and-swap and refilled by wall-clock time, so instances need
+/// reasonably synchronized clocks. A full bucket is the same as a missing
+/// key, so keys expire once refilled. If the store is unreachable requests
+/// are allowed.
+impl RateLimitStore for Arc<dyn KvStore> {
+    fn acquire(&self, key: &str, rate: &Rate) -> Pin<Box<dyn Future<Output = RateDecision> + Send + '_>> {
+        let key = format!("ratelimit:{key}");
+        let rate = *rate;
+        Box::pin(async move {
+            let result = async {
+                for _ in 0..CAS_ATTEMPTS {
+                    let current = self.get(&key).await?;
+                    let now = store::unix_millis();
+                    let mut tokens = Tokens::full(&rate);
+                    if let Some((stored, updated)) = current.as_deref().and_then(decode_tokens) {
+                        tokens = stored;
+                        tokens.refill(&rate, Duration::from_millis(now.saturating_sub(updated)));
+                    }
+                    let decision = tokens.take(&rate);
+                    if !decision.allowed() {
+                        return Ok(decision);
+                    }
+                    let ttl = decision.reset_after + Duration::from_secs(1);
+                    let new = encode_tokens(tokens, now);
+                    if self.cas(&key, current.as_deref(), new, Some(ttl)).await? {
+                        return Ok(decision);
+                    }
+                }
+                Err::<_, crate::BoxError>(format!("rate limit bucket `{key}` kept changing").into())
+            };
+            let fallback = RateDecision {
+                limit: rate.burst.capacity as u64,
+                remaining: rate.burst.capacity as u64,
+                reset_after: Duration::ZERO,
+                retry_after: None,
+            };
+            store::or_log(result.await, fallback)
+        })
+    }
+}
+
+/// `burst sustained updated_ms`
+fn encode_tokens(tokens: Tokens, updated_ms: u64) -> Vec<u8> {
+    format!("{} {} {updated_ms}", tokens.burst, tokens.sustained).into_bytes()
+}
+
+fn decode_tokens(bytes: &[u8]) -> Option<(Tokens, u64)> {
+    let text = std::str::from_utf8(bytes).ok()?;
+    let mut parts = text.split(' ');
+    let burst = parts.next()?.parse().ok()?;
+    let sustained = parts.next()?.parse().ok()?;
+    let updated = parts.next()?.parse().ok()?;
+    Some((Tokens { burst, sustained }, updated))
+}
+
+type KeyFn = Arc<dyn Fn(&RequestCtx) -> Option<String> + Send + Sync>;
+
+/// Rate limiting middleware configuration.
+#[derive(Clone)]
+pub struct RateLimit {
+    rate: Rate,
+    store: Arc<dyn RateLimitStore>,
+    key: KeyFn,
+}
+
+impl RateLimit {
+    /// Limit each client IP to `rate`, with buckets kept in the process.
+    pub fn new(rate: Rate) -> Self {
+        Self {
+            rate,
+            store: Arc::new(MemoryRateLimitStore::new()),
+            key: Arc::new(|ctx| ctx.client_ip().map(|ip| ip.to_string())),
+        }
+    }
+
+    /// Where the buckets are kept, e.g. an `Arc<dyn KvStore>` shared by all instances.
+    pub fn store(mut self, store: impl RateLimitStore) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+
+    /// Give each value of a request header its own budget, e.g. an API key.
+    pub fn key_by_header(self, name: &str) -> Self {
+        let name = name.to_string();
+        self.key(move |ctx| ctx.header(&name).map(str::to_owned))
+    }
+
+    /// Extract the client key. Requests without a key are not limited.
+    pub fn key<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&RequestCtx) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(f);
+        self
+    }
+
+    /// Build the middleware function for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let limit = Arc::new(self);
+        move |ctx, next| {
+            let limit = limit.clone();
+            Box::pin(async move { limit.handle(ctx, next).await })
+        }
+    }
+
+    async fn handle(&self, ctx: RequestCtx, next: Next) -> Response {
+        let Some(key) = (self.key)(&ctx) else {
+            return next(ctx).await;
+        };
+        let decision = self.store.acquire(&key, &self.rate).await;
+        if !decision.allowed() {
+            return too_many_requests(decision);
+        }
+        let mut response = next(ctx).await;
+        set_rate_headers(&mut response, decision);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_steady_rate() {
+        let limiter = Limiter::new(Rate::per_second(10).burst(3));
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.take(ip, start), Ok(()));
+        }
+        let wait = limiter.take(ip, start).unwrap_err();
+        assert!(wait <= Duration::from_millis(100) && wait > Duration::from_millis(90), "{wait:?}");
+        // Other clients have their own buckets
+        assert_eq!(limiter.take(IpAddr::from([10, 0, 0, 1]), start), Ok(()));
+
+        let later = start + Duration::from_millis(100);
+        assert_eq!(limiter.take(ip, later), Ok(()));
+        assert!(limiter.take(ip, later).is_err());
+        // Refills up to the burst, not beyond
+        let idle = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.take(ip, idle), Ok(()));
+        }
+        assert!(limiter.take(ip, idle).is_err());
+    }
+
+    #[test]
+    fn test_sustained_rate_caps_bursts() {
+        let limiter = Limiter::new(Rate::per_second(100).sustained(5, Duration::from_secs(60)));
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let start = Instant::now();
+        for _ in 0..5 {
+            assert_eq!(limiter.take(ip, start), Ok(()));
+        }
+        let wait = limiter.take(ip, start).unwrap_err();
+        assert!(wait > Duration::from_secs(11) && wait <= Duration::from_secs(12), "{wait:?}");
+        assert_eq!(limiter.take(ip, start + Duration::from_secs(12)), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_middleware_shares_kv_buckets_between_instances() {
+        let kv: Arc<dyn KvStore> = Arc::new(crate::store::MemoryKvStore::new());
+        let limit = || {
+            RateLimit::new(Rate::per_minute(2))
+                .key_by_header("x-api-key")
+                .store(kv.clone())
+                .middleware()
+        };
+        let (a, b) = (limit(), limit());
+        let next: Next = Arc::new(|_| Box::pin(async { ResponseBuilder::new().build("ok") }));
+        let request = |key: Option<&str>| {
+            let mut request = hyper::Request::get("/api");
+            if let Some(key) = key {
+                request = request.header("x-api-key", key);
+            }
+            RequestCtx::from_parts(request.body(()).unwrap().into_parts().0)
+        };
+
+        let response = a(request(Some("k1")), next.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-ratelimit-limit"], "2");
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "1");
+        assert_eq!(response.headers()["x-ratelimit-reset"], "30");
+        let response = b(request(Some("k1")), next.clone()).await;
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "0");
+
+        let response = a(request(Some("k1")), next.clone()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "30");
+        // Other keys have their own budget; requests without one are not limited
+        assert_eq!(b(request(Some("k2")), next.clone()).await.status(), StatusCode::OK);
+        let response = a(request(None), next).await;
+        assert!(response.headers().get("x-ratelimit-limit").is_none());
+    }
+}
@@ -1,25 +1,43 @@
 //! HTTP response utilities and type conversions.
 
-use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full};
-use hyper::body::Bytes;
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
 
-pub type Response = hyper::Response<BoxBody<Bytes, hyper::Error>>;
+use futures_util::{Stream, StreamExt};
+use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full, StreamBody};
+use hyper::body::{Bytes, Frame};
+
+use crate::log;
+
+/// Body type of every response
+pub(crate) type Body = BoxBody<Bytes, hyper::Error>;
+
+pub type Response = hyper::Response<Body>;
 
 /// Create a full body from any type that can convert to Bytes
-fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, hyper::Error> {
+pub(crate) fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, hyper::Error> {
     Full::new(chunk.into())
         .map_err(|never| match never {})
         .boxed()
 }
 
 /// Create an empty body
-fn empty() -> BoxBody<Bytes, hyper::Error> {
+pub(crate) fn empty() -> BoxBody<Bytes, hyper::Error> {
     Empty::<Bytes>::new()
         .map_err(|never| match never {})
         .boxed()
 }
 
 /// A builder for creating HTTP responses with method chaining
+///
+/// Invalid input (e.g. a header value containing a newline) never panics.
+/// `try_header` and `try_body` report it as an error; the `Result` from
+/// `try_body` is itself a response, answering 500 on error, so handlers can
+/// return it as is or handle the error with `?`. The deprecated `header` and
+/// `body` keep the first error and answer 500 without telling the caller.
 pub struct ResponseBuilder {
     builder: hyper::http::response::Builder,
 }
@@ -38,8 +56,17 @@ impl ResponseBuilder {
         self
     }
 
-    /// Add a header
-    pub fn header<V>(mut self, key: &str, value: V) -> Self
+    /// Add a header; an invalid name or value turns the response into a 500
+    #[deprecated(note = "use try_header, which reports an invalid name or value instead of answering 500")]
+    pub fn header<V>(self, key: &str, value: V) -> Self
+    where
+        V: AsRef<str>,
+    {
+        self.with_header(key, value)
+    }
+
+    /// `header` for the crate's own responses, whose headers are known to be valid
+    pub(crate) fn with_header<V>(mut self, key: &str, value: V) -> Self
     where
         V: AsRef<str>,
     {
@@ -47,28 +74,75 @@ impl ResponseBuilder {
         self
     }
 
+    /// Add a header, failing right away if the name or value is invalid
+    pub fn try_header<V>(mut self, key: &str, value: V) -> Result<Self, hyper::http::Error>
+    where
+        V: AsRef<str>,
+    {
+        let name = hyper::header::HeaderName::from_bytes(key.as_bytes())?;
+        let value = hyper::header::HeaderValue::from_str(value.as_ref())?;
+        self.builder = self.builder.header(name, value);
+        Ok(self)
+    }
+
+    /// Apply every header from `headers`, replacing values already set under the same names
+    pub fn headers(mut self, headers: hyper::HeaderMap) -> Self {
+        if let Some(map) = self.builder.headers_mut() {
+            map.extend(headers);
+        }
+        self
+    }
+
+    /// Append already-parsed headers, keeping values already set under the same names
+    pub fn extend_headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = (hyper::header::HeaderName, hyper::header::HeaderValue)>,
+    {
+        if let Some(map) = self.builder.headers_mut() {
+            map.extend(headers);
+        }
+        self
+    }
+
     /// Set content type
     pub fn content_type(self, content_type: &str) -> Self {
-        self.header("Content-Type", content_type)
+        self.with_header("Content-Type", content_type)
+    }
+
+    /// Set `Cache-Control`
+    pub fn cache_control(self, cache: &crate::cache::CacheControl) -> Self {
+        self.with_header("Cache-Control", cache.to_string())
     }
 
-    /// Build response with body
+    /// Build response with body, or a 500 if an earlier step was invalid
+    #[deprecated(note = "use try_body, which reports an earlier invalid step instead of answering 500")]
     pub fn body<T: Into<Bytes>>(self, body: T) -> Response {
-        self.builder.body(full(body)).unwrap_or_else(|_| {
-            hyper::Response::builder()
-                .status(hyper::StatusCode::INTERNAL_SERVER_ERROR)
-                .body(full("500 Internal Server Error"))
-                .expect("static fallback response is always valid")
+        self.build(body)
+    }
+
+    /// `body` for the crate's own responses
+    pub(crate) fn build<T: Into<Bytes>>(self, body: T) -> Response {
+        self.try_body(body).unwrap_or_else(|e| {
+            log::warn!("invalid response: {e}");
+            fallback_error()
         })
     }
 
-    /// Build response with empty body
+    /// Build response with body, returning the first error recorded by the builder
+    pub fn try_body<T: Into<Bytes>>(self, body: T) -> Result<Response, hyper::http::Error> {
+        self.builder.body(full(body))
+    }
+
+    /// Build response with empty body, or a 500 if an earlier step was invalid
     pub fn empty_body(self) -> Response {
-        self.builder.body(empty()).unwrap_or_else(|_| {
-            hyper::Response::builder()
-                .status(hyper::StatusCode::NO_CONTENT)
-                .body(empty())
-                .expect("static fallback response is always valid")
+        self.streaming(empty())
+    }
+
+    /// Build response with an already boxed body, or a 500 if an earlier step was invalid
+    pub(crate) fn streaming(self, body: Body) -> Response {
+        self.builder.body(body).unwrap_or_else(|e| {
+            log::warn!("invalid response: {e}");
+            fallback_error()
         })
     }
 
@@ -76,7 +150,7 @@ impl ResponseBuilder {
     pub fn html<T: Into<Bytes>>(body: T) -> Response {
         Self::new()
             .content_type("text/html; charset=utf-8")
-            .body(body)
+            .build(body)
     }
 
     /// Build a 404 response
@@ -84,7 +158,7 @@ impl ResponseBuilder {
         Self::new()
             .status(hyper::StatusCode::NOT_FOUND)
             .content_type("text/plain; charset=utf-8")
-            .body("404 Not Found")
+            .build("404 Not Found")
     }
 
     /// Build a 500 response
@@ -92,7 +166,17 @@ impl ResponseBuilder {
         Self::new()
             .status(hyper::StatusCode::INTERNAL_SERVER_ERROR)
             .content_type("text/plain; charset=utf-8")
-            .body("500 Internal Server Error")
+            .build("500 Internal Server Error")
+    }
+
+    /// Build a `101 Switching Protocols` response accepting an upgrade to
+    /// `protocol`; pair it with [`RequestCtx::upgrade`](crate::RequestCtx::upgrade).
+    pub fn switching_protocols(protocol: &str) -> Response {
+        Self::new()
+            .status(hyper::StatusCode::SWITCHING_PROTOCOLS)
+            .with_header("Connection", "upgrade")
+            .with_header("Upgrade", protocol)
+            .empty_body()
     }
 
     /// Build a 204 No Content response
@@ -109,6 +193,17 @@ impl Default for ResponseBuilder {
     }
 }
 
+/// Plain 500 used when a response could not be built
+fn fallback_error() -> Response {
+    let mut response = hyper::Response::new(full("500 Internal Server Error"));
+    *response.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response
+}
+
 /// Trait for converting types into HTTP responses
 pub trait IntoResponse {
     fn into_response(self) -> Response;
@@ -120,21 +215,25 @@ fn text_response(body: impl Into<Bytes>) -> Response {
     ResponseBuilder::new()
         .status(hyper::StatusCode::OK)
         .content_type("text/plain; charset=utf-8")
-        .body(body)
+        .build(body)
 }
 
 fn binary_response(body: impl Into<Bytes>) -> Response {
-    ResponseBuilder::new()
+    let body = body.into();
+    let mut response = ResponseBuilder::new()
         .status(hyper::StatusCode::OK)
         .content_type("application/octet-stream")
-        .body(body)
+        .with_header("Accept-Ranges", "bytes")
+        .build(body.clone());
+    response.extensions_mut().insert(crate::range::RangeSource::Memory(body));
+    response
 }
 
 fn json_response(body: String) -> Response {
     ResponseBuilder::new()
         .status(hyper::StatusCode::OK)
         .content_type("application/json; charset=utf-8")
-        .body(body)
+        .build(body)
 }
 
 // --- Text types ---
@@ -157,6 +256,32 @@ impl IntoResponse for &String {
     }
 }
 
+/// `text/html; charset=utf-8` response, e.g. `Html(rendered)`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Html<T>(pub T);
+
+impl<T> IntoResponse for Html<T>
+where
+    T: Into<Bytes>,
+{
+    fn into_response(self) -> Response {
+        ResponseBuilder::html(self.0)
+    }
+}
+
+/// `text/plain; charset=utf-8` response from anything convertible to bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Text<T>(pub T);
+
+impl<T> IntoResponse for Text<T>
+where
+    T: Into<Bytes>,
+{
+    fn into_response(self) -> Response {
+        text_response(self.0)
+    }
+}
+
 // --- JSON types ---
 
 impl IntoResponse for serde_json::Value {
@@ -177,6 +302,30 @@ impl IntoResponse for &serde_json::Value {
     }
 }
 
+/// JSON response from any `Serialize` value; a value that fails to serialize
+/// (e.g. a map with non-string keys) answers 500.
+///
+/// ```rust,ignore
+/// app.post("/users", |_| async { (StatusCode::CREATED, Json(User { id: 1, name: "Alice".into() })) });
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+
+impl<T> IntoResponse for Json<T>
+where
+    T: serde::Serialize,
+{
+    fn into_response(self) -> Response {
+        match serde_json::to_string(&self.0) {
+            Ok(json_str) => json_response(json_str),
+            Err(e) => {
+                log::warn!("failed to serialize JSON response: {e}");
+                ResponseBuilder::internal_error()
+            }
+        }
+    }
+}
+
 // --- Binary types ---
 
 impl IntoResponse for Vec<u8> {
@@ -211,18 +360,18 @@ impl IntoResponse for () {
     }
 }
 
+/// `Err` renders through [`Error`](crate::Error): errors implementing
+/// [`HttpError`](crate::HttpError) answer their own status, a `BoxError` or a
+/// library error is logged and answered with a plain 500.
 impl<T, E> IntoResponse for Result<T, E>
 where
     T: IntoResponse,
-    E: std::fmt::Debug,
+    E: Into<crate::Error>,
 {
     fn into_response(self) -> Response {
         match self {
             Ok(value) => value.into_response(),
-            Err(err) => {
-                eprintln!("[s_web] handler error: {:?}", err);
-                ResponseBuilder::internal_error()
-            }
+            Err(err) => err.into().into_response(),
         }
     }
 }
@@ -257,12 +406,40 @@ where
 {
     fn into_response(self) -> Response {
         let (status, content_type, content) = self;
+        let Ok(content_type) = hyper::header::HeaderValue::from_str(content_type) else {
+            log::warn!("invalid content type: {content_type:?}");
+            return fallback_error();
+        };
         let mut response = content.into_response();
         *response.status_mut() = status;
-        response.headers_mut().insert(
-            hyper::header::CONTENT_TYPE,
-            hyper::header::HeaderValue::from_static(content_type),
-        );
+        response
+            .headers_mut()
+            .insert(hyper::header::CONTENT_TYPE, content_type);
+        response
+    }
+}
+
+/// Headers from the map replace values the content set under the same names
+impl<T> IntoResponse for (hyper::HeaderMap, T)
+where
+    T: IntoResponse,
+{
+    fn into_response(self) -> Response {
+        let (headers, content) = self;
+        let mut response = content.into_response();
+        response.headers_mut().extend(headers);
+        response
+    }
+}
+
+impl<T> IntoResponse for (hyper::StatusCode, hyper::HeaderMap, T)
+where
+    T: IntoResponse,
+{
+    fn into_response(self) -> Response {
+        let (status, headers, content) = self;
+        let mut response = (headers, content).into_response();
+        *response.status_mut() = status;
         response
     }
 }
@@ -272,3 +449,460 @@ impl IntoResponse for Response {
         self
     }
 }
+
+// --- Server-Sent Events ---
+
+/// A single Server-Sent Event.
+#[derive(Debug, Clone, Default)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: Option<String>,
+    retry: Option<Duration>,
+    comment: Option<String>,
+}
+
+impl Event {
+    /// Event carrying `data`; multi-line data is split into several `data:` fields.
+    pub fn data(data: impl Into<String>) -> Self {
+        Self {
+            data: Some(data.into()),
+            ..Self::default()
+        }
+    }
+
+    /// Event whose data is `value` serialized as JSON.
+    pub fn json<T: serde::Serialize>(value: &T) -> Result<Self, serde_json::Error> {
+        Ok(Self::data(serde_json::to_string(value)?))
+    }
+
+    /// Event type, dispatched to `addEventListener(name)` on the client.
+    pub fn event(mut self, name: impl Into<String>) -> Self {
+        self.event = Some(name.into());
+        self
+    }
+
+    /// Event id, echoed back by the client in `Last-Event-ID` on reconnect.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Reconnection delay the client should use.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Comment line, ignored by clients.
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /// Encode in the `text/event-stream` wire format.
+    fn encode(&self) -> Bytes {
+        // Single-line fields can't contain line breaks without corrupting the stream.
+        fn line(out: &mut String, field: &str, value: &str) {
+            out.push_str(field);
+            out.push_str(": ");
+            out.extend(value.chars().filter(|c| *c != '\n' && *c != '\r'));
+            out.push('\n');
+        }
+
+        let mut out = String::new();
+        if let Some(comment) = &self.comment {
+            for part in comment.lines() {
+                out.push_str(": ");
+                out.push_str(part);
+                out.push('\n');
+            }
+        }
+        if let Some(event) = &self.event {
+            line(&mut out, "event", event);
+        }
+        if let Some(id) = &self.id {
+            line(&mut out, "id", id);
+        }
+        if let Some(retry) = self.retry {
+            line(&mut out, "retry", &retry.as_millis().to_string());
+        }
+        if let Some(data) = &self.data {
+            for part in data.split('\n') {
+                line(&mut out, "data", part);
+            }
+        }
+        out.push('\n');
+        Bytes::from(out)
+    }
+}
+
+/// A `text/event-stream` response fed by a stream of [`Event`]s.
+///
+/// A comment is sent whenever the stream has been quiet for the keep-alive
+/// interval (15s by default) so proxies don't drop the connection.
+///
+/// ```rust,ignore
+/// app.get("/ticks", |_| async {
+///     let ticks = futures_util::stream::iter(0..10).then(|i| async move {
+///         tokio::time::sleep(Duration::from_secs(1)).await;
+///         Event::data(i.to_string())
+///     });
+///     Sse::new(ticks)
+/// });
+/// ```
+pub struct Sse<S> {
+    stream: S,
+    keep_alive: Option<Duration>,
+}
+
+impl<S> Sse<S>
+where
+    S: Stream<Item = Event> + Send + 'static,
+{
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            keep_alive: Some(Duration::from_secs(15)),
+        }
+    }
+
+    /// Interval between keep-alive comments; `None` disables them.
+    pub fn keep_alive(mut self, interval: Option<Duration>) -> Self {
+        self.keep_alive = interval;
+        self
+    }
+}
+
+impl<S> IntoResponse for Sse<S>
+where
+    S: Stream<Item = Event> + Send + 'static,
+{
+    fn into_response(self) -> Response {
+        let events = KeepAlive {
+            inner: std::sync::Mutex::new(Box::pin(self.stream)),
+            interval: self.keep_alive,
+            sleep: self.keep_alive.map(|d| Box::pin(tokio::time::sleep(d))),
+        };
+        let body = StreamBody::new(events.map(|chunk| Ok::<_, hyper::Error>(Frame::data(chunk))));
+        hyper::Response::builder()
+            .header("Content-Type", "text/event-stream")
+            .header("Cache-Control", "no-cache")
+            .body(BodyExt::boxed(body))
+            .expect("static SSE headers are always valid")
+    }
+}
+
+/// Encodes events and interleaves keep-alive comments while the source is idle.
+struct KeepAlive {
+    // Response bodies must be `Sync`; the mutex provides that without requiring it of the
+    // stream, and is never contended because polling already has `&mut self`.
+    inner: std::sync::Mutex<Pin<Box<dyn Stream<Item = Event> + Send>>>,
+    interval: Option<Duration>,
+    sleep: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl KeepAlive {
+    fn reset(&mut self) {
+        if let (Some(sleep), Some(interval)) = (self.sleep.as_mut(), self.interval) {
+            sleep.as_mut().reset(tokio::time::Instant::now() + interval);
+        }
+    }
+}
+
+impl Stream for KeepAlive {
+    type Item = Bytes;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        let inner = self.inner.get_mut().unwrap_or_else(|e| e.into_inner());
+        match inner.as_mut().poll_next(cx) {
+            Poll::Ready(Some(event)) => {
+                self.reset();
+                return Poll::Ready(Some(event.encode()));
+            }
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => {}
+        }
+        if let Some(sleep) = self.sleep.as_mut()
+            && sleep.as_mut().poll(cx).is_ready()
+        {
+            self.reset();
+            return Poll::Ready(Some(Bytes::from_static(b":\n\n")));
+        }
+        Poll::Pending
+    }
+}
+
+// --- Streams of responses ---
+
+/// A chunked response made of the bodies of a stream of responses, sent as
+/// each item becomes ready. Item statuses and headers are ignored.
+///
+/// ```rust,ignore
+/// app.get("/export", |_| async {
+///     let rows = futures_util::stream::iter(0..1000).map(|i| format!("{{\"id\":{i}}}\n"));
+///     Chunked::new(rows).content_type("application/x-ndjson")
+/// });
+/// ```
+pub struct Chunked<S> {
+    stream: S,
+    content_type: String,
+}
+
+impl<S, R> Chunked<S>
+where
+    S: Stream<Item = R> + Send + 'static,
+    R: IntoResponse,
+{
+    /// Chunked `application/octet-stream` output.
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            content_type: "application/octet-stream".to_string(),
+        }
+    }
+
+    /// Content type of the whole response.
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.content_type = content_type.to_string();
+        self
+    }
+}
+
+impl<S, R> IntoResponse for Chunked<S>
+where
+    S: Stream<Item = R> + Send + 'static,
+    R: IntoResponse,
+{
+    fn into_response(self) -> Response {
+        let chunks = self
+            .stream
+            .flat_map(|item| item.into_response().into_body().into_data_stream());
+        ResponseBuilder::new()
+            .content_type(&self.content_type)
+            .streaming(stream_body(chunks))
+    }
+}
+
+/// A `multipart/mixed` response with one part per item of a stream of
+/// responses; each part keeps its item's headers (such as `Content-Type`).
+///
+/// ```rust,ignore
+/// app.get("/batch", |_| async {
+///     let results = futures_util::stream::iter(ids).then(|id| async move { load(id).await });
+///     Multipart::new(results)
+/// });
+/// ```
+pub struct Multipart<S> {
+    stream: S,
+    boundary: String,
+}
+
+impl<S, R> Multipart<S>
+where
+    S: Stream<Item = R> + Send + 'static,
+    R: IntoResponse,
+{
+    /// Multipart output with a random boundary.
+    pub fn new(stream: S) -> Self {
+        use std::hash::BuildHasher;
+        let random = std::collections::hash_map::RandomState::new().hash_one(0u8);
+        Self {
+            stream,
+            boundary: format!("s_web-{random:016x}"),
+        }
+    }
+
+    /// Boundary between parts; it must not occur in any part.
+    pub fn boundary(mut self, boundary: &str) -> Self {
+        self.boundary = boundary.to_string();
+        self
+    }
+}
+
+impl<S, R> IntoResponse for Multipart<S>
+where
+    S: Stream<Item = R> + Send + 'static,
+    R: IntoResponse,
+{
+    fn into_response(self) -> Response {
+        let boundary = self.boundary.clone();
+        let parts = self.stream.flat_map(move |item| {
+            let (parts, body) = item.into_response().into_parts();
+            let mut head = format!("--{boundary}\r\n");
+            for (name, value) in &parts.headers {
+                head.push_str(&format!("{name}: {}\r\n", String::from_utf8_lossy(value.as_bytes())));
+            }
+            head.push_str("\r\n");
+            futures_util::stream::iter([Ok(Bytes::from(head))])
+                .chain(body.into_data_stream())
+                .chain(futures_util::stream::iter([Ok(Bytes::from_static(b"\r\n"))]))
+        });
+        let closing = futures_util::stream::iter([Ok(Bytes::from(format!("--{}--\r\n", self.boundary)))]);
+        ResponseBuilder::new()
+            .content_type(&format!("multipart/mixed; boundary={}", self.boundary))
+            .streaming(stream_body(parts.chain(closing)))
+    }
+}
+
+/// Box a `Send` stream of chunks as a response body
+pub(crate) fn stream_body<S>(chunks: S) -> Body
+where
+    S: Stream<Item = Result<Bytes, hyper::Error>> + Send + 'static,
+{
+    frame_body(chunks.map(|chunk| chunk.map(Frame::data)))
+}
+
+/// Box a `Send` stream of frames as a response body
+pub(crate) fn frame_body<S>(frames: S) -> Body
+where
+    S: Stream<Item = Result<Frame<Bytes>, hyper::Error>> + Send + 'static,
+{
+    BodyExt::boxed(StreamBody::new(SyncStream(std::sync::Mutex::new(Box::pin(frames)))))
+}
+
+/// Response bodies must be `Sync`; the mutex provides that without requiring it
+/// of the stream, and is never contended because polling already has `&mut self`.
+struct SyncStream<T>(std::sync::Mutex<Pin<Box<dyn Stream<Item = T> + Send>>>);
+
+impl<T> Stream for SyncStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let inner = self.0.get_mut().unwrap_or_else(|e| e.into_inner());
+        inner.as_mut().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_invalid_header_becomes_500() {
+        let response = ResponseBuilder::new()
+            .header("X-Name", "line\nbreak")
+            .body("ok");
+        assert_eq!(response.status(), hyper::StatusCode::INTERNAL_SERVER_ERROR);
+        // The Result from try_body answers the same 500 when returned as a response
+        let response = ResponseBuilder::new().with_header("X-Name", "line\nbreak").try_body("ok");
+        assert_eq!(response.into_response().status(), hyper::StatusCode::INTERNAL_SERVER_ERROR);
+
+        assert!(ResponseBuilder::new().try_header("X-Name", "line\nbreak").is_err());
+        assert!(ResponseBuilder::new().try_header("bad name", "v").is_err());
+        assert!(
+            ResponseBuilder::new()
+                .try_header("X-Name", "ok")
+                .and_then(|b| b.try_body("ok"))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_headers_replace_and_extend_appends() {
+        use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+
+        let mut upstream = HeaderMap::new();
+        upstream.insert("x-origin", HeaderValue::from_static("upstream"));
+        upstream.append("vary", HeaderValue::from_static("accept"));
+
+        let response = ResponseBuilder::new()
+            .with_header("X-Origin", "local")
+            .headers(upstream)
+            .extend_headers([(
+                HeaderName::from_static("vary"),
+                HeaderValue::from_static("origin"),
+            )])
+            .build("ok");
+
+        let headers = response.headers();
+        assert_eq!(headers.get_all("x-origin").iter().count(), 1);
+        assert_eq!(headers["x-origin"], "upstream");
+        assert_eq!(headers.get_all("vary").iter().count(), 2);
+    }
+
+    #[test]
+    fn test_tuple_responses_with_headers() {
+        use hyper::{HeaderMap, StatusCode, header::HeaderValue};
+
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("text/csv"));
+        headers.append("set-cookie", HeaderValue::from_static("a=1"));
+        headers.append("set-cookie", HeaderValue::from_static("b=2"));
+
+        let response = (StatusCode::CREATED, headers.clone(), "id,name").into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["content-type"], "text/csv");
+        assert_eq!(response.headers().get_all("set-cookie").iter().count(), 2);
+
+        let response = (headers, Option::<&str>::None).into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["content-type"], "text/csv");
+
+        let response = (StatusCode::OK, "text/html; charset=utf-8", "<p>hi</p>").into_response();
+        assert_eq!(response.headers()["content-type"], "text/html; charset=utf-8");
+    }
+
+    #[test]
+    fn test_html_and_text_wrappers() {
+        let response = Html(format!("<h1>{}</h1>", "hi")).into_response();
+        assert_eq!(response.headers()["content-type"], "text/html; charset=utf-8");
+
+        let response = (hyper::StatusCode::ACCEPTED, Text(Bytes::from_static(b"queued"))).into_response();
+        assert_eq!(response.status(), hyper::StatusCode::ACCEPTED);
+        assert_eq!(response.headers()["content-type"], "text/plain; charset=utf-8");
+    }
+
+    #[tokio::test]
+    async fn test_json_wrapper() {
+        #[derive(serde::Serialize)]
+        struct User {
+            id: u32,
+            name: &'static str,
+        }
+
+        let response = (hyper::StatusCode::CREATED, Json(User { id: 1, name: "Alice" })).into_response();
+        assert_eq!(response.status(), hyper::StatusCode::CREATED);
+        assert_eq!(response.headers()["content-type"], "application/json; charset=utf-8");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, r#"{"id":1,"name":"Alice"}"#);
+
+        let unserializable = std::collections::HashMap::from([((1, 2), "tuple keys")]);
+        let response = Json(unserializable).into_response();
+        assert_eq!(response.status(), hyper::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_stream_responses() {
+        use futures_util::stream;
+
+        let response = Chunked::new(stream::iter(["a", "b", "c"])).into_response();
+        assert_eq!(response.headers()["content-type"], "application/octet-stream");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "abc");
+
+        let parts = stream::iter([
+            "plain".into_response(),
+            serde_json::json!({ "ok": true }).into_response(),
+        ]);
+        let response = Multipart::new(parts).boundary("XYZ").into_response();
+        assert_eq!(response.headers()["content-type"], "multipart/mixed; boundary=XYZ");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            body,
+            "--XYZ\r\ncontent-type: text/plain; charset=utf-8\r\n\r\nplain\r\n\
+             --XYZ\r\ncontent-type: application/json; charset=utf-8\r\n\r\n{\"ok\":true}\r\n\
+             --XYZ--\r\n"
+        );
+    }
+
+    #[test]
+    fn test_event_encoding_splits_data_lines() {
+        let event = Event::data("a\nb").event("up\ndate").id("7");
+        assert_eq!(
+            event.encode(),
+            Bytes::from("event: update\nid: 7\ndata: a\ndata: b\n\n")
+        );
+    }
+}
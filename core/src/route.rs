@@ -0,0 +1,177 @@
+//! Per-route options attached right after registration.
+
+use std::{
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::Arc,
+};
+
+use crate::{
+    Next, RequestCtx, Response, Router, SecurityScheme,
+    health::HealthRegistry,
+    log,
+    ratelimit::{Limiter, Rate},
+    router::WildcardLimit,
+    sunset::Sunset,
+};
+
+/// Implemented by route owners (`Engine`, `RouterGroup`) so [`RouteBuilder`]
+/// can reach the registered route.
+pub trait RouteTarget {
+    #[doc(hidden)]
+    fn router_mut(&mut self) -> &mut Router;
+
+    #[doc(hidden)]
+    fn health_registry(&self) -> HealthRegistry;
+}
+
+/// Handle to a freshly registered route.
+///
+/// Dereferences to the owner, so registrations can still be chained:
+/// `app.get("/a", a).get("/b", b)`.
+pub struct RouteBuilder<'a, T: RouteTarget> {
+    target: &'a mut T,
+    method: String,
+    pattern: String,
+}
+
+impl<'a, T: RouteTarget> RouteBuilder<'a, T> {
+    pub(crate) fn new(target: &'a mut T, method: &str, pattern: String) -> Self {
+        Self {
+            target,
+            method: method.to_string(),
+            pattern,
+        }
+    }
+
+    /// HTTP method of the route.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Full pattern of the route, including any group prefix.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Add middleware that only runs for this route, after global and group middleware.
+    pub fn with_middleware<F, Fut>(self, middleware: F) -> Self
+    where
+        F: Fn(RequestCtx, Next) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        let wrapped = move |ctx, next| {
+            let fut = middleware(ctx, next);
+            Box::pin(fut) as Pin<Box<dyn Future<Output = Response> + Send>>
+        };
+        if let Some(route) = self.target.router_mut().route_mut(&self.method, &self.pattern) {
+            route.push_middleware(Arc::new(wrapped));
+        }
+        self
+    }
+
+    /// Override the engine's body limit for this route. Middleware that reads
+    /// the body before the route's own middleware still uses the engine limit.
+    pub fn body_limit(self, bytes: usize) -> Self {
+        self.with_middleware(move |mut ctx: RequestCtx, next: Next| {
+            ctx.set_body_limit(bytes);
+            next(ctx)
+        })
+    }
+
+    /// Keep this route out of the OpenAPI document served at `/docs/`
+    /// (internal or debug endpoints). It is still routed and listed by `Engine::routes()`.
+    pub fn hide_from_docs(self) -> Self {
+        if let Some(route) = self.target.router_mut().route_mut(&self.method, &self.pattern) {
+            route.hide_from_docs();
+        }
+        self
+    }
+
+    /// Answer 503 while the named health dependency is unhealthy.
+    pub fn requires_dependency(self, name: &str) -> Self {
+        let registry = self.target.health_registry();
+        self.with_middleware(crate::health::requires_dependency(registry, name))
+    }
+
+    /// Answer `414 URI Too Long` when the route's `*wildcard` would capture
+    /// more than `max_segments` segments or `max_bytes` bytes, overriding
+    /// `Engine::wildcard_limit` for this route.
+    pub fn wildcard_limit(self, max_segments: usize, max_bytes: usize) -> Self {
+        if let Some(route) = self.target.router_mut().route_mut(&self.method, &self.pattern) {
+            route.set_wildcard_limit(WildcardLimit { max_segments, max_bytes });
+        }
+        self
+    }
+
+    /// Give this route its own per-client budget instead of the one set with
+    /// `Engine::rate_limit`, e.g. `.rate_limit(Rate::per_second(10).burst(50))`.
+    pub fn rate_limit(self, rate: Rate) -> Self {
+        if let Some(route) = self.target.router_mut().route_mut(&self.method, &self.pattern) {
+            route.set_rate_limit(Limiter::new(rate));
+        }
+        self
+    }
+
+    /// Name the route for reverse routing: `engine.url_for(name, ..)` and
+    /// `ctx.url_for(name, ..)` build its path from parameter values.
+    pub fn name(self, name: &str) -> Self {
+        if let Some(route) = self.target.router_mut().route_mut(&self.method, &self.pattern) {
+            route.set_name(name);
+        }
+        self
+    }
+
+    /// Retire this route at midnight UTC on `date` (`YYYY-MM-DD`): until then
+    /// responses carry `Deprecation` and `Sunset` headers, afterwards the
+    /// route answers `410 Gone`. The operation is marked deprecated in OpenAPI.
+    pub fn sunset(self, date: &str) -> Self {
+        let Some(sunset) = Sunset::parse(date) else {
+            log::warn!("invalid sunset date {date:?} for {} {}; expected YYYY-MM-DD", self.method, self.pattern);
+            return self;
+        };
+        if let Some(route) = self.target.router_mut().route_mut(&self.method, &self.pattern) {
+            route.set_sunset(sunset);
+        }
+        self
+    }
+
+    /// Body of the `410 Gone` served after the sunset date, e.g. where to
+    /// migrate to. Call after [`sunset`](Self::sunset).
+    pub fn sunset_message(self, message: &str) -> Self {
+        if let Some(sunset) = self
+            .target
+            .router_mut()
+            .route_mut(&self.method, &self.pattern)
+            .and_then(|route| route.sunset_mut())
+        {
+            sunset.set_message(message);
+        }
+        self
+    }
+
+    /// Declare the authentication this route expects: documented in Swagger,
+    /// listed by `Engine::routes()`, and enforced by a guard that answers 401
+    /// when the credential is missing.
+    pub fn secured(self, scheme: SecurityScheme) -> Self {
+        if let Some(route) = self.target.router_mut().route_mut(&self.method, &self.pattern) {
+            route.add_security(scheme.clone());
+        }
+        self.with_middleware(scheme.guard())
+    }
+}
+
+impl<T: RouteTarget> Deref for RouteBuilder<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.target
+    }
+}
+
+impl<T: RouteTarget> DerefMut for RouteBuilder<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.target
+    }
+}
@@ -1,14 +1,260 @@
 //! HTTP router with trie-based pattern matching.
 
-use crate::{Handler, RequestCtx, Response, ResponseBuilder, trie::Node};
-use std::collections::HashMap;
+use crate::{
+    Handler, Middleware, Next, RequestCtx, Response, ResponseBuilder, SecurityScheme, compose, log,
+    params::{Captures, Params},
+    ratelimit::Limiter,
+    sunset::Sunset,
+    trie::{Node, Segment, parse_segment},
+};
+use hyper::http::uri::PathAndQuery;
+use smallvec::SmallVec;
+use std::{
+    collections::HashMap,
+    future::Future,
+    ops::Range,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
 
 type HandlerFunc = Box<dyn Handler>;
 
+/// Method key of routes registered with `any()`; listed as `*` by `Engine::routes()`
+pub(crate) const ANY_METHOD: &str = "*";
+
+/// A registered endpoint: the handler plus middleware scoped to this route only
+#[derive(Clone)]
+pub struct Route {
+    handler: Arc<dyn Handler>,
+    middlewares: Vec<Middleware>,
+    /// `middlewares` composed around the handler; `None` without middleware
+    chain: Option<Next>,
+    security: Vec<SecurityScheme>,
+    hidden: bool,
+    sunset: Option<Arc<Sunset>>,
+    name: Option<String>,
+    wildcard_limit: Option<WildcardLimit>,
+    rate_limit: Option<Arc<Limiter>>,
+    /// Type name of the handler, for `Engine::print_routes`
+    handler_name: &'static str,
+}
+
+/// Largest `*wildcard` capture a route accepts; longer paths get `414 URI Too Long`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WildcardLimit {
+    pub(crate) max_segments: usize,
+    pub(crate) max_bytes: usize,
+}
+
+impl WildcardLimit {
+    /// Whether the captured `value` stays within the limit
+    fn allows(&self, value: &str) -> bool {
+        value.len() <= self.max_bytes && value.split('/').count() <= self.max_segments
+    }
+}
+
+impl Route {
+    fn new(handler: HandlerFunc) -> Self {
+        Self {
+            handler: Arc::from(handler),
+            middlewares: Vec::new(),
+            chain: None,
+            security: Vec::new(),
+            hidden: false,
+            sunset: None,
+            name: None,
+            wildcard_limit: None,
+            rate_limit: None,
+            handler_name: "",
+        }
+    }
+
+    /// Append route-level middleware
+    pub fn push_middleware(&mut self, middleware: Middleware) {
+        self.middlewares.push(middleware);
+        self.compose();
+    }
+
+    /// Record an authentication scheme this route expects
+    pub fn add_security(&mut self, scheme: SecurityScheme) {
+        if !self.security.contains(&scheme) {
+            self.security.push(scheme);
+        }
+    }
+
+    /// Authentication schemes declared with `.secured(..)`
+    pub fn security(&self) -> &[SecurityScheme] {
+        &self.security
+    }
+
+    /// Run `outer` before the route's own middleware (used when mounting an `App`)
+    pub(crate) fn wrap_middlewares(&mut self, outer: &[Middleware]) {
+        if outer.is_empty() {
+            return;
+        }
+        self.middlewares.splice(0..0, outer.iter().cloned());
+        self.compose();
+    }
+
+    /// Rebuild the middleware chain after the middleware list changed
+    fn compose(&mut self) {
+        let handler = self.handler.clone();
+        let endpoint: Next = Arc::new(move |ctx| handler.handle(ctx));
+        self.chain = Some(compose(&self.middlewares, endpoint));
+    }
+
+    /// Leave this route out of the generated OpenAPI document
+    pub fn hide_from_docs(&mut self) {
+        self.hidden = true;
+    }
+
+    /// Whether the route is left out of the OpenAPI document
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    /// Override the router-wide wildcard limit for this route
+    pub(crate) fn set_wildcard_limit(&mut self, limit: WildcardLimit) {
+        self.wildcard_limit = Some(limit);
+    }
+
+    /// Give this route its own request budget instead of the router-wide one
+    pub(crate) fn set_rate_limit(&mut self, limiter: Limiter) {
+        self.rate_limit = Some(Arc::new(limiter));
+    }
+
+    /// Name used for reverse routing with `url_for`
+    pub(crate) fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    /// Retire the route on `sunset`
+    pub(crate) fn set_sunset(&mut self, sunset: Sunset) {
+        self.sunset = Some(Arc::new(sunset));
+    }
+
+    pub(crate) fn sunset_mut(&mut self) -> Option<&mut Sunset> {
+        self.sunset.as_mut().map(Arc::make_mut)
+    }
+
+    /// Run the route middleware chain and the handler
+    fn call(&self, ctx: RequestCtx) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+        if let Some(sunset) = &self.sunset {
+            if sunset.is_past(SystemTime::now()) {
+                let gone = sunset.gone();
+                return Box::pin(async move { gone });
+            }
+            let sunset = sunset.clone();
+            let response = self.call_chain(ctx);
+            return Box::pin(async move { sunset.announce(response.await) });
+        }
+        self.call_chain(ctx)
+    }
+
+    fn call_chain(&self, ctx: RequestCtx) -> Pin<Box<dyn Future<Output = Response> + Send>> {
+        match &self.chain {
+            Some(chain) => chain(ctx),
+            None => self.handler.handle(ctx),
+        }
+    }
+}
+
+/// Route pattern (e.g. `/users/:id`) that served a request, stored in the
+/// request extensions for route-level middleware and in the response
+/// extensions so post-response middleware can report on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedRoute(pub Arc<str>);
+
+/// Methods the path does support, stored in the request extensions for a
+/// custom `Engine::method_not_allowed` handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllowedMethods(pub Vec<String>);
+
+/// Introspection entry returned by `Engine::routes()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteInfo {
+    pub method: String,
+    pub pattern: String,
+    pub security: Vec<SecurityScheme>,
+    /// Excluded from the OpenAPI document (`.hide_from_docs()`)
+    pub hidden: bool,
+    /// Retirement date (`YYYY-MM-DD`) set with `.sunset(..)`
+    pub sunset: Option<String>,
+    /// Name set with `.name(..)` for `url_for`
+    pub name: Option<String>,
+    /// Type name of the handler (`my_app::users::get_user`); empty when unknown
+    pub handler: &'static str,
+}
+
+/// Patterns of named routes, stored in the application state for `RequestCtx::url_for`
+#[derive(Debug, Default)]
+pub(crate) struct RouteUrls {
+    patterns: HashMap<String, String>,
+}
+
+impl RouteUrls {
+    pub(crate) fn new(routes: &[RouteInfo]) -> Self {
+        let mut patterns = HashMap::new();
+        for route in routes {
+            let Some(name) = &route.name else { continue };
+            match patterns.get(name) {
+                Some(existing) if existing != &route.pattern => log::warn!(
+                    "route name \"{name}\" is used by both \"{existing}\" and \"{}\"; url_for uses the first",
+                    route.pattern
+                ),
+                Some(_) => {}
+                None => {
+                    patterns.insert(name.clone(), route.pattern.clone());
+                }
+            }
+        }
+        Self { patterns }
+    }
+
+    pub(crate) fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Option<String> {
+        build_url(self.patterns.get(name)?, params)
+    }
+}
+
+/// Fill the parameters of `pattern` with percent-encoded `params`; `None` if one is missing
+pub(crate) fn build_url(pattern: &str, params: &[(&str, &str)]) -> Option<String> {
+    let value = |name: &str| params.iter().find(|(key, _)| *key == name).map(|(_, value)| *value);
+    let mut url = String::new();
+    for part in Router::parse_pattern(pattern) {
+        url.push('/');
+        match parse_segment(part) {
+            Segment::Static(part) => url.push_str(part),
+            Segment::Param { name, .. } => url.push_str(&encode_segment(value(name)?, false)),
+            Segment::Wildcard(name) => url.push_str(&encode_segment(value(name)?, true)),
+        }
+    }
+    if url.is_empty() || pattern.ends_with('/') {
+        url.push('/');
+    }
+    Some(url)
+}
+
+fn encode_segment(value: &str, keep_slashes: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            b'/' if keep_slashes => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
 /// HTTP router for matching requests to handlers
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Router {
-    roots: HashMap<String, Node<HandlerFunc>>,
+    roots: HashMap<String, Node<Route>>,
+    not_found: Option<Arc<dyn Handler>>,
+    method_not_allowed: Option<Arc<dyn Handler>>,
+    wildcard_limit: Option<WildcardLimit>,
+    rate_limit: Option<Arc<Limiter>>,
 }
 
 impl std::fmt::Debug for Router {
@@ -24,95 +270,437 @@ impl Router {
         Router::default()
     }
 
-    /// Parse a route pattern into parts (only one * is allowed)
+    /// Parse a route pattern or request path into its non-empty segments
     pub fn parse_pattern(pattern: &str) -> Vec<&str> {
-        let vs = pattern.split('/').collect::<Vec<&str>>();
-        let mut part = Vec::new();
-        for &item in vs.iter() {
-            if !item.is_empty() {
-                part.push(item);
-                if item.starts_with('*') {
-                    break;
-                }
+        pattern.split('/').filter(|part| !part.is_empty()).collect()
+    }
+
+    /// Byte ranges of the non-empty segments of a request path; typical paths
+    /// split without allocating
+    fn path_spans(path: &str) -> SmallVec<[Range<usize>; 8]> {
+        let mut spans = SmallVec::new();
+        let mut start = 0;
+        for part in path.split('/') {
+            if !part.is_empty() {
+                spans.push(start..start + part.len());
             }
+            start += part.len() + 1;
         }
-        part
+        spans
+    }
+
+    /// The non-empty segments of a request path, as [`parse_pattern`](Self::parse_pattern)
+    /// splits them, without allocating for typical paths
+    fn path_parts(path: &str) -> SmallVec<[&str; 8]> {
+        path.split('/').filter(|part| !part.is_empty()).collect()
+    }
+
+    /// Patterns with more than one `*wildcard` are ambiguous; they are
+    /// reported and left unregistered
+    fn check_pattern(pattern: &str, parts: &[&str]) -> bool {
+        if parts.iter().filter(|part| part.starts_with('*')).count() > 1 {
+            log::warn!(
+                "route \"{pattern}\" not registered: a pattern may contain only one *wildcard \
+                 (static and :param segments may follow it, e.g. /files/*path/meta)"
+            );
+            return false;
+        }
+        true
     }
 
     /// Add a route with the specified method, pattern, and handler
     pub fn add_route(&mut self, method: &str, pattern: &str, handler: HandlerFunc) {
         let parts = Self::parse_pattern(pattern);
+        if !Self::check_pattern(pattern, &parts) {
+            return;
+        }
         self.roots
             .entry(method.to_string())
             .or_default()
-            .insert(pattern, &parts, 0, handler);
-    }
-
-    /// Get a route handler for the given method and path
-    pub fn get_route(&self, method: &str, path: &str) -> (Option<&Node<HandlerFunc>>, HashMap<String, String>) {
-        let search_parts = Self::parse_pattern(path);
-        let mut params = HashMap::new();
-        let root = self.roots.get(method);
-        if root.is_none() {
-            return (None, HashMap::new());
-        }
-        if let Some(node) = root.unwrap().search(&search_parts, 0) {
-            for (index, name_with_prefix) in node.params() {
-                if let Some(name) = name_with_prefix.strip_prefix(':') {
-                    if let Some(part) = search_parts.get(*index) {
-                        params.insert(name.to_string(), part.to_string());
-                    }
-                } else if let Some(name) = name_with_prefix.strip_prefix('*')
-                    && let Some(wild_val) = search_parts.get(*index..) {
-                        params.insert(name.to_string(), wild_val.join("/"));
-                    }
-            }
-            return (Some(node), params);
+            .insert(pattern, &parts, 0, Route::new(handler));
+    }
+
+    /// Add a route, remembering the handler's type name for route listings
+    pub(crate) fn add_handler(&mut self, method: &str, pattern: &str, handler: impl Handler) {
+        let handler_name = std::any::type_name_of_val(&handler);
+        self.add_route(method, pattern, Box::new(handler));
+        if let Some(route) = self.route_mut(method, pattern) {
+            route.handler_name = handler_name;
         }
-        (None, HashMap::new())
     }
 
-    /// Get all registered routes (method, pattern) for swagger generation
-    pub fn get_all_routes(&self) -> Vec<(String, String)> {
+    /// Add an already configured route, keeping its middleware and options
+    pub(crate) fn insert_route(&mut self, method: &str, pattern: &str, route: Route) {
+        let parts = Self::parse_pattern(pattern);
+        if !Self::check_pattern(pattern, &parts) {
+            return;
+        }
+        self.roots
+            .entry(method.to_string())
+            .or_default()
+            .insert(pattern, &parts, 0, route);
+    }
+
+    /// Unregister the route for exactly this method and pattern
+    pub(crate) fn remove_route(&mut self, method: &str, pattern: &str) -> bool {
+        let parts = Self::parse_pattern(pattern);
+        self.roots
+            .get_mut(method)
+            .and_then(|root| root.find_mut(&parts, 0))
+            .and_then(|node| node.take_value())
+            .is_some()
+    }
+
+    /// Take every route out of the router as `(method, pattern, route)`
+    pub(crate) fn into_routes(self) -> Vec<(String, String, Route)> {
+        let mut routes = Vec::new();
+        for (method, root) in self.roots {
+            let mut values = Vec::new();
+            root.into_values(&mut values);
+            routes.extend(values.into_iter().map(|(pattern, route)| (method.clone(), pattern, route)));
+        }
+        routes
+    }
+
+    /// Handler answering requests no route matches
+    pub fn set_not_found(&mut self, handler: Arc<dyn Handler>) {
+        self.not_found = Some(handler);
+    }
+
+    pub(crate) fn not_found_handler(&self) -> Option<Arc<dyn Handler>> {
+        self.not_found.clone()
+    }
+
+    /// Limit applied to the wildcard captures of routes without their own
+    pub(crate) fn set_wildcard_limit(&mut self, limit: WildcardLimit) {
+        self.wildcard_limit = Some(limit);
+    }
+
+    /// Budget applied to routes without their own; shared by every router it is set on
+    pub(crate) fn set_rate_limit(&mut self, limiter: Arc<Limiter>) {
+        self.rate_limit = Some(limiter);
+    }
+
+    /// Handler answering requests whose path exists under other methods
+    pub fn set_method_not_allowed(&mut self, handler: Arc<dyn Handler>) {
+        self.method_not_allowed = Some(handler);
+    }
+
+    pub(crate) fn method_not_allowed_handler(&self) -> Option<Arc<dyn Handler>> {
+        self.method_not_allowed.clone()
+    }
+
+    /// Get the route registered for exactly this method and pattern
+    pub fn route_mut(&mut self, method: &str, pattern: &str) -> Option<&mut Route> {
+        let parts = Self::parse_pattern(pattern);
+        self.roots
+            .get_mut(method)?
+            .find_mut(&parts, 0)?
+            .value_mut()
+    }
+
+    /// Find the route node for `method` and `path`, with the parameters it captures
+    pub(crate) fn get_route(&self, method: &str, path: &str) -> Option<(&Node<Route>, Captures)> {
+        let spans = Self::path_spans(path);
+        let parts: SmallVec<[&str; 8]> = spans.iter().map(|span| &path[span.clone()]).collect();
+        let node = self.roots.get(method)?.search(&parts, 0)?;
+        // Segments the wildcard spans beyond its own; later segments shift by as many
+        let extra = parts.len().saturating_sub(node.segments());
+        let captures = node
+            .params()
+            .iter()
+            .filter_map(|(index, name)| {
+                let range = match node.wildcard() {
+                    Some(wildcard) if *index == wildcard => spans.get(wildcard)?.start..spans.get(wildcard + extra)?.end,
+                    Some(wildcard) if *index > wildcard => spans.get(index + extra)?.clone(),
+                    _ => spans.get(*index)?.clone(),
+                };
+                Some((name.clone(), range))
+            })
+            .collect();
+        Some((node, captures))
+    }
+
+    /// Describe every registered route
+    pub fn routes(&self) -> Vec<RouteInfo> {
         let mut routes = Vec::new();
 
         for (method, root) in &self.roots {
-            let mut patterns = Vec::new();
-            root.collect_patterns(&mut patterns);
+            let mut nodes = Vec::new();
+            root.collect_nodes(&mut nodes);
 
-            for pattern in patterns {
-                routes.push((method.clone(), pattern));
+            for node in nodes {
+                routes.push(RouteInfo {
+                    method: method.clone(),
+                    pattern: node.pattern().to_string(),
+                    security: node.value().map(|r| r.security.clone()).unwrap_or_default(),
+                    hidden: node.value().is_some_and(|r| r.hidden),
+                    sunset: node.value().and_then(|r| r.sunset.as_ref()).map(|s| s.date().to_string()),
+                    name: node.value().and_then(|r| r.name.clone()),
+                    handler: node.value().map_or("", |r| r.handler_name),
+                });
             }
         }
 
         routes
     }
 
+    /// Whether a route for any method matches `path`
+    pub(crate) fn matches_path(&self, path: &str) -> bool {
+        let parts = Self::path_parts(path);
+        self.roots
+            .values()
+            .any(|root| root.search(&parts, 0).is_some_and(|n| n.value().is_some()))
+    }
+
+    /// Methods with a route matching `path`, sorted, including the implicit
+    /// `HEAD` (for `GET` routes) and `OPTIONS`. Empty if no route matches.
+    pub fn allowed_methods(&self, path: &str) -> Vec<String> {
+        let parts = Self::path_parts(path);
+        let mut methods: Vec<String> = self
+            .roots
+            .iter()
+            .filter(|(_, root)| root.search(&parts, 0).is_some_and(|n| n.value().is_some()))
+            .map(|(method, _)| method.clone())
+            .collect();
+        if methods.is_empty() {
+            return methods;
+        }
+        if methods.iter().any(|m| m == "GET") {
+            methods.push("HEAD".to_string());
+        }
+        methods.push("OPTIONS".to_string());
+        methods.sort();
+        methods.dedup();
+        methods
+    }
+
     /// Handle an HTTP request
-    pub async fn handle_request(&self, mut ctx: RequestCtx) -> Response {
-        let method = ctx.request.method().as_str();
-        let path = ctx.request.uri().path();
-        let (node, params) = self.get_route(method, path);
+    ///
+    /// Routes for the exact method win over `any()` routes. `HEAD` falls back
+    /// to the `GET` route with the body stripped, and
+    /// `OPTIONS` falls back to a `204` listing the allowed methods in `Allow`,
+    /// unless routes are registered for those methods explicitly. A path that
+    /// exists under other methods only gets `405` with an `Allow` header.
+    pub async fn handle_request(&self, ctx: RequestCtx) -> Response {
+        let method = ctx.request.method().clone();
+        // A handle on the request's own buffer, not a copy
+        let target = match ctx.request.uri().path_and_query() {
+            Some(target) => target.clone(),
+            None => PathAndQuery::from_static("/"),
+        };
+        let path = target.path();
+
+        if let Some((route, pattern, captures)) = self.lookup(method.as_str(), path) {
+            return self.serve(route, pattern, &target, captures, ctx).await;
+        }
+        if method == hyper::Method::HEAD
+            && let Some((route, pattern, captures)) = self.lookup("GET", path)
+        {
+            return strip_body(self.serve(route, pattern, &target, captures, ctx).await);
+        }
+        if let Some((route, pattern, captures)) = self.lookup(ANY_METHOD, path) {
+            let response = self.serve(route, pattern, &target, captures, ctx).await;
+            return match method {
+                hyper::Method::HEAD => strip_body(response),
+                _ => response,
+            };
+        }
+
+        let allowed = self.allowed_methods(path);
+        if allowed.is_empty() {
+            return match &self.not_found {
+                Some(handler) => handler.handle(ctx).await,
+                None => ResponseBuilder::not_found(),
+            };
+        }
+        let allow = allowed.join(", ");
+        if method == hyper::Method::OPTIONS {
+            return ResponseBuilder::new()
+                .status(hyper::StatusCode::NO_CONTENT)
+                .with_header("Allow", allow)
+                .empty_body();
+        }
+        self.reject_method(ctx, allowed, allow).await
+    }
 
-        if node.is_none() {
-            return ResponseBuilder::not_found();
+    async fn reject_method(&self, mut ctx: RequestCtx, allowed: Vec<String>, allow: String) -> Response {
+        let Some(handler) = &self.method_not_allowed else {
+            return ResponseBuilder::new()
+                .status(hyper::StatusCode::METHOD_NOT_ALLOWED)
+                .content_type("text/plain; charset=utf-8")
+                .with_header("Allow", allow)
+                .build("405 Method Not Allowed");
+        };
+        ctx.request.extensions_mut().insert(AllowedMethods(allowed));
+        let mut response = handler.handle(ctx).await;
+        // RFC 9110 requires Allow on every 405; keep the handler's own if it set one
+        if !response.headers().contains_key(hyper::header::ALLOW)
+            && let Ok(value) = hyper::header::HeaderValue::from_str(&allow)
+        {
+            response.headers_mut().insert(hyper::header::ALLOW, value);
         }
+        response
+    }
+
+    /// Find the route serving `method` and `path`
+    fn lookup(&self, method: &str, path: &str) -> Option<(&Route, &Arc<str>, Captures)> {
+        let (node, captures) = self.get_route(method, path)?;
+        Some((node.value()?, node.shared_pattern(), captures))
+    }
 
+    async fn serve(
+        &self,
+        route: &Route,
+        pattern: &Arc<str>,
+        target: &PathAndQuery,
+        captures: Captures,
+        mut ctx: RequestCtx,
+    ) -> Response {
         // Merge routing parameters and middleware parameters instead of overwriting
-        ctx.params.extend(params);
-        let node = node.unwrap();
-        
-        if let Some(handler) = node.value() {
-            handler.handle(ctx).await
-        } else {
-            ResponseBuilder::not_found()
+        ctx.params.capture(target, captures);
+        if let Some(limit) = route.wildcard_limit.or(self.wildcard_limit)
+            && !wildcard_within(pattern, &ctx.params, limit)
+        {
+            return ResponseBuilder::new()
+                .status(hyper::StatusCode::URI_TOO_LONG)
+                .content_type("text/plain; charset=utf-8")
+                .build("414 URI Too Long");
+        }
+        if let Some(limiter) = route.rate_limit.as_ref().or(self.rate_limit.as_ref())
+            && let Some(response) = limiter.check(&ctx)
+        {
+            return response;
+        }
+
+        let matched = MatchedRoute(pattern.clone());
+        // Visible to route-level middleware as well as to post-response middleware
+        ctx.request.extensions_mut().insert(matched.clone());
+        let range = crate::range::RangeRequest::from_request(&ctx.request);
+        #[cfg(feature = "otel")]
+        let mut response = {
+            let span = crate::otel::handler_span(&ctx, pattern);
+            tracing::Instrument::instrument(route.call(ctx), span).await
+        };
+        #[cfg(not(feature = "otel"))]
+        let mut response = route.call(ctx).await;
+        if let Some(range) = range {
+            response = range.apply(response);
+        }
+        response.extensions_mut().insert(matched);
+        response
+    }
+}
+
+/// Shared handle on the engine's main router, for adding and removing routes
+/// while the server runs (plugins, admin toggles). Made by
+/// [`Engine::routes_handle`](crate::Engine::routes_handle); cheap to clone.
+///
+/// ```rust,ignore
+/// let routes = app.routes_handle();
+/// app.post("/admin/maintenance/:on", move |ctx: RequestCtx| {
+///     let routes = routes.clone();
+///     async move {
+///         if ctx.get_param("on") == Some("on") {
+///             routes.add("GET", "/maintenance", |_| async { "back soon" });
+///         } else {
+///             routes.remove("GET", "/maintenance");
+///         }
+///         "ok"
+///     }
+/// });
+/// ```
+///
+/// Changes apply to requests arriving afterwards; requests in flight finish
+/// on the routes they started with. Routes added here run behind global
+/// middleware like any other main-router route, but are not part of groups,
+/// the Swagger document or `url_for`.
+#[derive(Clone, Default)]
+pub struct RoutesHandle {
+    router: Arc<RwLock<Arc<Router>>>,
+}
+
+impl RoutesHandle {
+    /// Register `handler` for `method` (`"*"` for any method) and `pattern`,
+    /// replacing a route registered there.
+    pub fn add(&self, method: &str, pattern: &str, handler: impl Handler) -> &Self {
+        self.update(|router| router.add_handler(method, pattern, handler));
+        self
+    }
+
+    /// Unregister the route for exactly this method and pattern, including
+    /// one registered on the engine. Returns whether there was one.
+    pub fn remove(&self, method: &str, pattern: &str) -> bool {
+        let mut removed = false;
+        self.update(|router| removed = router.remove_route(method, pattern));
+        removed
+    }
+
+    /// Describe the routes currently registered on the main router.
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        self.current().routes()
+    }
+
+    /// Router serving requests right now
+    pub(crate) fn current(&self) -> Arc<Router> {
+        self.router.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Change the router; requests holding the previous one keep their copy
+    fn update(&self, change: impl FnOnce(&mut Router)) {
+        let mut router = self.router.write().unwrap_or_else(|e| e.into_inner());
+        change(Arc::make_mut(&mut router));
+    }
+
+    /// Start serving the engine's router, keeping routes added to the handle
+    /// before the server started
+    pub(crate) fn install(&self, mut router: Router) {
+        let mut slot = self.router.write().unwrap_or_else(|e| e.into_inner());
+        let early = Arc::unwrap_or_clone(std::mem::take(&mut *slot));
+        for (method, pattern, route) in early.into_routes() {
+            router.insert_route(&method, &pattern, route);
         }
+        *slot = Arc::new(router);
     }
 }
 
+/// Whether the wildcard capture of `pattern`, if it has one, fits `limit`
+fn wildcard_within(pattern: &str, params: &Params, limit: WildcardLimit) -> bool {
+    pattern
+        .split('/')
+        .find_map(|part| match parse_segment(part) {
+            Segment::Wildcard(name) => params.get(name),
+            _ => None,
+        })
+        .is_none_or(|value| limit.allows(value))
+}
+
+/// Drop the body of a response produced for `HEAD`, keeping its length
+fn strip_body(response: Response) -> Response {
+    let (mut parts, body) = response.into_parts();
+    if let Some(len) = hyper::body::Body::size_hint(&body).exact() {
+        parts
+            .headers
+            .entry(hyper::header::CONTENT_LENGTH)
+            .or_insert_with(|| len.into());
+    }
+    Response::from_parts(parts, crate::response::empty())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    /// Route node matching `GET path`, with the parameters it captured
+    fn get_route<'a>(router: &'a Router, path: &'static str) -> (Option<&'a Node<Route>>, Params) {
+        let mut params = Params::default();
+        let Some((node, captures)) = router.get_route("GET", path) else {
+            return (None, params);
+        };
+        params.capture(&PathAndQuery::from_static(path), captures);
+        (Some(node), params)
+    }
+
     #[test]
     fn test_new_router() {
         let mut router = Router::new();
@@ -136,11 +724,155 @@ mod tests {
             "/p/:lang/doc",
             Box::new(|_ctx| async { "Hello, World!" }),
         );
-        let (node, params) = router.get_route("GET", "/p/rust/doc");
+        let (node, params) = get_route(&router, "/p/rust/doc");
         assert!(node.is_some());
         assert_eq!(params.get("lang").unwrap(), "rust");
     }
 
+    #[test]
+    fn test_wildcard_limit() {
+        let limit = WildcardLimit { max_segments: 3, max_bytes: 12 };
+        let params = |value: &str| {
+            let mut params = Params::default();
+            params.insert("path", value);
+            params
+        };
+        assert!(wildcard_within("/files/*path", &params("a/b/c"), limit));
+        assert!(!wildcard_within("/files/*path", &params("a/b/c/d"), limit));
+        assert!(!wildcard_within("/files/*path", &params("0123456789abc"), limit));
+        assert!(wildcard_within("/files/:path", &params("a/b/c/d/e/f/g"), limit));
+    }
+
+    #[test]
+    fn test_build_url() {
+        assert_eq!(build_url("/users/:id", &[("id", "42")]).as_deref(), Some("/users/42"));
+        assert_eq!(
+            build_url("/users/{id:u32}/files/*path", &[("id", "7"), ("path", "a b/c.txt")]).as_deref(),
+            Some("/users/7/files/a%20b/c.txt")
+        );
+        assert_eq!(build_url(r"/tags/:tag<\w+>", &[("tag", "a/b")]).as_deref(), Some("/tags/a%2Fb"));
+        assert_eq!(build_url("/", &[]).as_deref(), Some("/"));
+        assert_eq!(build_url("/admin/", &[]).as_deref(), Some("/admin/"));
+        assert_eq!(build_url("/users/:id", &[]), None);
+    }
+
+    #[test]
+    fn test_wildcard_with_suffix_params() {
+        let mut router = Router::new();
+        router.add_route("GET", "/repos/:owner/*path/v/:version", Box::new(|_ctx| async { "ok" }));
+        router.add_route("GET", "/a/*x/*y", Box::new(|_ctx| async { "ambiguous" }));
+        let (node, params) = get_route(&router, "/repos/me/src/lib.rs/v/3");
+        assert!(node.is_some());
+        assert_eq!(params.get("owner"), Some("me"));
+        assert_eq!(params.get("path"), Some("src/lib.rs"));
+        assert_eq!(params.get("version"), Some("3"));
+        assert!(router.get_route("GET", "/a/1/2").is_none());
+    }
+
+    #[test]
+    fn test_lookup_does_not_allocate() {
+        let before = crate::test_alloc::allocations();
+        drop(std::hint::black_box(Box::new(0u64)));
+        assert!(crate::test_alloc::allocations() > before, "counting allocator not installed");
+        let mut router = Router::new();
+        router.add_route("GET", "/users/:id/posts/{post:u32}", Box::new(|_ctx| async { "post" }));
+        router.add_route("GET", "/files/*path/meta", Box::new(|_ctx| async { "meta" }));
+        router.add_route("GET", "/health", Box::new(|_ctx| async { "ok" }));
+        let allocations = |path: &str| {
+            let before = crate::test_alloc::allocations();
+            let captures = std::hint::black_box(router.lookup("GET", path)).map(|(_, _, captures)| captures.len());
+            (captures, crate::test_alloc::allocations() - before)
+        };
+        assert_eq!(allocations("/users/42/posts/7"), (Some(2), 0));
+        assert_eq!(allocations("/files/a/b/c/meta"), (Some(1), 0));
+        assert_eq!(allocations("/health"), (Some(0), 0));
+        assert_eq!(allocations("/missing"), (None, 0));
+        assert!(router.matches_path("/users/1/posts/2"));
+    }
+
+    #[test]
+    fn test_route_chains_shared_across_table_versions() {
+        let layer: Middleware = Arc::new(|ctx, next: Next| next(ctx));
+        let mut router = Router::new();
+        router.add_route("GET", "/a", Box::new(|_ctx| async { "a" }));
+        router.route_mut("GET", "/a").unwrap().push_middleware(layer);
+        let routes = RoutesHandle::default();
+        routes.install(router);
+        let chain = |router: &Router| router.lookup("GET", "/a").and_then(|(route, _, _)| route.chain.clone()).unwrap();
+
+        let first = routes.current();
+        routes.add("GET", "/b", |_ctx: RequestCtx| async { "b" });
+        let second = routes.current();
+        assert!(!Arc::ptr_eq(&first, &second), "a change publishes a new table");
+        assert!(Arc::ptr_eq(&chain(&first), &chain(&second)), "existing chains are not recomposed");
+    }
+
+    #[test]
+    fn test_any_route_is_a_fallback() {
+        let mut router = Router::new();
+        router.add_route("GET", "/items", Box::new(|_ctx| async { "get" }));
+        router.add_route(ANY_METHOD, "/items", Box::new(|_ctx| async { "any" }));
+
+        assert!(router.lookup("GET", "/items").is_some());
+        assert!(router.lookup("PROPFIND", "/items").is_none());
+        assert!(router.lookup(ANY_METHOD, "/items").is_some());
+        assert!(router.lookup(ANY_METHOD, "/other").is_none());
+    }
+
+    #[test]
+    fn test_route_mut_exact_pattern() {
+        let mut router = Router::new();
+        router.add_route("GET", "/users/:id", Box::new(|_ctx| async { "user" }));
+
+        assert!(router.route_mut("GET", "/users/:id").is_some());
+        assert!(router.route_mut("GET", "/users/42").is_none());
+        assert!(router.route_mut("POST", "/users/:id").is_none());
+    }
+
+    #[test]
+    fn test_routes_report_security() {
+        let mut router = Router::new();
+        router.add_route("GET", "/me", Box::new(|_ctx| async { "me" }));
+        router
+            .route_mut("GET", "/me")
+            .unwrap()
+            .add_security(SecurityScheme::Bearer);
+
+        let routes = router.routes();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].pattern, "/me");
+        assert_eq!(routes[0].security, vec![SecurityScheme::Bearer]);
+    }
+
+    #[test]
+    fn test_hidden_routes_left_out_of_docs() {
+        let mut router = Router::new();
+        router.add_route("GET", "/users", Box::new(|_ctx| async { "users" }));
+        router.add_route("GET", "/debug/vars", Box::new(|_ctx| async { "vars" }));
+        router.route_mut("GET", "/debug/vars").unwrap().hide_from_docs();
+
+        let routes = router.routes();
+        assert!(routes.iter().any(|r| r.pattern == "/debug/vars" && r.hidden));
+        let doc = crate::swagger::generate_enhanced_swagger_json(&routes, &HashMap::new(), &HashMap::new());
+        assert!(doc.contains("\"/users\""));
+        assert!(!doc.contains("/debug/vars"));
+    }
+
+    #[test]
+    fn test_allowed_methods() {
+        let mut router = Router::new();
+        router.add_route("GET", "/users/:id", Box::new(|_ctx| async { "user" }));
+        router.add_route("DELETE", "/users/:id", Box::new(|_ctx| async { "deleted" }));
+        router.add_route("POST", "/users", Box::new(|_ctx| async { "created" }));
+
+        assert_eq!(
+            router.allowed_methods("/users/7"),
+            vec!["DELETE", "GET", "HEAD", "OPTIONS"]
+        );
+        assert_eq!(router.allowed_methods("/users"), vec!["OPTIONS", "POST"]);
+        assert!(router.allowed_methods("/missing").is_empty());
+    }
+
     #[test]
     fn test_static_file_route() {
         let mut router = Router::new();
@@ -153,7 +885,7 @@ mod tests {
         );
 
         // Test matching static file path
-        let (node, params) = router.get_route("GET", "/static/js/app.js");
+        let (node, params) = get_route(&router, "/static/js/app.js");
 
         // Verify route node matched
         assert!(node.is_some());
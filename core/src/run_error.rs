@@ -0,0 +1,154 @@
+//! Why `Engine::run` stopped, and the exit code a supervisor should see.
+
+use std::{io, net::SocketAddr};
+
+use crate::{BoxError, PreflightError, ShutdownError};
+
+/// Why [`Engine::run`](crate::Engine::run) returned an error.
+///
+/// Variants tell configuration problems, which a restart won't fix, apart
+/// from runtime failures; [`exit_code`](Self::exit_code) maps them to
+/// `sysexits.h` codes for process supervisors:
+///
+/// ```rust,ignore
+/// if let Err(e) = app.run("0.0.0.0:8080").await {
+///     eprintln!("{e}");
+///     std::process::exit(e.exit_code().into());
+/// }
+/// ```
+#[derive(Debug)]
+pub enum RunError {
+    /// The listen address could not be parsed
+    InvalidAddr {
+        addr: String,
+        source: std::net::AddrParseError,
+    },
+    /// Invalid engine setup, e.g. a subsystem dependency cycle
+    Config(String),
+    /// Certificates or keys could not be loaded
+    Tls(BoxError),
+    /// A preflight check failed
+    Preflight(PreflightError),
+    /// The listener could not be bound (port in use, permission denied)
+    Bind { addr: SocketAddr, source: io::Error },
+    /// A startup or shutdown hook failed or panicked while
+    /// [`HookFailure::Fatal`] is in effect
+    Hook { phase: &'static str, source: BoxError },
+    /// The listening socket broke and can no longer accept connections
+    Accept(io::Error),
+    /// Subsystems failed to stop
+    Shutdown(ShutdownError),
+}
+
+impl RunError {
+    /// `sysexits.h` code for this error: `EX_CONFIG` (78) for configuration
+    /// errors, `EX_UNAVAILABLE` (69) for bind failures, `EX_IOERR` (74) for a
+    /// broken listener and `EX_SOFTWARE` (70) for hook and subsystem failures.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            RunError::InvalidAddr { .. }
+            | RunError::Config(_)
+            | RunError::Tls(_)
+            | RunError::Preflight(_) => 78,
+            RunError::Bind { .. } => 69,
+            RunError::Accept(_) => 74,
+            RunError::Hook { .. } | RunError::Shutdown(_) => 70,
+        }
+    }
+}
+
+impl std::fmt::Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::InvalidAddr { addr, source } => write!(f, "invalid listen address `{addr}`: {source}"),
+            RunError::Config(msg) => write!(f, "invalid configuration: {msg}"),
+            RunError::Tls(e) => write!(f, "TLS configuration error: {e}"),
+            RunError::Preflight(e) => write!(f, "{e}"),
+            RunError::Bind { addr, source } => write!(f, "failed to bind {addr}: {source}"),
+            RunError::Hook { phase, source } => write!(f, "{phase} hook failed: {source}"),
+            RunError::Accept(e) => write!(f, "listener failed: {e}"),
+            RunError::Shutdown(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for RunError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RunError::InvalidAddr { source, .. } => Some(source),
+            RunError::Config(_) => None,
+            RunError::Tls(e) | RunError::Hook { source: e, .. } => Some(e.as_ref()),
+            RunError::Preflight(e) => Some(e),
+            RunError::Bind { source, .. } | RunError::Accept(source) => Some(source),
+            RunError::Shutdown(e) => Some(e),
+        }
+    }
+}
+
+impl From<PreflightError> for RunError {
+    fn from(e: PreflightError) -> Self {
+        RunError::Preflight(e)
+    }
+}
+
+impl From<ShutdownError> for RunError {
+    fn from(e: ShutdownError) -> Self {
+        RunError::Shutdown(e)
+    }
+}
+
+/// What a failing (or panicking) startup or shutdown hook does to the run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HookFailure {
+    /// A failed startup hook aborts startup; a failed shutdown hook lets the
+    /// remaining shutdown finish, then `run` returns [`RunError::Hook`].
+    #[default]
+    Fatal,
+    /// Log the failure and carry on; `run` is not affected.
+    Log,
+}
+
+/// Accept errors that mean the listening socket itself is unusable. Anything
+/// else is about a single connection or a temporary shortage (e.g. out of
+/// file descriptors) and is retried.
+pub(crate) fn is_fatal_accept_error(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported)
+}
+
+/// Accept errors caused by one client giving up; they are skipped silently
+pub(crate) fn is_connection_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_and_accept_classification() {
+        let addr = "nope".parse::<SocketAddr>().unwrap_err();
+        let invalid = RunError::InvalidAddr { addr: "nope".into(), source: addr };
+        assert_eq!(invalid.exit_code(), 78);
+        let bind = RunError::Bind {
+            addr: ([127, 0, 0, 1], 80).into(),
+            source: io::ErrorKind::AddrInUse.into(),
+        };
+        assert_eq!(bind.exit_code(), 69);
+        assert_eq!(bind.to_string(), "failed to bind 127.0.0.1:80: address in use");
+        assert_eq!(RunError::Hook { phase: "startup", source: "boom".into() }.exit_code(), 70);
+
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert!(is_connection_error(&reset) && !is_fatal_accept_error(&reset));
+        let emfile = io::Error::other("too many open files");
+        assert!(!is_connection_error(&emfile) && !is_fatal_accept_error(&emfile));
+        assert!(is_fatal_accept_error(&io::ErrorKind::InvalidInput.into()));
+    }
+}
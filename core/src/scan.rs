@@ -0,0 +1,179 @@
+//! Payload scanning before handlers run (virus scanners, banned-content checks).
+//!
+//! [`BodyScan`] buffers qualifying request bodies and hands them to a
+//! [`BodyScanner`]; a rejection becomes `422 Unprocessable Entity` and the
+//! handler never sees the payload. Uploads (any non-text content type) are
+//! always scanned, text bodies only from a size threshold on.
+//!
+//! ```rust,ignore
+//! use s_web::scan::{BodyScan, BodyScanner, Verdict};
+//!
+//! struct ClamAv { addr: String }
+//!
+//! impl BodyScanner for ClamAv {
+//!     fn scan<'a>(&'a self, content_type: Option<&'a str>, body: &'a Bytes)
+//!         -> Pin<Box<dyn Future<Output = Result<Verdict, BoxError>> + Send + 'a>>
+//!     {
+//!         Box::pin(async move {
+//!             let infected = clamd_instream(&self.addr, body).await?;
+//!             Ok(match infected {
+//!                 Some(name) => Verdict::Reject(format!("malware detected: {name}")),
+//!                 None => Verdict::Clean,
+//!             })
+//!         })
+//!     }
+//! }
+//!
+//! uploads.use_middleware(BodyScan::new(ClamAv { addr }).middleware());
+//! ```
+//!
+//! Scanned bodies stay buffered, so handlers behind the middleware read them
+//! from memory instead of streaming.
+
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use hyper::{StatusCode, body::Bytes};
+
+use crate::{BoxError, Next, RequestCtx, Response, ResponseBuilder, log};
+
+/// Outcome of a [`BodyScanner::scan`] call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    /// Reject the request; the reason is sent as the 422 body.
+    Reject(String),
+}
+
+/// Inspects a buffered request body.
+pub trait BodyScanner: Send + Sync + 'static {
+    fn scan<'a>(
+        &'a self,
+        content_type: Option<&'a str>,
+        body: &'a Bytes,
+    ) -> Pin<Box<dyn Future<Output = Result<Verdict, BoxError>> + Send + 'a>>;
+}
+
+/// Body scanning middleware configuration.
+#[derive(Clone)]
+pub struct BodyScan {
+    scanner: Arc<dyn BodyScanner>,
+    text_threshold: usize,
+    fail_open: bool,
+}
+
+impl BodyScan {
+    /// Scan uploads and text bodies of 64 KiB or more with `scanner`.
+    /// Requests are rejected with 503 if the scanner itself fails.
+    pub fn new(scanner: impl BodyScanner) -> Self {
+        Self {
+            scanner: Arc::new(scanner),
+            text_threshold: 64 * 1024,
+            fail_open: false,
+        }
+    }
+
+    /// Minimum size of text, JSON and form bodies to scan (`0` scans all of them).
+    pub fn text_threshold(mut self, bytes: usize) -> Self {
+        self.text_threshold = bytes;
+        self
+    }
+
+    /// Let requests through when the scanner errors instead of answering 503.
+    pub fn fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// Build the middleware function for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let scan = Arc::new(self);
+        move |ctx, next| {
+            let scan = scan.clone();
+            Box::pin(async move { scan.handle(ctx, next).await })
+        }
+    }
+
+    async fn handle(&self, mut ctx: RequestCtx, next: Next) -> Response {
+        let content_type = ctx.header("content-type").map(str::to_owned);
+        let text = content_type.as_deref().is_some_and(is_text);
+        let declared_len = ctx
+            .header("content-length")
+            .and_then(|v| v.parse::<usize>().ok());
+        // Skip small text bodies without buffering them when the length is known
+        if text && declared_len.is_some_and(|len| len < self.text_threshold) {
+            return next(ctx).await;
+        }
+
+        let body = match ctx.body_bytes().await {
+            Ok(Some(body)) => body.clone(),
+            Ok(None) => return next(ctx).await,
+            Err(e) => {
+                log::warn!("body scan: reading request body failed: {e}");
+                return reject(StatusCode::BAD_REQUEST, "400 Bad Request".to_string());
+            }
+        };
+        if text && body.len() < self.text_threshold {
+            return next(ctx).await;
+        }
+
+        match self.scanner.scan(content_type.as_deref(), &body).await {
+            Ok(Verdict::Clean) => next(ctx).await,
+            Ok(Verdict::Reject(reason)) => reject(StatusCode::UNPROCESSABLE_ENTITY, reason),
+            Err(e) if self.fail_open => {
+                log::warn!("body scanner failed, letting request through: {e}");
+                next(ctx).await
+            }
+            Err(e) => {
+                log::warn!("body scanner failed: {e}");
+                reject(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "503 Service Unavailable".to_string(),
+                )
+            }
+        }
+    }
+}
+
+/// Text-like payloads that are only scanned above the size threshold
+fn is_text(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime == "application/json"
+        || mime.ends_with("+json")
+        || mime == "application/x-www-form-urlencoded"
+        || mime == "application/xml"
+        || mime.ends_with("+xml")
+}
+
+fn reject(status: StatusCode, body: String) -> Response {
+    ResponseBuilder::new()
+        .status(status)
+        .content_type("text/plain; charset=utf-8")
+        .build(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_content_types() {
+        assert!(is_text("text/plain; charset=utf-8"));
+        assert!(is_text("Application/JSON"));
+        assert!(is_text("application/vnd.api+json"));
+        assert!(is_text("application/x-www-form-urlencoded"));
+        assert!(!is_text("multipart/form-data; boundary=x"));
+        assert!(!is_text("application/octet-stream"));
+        assert!(!is_text("image/png"));
+    }
+}
@@ -0,0 +1,362 @@
+//! Authentication schemes declared on routes.
+//!
+//! `.secured(scheme)` keeps docs and enforcement in one place: the scheme is
+//! listed in the OpenAPI operation, shown by `Engine::routes()`, and a guard
+//! rejects requests that don't carry the credential at all.
+//!
+//! ```rust,ignore
+//! app.get("/me", me).secured(SecurityScheme::Bearer);
+//! ```
+//!
+//! The guard only checks that credentials are present and well-formed;
+//! [`RequireAuth`] also verifies them with a validator of your own and hands
+//! the caller's identity to handlers:
+//!
+//! ```rust,ignore
+//! use s_web::RequireAuth;
+//!
+//! #[derive(Clone)]
+//! struct AuthUser { id: u64 }
+//!
+//! app.group("/api").use_middleware(
+//!     RequireAuth::bearer(move |token| {
+//!         let db = db.clone();
+//!         async move { db.user_for_token(&token).await.map(|id| AuthUser { id }) }
+//!     })
+//!     .middleware(),
+//! );
+//! // In a handler: ctx.get::<AuthUser>()
+//! ```
+//!
+//! Handlers that only need the raw credential can use `ctx.bearer_token()` or
+//! `ctx.basic_auth()`.
+
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+use serde_json::{Value, json};
+
+use crate::{Next, RequestCtx, Response, ResponseBuilder, StatusCode};
+
+/// How a route expects callers to authenticate.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SecurityScheme {
+    /// `Authorization: Bearer <token>`
+    Bearer,
+    /// `Authorization: Basic <credentials>`
+    Basic,
+    /// API key sent in the named request header.
+    ApiKey(String),
+}
+
+impl SecurityScheme {
+    /// Key under `components.securitySchemes` in the OpenAPI document.
+    pub fn swagger_name(&self) -> String {
+        match self {
+            SecurityScheme::Bearer => "bearerAuth".to_string(),
+            SecurityScheme::Basic => "basicAuth".to_string(),
+            SecurityScheme::ApiKey(header) => format!("apiKey_{header}"),
+        }
+    }
+
+    /// OpenAPI security scheme definition.
+    pub(crate) fn swagger_definition(&self) -> Value {
+        match self {
+            SecurityScheme::Bearer => json!({ "type": "http", "scheme": "bearer", "bearerFormat": "JWT" }),
+            SecurityScheme::Basic => json!({ "type": "http", "scheme": "basic" }),
+            SecurityScheme::ApiKey(header) => json!({ "type": "apiKey", "in": "header", "name": header }),
+        }
+    }
+
+    /// Whether the request carries a credential of this scheme.
+    fn is_present(&self, ctx: &RequestCtx) -> bool {
+        match self {
+            SecurityScheme::Bearer => has_auth_scheme(ctx, "Bearer"),
+            SecurityScheme::Basic => has_auth_scheme(ctx, "Basic"),
+            SecurityScheme::ApiKey(header) => ctx
+                .header(header)
+                .is_some_and(|v| !v.trim().is_empty()),
+        }
+    }
+
+    fn challenge(&self) -> Option<&'static str> {
+        match self {
+            SecurityScheme::Bearer => Some("Bearer"),
+            SecurityScheme::Basic => Some("Basic"),
+            SecurityScheme::ApiKey(_) => None,
+        }
+    }
+
+    /// Middleware answering 401 when the credential is missing.
+    pub fn guard(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>> + Send + Sync + 'static
+    {
+        move |ctx, next| {
+            let present = self.is_present(&ctx);
+            let challenge = self.challenge();
+            Box::pin(async move {
+                if present {
+                    return next(ctx).await;
+                }
+                let mut builder = ResponseBuilder::new()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .content_type("text/plain; charset=utf-8");
+                if let Some(challenge) = challenge {
+                    builder = builder.with_header("WWW-Authenticate", challenge);
+                }
+                builder.build("401 Unauthorized")
+            })
+        }
+    }
+}
+
+/// `Authorization: <scheme> <non-empty credential>`, scheme compared case-insensitively
+fn has_auth_scheme(ctx: &RequestCtx, scheme: &str) -> bool {
+    auth_credential(ctx, scheme).is_some()
+}
+
+/// The credential of an `Authorization: <scheme> <credential>` header
+fn auth_credential<'a>(ctx: &'a RequestCtx, scheme: &str) -> Option<&'a str> {
+    let (given, credential) = ctx.header("Authorization")?.split_once(' ')?;
+    let credential = credential.trim();
+    (given.eq_ignore_ascii_case(scheme) && !credential.is_empty()).then_some(credential)
+}
+
+/// Standard base64, padding optional
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let mut bits: u32 = 0;
+    let mut len = 0;
+    let mut bytes = Vec::with_capacity(input.len() / 4 * 3);
+    for c in input.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6) | u32::from(value);
+        len += 6;
+        if len >= 8 {
+            len -= 8;
+            bytes.push((bits >> len) as u8);
+        }
+    }
+    // A single leftover character can't encode a whole byte
+    (len < 6).then_some(bytes)
+}
+
+/// User name and password from `Authorization: Basic`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BasicCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for BasicCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BasicCredentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+impl RequestCtx {
+    /// Token of an `Authorization: Bearer <token>` header.
+    pub fn bearer_token(&self) -> Option<&str> {
+        auth_credential(self, "Bearer")
+    }
+
+    /// Credentials of an `Authorization: Basic` header; `None` if the header
+    /// is missing or not valid base64-encoded UTF-8 `user:password`.
+    pub fn basic_auth(&self) -> Option<BasicCredentials> {
+        let decoded = String::from_utf8(decode_base64(auth_credential(self, "Basic")?)?).ok()?;
+        let (username, password) = decoded.split_once(':')?;
+        Some(BasicCredentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+}
+
+/// What a successful validation leaves in the request
+type Grant = Box<dyn FnOnce(&mut RequestCtx) + Send>;
+
+type ValidateFn = Arc<
+    dyn Fn(&RequestCtx) -> Pin<Box<dyn Future<Output = Option<Grant>> + Send>> + Send + Sync,
+>;
+
+/// Middleware verifying credentials with a validator of your own.
+///
+/// The validator returns the caller's identity, which is stored for the rest
+/// of the chain (`ctx.get::<U>()`), or `None` to answer `401` with a
+/// `WWW-Authenticate` challenge. Return `Some(())` when there is nothing to
+/// store.
+#[derive(Clone)]
+pub struct RequireAuth {
+    scheme: SecurityScheme,
+    validate: ValidateFn,
+    realm: String,
+}
+
+impl RequireAuth {
+    /// Require `Authorization: Bearer <token>` and check the token.
+    pub fn bearer<F, Fut, U>(validate: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<U>> + Send + 'static,
+        U: Clone + Send + Sync + 'static,
+    {
+        Self::with(SecurityScheme::Bearer, move |ctx| ctx.bearer_token().map(|t| validate(t.to_string())))
+    }
+
+    /// Require `Authorization: Basic` and check the user name and password.
+    pub fn basic<F, Fut, U>(validate: F) -> Self
+    where
+        F: Fn(BasicCredentials) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<U>> + Send + 'static,
+        U: Clone + Send + Sync + 'static,
+    {
+        Self::with(SecurityScheme::Basic, move |ctx| ctx.basic_auth().map(&validate))
+    }
+
+    /// Require an API key in `header` and check it.
+    pub fn api_key<F, Fut, U>(header: &str, validate: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<U>> + Send + 'static,
+        U: Clone + Send + Sync + 'static,
+    {
+        let name = header.to_string();
+        Self::with(SecurityScheme::ApiKey(name.clone()), move |ctx| {
+            ctx.header(&name)
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(|key| validate(key.to_string()))
+        })
+    }
+
+    fn with<C, Fut, U>(scheme: SecurityScheme, check: C) -> Self
+    where
+        C: Fn(&RequestCtx) -> Option<Fut> + Send + Sync + 'static,
+        Fut: Future<Output = Option<U>> + Send + 'static,
+        U: Clone + Send + Sync + 'static,
+    {
+        Self {
+            scheme,
+            validate: Arc::new(move |ctx| {
+                let pending = check(ctx);
+                Box::pin(async move {
+                    let identity = pending?.await?;
+                    Some(Box::new(move |ctx: &mut RequestCtx| {
+                        ctx.insert(identity);
+                    }) as Grant)
+                })
+            }),
+            realm: "Restricted".to_string(),
+        }
+    }
+
+    /// Realm named in the `WWW-Authenticate` challenge (default `Restricted`).
+    pub fn realm(mut self, realm: &str) -> Self {
+        self.realm = realm.replace(['"', '\\'], "");
+        self
+    }
+
+    /// Build the middleware function for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let auth = Arc::new(self);
+        move |mut ctx, next| {
+            let auth = auth.clone();
+            Box::pin(async move {
+                match (auth.validate)(&ctx).await {
+                    Some(grant) => {
+                        grant(&mut ctx);
+                        next(ctx).await
+                    }
+                    None => auth.unauthorized(),
+                }
+            })
+        }
+    }
+
+    fn unauthorized(&self) -> Response {
+        let mut builder = ResponseBuilder::new()
+            .status(StatusCode::UNAUTHORIZED)
+            .content_type("text/plain; charset=utf-8");
+        if let Some(challenge) = self.scheme.challenge() {
+            let charset = if self.scheme == SecurityScheme::Basic { ", charset=\"UTF-8\"" } else { "" };
+            builder = builder.with_header("WWW-Authenticate", format!("{challenge} realm=\"{}\"{charset}", self.realm));
+        }
+        builder.build("401 Unauthorized")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    fn ctx(authorization: Option<&str>) -> RequestCtx {
+        let mut request = hyper::Request::get("/");
+        if let Some(value) = authorization {
+            request = request.header("authorization", value);
+        }
+        RequestCtx::from_parts(request.body(()).unwrap().into_parts().0)
+    }
+
+    #[test]
+    fn test_credential_helpers() {
+        assert_eq!(ctx(Some("bearer abc.def")).bearer_token(), Some("abc.def"));
+        assert_eq!(ctx(Some("Bearer ")).bearer_token(), None);
+        assert_eq!(ctx(Some("Basic abc")).bearer_token(), None);
+
+        // "Aladdin:open sesame" from RFC 7617, with and without padding
+        for header in ["Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==", "basic QWxhZGRpbjpvcGVuIHNlc2FtZQ"] {
+            let creds = ctx(Some(header)).basic_auth().unwrap();
+            assert_eq!((creds.username.as_str(), creds.password.as_str()), ("Aladdin", "open sesame"));
+        }
+        assert!(!format!("{:?}", ctx(Some("Basic YTpiOmM=")).basic_auth().unwrap()).contains("b:c"));
+        assert_eq!(ctx(Some("Basic YTpiOmM=")).basic_auth().unwrap().password, "b:c");
+        for bad in ["Basic bm9jb2xvbg==", "Basic !!!!", "Basic QUJDR", "Bearer QWxhZGRpbjpvcGVuIHNlc2FtZQ=="] {
+            assert_eq!(ctx(Some(bad)).basic_auth(), None, "{bad}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_require_auth_stores_identity() {
+        #[derive(Clone)]
+        struct AuthUser(&'static str);
+
+        let mw = RequireAuth::bearer(|token| async move { (token == "t0k3n").then_some(AuthUser("alice")) })
+            .middleware();
+        let next: Next = Arc::new(|ctx| {
+            let user = ctx.get::<AuthUser>().map_or("anonymous", |user| user.0);
+            Box::pin(async move { ResponseBuilder::new().build(user) })
+        });
+
+        let response = mw(ctx(Some("Bearer t0k3n")), next.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.into_body().collect().await.unwrap().to_bytes(), "alice");
+        for header in [None, Some("Bearer nope"), Some("Basic dTpw")] {
+            let response = mw(ctx(header), next.clone()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()["www-authenticate"], "Bearer realm=\"Restricted\"");
+        }
+
+        let basic = RequireAuth::basic(|c| async move { (c.password == "p").then_some(()) })
+            .realm("admin")
+            .middleware();
+        assert_eq!(basic(ctx(Some("Basic dTpw")), next.clone()).await.status(), StatusCode::OK);
+        let denied = basic(ctx(Some("Basic dTpx")), next).await;
+        assert_eq!(denied.headers()["www-authenticate"], "Basic realm=\"admin\", charset=\"UTF-8\"");
+    }
+}
@@ -0,0 +1,1184 @@
+//! Connection handling shared by the plain and TLS listeners.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use hyper::{body::Incoming, server::conn::http1};
+use hyper_util::rt::TokioIo;
+use tokio::sync::watch;
+
+use crate::{
+    IntoResponse, Next, RequestCtx, Response, ResponseBuilder, Router, RouterGroup, RoutesHandle, StatusCode,
+    error::{ErrorHandler, ErrorReport},
+    ipfilter::Cidr,
+    log,
+    run_error::{is_connection_error, is_fatal_accept_error},
+    state::StateMap,
+    watermark::Watermark,
+};
+
+/// Pause before accepting again after a resource error such as running out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// hyper's minimum for the HTTP/1 read buffer
+const MIN_BUF_SIZE: usize = 8192;
+
+/// Connection options for the HTTP server, set through
+/// [`Engine::server_config`](crate::Engine::server_config).
+///
+/// Against slow clients (slowloris) combine the header and body read
+/// timeouts with a per-IP connection cap. On shutdown, connections still
+/// sending headers are closed at once; slow bodies hold the drain for at
+/// most the body read timeout.
+///
+/// ```rust,ignore
+/// app.server_config()
+///     .header_read_timeout(Duration::from_secs(10))
+///     .body_read_timeout(Duration::from_secs(30))
+///     .max_connections_per_ip(64)
+///     .keep_alive_timeout(Duration::from_secs(75))
+///     .max_requests_per_connection(1000)
+///     .max_header_size(16 * 1024)
+///     .tcp_nodelay(true);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    keep_alive: bool,
+    keep_alive_timeout: Option<Duration>,
+    max_requests_per_connection: Option<usize>,
+    header_read_timeout: Option<Duration>,
+    body_read_timeout: Option<Duration>,
+    max_connections_per_ip: Option<usize>,
+    max_header_size: Option<usize>,
+    max_buf_size: Option<usize>,
+    half_close: bool,
+    tcp_nodelay: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            keep_alive: true,
+            keep_alive_timeout: None,
+            max_requests_per_connection: None,
+            header_read_timeout: None,
+            body_read_timeout: None,
+            max_connections_per_ip: None,
+            max_header_size: None,
+            max_buf_size: None,
+            half_close: false,
+            tcp_nodelay: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Keep HTTP/1 connections open between requests (default `true`).
+    /// Turn off to close every connection after one response.
+    pub fn keep_alive(&mut self, enabled: bool) -> &mut Self {
+        self.keep_alive = enabled;
+        self
+    }
+
+    /// Close keep-alive connections that stay idle for this long (default: never).
+    pub fn keep_alive_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// Close a connection after it has served this many requests (default: unlimited).
+    /// The last response carries `Connection: close`.
+    pub fn max_requests_per_connection(&mut self, max: usize) -> &mut Self {
+        self.max_requests_per_connection = Some(max.max(1));
+        self
+    }
+
+    /// Close HTTP/1 connections that take longer than this to send a
+    /// request's headers (default: no limit).
+    pub fn header_read_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.header_read_timeout = Some(timeout);
+        self
+    }
+
+    /// Fail body reads that buffer (`ctx.body_bytes()`, `json`, `form`, ...)
+    /// with [`BodyTimeout`](crate::BodyTimeout), which answers `408 Request
+    /// Timeout`, when the body has not fully arrived this long after the
+    /// request headers (default: no limit).
+    /// Streamed bodies (`take_body_stream`) are the handler's to time out.
+    pub fn body_read_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.body_read_timeout = Some(timeout);
+        self
+    }
+
+    /// Close new connections from an IP address that already has `max` open
+    /// (default: unlimited). Counts the socket's peer, so behind a load
+    /// balancer every client shares its address.
+    pub fn max_connections_per_ip(&mut self, max: usize) -> &mut Self {
+        self.max_connections_per_ip = Some(max.max(1));
+        self
+    }
+
+    /// Answer `431 Request Header Fields Too Large` to requests whose headers
+    /// add up to more than `bytes` (names, values and 32 bytes per field, as
+    /// HTTP/2 counts them; default: no limit beyond the read buffer).
+    pub fn max_header_size(&mut self, bytes: usize) -> &mut Self {
+        self.max_header_size = Some(bytes);
+        self
+    }
+
+    /// Largest HTTP/1 read buffer per connection, which also bounds the
+    /// request head (default ~400 KiB, at least 8 KiB).
+    pub fn max_buf_size(&mut self, bytes: usize) -> &mut Self {
+        self.max_buf_size = Some(bytes.max(MIN_BUF_SIZE));
+        self
+    }
+
+    /// Keep answering after the client shuts down its sending half of an
+    /// HTTP/1 connection (default `false`: treat it as a disconnect).
+    pub fn half_close(&mut self, enabled: bool) -> &mut Self {
+        self.half_close = enabled;
+        self
+    }
+
+    /// Set `TCP_NODELAY` on accepted sockets, sending small responses without
+    /// waiting to coalesce them (default `false`).
+    pub fn tcp_nodelay(&mut self, enabled: bool) -> &mut Self {
+        self.tcp_nodelay = enabled;
+        self
+    }
+
+    fn http1(&self) -> http1::Builder {
+        let mut builder = http1::Builder::new();
+        builder
+            .timer(hyper_util::rt::TokioTimer::new())
+            .keep_alive(self.keep_alive)
+            .half_close(self.half_close)
+            // With a timer, hyper would otherwise apply its own 30s default
+            .header_read_timeout(self.header_read_timeout);
+        if let Some(max) = self.max_buf_size {
+            builder.max_buf_size(max);
+        }
+        builder
+    }
+
+    /// Whether the request's headers exceed `max_header_size`
+    fn headers_too_large(&self, headers: &hyper::HeaderMap) -> bool {
+        self.max_header_size.is_some_and(|max| {
+            let size: usize = headers.iter().map(|(name, value)| name.as_str().len() + value.len() + 32).sum();
+            size > max
+        })
+    }
+}
+
+/// Pre-processed server data ready for the accept loop: a group with the
+/// global and group middleware composed around it (`None` without middleware)
+pub(crate) type PreprocessedGroup = (String, Arc<RouterGroup>, Option<Next>);
+
+pub(crate) struct ServerContext {
+    pub(crate) router: RoutesHandle,
+    pub(crate) groups: Arc<Vec<PreprocessedGroup>>,
+    /// Global middleware composed around the main router; `None` without middleware
+    pub(crate) pipeline: Option<Next>,
+    pub(crate) state: Arc<StateMap>,
+    /// Set once shutdown starts; new requests are turned away instead of racing the drain
+    pub(crate) draining: Arc<AtomicBool>,
+    /// Report requests whose futures block the executor for longer than this in one poll
+    pub(crate) blocking_threshold: Option<Duration>,
+    pub(crate) body_limit: usize,
+    pub(crate) request_timeout: Option<crate::timeout::Timeout>,
+    /// Handler for `CONNECT` requests, which carry no path to route on,
+    /// behind the global middleware
+    pub(crate) connect: Option<Next>,
+    pub(crate) watermark: Option<Watermark>,
+    pub(crate) error_handler: Option<ErrorHandler>,
+    /// Proxies whose forwarding headers `ctx.client_ip()` believes
+    pub(crate) trusted_proxies: Arc<Vec<Cidr>>,
+    pub(crate) config: ServerConfig,
+    #[cfg(feature = "alloc-tracking")]
+    pub(crate) alloc_tracker: Option<Arc<crate::alloc_tracking::AllocTracker>>,
+    /// Placeholder serving 503s while startup hooks run (`StartupMode::ServeUnavailable`)
+    pub(crate) starting: bool,
+}
+
+impl ServerContext {
+    /// Context used before startup completes; every request gets a 503
+    pub(crate) fn starting(watermark: Option<Watermark>, config: ServerConfig) -> Self {
+        Self {
+            router: RoutesHandle::default(),
+            groups: Arc::new(Vec::new()),
+            pipeline: None,
+            state: Arc::new(StateMap::new()),
+            draining: Arc::new(AtomicBool::new(false)),
+            blocking_threshold: None,
+            body_limit: crate::DEFAULT_BODY_LIMIT,
+            request_timeout: None,
+            connect: None,
+            watermark,
+            error_handler: None,
+            trusted_proxies: Arc::new(Vec::new()),
+            config,
+            #[cfg(feature = "alloc-tracking")]
+            alloc_tracker: None,
+            starting: true,
+        }
+    }
+
+    /// Serve one request: header limits, tracing, the request timeout and
+    /// watchdogs around `dispatch`, then the watermark
+    pub(crate) async fn respond(&self, ctx: RequestCtx) -> Response {
+        if self.config.headers_too_large(ctx.request.headers()) {
+            let mut response =
+                crate::Error::from_status(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE).into_response();
+            response.headers_mut().insert(
+                hyper::header::CONNECTION,
+                hyper::header::HeaderValue::from_static("close"),
+            );
+            return response;
+        }
+        let head = ctx.request.method() == hyper::Method::HEAD;
+        // Only the watchdog and allocation tracking name the request, in their logs
+        let watched = self.blocking_threshold.is_some();
+        #[cfg(feature = "alloc-tracking")]
+        let watched = watched || self.alloc_tracker.is_some();
+        let label = watched.then(|| (ctx.request.method().clone(), ctx.request.uri().path().to_owned()));
+        #[cfg(feature = "tracing")]
+        let (trace, ctx) = {
+            let mut ctx = ctx;
+            (crate::trace::RequestTrace::start(&mut ctx.request), ctx)
+        };
+        let dispatch: Pin<Box<dyn Future<Output = Response> + Send + '_>> = match &self.request_timeout {
+            Some(timeout) => Box::pin(timeout.run(self.dispatch(ctx))),
+            None => Box::pin(self.dispatch(ctx)),
+        };
+        #[cfg(feature = "tracing")]
+        let dispatch: Pin<Box<dyn Future<Output = Response> + Send + '_>> =
+            Box::pin(tracing::Instrument::instrument(dispatch, trace.span.clone()));
+        #[cfg(feature = "alloc-tracking")]
+        let dispatch: Pin<Box<dyn Future<Output = Response> + Send + '_>> = match (&self.alloc_tracker, &label) {
+            (Some(tracker), Some((method, path))) => Box::pin(crate::alloc_tracking::AllocMeter {
+                inner: dispatch,
+                tracker: tracker.clone(),
+                method: method.clone(),
+                path: path.clone(),
+                allocations: 0,
+                bytes: 0,
+            }),
+            _ => dispatch,
+        };
+        let mut response = match (self.blocking_threshold, label) {
+            (Some(threshold), Some((method, path))) => {
+                Watchdog {
+                    inner: dispatch,
+                    threshold,
+                    method,
+                    path,
+                    slow_polls: 0,
+                    worst: Duration::ZERO,
+                }
+                .await
+            }
+            _ => dispatch.await,
+        };
+        #[cfg(feature = "tracing")]
+        trace.finish(&mut response);
+        if let Some(watermark) = &self.watermark {
+            response = watermark.apply(response, head);
+        }
+        response
+    }
+
+    /// Route a request through the matching group (or the main router) and its middleware
+    async fn dispatch(&self, mut ctx: RequestCtx) -> Response {
+        let router = self.router.current();
+        let matched_group = select_group(&self.groups, &router, ctx.request.uri().path());
+
+        let connect = match ctx.request.method() {
+            &hyper::Method::CONNECT => self.connect.as_ref(),
+            _ => None,
+        };
+
+        if !self.trusted_proxies.is_empty() {
+            ctx.resolve_client_ip(&self.trusted_proxies);
+        }
+        ctx.set_state(self.state.clone());
+        ctx.set_body_limit(self.body_limit);
+        if let Some(timeout) = self.config.body_read_timeout {
+            ctx.set_body_read_timeout(timeout);
+        }
+
+        let response = if let Some(connect) = connect {
+            connect(ctx).await
+        } else if let Some((_, group, pipeline)) = matched_group {
+            match pipeline {
+                Some(pipeline) => pipeline(ctx).await,
+                None => group.handle_request(ctx).await,
+            }
+        } else {
+            match &self.pipeline {
+                Some(pipeline) => pipeline(ctx).await,
+                None => router.handle_request(ctx).await,
+            }
+        };
+
+        if let Some(handler) = &self.error_handler
+            && let Some(ErrorReport(error)) = response.extensions().get::<ErrorReport>()
+        {
+            return handler(error);
+        }
+        response
+    }
+}
+
+/// Per-connection bookkeeping for keep-alive limits
+struct ConnActivity {
+    in_flight: AtomicUsize,
+    served: AtomicUsize,
+    last: Mutex<Instant>,
+    /// Signalled when a response asks for the connection to be dropped
+    abort: tokio::sync::Notify,
+    /// Signalled when the last in-flight request ends
+    finished: tokio::sync::Notify,
+}
+
+impl ConnActivity {
+    fn new() -> Self {
+        Self {
+            in_flight: AtomicUsize::new(0),
+            served: AtomicUsize::new(0),
+            last: Mutex::new(Instant::now()),
+            abort: tokio::sync::Notify::new(),
+            finished: tokio::sync::Notify::new(),
+        }
+    }
+
+    /// Mark a request as started and return how many this connection has served, including it
+    fn begin(&self) -> usize {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        self.served.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn end(&self) {
+        *self.last.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+        if self.in_flight.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.finished.notify_waiters();
+        }
+    }
+
+    /// Resolve once no request has been in flight for `timeout`
+    async fn idle_for(&self, timeout: Duration) {
+        loop {
+            // Registered before the check so an `end` in between is not missed
+            let finished = self.finished.notified();
+            if self.in_flight.load(Ordering::Relaxed) > 0 {
+                // A request outliving the timeout leaves the deadline in the past; wait it out
+                finished.await;
+                continue;
+            }
+            let last = *self.last.lock().unwrap_or_else(|e| e.into_inner());
+            tokio::time::sleep_until((last + timeout).into()).await;
+            let last = *self.last.lock().unwrap_or_else(|e| e.into_inner());
+            if self.in_flight.load(Ordering::Relaxed) == 0 && last.elapsed() >= timeout {
+                return;
+            }
+        }
+    }
+}
+
+/// Times every poll of a request future and reports polls that exceed the threshold,
+/// which almost always means blocking work (sync IO, `thread::sleep`, heavy CPU) in async code
+struct Watchdog<F: ?Sized> {
+    inner: Pin<Box<F>>,
+    threshold: Duration,
+    method: hyper::Method,
+    path: String,
+    slow_polls: u32,
+    worst: Duration,
+}
+
+impl<F: Future<Output = Response> + ?Sized> Future for Watchdog<F> {
+    type Output = Response;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Response> {
+        let started = Instant::now();
+        let result = self.inner.as_mut().poll(cx);
+        let elapsed = started.elapsed();
+        if elapsed >= self.threshold {
+            self.slow_polls += 1;
+            self.worst = self.worst.max(elapsed);
+        }
+        if let Poll::Ready(response) = &result
+            && self.slow_polls > 0
+        {
+            let route = response
+                .extensions()
+                .get::<crate::MatchedRoute>()
+                .map(|r| &*r.0)
+                .unwrap_or("<unmatched>");
+            log::warn!(
+                "blocking watchdog: {} {} (route {route}) blocked the executor for {:?} in a single poll ({} slow poll(s)); move blocking work to ctx.blocking()",
+                self.method, self.path, self.worst, self.slow_polls
+            );
+        }
+        result
+    }
+}
+
+/// Seconds clients are told to wait before retrying a request refused while starting or draining
+const RETRY_AFTER_SECS: u64 = 5;
+
+/// Fast 503 for requests arriving on kept-alive connections during shutdown
+fn draining_response() -> Response {
+    unavailable("503 Service Unavailable: server is shutting down")
+}
+
+/// 503 for requests arriving before startup hooks have finished
+fn starting_response() -> Response {
+    unavailable("503 Service Unavailable: server is starting")
+}
+
+/// Connection: close so the client's next attempt reaches a fresh connection
+fn unavailable(body: &'static str) -> Response {
+    ResponseBuilder::new()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .content_type("text/plain; charset=utf-8")
+        .with_header("Retry-After", RETRY_AFTER_SECS.to_string())
+        .with_header("Connection", "close")
+        .build(body)
+}
+
+/// hyper service for one connection
+#[derive(Clone)]
+struct ConnService {
+    app: Arc<ServerContext>,
+    remote_addr: SocketAddr,
+    activity: Arc<ConnActivity>,
+}
+
+impl hyper::service::Service<hyper::Request<Incoming>> for ConnService {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn call(&self, req: hyper::Request<Incoming>) -> Self::Future {
+        let this = self.clone();
+        Box::pin(async move {
+            if this.app.starting {
+                return Ok(starting_response());
+            }
+            if this.app.draining.load(Ordering::Relaxed) {
+                return Ok(draining_response());
+            }
+            let served = this.activity.begin();
+            let ctx = RequestCtx::new(req).with_remote_addr(this.remote_addr);
+            let mut response = this.app.respond(ctx).await;
+            this.activity.end();
+            if response.extensions().get::<crate::chaos::DropConnection>().is_some() {
+                // Never hand the response to hyper; `drive` closes the socket instead
+                this.activity.abort.notify_one();
+                std::future::pending::<()>().await;
+            }
+            if this
+                .app
+                .config
+                .max_requests_per_connection
+                .is_some_and(|max| served >= max)
+            {
+                response.headers_mut().insert(
+                    hyper::header::CONNECTION,
+                    hyper::header::HeaderValue::from_static("close"),
+                );
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// Resolve on Ctrl-C, SIGTERM (unix), or console close and system shutdown (Windows)
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = term.recv() => {}
+            },
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows::{ctrl_close, ctrl_shutdown};
+        match (ctrl_close(), ctrl_shutdown()) {
+            (Ok(mut close), Ok(mut shutdown)) => tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = close.recv() => {}
+                _ = shutdown.recv() => {}
+            },
+            _ => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Whether `path` lies under `prefix`, matching whole segments only
+/// (`/api` covers `/api` and `/api/users` but not `/apiv2`)
+fn prefix_matches(prefix: &str, path: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path.starts_with(prefix)
+        && (path.len() == prefix.len() || path.as_bytes().get(prefix.len()) == Some(&b'/'))
+}
+
+/// Group serving `path`: the longest matching prefix whose group has a route
+/// for it. Otherwise the main router takes the request if one of its routes
+/// matches, and failing that the longest matching group answers with its 404.
+fn select_group<'a>(
+    groups: &'a [PreprocessedGroup],
+    router: &Router,
+    path: &str,
+) -> Option<&'a PreprocessedGroup> {
+    // Groups are sorted longest prefix first
+    let mut candidates = groups.iter().filter(|(prefix, _, _)| prefix_matches(prefix, path));
+    let longest = candidates.next()?;
+    if longest.1.matches_path(path) {
+        return Some(longest);
+    }
+    if let Some(group) = candidates.find(|(_, group, _)| group.matches_path(path)) {
+        return Some(group);
+    }
+    if router.matches_path(path) {
+        return None;
+    }
+    Some(longest)
+}
+
+/// How accepted sockets are turned into HTTP connections
+#[derive(Clone)]
+pub(crate) enum Acceptor {
+    Plain,
+    #[cfg(feature = "tls")]
+    Tls(tokio_rustls::TlsAcceptor),
+}
+
+/// Accept and handle incoming connections until Ctrl-C or SIGTERM, or until a
+/// listener fails in a way retrying can't fix. Each entry of `workers` is the
+/// set of listeners one acceptor task polls.
+pub(crate) async fn accept_loop(
+    workers: Arc<[Vec<tokio::net::TcpListener>]>,
+    app: ServerContext,
+    acceptor: Acceptor,
+    drain: watch::Receiver<bool>,
+) -> std::io::Result<()> {
+    let app = Arc::new(app);
+    let per_ip = Arc::new(ConnCounts::default());
+    let mut tasks = tokio::task::JoinSet::new();
+    for worker in 0..workers.len() {
+        let accept = accept_worker(
+            workers.clone(),
+            worker,
+            app.clone(),
+            per_ip.clone(),
+            acceptor.clone(),
+            drain.clone(),
+        );
+        tasks.spawn(accept);
+    }
+    let stopped = tokio::select! {
+        Some(failed) = tasks.join_next() => {
+            let err = failed.unwrap_or_else(std::io::Error::other);
+            app.draining.store(true, Ordering::Relaxed);
+            log::error!("❌ Listener failed: {err}");
+            Err(err)
+        }
+        _ = shutdown_signal() => {
+            app.draining.store(true, Ordering::Relaxed);
+            log::info!(stderr; "\n🛑 Graceful shutdown signal received");
+            Ok(())
+        }
+    };
+    // Stop accepting on every worker before the caller starts draining
+    tasks.shutdown().await;
+    stopped
+}
+
+/// One acceptor task: accept on `workers[worker]` and spawn a task per
+/// connection, until a listener fails for good
+async fn accept_worker(
+    workers: Arc<[Vec<tokio::net::TcpListener>]>,
+    worker: usize,
+    app: Arc<ServerContext>,
+    per_ip: Arc<ConnCounts>,
+    acceptor: Acceptor,
+    drain: watch::Receiver<bool>,
+) -> std::io::Error {
+    let listeners = &workers[worker];
+    let mut next = 0;
+    loop {
+        let (stream, remote_addr) = match accept_any(listeners, &mut next).await {
+            Ok(accepted) => accepted,
+            Err(err) if is_connection_error(&err) => continue,
+            Err(err) if is_fatal_accept_error(&err) => return err,
+            Err(err) => {
+                // Usually out of file descriptors; retrying at once would spin
+                log::warn!("accept failed, retrying: {err}");
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let slot = match app.config.max_connections_per_ip {
+            Some(max) => match per_ip.acquire(remote_addr.ip(), max) {
+                Some(slot) => Some(slot),
+                // Over the cap: close at once, spending nothing on the client
+                None => continue,
+            },
+            None => None,
+        };
+        if app.config.tcp_nodelay {
+            let _ = stream.set_nodelay(true);
+        }
+        let service = ConnService {
+            app: app.clone(),
+            remote_addr,
+            activity: Arc::new(ConnActivity::new()),
+        };
+        let acceptor = acceptor.clone();
+        let drain = drain.clone();
+        tokio::spawn(async move {
+            let _slot = slot;
+            match acceptor {
+                Acceptor::Plain => serve_connection(TokioIo::new(stream), service, drain, false).await,
+                #[cfg(feature = "tls")]
+                Acceptor::Tls(tls) => {
+                    let stream = match crate::tls::handshake(&tls, stream).await {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            log::warn!("TLS handshake failed {remote_addr}: {err}");
+                            return;
+                        }
+                    };
+                    let h2 = stream.get_ref().1.alpn_protocol() == Some(b"h2");
+                    serve_connection(TokioIo::new(stream), service, drain, h2).await
+                }
+            }
+        });
+    }
+}
+
+/// Open connections per client IP, for `ServerConfig::max_connections_per_ip`
+#[derive(Default)]
+struct ConnCounts(Mutex<HashMap<IpAddr, usize>>);
+
+impl ConnCounts {
+    /// Count a connection from `ip`, or `None` when it already has `max` open
+    fn acquire(self: &Arc<Self>, ip: IpAddr, max: usize) -> Option<ConnSlot> {
+        let ip = ip.to_canonical();
+        let mut counts = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let count = counts.entry(ip).or_insert(0);
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(ConnSlot { counts: self.clone(), ip })
+    }
+}
+
+/// A counted connection; dropping it when the connection ends frees the slot
+struct ConnSlot {
+    counts: Arc<ConnCounts>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnSlot {
+    fn drop(&mut self) {
+        let mut counts = self.counts.0.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Accept from whichever listener has a connection waiting, scanning from the
+/// one after the last served so a busy listener can't starve the others
+fn accept_any<'a>(
+    listeners: &'a [tokio::net::TcpListener],
+    next: &'a mut usize,
+) -> impl Future<Output = std::io::Result<(tokio::net::TcpStream, SocketAddr)>> + 'a {
+    std::future::poll_fn(move |cx| {
+        for offset in 0..listeners.len() {
+            let index = (*next + offset) % listeners.len();
+            if let Poll::Ready(accepted) = listeners[index].poll_accept(cx) {
+                *next = index + 1;
+                return Poll::Ready(accepted);
+            }
+        }
+        Poll::Pending
+    })
+}
+
+/// A connection hyper can wind down after its in-flight requests
+trait GracefulConnection: Future<Output = Result<(), hyper::Error>> {
+    fn graceful_shutdown(self: Pin<&mut Self>);
+}
+
+impl<I, S> GracefulConnection for http1::UpgradeableConnection<I, S>
+where
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+    S: hyper::service::HttpService<Incoming, ResBody = crate::response::Body>,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    fn graceful_shutdown(self: Pin<&mut Self>) {
+        http1::UpgradeableConnection::graceful_shutdown(self)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl<I, S> GracefulConnection
+    for hyper::server::conn::http2::Connection<I, S, hyper_util::rt::TokioExecutor>
+where
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + 'static,
+    S: hyper::service::HttpService<Incoming, ResBody = crate::response::Body>,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    S::Future: Send + 'static,
+{
+    fn graceful_shutdown(self: Pin<&mut Self>) {
+        hyper::server::conn::http2::Connection::graceful_shutdown(self)
+    }
+}
+
+async fn serve_connection<I>(io: I, service: ConnService, drain: watch::Receiver<bool>, h2: bool)
+where
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let remote_addr = service.remote_addr;
+    let idle_timeout = service.app.config.keep_alive_timeout;
+    let activity = service.activity.clone();
+
+    #[cfg(feature = "tls")]
+    if h2 {
+        let mut builder = hyper::server::conn::http2::Builder::new(hyper_util::rt::TokioExecutor::new());
+        if let Some(max) = service.app.config.max_header_size {
+            builder.max_header_list_size(u32::try_from(max).unwrap_or(u32::MAX));
+        }
+        let conn = builder.serve_connection(io, service);
+        drive(conn, &activity, idle_timeout, drain, remote_addr).await;
+        return;
+    }
+    #[cfg(not(feature = "tls"))]
+    let _ = h2;
+
+    // Upgrades (`ctx.upgrade()`) hand the connection to the handler once the 101 is sent
+    let conn = service.app.config.http1()
+        .serve_connection(io, service)
+        .with_upgrades();
+    drive(conn, &activity, idle_timeout, drain, remote_addr).await;
+}
+
+/// Poll a connection to completion, closing it gracefully on shutdown or after idling
+async fn drive<C: GracefulConnection>(
+    conn: C,
+    activity: &ConnActivity,
+    idle_timeout: Option<Duration>,
+    mut drain: watch::Receiver<bool>,
+    remote_addr: SocketAddr,
+) {
+    let mut conn = std::pin::pin!(conn);
+    let mut closing = false;
+    loop {
+        let idle = async {
+            match idle_timeout {
+                Some(timeout) => activity.idle_for(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            result = conn.as_mut() => {
+                if let Err(err) = result {
+                    log::warn!("Connection error {remote_addr}: {err:?}");
+                }
+                break;
+            }
+            _ = activity.abort.notified() => break,
+            // Finish the in-flight request, then close
+            _ = drain.wait_for(|stop| *stop), if !closing => {
+                closing = true;
+                conn.as_mut().graceful_shutdown();
+            }
+            _ = idle, if !closing => {
+                closing = true;
+                conn.as_mut().graceful_shutdown();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::HealthRegistry;
+
+    fn group(prefix: &str, routes: &[&str]) -> PreprocessedGroup {
+        let mut group = RouterGroup::new(prefix.to_string(), HealthRegistry::new());
+        for route in routes {
+            group.get(route, |_| async { ResponseBuilder::new().build("") });
+        }
+        (prefix.to_string(), Arc::new(group), None)
+    }
+
+    #[test]
+    fn test_group_selection_respects_segments_and_falls_back() {
+        let groups = [group("/api/v1", &["/users"]), group("/api/", &["/status"]), group("/api", &["/users/:id"])];
+        let mut router = Router::new();
+        router.add_route("GET", "/apiv2/foo", Box::new(|_| async { ResponseBuilder::new().build("") }));
+        router.add_route("GET", "/api/v1/health", Box::new(|_| async { ResponseBuilder::new().build("") }));
+        let selected = |path| select_group(&groups, &router, path).map(|g| g.0.as_str());
+
+        assert_eq!(selected("/api/v1/users"), Some("/api/v1"));
+        assert_eq!(selected("/api/status"), Some("/api/"));
+        assert_eq!(selected("/api/users/7"), Some("/api"));
+        assert_eq!(selected("/apiv2/foo"), None);
+        assert_eq!(selected("/api/v1/health"), None, "main router route under a group prefix");
+        assert_eq!(selected("/api/v1/missing"), Some("/api/v1"), "404 from the closest group");
+        assert_eq!(selected("/other"), None);
+    }
+
+    #[tokio::test]
+    async fn test_handler_answers_its_own_param_errors() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let mut app = crate::Engine::new();
+        app.get("/own/:id", |ctx: RequestCtx| async move {
+            match ctx.param::<u64>("id") {
+                Ok(id) => ResponseBuilder::new().build(id.to_string()),
+                Err(_) => ResponseBuilder::new().status(StatusCode::BAD_REQUEST).build("id must be a number"),
+            }
+        });
+        app.get("/propagated/:id", |ctx: RequestCtx| async move {
+            let id: u64 = ctx.param("id")?;
+            Ok::<_, crate::Error>(id.to_string())
+        });
+        let server = tokio::spawn(app.run_with_listener(listener));
+
+        let client = crate::client::Client::new();
+        let response = client.get(&format!("{base}/own/abc")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.text().await.unwrap(), "id must be a number");
+        let response = client.get(&format!("{base}/propagated/abc")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(response.text().await.unwrap().contains("invalid path parameter `id`"));
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_server_config_limits_headers_and_keep_alive() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let mut app = crate::Engine::new();
+        app.get("/", |_| async { "ok" });
+        app.server_config()
+            .keep_alive(false)
+            .max_header_size(1024)
+            .header_read_timeout(Duration::from_secs(5))
+            .max_buf_size(1)
+            .tcp_nodelay(true);
+        let server = tokio::spawn(app.run_with_listener(listener));
+
+        let client = crate::client::Client::new();
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["connection"], "close");
+        let response = client.get(&url).header("x-padding", "a".repeat(2000)).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_idle_wait_sleeps_through_long_requests() {
+        let activity = ConnActivity::new();
+        activity.begin();
+        let mut polls = 0;
+        let mut idle = std::pin::pin!(activity.idle_for(Duration::from_millis(20)));
+        let counted = std::future::poll_fn(|cx| {
+            polls += 1;
+            idle.as_mut().poll(cx)
+        });
+        assert!(tokio::time::timeout(Duration::from_millis(300), counted).await.is_err());
+        assert!(polls < 5, "idle wait polled {polls} times during one request");
+
+        activity.end();
+        let started = Instant::now();
+        activity.idle_for(Duration::from_millis(20)).await;
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_timeout_spares_long_requests() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut app = crate::Engine::new();
+        app.get("/slow", |_| async {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            "done"
+        });
+        app.server_config().keep_alive_timeout(Duration::from_millis(100));
+        let server = tokio::spawn(app.run_with_listener(listener));
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /slow HTTP/1.1\r\nHost: x\r\n\r\n").await.unwrap();
+        // The connection stays open through the request and closes once it has idled
+        let mut raw = String::new();
+        let started = Instant::now();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut raw)).await.unwrap().unwrap();
+        assert!(raw.starts_with("HTTP/1.1 200"), "{raw}");
+        assert!(raw.ends_with("done"));
+        assert!(started.elapsed() >= Duration::from_millis(600));
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_server_config_caps_requests_per_connection() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut app = crate::Engine::new();
+        app.get("/", |_| async { "ok" });
+        app.server_config().keep_alive_timeout(Duration::from_secs(5)).max_requests_per_connection(2);
+        let server = tokio::spawn(app.run_with_listener(listener));
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = "GET / HTTP/1.1\r\nHost: x\r\n\r\n";
+        stream.write_all(request.repeat(3).as_bytes()).await.unwrap();
+        let mut raw = String::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut raw)).await.unwrap().unwrap();
+        assert_eq!(raw.matches("HTTP/1.1 200").count(), 2);
+        assert_eq!(raw.matches("connection: close").count(), 1);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_slow_clients_are_cut_off() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut app = crate::Engine::new();
+        app.post("/", |mut ctx: RequestCtx| async move {
+            ctx.body_bytes().await?;
+            Ok::<_, crate::Error>("ok")
+        });
+        app.server_config().body_read_timeout(Duration::from_millis(300)).max_connections_per_ip(1);
+        let server = tokio::spawn(app.run_with_listener(listener));
+
+        // Half the announced body, then nothing
+        let mut slow = tokio::net::TcpStream::connect(addr).await.unwrap();
+        slow.write_all(b"POST / HTTP/1.1\r\nhost: x\r\ncontent-length: 10\r\n\r\n01234").await.unwrap();
+        // A second connection from the same IP is closed without a response
+        let mut second = tokio::net::TcpStream::connect(addr).await.unwrap();
+        second.write_all(b"GET / HTTP/1.1\r\nhost: x\r\n\r\n").await.unwrap();
+        let mut buf = Vec::new();
+        second.read_to_end(&mut buf).await.unwrap();
+        assert!(buf.is_empty());
+
+        let mut head = [0; 12];
+        slow.read_exact(&mut head).await.unwrap();
+        assert_eq!(&head, b"HTTP/1.1 408");
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_handler_answers_its_own_body_timeout() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut app = crate::Engine::new();
+        app.post("/", |mut ctx: RequestCtx| async move {
+            match ctx.body_bytes().await {
+                Ok(_) => ResponseBuilder::new().build("ok"),
+                Err(e) if e.is::<crate::BodyTimeout>() => {
+                    ResponseBuilder::new().status(StatusCode::BAD_REQUEST).build("upload stalled")
+                }
+                Err(_) => ResponseBuilder::new().status(StatusCode::INTERNAL_SERVER_ERROR).build(""),
+            }
+        });
+        app.server_config().body_read_timeout(Duration::from_millis(200));
+        let server = tokio::spawn(app.run_with_listener(listener));
+
+        let mut slow = tokio::net::TcpStream::connect(addr).await.unwrap();
+        slow.write_all(b"POST / HTTP/1.1\r\nhost: x\r\nconnection: close\r\ncontent-length: 10\r\n\r\n01234").await.unwrap();
+        let mut response = Vec::new();
+        slow.read_to_end(&mut response).await.unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+        assert!(response.ends_with("upload stalled"), "{response}");
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_round_trip() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut app = crate::Engine::new();
+        app.get("/shout", |mut ctx: RequestCtx| async move {
+            if !ctx.is_upgrade_request() {
+                return ResponseBuilder::new().status(StatusCode::UPGRADE_REQUIRED).build("");
+            }
+            let on_upgrade = ctx.upgrade();
+            tokio::spawn(async move {
+                // Echo in upper case until the client hangs up
+                let mut io = TokioIo::new(on_upgrade.await.unwrap());
+                let mut buf = [0; 64];
+                while let Ok(n @ 1..) = io.read(&mut buf).await {
+                    io.write_all(&buf[..n].to_ascii_uppercase()).await.unwrap();
+                }
+            });
+            ResponseBuilder::switching_protocols("shout/1")
+        });
+        let server = tokio::spawn(app.run_with_listener(listener));
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /shout HTTP/1.1\r\nhost: x\r\nconnection: upgrade\r\nupgrade: shout/1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0; 1];
+            stream.read_exact(&mut byte).await.unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101"), "{head}");
+        assert!(head.contains("upgrade: shout/1"), "{head}");
+
+        // The connection now speaks the handler's protocol
+        for word in ["hello", "again"] {
+            stream.write_all(word.as_bytes()).await.unwrap();
+            let mut echoed = [0; 5];
+            tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut echoed)).await.unwrap().unwrap();
+            assert_eq!(&echoed, word.to_ascii_uppercase().as_bytes());
+        }
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_draining_turns_new_requests_away() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut app = crate::Engine::new();
+        app.get("/", |_| async { "ok" });
+        app.get("/slow", |_| async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            "done"
+        });
+        let app = Arc::new(app.build_server_context());
+        let (drain_tx, drain_rx) = watch::channel(false);
+        let workers: Arc<[Vec<tokio::net::TcpListener>]> = Arc::from(vec![vec![listener]]);
+        let accept = accept_worker(workers, 0, app.clone(), Arc::default(), Acceptor::Plain, drain_rx);
+        let accept = tokio::spawn(accept);
+
+        // A kept-alive connection that has already been served once
+        let mut kept = tokio::net::TcpStream::connect(addr).await.unwrap();
+        kept.write_all(b"GET / HTTP/1.1\r\nhost: x\r\n\r\n").await.unwrap();
+        let mut first = Vec::new();
+        while !first.ends_with(b"ok") {
+            let mut buf = [0; 512];
+            let n = kept.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed after the first response");
+            first.extend_from_slice(&buf[..n]);
+        }
+        let mut busy = tokio::net::TcpStream::connect(addr).await.unwrap();
+        busy.write_all(b"GET /slow HTTP/1.1\r\nhost: x\r\n\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Shutdown starts as in `accept_loop`: the flag first, the drain once the hooks have run
+        app.draining.store(true, Ordering::Relaxed);
+        kept.write_all(b"GET / HTTP/1.1\r\nhost: x\r\n\r\n").await.unwrap();
+        let mut refused = String::new();
+        tokio::time::timeout(Duration::from_secs(5), kept.read_to_string(&mut refused)).await.unwrap().unwrap();
+        assert!(refused.starts_with("HTTP/1.1 503"), "{refused}");
+        assert!(refused.contains("connection: close"), "{refused}");
+        assert!(refused.contains("retry-after: 5"), "{refused}");
+
+        // The request already in flight still completes
+        let _ = drain_tx.send(true);
+        let mut finished = String::new();
+        tokio::time::timeout(Duration::from_secs(5), busy.read_to_string(&mut finished)).await.unwrap().unwrap();
+        assert!(finished.starts_with("HTTP/1.1 200"), "{finished}");
+        assert!(finished.ends_with("done"), "{finished}");
+        accept.abort();
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_flags_blocking_polls() {
+        fn watch(
+            inner: Pin<Box<dyn Future<Output = Response> + Send>>,
+        ) -> Watchdog<dyn Future<Output = Response> + Send> {
+            Watchdog {
+                inner,
+                threshold: Duration::from_millis(20),
+                method: hyper::Method::GET,
+                path: "/".into(),
+                slow_polls: 0,
+                worst: Duration::ZERO,
+            }
+        }
+
+        // Blocks the executor in two separate polls
+        let mut stalled = watch(Box::pin(async {
+            std::thread::sleep(Duration::from_millis(30));
+            tokio::task::yield_now().await;
+            std::thread::sleep(Duration::from_millis(50));
+            ResponseBuilder::new().build("")
+        }));
+        (&mut stalled).await;
+        assert_eq!(stalled.slow_polls, 2);
+        assert!(stalled.worst >= Duration::from_millis(50));
+
+        // Waiting asynchronously for just as long is fine
+        let mut waiting = watch(Box::pin(async {
+            tokio::time::sleep(Duration::from_millis(80)).await;
+            ResponseBuilder::new().build("")
+        }));
+        (&mut waiting).await;
+        assert_eq!(waiting.slow_polls, 0);
+    }
+
+    #[test]
+    fn test_connection_slots_per_ip() {
+        let counts = Arc::new(ConnCounts::default());
+        let v4: IpAddr = [10, 0, 0, 1].into();
+        let first = counts.acquire(v4, 2).unwrap();
+        // The IPv4-mapped form is the same client
+        let _second = counts.acquire("::ffff:10.0.0.1".parse().unwrap(), 2).unwrap();
+        assert!(counts.acquire(v4, 2).is_none());
+        assert!(counts.acquire([10, 0, 0, 2].into(), 2).is_some());
+        drop(first);
+        assert!(counts.acquire(v4, 2).is_some());
+    }
+}
@@ -0,0 +1,115 @@
+//! The engine's request handling as a service, detached from the accept loop.
+
+use std::{convert::Infallible, fmt, future::Future, net::SocketAddr, pin::Pin, sync::Arc};
+
+use hyper::body::Incoming;
+
+use crate::{RequestCtx, Response, server::ServerContext};
+
+/// A built [`Engine`](crate::Engine): routes, groups and middleware ready to
+/// answer requests, without a listener. Made by
+/// [`Engine::build`](crate::Engine::build); cheap to clone.
+///
+/// It is a hyper `Service`, for custom accept loops (Unix sockets, inherited
+/// file descriptors, in-process tests) and serverless adapters:
+///
+/// ```rust,ignore
+/// let service = app.build();
+/// loop {
+///     let (stream, addr) = listener.accept().await?;
+///     let service = service.clone().with_remote_addr(addr);
+///     tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+/// }
+/// ```
+///
+/// With the `tower` feature it is also a tower `Service`, so tower middleware
+/// (retry, buffer, load shedding, ...) can wrap the whole application:
+///
+/// ```rust,ignore
+/// let app = tower::ServiceBuilder::new()
+///     .concurrency_limit(512)
+///     .service(engine.build());
+/// ```
+///
+/// Connection handling set on the engine (keep-alive and per-connection
+/// limits, `server_config()`, graceful shutdown) is up to whoever serves it.
+#[derive(Clone)]
+pub struct EngineService {
+    app: Arc<ServerContext>,
+    remote_addr: Option<SocketAddr>,
+}
+
+impl EngineService {
+    pub(crate) fn new(app: ServerContext) -> Self {
+        Self {
+            app: Arc::new(app),
+            remote_addr: None,
+        }
+    }
+
+    /// Peer address handlers see through `ctx.remote_addr()` and
+    /// `ctx.client_ip()`; set it per connection in a custom accept loop.
+    pub fn with_remote_addr(mut self, addr: SocketAddr) -> Self {
+        self.remote_addr = Some(addr);
+        self
+    }
+
+    /// Answer a request already turned into a context
+    pub(crate) async fn respond(&self, mut ctx: RequestCtx) -> Response {
+        if let Some(addr) = self.remote_addr {
+            ctx = ctx.with_remote_addr(addr);
+        }
+        self.app.respond(ctx).await
+    }
+
+    /// Largest body buffered when a request body has to be read up front
+    #[cfg(feature = "tower")]
+    pub(crate) fn body_limit(&self) -> usize {
+        self.app.body_limit
+    }
+}
+
+impl hyper::service::Service<hyper::Request<Incoming>> for EngineService {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn call(&self, request: hyper::Request<Incoming>) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move { Ok(service.respond(RequestCtx::new(request)).await) })
+    }
+}
+
+impl fmt::Debug for EngineService {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EngineService").field("remote_addr", &self.remote_addr).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper_util::rt::TokioIo;
+
+    use super::*;
+    use crate::Engine;
+
+    #[tokio::test]
+    async fn test_custom_accept_loop() {
+        let mut app = Engine::new();
+        app.get("/who", |ctx: RequestCtx| async move { ctx.remote_addr().map(|addr| addr.ip().to_string()) });
+        let service = app.build();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/who", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = listener.accept().await.unwrap();
+                let service = service.clone().with_remote_addr(addr);
+                tokio::spawn(hyper::server::conn::http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+
+        let response = crate::client::Client::new().get(&url).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "127.0.0.1");
+    }
+}
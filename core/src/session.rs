@@ -0,0 +1,461 @@
+//! Cookie-identified sessions with pluggable storage (`session` feature).
+//!
+//! ```rust,ignore
+//! use s_web::session::{MemoryStore, SessionMiddleware};
+//!
+//! app.use_middleware(SessionMiddleware::new(MemoryStore::new()).middleware());
+//!
+//! app.post("/login", |ctx: RequestCtx| async move {
+//!     let session = ctx.session().ok_or("sessions not enabled")?;
+//!     session.regenerate();
+//!     session.insert("user_id", 42)?;
+//!     Ok::<_, BoxError>("welcome")
+//! });
+//! ```
+//!
+//! [`MemoryStore`] keeps data in the process, [`CookieStore`] keeps it in the
+//! (encrypted) cookie itself, and anything else — Redis, a SQL table — only
+//! needs a [`SessionStore`] impl.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use hyper::header::{HeaderValue, SET_COOKIE};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{
+    BoxError, Next, RequestCtx, Response, ResponseBuilder,
+    cookie::{Cookie, Key, SameSite},
+    log,
+    store::KvStore,
+};
+
+/// Values stored in one session.
+pub type SessionData = HashMap<String, Value>;
+
+type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, BoxError>> + Send + 'a>>;
+
+/// Backend persisting session data, addressed by the session cookie's value.
+pub trait SessionStore: Send + Sync + 'static {
+    /// Data for `cookie`, or `None` if it is unknown, expired or invalid.
+    fn load<'a>(&'a self, cookie: &'a str) -> StoreFuture<'a, Option<SessionData>>;
+
+    /// Persist `data` and return the cookie value to send. `cookie` is the
+    /// current value, or `None` for a new (or regenerated) session.
+    fn save<'a>(
+        &'a self,
+        cookie: Option<&'a str>,
+        data: &'a SessionData,
+        ttl: Duration,
+    ) -> StoreFuture<'a, String>;
+
+    /// Drop the session behind `cookie`.
+    fn destroy<'a>(&'a self, cookie: &'a str) -> StoreFuture<'a, ()>;
+}
+
+/// In-process store keyed by random 128-bit ids. Data is lost on restart and
+/// not shared between instances.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    sessions: Arc<Mutex<HashMap<String, (SessionData, Instant)>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, HashMap<String, (SessionData, Instant)>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load<'a>(&'a self, cookie: &'a str) -> StoreFuture<'a, Option<SessionData>> {
+        let data = self
+            .sessions()
+            .get(cookie)
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(data, _)| data.clone());
+        Box::pin(async move { Ok(data) })
+    }
+
+    fn save<'a>(
+        &'a self,
+        cookie: Option<&'a str>,
+        data: &'a SessionData,
+        ttl: Duration,
+    ) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let id = match cookie {
+                Some(id) => id.to_string(),
+                None => random_id()?,
+            };
+            let now = Instant::now();
+            let mut sessions = self.sessions();
+            sessions.retain(|_, (_, expires)| *expires > now);
+            sessions.insert(id.clone(), (data.clone(), now + ttl));
+            Ok(id)
+        })
+    }
+
+    fn destroy<'a>(&'a self, cookie: &'a str) -> StoreFuture<'a, ()> {
+        self.sessions().remove(cookie);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Keeps the whole session in the cookie, encrypted with a [`Key`]. Nothing
+/// is stored server-side, so keep the data small (browsers cap cookies at ~4 KB)
+/// and note that destroying a session only clears the client's copy.
+#[derive(Clone)]
+pub struct CookieStore {
+    key: Key,
+}
+
+impl CookieStore {
+    /// Fixed associated data, so values can't be replayed under other cookie names
+    const LABEL: &'static str = "s_web.session";
+
+    pub fn new(key: Key) -> Self {
+        Self { key }
+    }
+}
+
+impl SessionStore for CookieStore {
+    fn load<'a>(&'a self, cookie: &'a str) -> StoreFuture<'a, Option<SessionData>> {
+        let data = self
+            .key
+            .decrypt(Self::LABEL, cookie)
+            .and_then(|json| serde_json::from_str::<(SessionData, u64)>(&json).ok())
+            .filter(|(_, expires)| *expires > unix_now())
+            .map(|(data, _)| data);
+        Box::pin(async move { Ok(data) })
+    }
+
+    fn save<'a>(
+        &'a self,
+        _cookie: Option<&'a str>,
+        data: &'a SessionData,
+        ttl: Duration,
+    ) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            // The expiry travels inside the ciphertext; Max-Age alone is client-controlled
+            let json = serde_json::to_string(&(data, unix_now() + ttl.as_secs()))?;
+            let sealed = self.key.encrypt(Cookie::new(Self::LABEL, json));
+            Ok(sealed.value().to_string())
+        })
+    }
+
+    fn destroy<'a>(&'a self, _cookie: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Sessions as JSON under `session:` keys of a shared [`KvStore`], keyed by
+/// random 128-bit ids like [`MemoryStore`].
+impl SessionStore for Arc<dyn KvStore> {
+    fn load<'a>(&'a self, cookie: &'a str) -> StoreFuture<'a, Option<SessionData>> {
+        Box::pin(async move {
+            let json = self.get(&format!("session:{cookie}")).await?;
+            Ok(json.and_then(|json| serde_json::from_slice(&json).ok()))
+        })
+    }
+
+    fn save<'a>(
+        &'a self,
+        cookie: Option<&'a str>,
+        data: &'a SessionData,
+        ttl: Duration,
+    ) -> StoreFuture<'a, String> {
+        Box::pin(async move {
+            let id = match cookie {
+                Some(id) => id.to_string(),
+                None => random_id()?,
+            };
+            let json = serde_json::to_vec(data)?;
+            self.set(&format!("session:{id}"), json, Some(ttl)).await?;
+            Ok(id)
+        })
+    }
+
+    fn destroy<'a>(&'a self, cookie: &'a str) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            self.delete(&format!("session:{cookie}")).await?;
+            Ok(())
+        })
+    }
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn random_id() -> Result<String, BoxError> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(|e| e.to_string())?;
+    Ok(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+#[derive(Default)]
+struct SessionState {
+    data: SessionData,
+    changed: bool,
+    regenerate: bool,
+    destroyed: bool,
+}
+
+/// Handle to the current request's session, returned by `ctx.session()`.
+///
+/// Changes are saved after the handler returns; untouched sessions don't
+/// trigger a store write or a `Set-Cookie`.
+#[derive(Clone, Default)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+impl Session {
+    fn with_data(data: SessionData) -> Self {
+        let session = Self::default();
+        session.lock().data = data;
+        session
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SessionState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Read `key`, or `None` if it is missing or doesn't deserialize into `T`.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.lock().data.get(key)?.clone();
+        serde_json::from_value(value).ok()
+    }
+
+    pub fn insert<T: Serialize>(&self, key: &str, value: T) -> Result<(), serde_json::Error> {
+        let value = serde_json::to_value(value)?;
+        let mut state = self.lock();
+        state.data.insert(key.to_string(), value);
+        state.changed = true;
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &str) -> Option<Value> {
+        let mut state = self.lock();
+        let removed = state.data.remove(key);
+        state.changed |= removed.is_some();
+        removed
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.changed |= !state.data.is_empty();
+        state.data.clear();
+    }
+
+    /// Issue a new session id while keeping the data. Call it on login so an
+    /// id planted before authentication can't be reused (session fixation).
+    pub fn regenerate(&self) {
+        let mut state = self.lock();
+        state.regenerate = true;
+        state.changed = true;
+    }
+
+    /// Delete the session from the store and expire the cookie.
+    pub fn destroy(&self) {
+        let mut state = self.lock();
+        state.data.clear();
+        state.destroyed = true;
+    }
+}
+
+impl RequestCtx {
+    /// The session attached by [`SessionMiddleware`].
+    pub fn session(&self) -> Option<Session> {
+        self.request.extensions().get::<Session>().cloned()
+    }
+}
+
+/// Session middleware configuration.
+#[derive(Clone)]
+pub struct SessionMiddleware {
+    store: Arc<dyn SessionStore>,
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl SessionMiddleware {
+    /// Sessions in `store`, identified by an `HttpOnly`, `SameSite=Lax`
+    /// cookie named `sid` that lives for 24 hours.
+    pub fn new(store: impl SessionStore) -> Self {
+        Self {
+            store: Arc::new(store),
+            cookie_name: "sid".to_string(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            secure: false,
+            same_site: SameSite::Lax,
+        }
+    }
+
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    /// Lifetime of a session after its last change.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Mark the cookie `Secure` (HTTPS only).
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    /// Build the middleware function for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let config = Arc::new(self);
+        move |ctx, next| {
+            let config = config.clone();
+            Box::pin(async move { config.handle(ctx, next).await })
+        }
+    }
+
+    async fn handle(&self, mut ctx: RequestCtx, next: Next) -> Response {
+        let cookie = ctx.cookies().get(&self.cookie_name).map(str::to_owned);
+        let loaded = match &cookie {
+            Some(value) => match self.store.load(value).await {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("session store load failed: {e}");
+                    return ResponseBuilder::internal_error();
+                }
+            },
+            None => None,
+        };
+        // An unknown or expired cookie gets a fresh id rather than being reused
+        let existing = cookie.filter(|_| loaded.is_some());
+        let session = Session::with_data(loaded.unwrap_or_default());
+        ctx.request.extensions_mut().insert(session.clone());
+
+        let mut response = next(ctx).await;
+
+        let (data, regenerate, destroyed, changed) = {
+            let mut state = session.lock();
+            (
+                std::mem::take(&mut state.data),
+                state.regenerate,
+                state.destroyed,
+                state.changed,
+            )
+        };
+        if (destroyed || regenerate)
+            && let Some(old) = &existing
+            && let Err(e) = self.store.destroy(old).await
+        {
+            log::warn!("session store destroy failed: {e}");
+        }
+        let set_cookie = if destroyed {
+            if existing.is_none() {
+                return response;
+            }
+            self.cookie(Cookie::remove(&self.cookie_name))
+        } else if changed {
+            let current = existing.as_deref().filter(|_| !regenerate);
+            match self.store.save(current, &data, self.ttl).await {
+                Ok(value) => self.cookie(Cookie::new(&self.cookie_name, value).max_age(self.ttl)),
+                Err(e) => {
+                    log::warn!("session store save failed: {e}");
+                    return ResponseBuilder::internal_error();
+                }
+            }
+        } else {
+            return response;
+        };
+        match HeaderValue::from_str(&set_cookie.to_string()) {
+            Ok(value) => {
+                response.headers_mut().append(SET_COOKIE, value);
+            }
+            Err(e) => log::warn!("invalid session cookie: {e}"),
+        }
+        response
+    }
+
+    fn cookie(&self, cookie: Cookie) -> Cookie {
+        cookie
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_store_roundtrip_and_expiry() {
+        let store = MemoryStore::new();
+        let data = SessionData::from([("user".to_string(), Value::from(7))]);
+
+        let id = store.save(None, &data, Duration::from_secs(60)).await.unwrap();
+        assert_eq!(id.len(), 32);
+        assert_eq!(store.load(&id).await.unwrap(), Some(data.clone()));
+
+        store.destroy(&id).await.unwrap();
+        assert_eq!(store.load(&id).await.unwrap(), None);
+
+        let id = store.save(None, &data, Duration::ZERO).await.unwrap();
+        assert_eq!(store.load(&id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_cookie_store_is_opaque_and_tamper_proof() {
+        let store = CookieStore::new(Key::from_secret(&[9; 32]).unwrap());
+        let data = SessionData::from([("user".to_string(), Value::from("alice"))]);
+
+        let value = store.save(None, &data, Duration::from_secs(60)).await.unwrap();
+        assert!(!value.contains("alice"));
+        assert_eq!(store.load(&value).await.unwrap(), Some(data.clone()));
+        let mut tampered = value.clone().into_bytes();
+        let mid = tampered.len() / 2;
+        tampered[mid] = if tampered[mid] == b'A' { b'B' } else { b'A' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        assert_eq!(store.load(&tampered).await.unwrap(), None);
+
+        let expired = store.save(None, &data, Duration::ZERO).await.unwrap();
+        assert_eq!(store.load(&expired).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_session_tracks_changes() {
+        let session = Session::default();
+        assert!(!session.lock().changed);
+        session.insert("n", 1).unwrap();
+        assert_eq!(session.get::<i32>("n"), Some(1));
+        assert_eq!(session.get::<String>("n"), None);
+        assert!(session.lock().changed);
+    }
+}
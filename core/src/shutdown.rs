@@ -0,0 +1,245 @@
+//! Ordered shutdown of background subsystems (job runners, webhook senders,
+//! pools) registered with [`Engine::subsystem`](crate::Engine::subsystem).
+
+use std::{collections::HashMap, future::Future, pin::Pin, time::Duration};
+
+use futures_util::future::join_all;
+
+use crate::{BoxError, lifecycle::LifecycleCtx, log};
+
+type StopFn = Box<dyn Fn(LifecycleCtx) -> Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send>> + Send + Sync>;
+
+/// A background subsystem stopped after the server has drained its connections.
+///
+/// Subsystems stop after everything that [depends on](Self::depends_on) them,
+/// so a job runner is stopped before the database pool it uses. Subsystems
+/// without an ordering constraint between them stop concurrently.
+///
+/// ```rust,ignore
+/// let app = app
+///     .subsystem(Subsystem::new("db", |ctx| async move {
+///         ctx.get::<PgPool>().unwrap().close().await;
+///         Ok::<_, BoxError>(())
+///     }))
+///     .subsystem(
+///         Subsystem::new("jobs", |ctx| async move { ctx.get::<Jobs>().unwrap().stop().await })
+///             .depends_on("db")
+///             .timeout(Duration::from_secs(30)),
+///     );
+/// ```
+pub struct Subsystem {
+    name: String,
+    depends_on: Vec<String>,
+    timeout: Duration,
+    stop: StopFn,
+}
+
+impl Subsystem {
+    /// `stop` is given the lifecycle context (shared state) and must resolve
+    /// once the subsystem has stopped. The default timeout is 10 seconds.
+    pub fn new<F, Fut, E>(name: &str, stop: F) -> Self
+    where
+        F: Fn(LifecycleCtx) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        let stop = move |ctx| {
+            let fut = stop(ctx);
+            Box::pin(async move { fut.await.map_err(Into::into) })
+                as Pin<Box<dyn Future<Output = Result<(), BoxError>> + Send>>
+        };
+        Self {
+            name: name.to_string(),
+            depends_on: Vec::new(),
+            timeout: Duration::from_secs(10),
+            stop: Box::new(stop),
+        }
+    }
+
+    /// Keep `name` running until this subsystem has stopped.
+    pub fn depends_on(mut self, name: &str) -> Self {
+        self.depends_on.push(name.to_string());
+        self
+    }
+
+    /// How long stopping may take before it is reported as failed.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Why a subsystem failed to stop
+#[derive(Debug)]
+pub enum StopFailure {
+    Error(BoxError),
+    TimedOut(Duration),
+}
+
+impl std::fmt::Display for StopFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StopFailure::Error(e) => write!(f, "{e}"),
+            StopFailure::TimedOut(after) => write!(f, "did not stop within {after:?}"),
+        }
+    }
+}
+
+/// Subsystems failed to stop; returned by `Engine::run` as
+/// [`RunError::Shutdown`](crate::RunError::Shutdown) so the process exits
+/// with a non-zero code.
+#[derive(Debug)]
+pub struct ShutdownError {
+    pub failed: Vec<(String, StopFailure)>,
+}
+
+impl std::fmt::Display for ShutdownError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "subsystems failed to stop: ")?;
+        for (i, (name, failure)) in self.failed.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "`{name}` ({failure})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ShutdownError {}
+
+/// Group subsystems into stages: each stage only holds subsystems whose
+/// dependents all stopped in earlier stages. Fails on unknown names and cycles.
+pub(crate) fn plan(subsystems: &[Subsystem]) -> Result<Vec<Vec<usize>>, String> {
+    let index: HashMap<&str, usize> = subsystems
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.name.as_str(), i))
+        .collect();
+    // Number of subsystems still running that depend on each subsystem
+    let mut dependents = vec![0usize; subsystems.len()];
+    for subsystem in subsystems {
+        for dep in &subsystem.depends_on {
+            let Some(&i) = index.get(dep.as_str()) else {
+                return Err(format!(
+                    "subsystem `{}` depends on unknown subsystem `{dep}`",
+                    subsystem.name
+                ));
+            };
+            dependents[i] += 1;
+        }
+    }
+
+    let mut stopped = vec![false; subsystems.len()];
+    let mut stages = Vec::new();
+    while stopped.iter().any(|s| !s) {
+        let stage: Vec<usize> = (0..subsystems.len())
+            .filter(|&i| !stopped[i] && dependents[i] == 0)
+            .collect();
+        if stage.is_empty() {
+            let cycle: Vec<&str> = (0..subsystems.len())
+                .filter(|&i| !stopped[i])
+                .map(|i| subsystems[i].name.as_str())
+                .collect();
+            return Err(format!("subsystem dependency cycle among {}", cycle.join(", ")));
+        }
+        for &i in &stage {
+            stopped[i] = true;
+            for dep in &subsystems[i].depends_on {
+                dependents[index[dep.as_str()]] -= 1;
+            }
+        }
+        stages.push(stage);
+    }
+    Ok(stages)
+}
+
+/// Stop every subsystem stage by stage, logging each outcome
+pub(crate) async fn stop_all(
+    subsystems: &[Subsystem],
+    stages: &[Vec<usize>],
+    ctx: &LifecycleCtx,
+) -> Result<(), ShutdownError> {
+    let mut failed = Vec::new();
+    for stage in stages {
+        let results = join_all(stage.iter().map(|&i| {
+            let subsystem = &subsystems[i];
+            let stop = (subsystem.stop)(ctx.clone());
+            async move {
+                match tokio::time::timeout(subsystem.timeout, stop).await {
+                    Ok(Ok(())) => Ok(()),
+                    Ok(Err(e)) => Err(StopFailure::Error(e)),
+                    Err(_) => Err(StopFailure::TimedOut(subsystem.timeout)),
+                }
+            }
+        }))
+        .await;
+        for (&i, result) in stage.iter().zip(results) {
+            let name = &subsystems[i].name;
+            match result {
+                Ok(()) => log::info!("✔ Subsystem `{name}` stopped"),
+                Err(failure) => {
+                    log::error!("❌ Subsystem `{name}` failed to stop: {failure}");
+                    failed.push((name.clone(), failure));
+                }
+            }
+        }
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(ShutdownError { failed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subsystem(name: &str, deps: &[&str]) -> Subsystem {
+        deps.iter().fold(
+            Subsystem::new(name, |_| async { Ok::<_, BoxError>(()) }),
+            |s, dep| s.depends_on(dep),
+        )
+    }
+
+    #[test]
+    fn test_plan_stops_dependents_first() {
+        let subsystems = [
+            subsystem("db", &[]),
+            subsystem("cache", &[]),
+            subsystem("jobs", &["db", "cache"]),
+            subsystem("webhooks", &["jobs"]),
+        ];
+        assert_eq!(plan(&subsystems).unwrap(), vec![vec![3], vec![2], vec![0, 1]]);
+
+        let cyclic = [subsystem("a", &["b"]), subsystem("b", &["a"])];
+        assert!(plan(&cyclic).unwrap_err().contains("cycle"));
+        assert!(plan(&[subsystem("a", &["nope"])]).unwrap_err().contains("`nope`"));
+    }
+
+    #[tokio::test]
+    async fn test_stop_reports_failures() {
+        let subsystems = [
+            subsystem("ok", &[]),
+            Subsystem::new("broken", |_| async { Err::<(), _>("pool busy") }),
+            Subsystem::new("stuck", |_| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok::<_, BoxError>(())
+            })
+            .timeout(Duration::from_millis(20)),
+        ];
+        let stages = plan(&subsystems).unwrap();
+        let (_tx, token) = crate::lifecycle::ShutdownToken::new();
+        let ctx = LifecycleCtx::new(crate::state::StateMap::new(), token);
+
+        let err = stop_all(&subsystems, &stages, &ctx).await.unwrap_err();
+        let names: Vec<&str> = err.failed.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["broken", "stuck"]);
+        assert!(matches!(err.failed[1].1, StopFailure::TimedOut(_)));
+    }
+}
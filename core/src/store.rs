@@ -0,0 +1,595 @@
+//! Key-value storage shared by the framework's stateful middleware.
+//!
+//! Sessions, webhook dedup, quotas, lockouts, rate limits and response caches
+//! each define their own store trait, and each is also implemented for
+//! `Arc<dyn KvStore>`. Configure one backend and hand clones of it to all of
+//! them; keys are namespaced (`session:`, `dedup:`, `quota:`, `lockout:`,
+//! `ratelimit:`, `cache:`) so they don't collide:
+//!
+//! ```rust,ignore
+//! use s_web::store::{KvStore, RedisStore};
+//! use std::sync::Arc;
+//!
+//! let kv: Arc<dyn KvStore> = Arc::new(RedisStore::connect("redis://127.0.0.1/").await?);
+//!
+//! app.use_middleware(SessionMiddleware::new(kv.clone()).middleware());
+//! app.use_middleware(Quota::new(kv.clone()).limit(10_000).middleware());
+//! app.post("/login", login).with_middleware(Lockout::new(kv.clone()).middleware());
+//! app.post("/webhooks/github", on_push).with_middleware(Dedup::new(kv).middleware());
+//! ```
+//!
+//! [`MemoryKvStore`] keeps entries in the process, [`FileKvStore`] also
+//! writes them to a JSON file so they survive restarts, and [`RedisStore`]
+//! (`redis` feature) shares them between instances.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::BoxError;
+use crate::log;
+
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, BoxError>> + Send + 'a>>;
+
+/// Byte values with optional expiry.
+///
+/// `ttl` arguments set the expiry of the written key; `None` keeps it until
+/// deleted. `incr` and `cas` must be atomic.
+pub trait KvStore: Send + Sync + 'static {
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<Vec<u8>>>;
+
+    fn set<'a>(&'a self, key: &'a str, value: Vec<u8>, ttl: Option<Duration>) -> StoreFuture<'a, ()>;
+
+    /// Remove `key`; `false` if it didn't exist.
+    fn delete<'a>(&'a self, key: &'a str) -> StoreFuture<'a, bool>;
+
+    /// Time until `key` expires, or `None` if it is missing or never expires.
+    fn ttl<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<Duration>>;
+
+    /// Add `by` to the integer at `key` (missing counts as 0) and return the
+    /// result. `ttl` only applies when this call creates the key, so a
+    /// counter expires a fixed time after its first increment.
+    fn incr<'a>(&'a self, key: &'a str, by: i64, ttl: Option<Duration>) -> StoreFuture<'a, i64>;
+
+    /// Write `new` only if the value is still `current` (`None`: only if
+    /// `key` is missing); `false` if it changed in between.
+    fn cas<'a>(
+        &'a self,
+        key: &'a str,
+        current: Option<&'a [u8]>,
+        new: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> StoreFuture<'a, bool>;
+}
+
+/// Result of an infallible store method: log the error and fall back, so a
+/// backend outage doesn't take every request down with it
+pub(crate) fn or_log<T>(result: Result<T, BoxError>, fallback: T) -> T {
+    result.unwrap_or_else(|e| {
+        log::warn!("key-value store error: {e}");
+        fallback
+    })
+}
+
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn expiry(ttl: Option<Duration>, now: u64) -> Option<u64> {
+    ttl.map(|ttl| now.saturating_add(ttl.as_millis() as u64))
+}
+
+/// One value; expiry is wall-clock so the file store can persist it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    #[serde(with = "hex")]
+    value: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_ms: Option<u64>,
+}
+
+impl Entry {
+    fn is_live(&self, now: u64) -> bool {
+        self.expires_ms.is_none_or(|expires| expires > now)
+    }
+}
+
+/// The map behind the in-process stores
+#[derive(Debug, Default)]
+struct Entries {
+    map: HashMap<String, Entry>,
+    /// Size at which expired entries are swept
+    sweep_at: usize,
+    /// Bumped on every change, so file writes can skip stale snapshots
+    version: u64,
+}
+
+impl Entries {
+    fn live(&self, key: &str, now: u64) -> Option<&Entry> {
+        self.map.get(key).filter(|entry| entry.is_live(now))
+    }
+
+    fn insert(&mut self, key: &str, entry: Entry, now: u64) {
+        if self.map.len() >= self.sweep_at {
+            self.map.retain(|_, entry| entry.is_live(now));
+            self.sweep_at = (self.map.len() * 2).max(1024);
+        }
+        self.map.insert(key.to_string(), entry);
+        self.version += 1;
+    }
+
+    fn get(&self, key: &str, now: u64) -> Option<Vec<u8>> {
+        self.live(key, now).map(|entry| entry.value.clone())
+    }
+
+    fn set(&mut self, key: &str, value: Vec<u8>, ttl: Option<Duration>, now: u64) {
+        let expires_ms = expiry(ttl, now);
+        self.insert(key, Entry { value, expires_ms }, now);
+    }
+
+    fn delete(&mut self, key: &str, now: u64) -> bool {
+        let Some(entry) = self.map.remove(key) else {
+            return false;
+        };
+        self.version += 1;
+        entry.is_live(now)
+    }
+
+    fn ttl(&self, key: &str, now: u64) -> Option<Duration> {
+        let expires = self.live(key, now)?.expires_ms?;
+        Some(Duration::from_millis(expires - now))
+    }
+
+    fn incr(&mut self, key: &str, by: i64, ttl: Option<Duration>, now: u64) -> Result<i64, BoxError> {
+        let (current, expires_ms) = match self.live(key, now) {
+            Some(entry) => {
+                let current = std::str::from_utf8(&entry.value)
+                    .ok()
+                    .and_then(|text| text.parse::<i64>().ok())
+                    .ok_or_else(|| format!("value at {key:?} is not an integer"))?;
+                (current, entry.expires_ms)
+            }
+            None => (0, expiry(ttl, now)),
+        };
+        let value = current.checked_add(by).ok_or("increment overflows")?;
+        self.insert(key, Entry { value: value.to_string().into_bytes(), expires_ms }, now);
+        Ok(value)
+    }
+
+    fn cas(
+        &mut self,
+        key: &str,
+        current: Option<&[u8]>,
+        new: Vec<u8>,
+        ttl: Option<Duration>,
+        now: u64,
+    ) -> bool {
+        let matches = self.live(key, now).map(|entry| entry.value.as_slice()) == current;
+        if matches {
+            self.set(key, new, ttl, now);
+        }
+        matches
+    }
+}
+
+/// In-process store. Data is lost on restart and not shared between instances.
+#[derive(Debug, Default)]
+pub struct MemoryKvStore {
+    entries: Mutex<Entries>,
+}
+
+impl MemoryKvStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl KvStore for MemoryKvStore {
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<Vec<u8>>> {
+        let value = self.entries().get(key, unix_millis());
+        Box::pin(async move { Ok(value) })
+    }
+
+    fn set<'a>(&'a self, key: &'a str, value: Vec<u8>, ttl: Option<Duration>) -> StoreFuture<'a, ()> {
+        self.entries().set(key, value, ttl, unix_millis());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> StoreFuture<'a, bool> {
+        let existed = self.entries().delete(key, unix_millis());
+        Box::pin(async move { Ok(existed) })
+    }
+
+    fn ttl<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<Duration>> {
+        let ttl = self.entries().ttl(key, unix_millis());
+        Box::pin(async move { Ok(ttl) })
+    }
+
+    fn incr<'a>(&'a self, key: &'a str, by: i64, ttl: Option<Duration>) -> StoreFuture<'a, i64> {
+        let value = self.entries().incr(key, by, ttl, unix_millis());
+        Box::pin(async move { value })
+    }
+
+    fn cas<'a>(
+        &'a self,
+        key: &'a str,
+        current: Option<&'a [u8]>,
+        new: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> StoreFuture<'a, bool> {
+        let swapped = self.entries().cas(key, current, new, ttl, unix_millis());
+        Box::pin(async move { Ok(swapped) })
+    }
+}
+
+/// [`MemoryKvStore`] persisted to a JSON file, for single-instance
+/// deployments that should keep sessions and lockouts across restarts.
+///
+/// Every change rewrites the whole file (atomically, through a temporary
+/// file), so keep it to small data sets.
+#[derive(Debug)]
+pub struct FileKvStore {
+    path: PathBuf,
+    entries: Mutex<Entries>,
+    /// Version of the snapshot last written
+    written: tokio::sync::Mutex<u64>,
+}
+
+impl FileKvStore {
+    /// Load `path`, or start empty if it doesn't exist yet.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let map: HashMap<String, Entry> = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        let now = unix_millis();
+        let entries = Entries {
+            map: map.into_iter().filter(|(_, entry)| entry.is_live(now)).collect(),
+            ..Entries::default()
+        };
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+            written: tokio::sync::Mutex::new(0),
+        })
+    }
+
+    /// Apply `change` and write the resulting snapshot if it changed anything
+    fn update<'a, T: Send + 'a>(
+        &'a self,
+        change: impl FnOnce(&mut Entries, u64) -> Result<T, BoxError>,
+    ) -> StoreFuture<'a, T> {
+        let result = {
+            let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            let before = entries.version;
+            change(&mut entries, unix_millis()).map(|value| {
+                let snapshot = (entries.version != before)
+                    .then(|| (entries.version, serde_json::to_vec(&entries.map)));
+                (value, snapshot)
+            })
+        };
+        Box::pin(async move {
+            let (value, snapshot) = result?;
+            if let Some((version, json)) = snapshot {
+                self.write(version, json?).await?;
+            }
+            Ok(value)
+        })
+    }
+
+    async fn write(&self, version: u64, json: Vec<u8>) -> io::Result<()> {
+        let mut written = self.written.lock().await;
+        // A later change already wrote a newer snapshot
+        if *written >= version {
+            return Ok(());
+        }
+        let tmp = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp, json).await?;
+        tokio::fs::rename(&tmp, &self.path).await?;
+        *written = version;
+        Ok(())
+    }
+}
+
+impl KvStore for FileKvStore {
+    fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<Vec<u8>>> {
+        let value = self.entries.lock().unwrap_or_else(|e| e.into_inner()).get(key, unix_millis());
+        Box::pin(async move { Ok(value) })
+    }
+
+    fn set<'a>(&'a self, key: &'a str, value: Vec<u8>, ttl: Option<Duration>) -> StoreFuture<'a, ()> {
+        self.update(move |entries, now| {
+            entries.set(key, value, ttl, now);
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> StoreFuture<'a, bool> {
+        self.update(move |entries, now| Ok(entries.delete(key, now)))
+    }
+
+    fn ttl<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<Duration>> {
+        let ttl = self.entries.lock().unwrap_or_else(|e| e.into_inner()).ttl(key, unix_millis());
+        Box::pin(async move { Ok(ttl) })
+    }
+
+    fn incr<'a>(&'a self, key: &'a str, by: i64, ttl: Option<Duration>) -> StoreFuture<'a, i64> {
+        self.update(move |entries, now| entries.incr(key, by, ttl, now))
+    }
+
+    fn cas<'a>(
+        &'a self,
+        key: &'a str,
+        current: Option<&'a [u8]>,
+        new: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> StoreFuture<'a, bool> {
+        self.update(move |entries, now| Ok(entries.cas(key, current, new, ttl, now)))
+    }
+}
+
+/// Values are stored as hex strings in the file store
+mod hex {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+        serializer.serialize_str(&hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if hex.len() % 2 != 0 {
+            return Err(D::Error::custom("odd length hex value"));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use redis_store::RedisStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use std::time::Duration;
+
+    use redis::{Script, aio::ConnectionManager};
+
+    use super::{KvStore, StoreFuture};
+    use crate::BoxError;
+
+    /// Redis backend (`redis` feature). The connection reconnects on its own
+    /// after failures; calls made while Redis is down return errors.
+    #[derive(Clone)]
+    pub struct RedisStore {
+        conn: ConnectionManager,
+        prefix: String,
+        incr: Script,
+        cas: Script,
+    }
+
+    impl RedisStore {
+        /// Connect to e.g. `redis://127.0.0.1:6379/0`.
+        pub async fn connect(url: &str) -> Result<Self, BoxError> {
+            let client = redis::Client::open(url)?;
+            Ok(Self::new(client.get_connection_manager().await?))
+        }
+
+        pub fn new(conn: ConnectionManager) -> Self {
+            Self {
+                conn,
+                prefix: String::new(),
+                // The expiry is only set by the increment that creates the key
+                incr: Script::new(
+                    "local created = redis.call('EXISTS', KEYS[1]) == 0
+                     local value = redis.call('INCRBY', KEYS[1], ARGV[1])
+                     if created and ARGV[2] ~= '0' then redis.call('PEXPIRE', KEYS[1], ARGV[2]) end
+                     return value",
+                ),
+                cas: Script::new(
+                    "if redis.call('GET', KEYS[1]) ~= ARGV[1] then return 0 end
+                     if ARGV[3] == '0' then redis.call('SET', KEYS[1], ARGV[2])
+                     else redis.call('SET', KEYS[1], ARGV[2], 'PX', ARGV[3]) end
+                     return 1",
+                ),
+            }
+        }
+
+        /// Prepend `prefix` to every key, to share a Redis database with other apps.
+        pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+            self.prefix = prefix.into();
+            self
+        }
+
+        fn key(&self, key: &str) -> String {
+            format!("{}{key}", self.prefix)
+        }
+    }
+
+    /// Milliseconds for `PX`; `0` means no expiry, and a zero TTL still expires
+    fn millis(ttl: Option<Duration>) -> u64 {
+        ttl.map_or(0, |ttl| (ttl.as_millis() as u64).max(1))
+    }
+
+    impl KvStore for RedisStore {
+        fn get<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<Vec<u8>>> {
+            let mut conn = self.conn.clone();
+            Box::pin(async move { Ok(redis::cmd("GET").arg(self.key(key)).query_async(&mut conn).await?) })
+        }
+
+        fn set<'a>(&'a self, key: &'a str, value: Vec<u8>, ttl: Option<Duration>) -> StoreFuture<'a, ()> {
+            let mut conn = self.conn.clone();
+            Box::pin(async move {
+                let mut cmd = redis::cmd("SET");
+                cmd.arg(self.key(key)).arg(value);
+                if ttl.is_some() {
+                    cmd.arg("PX").arg(millis(ttl));
+                }
+                Ok(cmd.query_async(&mut conn).await?)
+            })
+        }
+
+        fn delete<'a>(&'a self, key: &'a str) -> StoreFuture<'a, bool> {
+            let mut conn = self.conn.clone();
+            Box::pin(async move {
+                let removed: i64 = redis::cmd("DEL").arg(self.key(key)).query_async(&mut conn).await?;
+                Ok(removed > 0)
+            })
+        }
+
+        fn ttl<'a>(&'a self, key: &'a str) -> StoreFuture<'a, Option<Duration>> {
+            let mut conn = self.conn.clone();
+            Box::pin(async move {
+                // -2: missing, -1: no expiry
+                let ttl: i64 = redis::cmd("PTTL").arg(self.key(key)).query_async(&mut conn).await?;
+                Ok(u64::try_from(ttl).ok().map(Duration::from_millis))
+            })
+        }
+
+        fn incr<'a>(&'a self, key: &'a str, by: i64, ttl: Option<Duration>) -> StoreFuture<'a, i64> {
+            let mut conn = self.conn.clone();
+            Box::pin(async move {
+                Ok(self
+                    .incr
+                    .key(self.key(key))
+                    .arg(by)
+                    .arg(millis(ttl))
+                    .invoke_async(&mut conn)
+                    .await?)
+            })
+        }
+
+        fn cas<'a>(
+            &'a self,
+            key: &'a str,
+            current: Option<&'a [u8]>,
+            new: Vec<u8>,
+            ttl: Option<Duration>,
+        ) -> StoreFuture<'a, bool> {
+            let mut conn = self.conn.clone();
+            Box::pin(async move {
+                let Some(current) = current else {
+                    let mut cmd = redis::cmd("SET");
+                    cmd.arg(self.key(key)).arg(new).arg("NX");
+                    if ttl.is_some() {
+                        cmd.arg("PX").arg(millis(ttl));
+                    }
+                    let set: Option<String> = cmd.query_async(&mut conn).await?;
+                    return Ok(set.is_some());
+                };
+                let swapped: i64 = self
+                    .cas
+                    .key(self.key(key))
+                    .arg(current)
+                    .arg(new)
+                    .arg(millis(ttl))
+                    .invoke_async(&mut conn)
+                    .await?;
+                Ok(swapped == 1)
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{dedup::DedupStore, lockout::LockoutStore, quota::QuotaStore};
+
+    #[tokio::test]
+    async fn test_memory_store_operations() {
+        let store = MemoryKvStore::new();
+        assert_eq!(store.get("a").await.unwrap(), None);
+        store.set("a", b"1".to_vec(), None).await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.ttl("a").await.unwrap(), None);
+
+        assert_eq!(store.incr("a", 41, None).await.unwrap(), 42);
+        assert_eq!(store.incr("n", -2, Some(Duration::from_secs(60))).await.unwrap(), -2);
+        let ttl = store.ttl("n").await.unwrap().unwrap();
+        assert!(ttl > Duration::from_secs(59) && ttl <= Duration::from_secs(60));
+        store.set("text", b"abc".to_vec(), None).await.unwrap();
+        assert!(store.incr("text", 1, None).await.is_err());
+
+        assert!(!store.cas("a", Some(b"1"), b"2".to_vec(), None).await.unwrap());
+        assert!(store.cas("a", Some(b"42"), b"2".to_vec(), None).await.unwrap());
+        assert!(!store.cas("a", None, b"3".to_vec(), None).await.unwrap());
+        assert!(store.cas("b", None, b"3".to_vec(), None).await.unwrap());
+
+        assert!(store.delete("a").await.unwrap());
+        assert!(!store.delete("a").await.unwrap());
+
+        // Expired keys read as missing and can be claimed again
+        store.set("gone", b"x".to_vec(), Some(Duration::ZERO)).await.unwrap();
+        assert_eq!(store.get("gone").await.unwrap(), None);
+        assert!(store.cas("gone", None, b"y".to_vec(), None).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_file_store_survives_reopen() {
+        let path = std::env::temp_dir().join(format!("s_web-kv-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        {
+            let store = FileKvStore::open(&path).unwrap();
+            store.set("kept", vec![0, 255], None).await.unwrap();
+            store.set("expired", b"x".to_vec(), Some(Duration::ZERO)).await.unwrap();
+            store.incr("count", 3, Some(Duration::from_secs(60))).await.unwrap();
+        }
+        let store = FileKvStore::open(&path).unwrap();
+        assert_eq!(store.get("kept").await.unwrap(), Some(vec![0, 255]));
+        assert_eq!(store.get("expired").await.unwrap(), None);
+        assert_eq!(store.incr("count", 1, None).await.unwrap(), 4);
+        assert!(store.ttl("count").await.unwrap().is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_middleware_stores_share_one_backend() {
+        let backend = Arc::new(MemoryKvStore::new());
+        let kv: Arc<dyn KvStore> = backend.clone();
+        let window = Duration::from_secs(60);
+
+        assert!(kv.first_seen("delivery-1", window).await);
+        assert!(!kv.first_seen("delivery-1", window).await);
+        DedupStore::forget(&kv, "delivery-1").await;
+        assert!(kv.first_seen("delivery-1", window).await);
+
+        let usage = kv.consume("tenant", 3, 4, window).await;
+        assert!(usage.allowed && usage.used == 3);
+        let usage = kv.consume("tenant", 2, 4, window).await;
+        assert!(!usage.allowed && usage.used == 3);
+
+        let lockout = Duration::from_secs(300);
+        assert_eq!(kv.record_failure("alice", 2, window, lockout).await, None);
+        assert_eq!(kv.record_failure("alice", 2, window, lockout).await, Some(lockout));
+        assert!(kv.locked_for("alice").await.is_some());
+
+        // Namespaced keys in the one backend
+        assert!(backend.get("dedup:delivery-1").await.unwrap().is_some());
+        assert_eq!(backend.get("quota:tenant").await.unwrap(), Some(b"3".to_vec()));
+        assert!(backend.ttl("lockout:alice:locked").await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,142 @@
+//! Scheduled route retirement (`RouteBuilder::sunset`).
+//!
+//! Until the cutoff, responses carry `Deprecation` and `Sunset` headers
+//! (RFC 8594) so clients can notice ahead of time; from the cutoff on, the
+//! route answers `410 Gone` with a migration message instead of running.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{Response, ResponseBuilder, StatusCode};
+
+/// Retirement date of a route
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Sunset {
+    /// The date as configured, `YYYY-MM-DD`
+    date: String,
+    at: SystemTime,
+    message: Option<String>,
+}
+
+impl Sunset {
+    /// Parse a `YYYY-MM-DD` date; the route retires at midnight UTC
+    pub(crate) fn parse(date: &str) -> Option<Self> {
+        let days = parse_date(date)?;
+        Some(Self {
+            date: date.to_string(),
+            at: UNIX_EPOCH + Duration::from_secs(days * 86_400),
+            message: None,
+        })
+    }
+
+    pub(crate) fn date(&self) -> &str {
+        &self.date
+    }
+
+    pub(crate) fn set_message(&mut self, message: &str) {
+        self.message = Some(message.to_string());
+    }
+
+    pub(crate) fn is_past(&self, now: SystemTime) -> bool {
+        now >= self.at
+    }
+
+    /// The `410` served from the cutoff on
+    pub(crate) fn gone(&self) -> Response {
+        let message = match &self.message {
+            Some(message) => message.clone(),
+            None => format!("this endpoint was retired on {}", self.date),
+        };
+        ResponseBuilder::new()
+            .status(StatusCode::GONE)
+            .content_type("text/plain; charset=utf-8")
+            .with_header("Sunset", http_date(self.at))
+            .build(format!("410 Gone: {message}"))
+    }
+
+    /// Announce the upcoming retirement on a response served before the cutoff
+    pub(crate) fn announce(&self, mut response: Response) -> Response {
+        let headers = response.headers_mut();
+        headers.insert("Deprecation", hyper::header::HeaderValue::from_static("true"));
+        if let Ok(value) = http_date(self.at).parse() {
+            headers.insert("Sunset", value);
+        }
+        response
+    }
+}
+
+/// Days since the unix epoch for a `YYYY-MM-DD` date
+fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next().filter(|y| y.len() == 4)?.parse().ok()?;
+    let month: u32 = parts.next().filter(|m| m.len() == 2)?.parse().ok()?;
+    let day: u32 = parts.next().filter(|d| d.len() == 2)?.parse().ok()?;
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let month_len = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if year < 1970 || day == 0 || day > month_len {
+        return None;
+    }
+    // Howard Hinnant's days_from_civil
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * m as i64 + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    u64::try_from(era * 146_097 + doe - 719_468).ok()
+}
+
+/// IMF-fixdate, e.g. `Sun, 01 Jun 2025 00:00:00 GMT`
+pub(crate) fn http_date(time: SystemTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sunset_dates() {
+        let sunset = Sunset::parse("2025-06-01").unwrap();
+        assert_eq!(http_date(sunset.at), "Sun, 01 Jun 2025 00:00:00 GMT");
+        assert_eq!(http_date(Sunset::parse("2024-02-29").unwrap().at), "Thu, 29 Feb 2024 00:00:00 GMT");
+        assert!(sunset.is_past(sunset.at));
+        assert!(!sunset.is_past(sunset.at - Duration::from_secs(1)));
+
+        for invalid in ["2025-02-29", "2025-13-01", "2025-6-1", "June 1st", "1969-12-31"] {
+            assert_eq!(Sunset::parse(invalid), None, "{invalid}");
+        }
+
+        let gone = sunset.gone();
+        assert_eq!(gone.status(), StatusCode::GONE);
+        assert_eq!(gone.headers()["Sunset"], "Sun, 01 Jun 2025 00:00:00 GMT");
+    }
+}
@@ -4,6 +4,10 @@ use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::collections::HashMap;
 
+use crate::RouteInfo;
+use crate::log;
+use crate::trie::{ConstraintSpec, Segment, parse_segment};
+
 /// Swagger configuration for a route
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct SwaggerInfo {
@@ -46,6 +50,7 @@ pub struct RequestBody {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MediaType {
     pub schema: Schema,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub example: Option<Value>,
 }
 
@@ -54,34 +59,145 @@ pub struct MediaType {
 pub struct Schema {
     #[serde(rename = "type")]
     pub type_: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub example: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub properties: Option<HashMap<String, Schema>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub items: Option<Box<Schema>>,
+    #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
+    pub enum_values: Option<Vec<String>>,
 }
 
 impl Schema {
-    pub fn string() -> Self {
+    fn typed(type_: &str, format: Option<&str>) -> Self {
         Self {
-            type_: "string".to_string(),
-            format: None,
+            type_: type_.to_string(),
+            format: format.map(str::to_string),
+            description: None,
             example: None,
             properties: None,
+            required: None,
             items: None,
+            enum_values: None,
         }
     }
 
+    pub fn string() -> Self {
+        Self::typed("string", None)
+    }
+
     pub fn object() -> Self {
+        Self::typed("object", None)
+    }
+
+    pub fn array(items: Schema) -> Self {
         Self {
-            type_: "object".to_string(),
-            format: None,
-            example: None,
-            properties: None,
-            items: None,
+            items: Some(Box::new(items)),
+            ..Self::typed("array", None)
         }
     }
 }
 
+/// Types that describe their JSON shape for the Swagger document, used by
+/// [`SwaggerBuilder::request_body_of`] and [`SwaggerBuilder::json_response_of`].
+///
+/// `#[derive(ToSchema)]` implements it from a type's fields and serde
+/// attributes:
+///
+/// ```rust,ignore
+/// #[derive(Deserialize, ToSchema)]
+/// struct CreateUser {
+///     /// Display name
+///     name: String,
+///     email: Option<String>,
+/// }
+///
+/// swagger().request_body_of::<CreateUser>().json_response_of::<User>("201", "Created")
+/// ```
+pub trait ToSchema {
+    fn schema() -> Schema;
+
+    /// Whether a struct field of this type is required; `false` for `Option`
+    fn required() -> bool {
+        true
+    }
+}
+
+macro_rules! primitive_schema {
+    ($type_:literal, $format:expr => $($ty:ty),+) => {
+        $(impl ToSchema for $ty {
+            fn schema() -> Schema {
+                Schema::typed($type_, $format)
+            }
+        })+
+    };
+}
+
+primitive_schema!("string", None => String, str, char);
+primitive_schema!("boolean", None => bool);
+primitive_schema!("integer", Some("int32") => i8, i16, i32, u8, u16, u32);
+primitive_schema!("integer", Some("int64") => i64, i128, isize, u64, u128, usize);
+primitive_schema!("number", Some("float") => f32);
+primitive_schema!("number", Some("double") => f64);
+primitive_schema!("object", None => Value);
+
+impl<T: ToSchema> ToSchema for Option<T> {
+    fn schema() -> Schema {
+        T::schema()
+    }
+
+    fn required() -> bool {
+        false
+    }
+}
+
+macro_rules! wrapper_schema {
+    ($($ty:ty),+) => {
+        $(impl<T: ToSchema + ?Sized> ToSchema for $ty {
+            fn schema() -> Schema {
+                T::schema()
+            }
+
+            fn required() -> bool {
+                T::required()
+            }
+        })+
+    };
+}
+
+wrapper_schema!(&T, Box<T>, std::sync::Arc<T>, std::rc::Rc<T>);
+
+macro_rules! array_schema {
+    ($($ty:ty),+) => {
+        $(impl<T: ToSchema> ToSchema for $ty {
+            fn schema() -> Schema {
+                Schema::array(T::schema())
+            }
+        })+
+    };
+}
+
+array_schema!(Vec<T>, [T], std::collections::VecDeque<T>, std::collections::HashSet<T>, std::collections::BTreeSet<T>);
+
+impl<K, V> ToSchema for HashMap<K, V> {
+    fn schema() -> Schema {
+        Schema::object()
+    }
+}
+
+impl<K, V> ToSchema for std::collections::BTreeMap<K, V> {
+    fn schema() -> Schema {
+        Schema::object()
+    }
+}
+
 /// Security requirement
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SecurityRequirement {
@@ -177,6 +293,27 @@ impl SwaggerBuilder {
         self
     }
 
+    /// Add a JSON response documented with the schema of `T`
+    pub fn json_response_of<T: ToSchema + ?Sized>(mut self, status: impl Into<String>, description: impl Into<String>) -> Self {
+        let mut content = HashMap::new();
+        content.insert(
+            "application/json".to_string(),
+            MediaType {
+                schema: T::schema(),
+                example: None,
+            },
+        );
+
+        self.info.responses.insert(
+            status.into(),
+            ApiResponse {
+                description: description.into(),
+                content: Some(content),
+            },
+        );
+        self
+    }
+
     pub fn request_body(mut self, example: Value) -> Self {
         let mut content = HashMap::new();
         content.insert(
@@ -195,6 +332,25 @@ impl SwaggerBuilder {
         self
     }
 
+    /// Document the JSON request body with the schema of `T`
+    pub fn request_body_of<T: ToSchema + ?Sized>(mut self) -> Self {
+        let mut content = HashMap::new();
+        content.insert(
+            "application/json".to_string(),
+            MediaType {
+                schema: T::schema(),
+                example: None,
+            },
+        );
+
+        self.info.request_body = Some(RequestBody {
+            description: Some("Request body".to_string()),
+            content,
+            required: true,
+        });
+        self
+    }
+
     pub fn security<S: Into<String>>(mut self, name: S, scopes: Vec<String>) -> Self {
         self.info.security.push(SecurityRequirement {
             name: name.into(),
@@ -239,27 +395,52 @@ pub fn swagger() -> SwaggerBuilder {
     SwaggerBuilder::new()
 }
 
-/// Generate enhanced OpenAPI JSON with custom swagger info
+/// Key of a route's entry in the swagger info maps
+pub(crate) fn route_key(method: &str, pattern: &str) -> String {
+    format!("{}-{}", method.to_uppercase(), pattern)
+}
+
+/// Generate enhanced OpenAPI JSON with custom swagger info and the defaults
+/// of the groups routes belong to, both keyed by [`route_key`]
 pub fn generate_enhanced_swagger_json(
-    routes: &[(String, String)],
+    routes: &[RouteInfo],
     custom_info: &HashMap<String, SwaggerInfo>,
+    group_defaults: &HashMap<String, SwaggerInfo>,
 ) -> String {
     let mut paths = serde_json::Map::new();
-
-    for (method, path) in routes {
+    let mut security_schemes = serde_json::Map::new();
+    security_schemes.insert(
+        "bearerAuth".to_string(),
+        crate::SecurityScheme::Bearer.swagger_definition(),
+    );
+
+    for route in routes.iter().filter(|route| !route.hidden) {
+        let (method, path) = (&route.method, &route.pattern);
         let openapi_path = convert_path_format(path);
-        let route_key = format!("{}-{}", method.to_uppercase(), path);
+        let route_key = route_key(method, path);
 
         let path_item = paths
             .entry(openapi_path.clone())
             .or_insert_with(|| json!({}));
 
         if let Some(path_obj) = path_item.as_object_mut() {
-            let operation = if let Some(custom) = custom_info.get(&route_key) {
+            let mut operation = if let Some(custom) = custom_info.get(&route_key) {
                 create_operation_from_custom(custom, path)
             } else {
                 create_default_operation(method, path)
             };
+            if let Some(defaults) = group_defaults.get(&route_key) {
+                apply_group_defaults(&mut operation, defaults);
+            }
+
+            if let Some(date) = &route.sunset {
+                mark_sunset(&mut operation, date);
+            }
+
+            for scheme in &route.security {
+                security_schemes.insert(scheme.swagger_name(), scheme.swagger_definition());
+                add_route_security(&mut operation, &scheme.swagger_name());
+            }
 
             path_obj.insert(method.to_lowercase(), operation);
         }
@@ -273,54 +454,113 @@ pub fn generate_enhanced_swagger_json(
             "description": "API documentation generated by s_web framework"
         },
         "components": {
-            "securitySchemes": {
-                "bearerAuth": {
-                    "type": "http",
-                    "scheme": "bearer",
-                    "bearerFormat": "JWT"
-                }
-            }
+            "securitySchemes": security_schemes
         },
         "paths": paths
     });
 
     serde_json::to_string_pretty(&swagger_doc).unwrap_or_else(|e| {
-        eprintln!("[s_web] swagger serialization error: {e}");
+        log::warn!("swagger serialization error: {e}");
         String::from("{}")
     })
 }
 
+/// Add a group's tags, security requirements and responses to an operation,
+/// keeping what the route documents itself
+fn apply_group_defaults(operation: &mut Value, defaults: &SwaggerInfo) {
+    let Some(op) = operation.as_object_mut() else {
+        return;
+    };
+    if let Value::Array(tags) = op.entry("tags").or_insert_with(|| json!([])) {
+        for tag in defaults.tags.iter().rev() {
+            if !tags.iter().any(|t| t == tag) {
+                tags.insert(0, json!(tag));
+            }
+        }
+    }
+    if let Value::Array(requirements) = op.entry("security").or_insert_with(|| json!([])) {
+        for req in &defaults.security {
+            if !requirements.iter().any(|r| r.get(&req.name).is_some()) {
+                requirements.push(json!({ req.name.clone(): req.scopes }));
+            }
+        }
+    }
+    if let Some(responses) = op.get_mut("responses").and_then(Value::as_object_mut) {
+        for (status, response) in &defaults.responses {
+            responses
+                .entry(status.clone())
+                .or_insert_with(|| serde_json::to_value(response).unwrap_or(json!({})));
+        }
+    }
+}
+
+/// Add a security requirement declared with `.secured(..)` unless the custom
+/// swagger info already lists it, and document the 401 it can produce
+fn add_route_security(operation: &mut Value, name: &str) {
+    let security = operation
+        .as_object_mut()
+        .map(|op| op.entry("security").or_insert_with(|| json!([])));
+    if let Some(Value::Array(requirements)) = security
+        && !requirements.iter().any(|req| req.get(name).is_some())
+    {
+        requirements.push(json!({ name: [] }));
+    }
+    if let Some(responses) = operation["responses"].as_object_mut() {
+        responses
+            .entry("401")
+            .or_insert_with(|| json!({ "description": "Unauthorized" }));
+    }
+}
+
+/// Flag a route retired with `.sunset(..)` and document its `410`
+fn mark_sunset(operation: &mut Value, date: &str) {
+    operation["deprecated"] = json!(true);
+    operation["x-sunset"] = json!(date);
+    if let Some(responses) = operation["responses"].as_object_mut() {
+        responses
+            .entry("410")
+            .or_insert_with(|| json!({ "description": format!("Gone: retired on {date}") }));
+    }
+}
+
 fn convert_path_format(path: &str) -> String {
     path.split('/')
-        .map(|part| {
-            if let Some(param_name) = part.strip_prefix(':') {
-                format!("{{{}}}", param_name)
-            } else if let Some(param_name) = part.strip_prefix('*') {
-                format!("{{{}}}", param_name)
-            } else {
-                part.to_string()
-            }
+        .map(|part| match parse_segment(part) {
+            Segment::Static(part) => part.to_string(),
+            Segment::Param { name, .. } | Segment::Wildcard(name) => format!("{{{}}}", name),
         })
         .collect::<Vec<_>>()
         .join("/")
 }
 
-/// Extract path parameters from a route pattern
-fn extract_path_params(path: &str) -> Vec<(&str, bool)> {
+/// Extract path parameters from a route pattern: name, whether it is a
+/// wildcard, and the schema implied by its constraint
+fn extract_path_params(path: &str) -> Vec<(&str, bool, Schema)> {
     path.split('/')
-        .filter_map(|part| {
-            if let Some(name) = part.strip_prefix(':') {
-                Some((name, false))
-            } else if let Some(name) = part.strip_prefix('*') {
-                Some((name, true))
-            } else {
-                None
-            }
+        .filter_map(|part| match parse_segment(part) {
+            Segment::Static(_) => None,
+            Segment::Param { name, constraint } => Some((name, false, param_schema(constraint))),
+            Segment::Wildcard(name) => Some((name, true, Schema::string())),
         })
         .collect()
 }
 
-fn string_param_json(name: &str, is_wildcard: bool) -> Value {
+/// `{id:u32}` is documented as an integer, `{id:uuid}` as a uuid string, ...
+fn param_schema(constraint: Option<ConstraintSpec<'_>>) -> Schema {
+    let (type_, format) = match constraint {
+        Some(ConstraintSpec::Type("i32")) => ("integer", Some("int32")),
+        Some(ConstraintSpec::Type("i64")) => ("integer", Some("int64")),
+        Some(ConstraintSpec::Type(ty)) if ty.starts_with(['u', 'i']) && ty != "uuid" => ("integer", None),
+        Some(ConstraintSpec::Type("f32")) => ("number", Some("float")),
+        Some(ConstraintSpec::Type("f64")) => ("number", Some("double")),
+        Some(ConstraintSpec::Type("bool")) => ("boolean", None),
+        Some(ConstraintSpec::Type("uuid")) => ("string", Some("uuid")),
+        _ => ("string", None),
+    };
+    Schema::typed(type_, format)
+}
+
+fn string_param_json(name: &str, is_wildcard: bool, schema: &Schema) -> Value {
     let desc = if is_wildcard {
         format!("The {} wildcard parameter", name)
     } else {
@@ -330,11 +570,18 @@ fn string_param_json(name: &str, is_wildcard: bool) -> Value {
         "name": name,
         "in": "path",
         "required": true,
-        "schema": { "type": "string" },
+        "schema": schema_json(schema),
         "description": desc
     })
 }
 
+fn schema_json(schema: &Schema) -> Value {
+    match &schema.format {
+        Some(format) => json!({ "type": schema.type_, "format": format }),
+        None => json!({ "type": schema.type_ }),
+    }
+}
+
 fn create_operation_from_custom(custom: &SwaggerInfo, path: &str) -> Value {
     let mut operation = json!({
         "summary": custom.summary,
@@ -343,7 +590,7 @@ fn create_operation_from_custom(custom: &SwaggerInfo, path: &str) -> Value {
     });
 
     let mut parameters = custom.parameters.clone();
-    for (name, is_wildcard) in extract_path_params(path) {
+    for (name, is_wildcard, schema) in extract_path_params(path) {
         if !parameters.iter().any(|p| p.name == name) {
             parameters.push(Parameter {
                 name: name.to_string(),
@@ -354,7 +601,7 @@ fn create_operation_from_custom(custom: &SwaggerInfo, path: &str) -> Value {
                     format!("The {} parameter", name)
                 }),
                 required: true,
-                schema: Schema::string(),
+                schema,
             });
         }
     }
@@ -395,7 +642,7 @@ fn create_default_operation(method: &str, path: &str) -> Value {
 
     let parameters: Vec<Value> = extract_path_params(path)
         .iter()
-        .map(|(name, is_wildcard)| string_param_json(name, *is_wildcard))
+        .map(|(name, is_wildcard, schema)| string_param_json(name, *is_wildcard, schema))
         .collect();
 
     if !parameters.is_empty() {
@@ -451,3 +698,56 @@ pub fn generate_swagger_ui(json_url: &str) -> String {
         json_url = json_url
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ToSchema;
+
+    #[allow(dead_code)]
+    #[derive(Serialize, ToSchema)]
+    #[serde(rename_all = "snake_case")]
+    enum Role {
+        Admin,
+        ReadOnly,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Serialize, ToSchema)]
+    #[serde(rename_all = "camelCase")]
+    struct User {
+        /// Stable identifier
+        id: u64,
+        display_name: String,
+        email: Option<String>,
+        roles: Vec<Role>,
+        #[serde(default)]
+        active: bool,
+        #[serde(skip)]
+        password_hash: String,
+    }
+
+    #[test]
+    fn test_derived_schema() {
+        let info = swagger().request_body_of::<User>().json_response_of::<Vec<User>>("200", "Users").build();
+        let body = serde_json::to_value(&info.request_body.unwrap().content["application/json"]).unwrap();
+        assert_eq!(
+            body,
+            json!({ "schema": {
+                "type": "object",
+                "properties": {
+                    "id": { "type": "integer", "format": "int64", "description": "Stable identifier" },
+                    "displayName": { "type": "string" },
+                    "email": { "type": "string" },
+                    "roles": { "type": "array", "items": { "type": "string", "enum": ["admin", "read_only"] } },
+                    "active": { "type": "boolean" },
+                },
+                "required": ["id", "displayName", "roles"],
+            }})
+        );
+
+        let list = &info.responses["200"].content.as_ref().unwrap()["application/json"].schema;
+        assert_eq!(list.type_, "array");
+        assert!(list.items.as_ref().unwrap().properties.as_ref().unwrap().contains_key("displayName"));
+    }
+}
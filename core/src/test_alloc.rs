@@ -0,0 +1,55 @@
+//! Per-thread allocation counting for unit tests, e.g. that routing a request
+//! allocates nothing. Installed as the test binary's global allocator with or
+//! without the `alloc-tracking` feature.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+pub(crate) struct Counting;
+
+fn count() {
+    // `try_with` because allocations also happen while thread-locals are torn down
+    let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+/// Allocations made on the current thread so far
+pub(crate) fn allocations() -> u64 {
+    ALLOCATIONS.try_with(Cell::get).unwrap_or(0)
+}
+
+#[cfg(not(feature = "alloc-tracking"))]
+#[global_allocator]
+static ALLOC: Counting = Counting;
+
+// `alloc_tracking` only counts through its own allocator, so stack the two
+#[cfg(feature = "alloc-tracking")]
+#[global_allocator]
+static ALLOC: crate::alloc_tracking::CountingAllocator<Counting> =
+    crate::alloc_tracking::CountingAllocator::new(Counting);
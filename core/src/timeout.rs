@@ -0,0 +1,109 @@
+//! Deadlines for request handling.
+//!
+//! [`Timeout`] races everything behind it against a deadline; when the
+//! deadline passes first the handler future is dropped and the client gets
+//! `504 Gateway Timeout` (or `408 Request Timeout`, see [`Timeout::status`]).
+//! [`Engine::request_timeout`](crate::Engine::request_timeout) applies the
+//! same deadline to every request, including global middleware.
+//!
+//! ```rust,ignore
+//! use s_web::timeout::Timeout;
+//! use std::time::Duration;
+//!
+//! app.request_timeout(Duration::from_secs(30));
+//! app.post("/reports", build_report)
+//!     .with_middleware(Timeout::new(Duration::from_secs(120)).middleware());
+//! ```
+//!
+//! A route deadline longer than the engine's has no effect: the outer one
+//! fires first.
+
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use hyper::StatusCode;
+
+use crate::{Next, RequestCtx, Response, ResponseBuilder, log};
+
+/// Timeout middleware configuration.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeout {
+    duration: Duration,
+    status: StatusCode,
+}
+
+impl Timeout {
+    /// Answer 504 when the request takes longer than `duration`.
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            status: StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// Status sent when the deadline passes, e.g. 408 for upload routes where
+    /// a slow client rather than a slow handler is the usual cause.
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Build the middleware function for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let timeout = Arc::new(self);
+        move |ctx, next| {
+            let timeout = timeout.clone();
+            Box::pin(async move { timeout.run(next(ctx)).await })
+        }
+    }
+
+    /// Drive `fut` to completion or answer with the timeout status
+    pub(crate) async fn run<F>(&self, fut: F) -> Response
+    where
+        F: Future<Output = Response>,
+    {
+        match tokio::time::timeout(self.duration, fut).await {
+            Ok(response) => response,
+            Err(_) => {
+                log::warn!("request timed out after {:?}", self.duration);
+                timed_out(self.status)
+            }
+        }
+    }
+}
+
+/// Connection: close because the request body may be half read
+fn timed_out(status: StatusCode) -> Response {
+    let reason = status.canonical_reason().unwrap_or("Timeout");
+    ResponseBuilder::new()
+        .status(status)
+        .content_type("text/plain; charset=utf-8")
+        .with_header("Connection", "close")
+        .build(format!("{} {reason}", status.as_u16()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let timeout = Timeout::new(Duration::from_millis(50));
+        let fast = timeout.run(async { ResponseBuilder::new().build("ok") }).await;
+        assert_eq!(fast.status(), StatusCode::OK);
+
+        let resp = timeout
+            .status(StatusCode::REQUEST_TIMEOUT)
+            .run(async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                ResponseBuilder::new().build("late")
+            })
+            .await;
+        assert_eq!(resp.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(resp.headers()["connection"], "close");
+    }
+}
@@ -0,0 +1,536 @@
+//! Interop with the tower ecosystem (`tower` feature).
+//!
+//! [`layer`] runs a tower `Layer` (from tower-http, say) as s_web middleware,
+//! [`service`] mounts a tower `Service` such as an axum router as a handler,
+//! and [`MiddlewareLayer`] goes the other way, wrapping s_web middleware for
+//! a tower stack:
+//!
+//! ```rust,ignore
+//! use s_web::tower::{MiddlewareLayer, layer, service};
+//! use tower_http::{cors::CorsLayer, trace::TraceLayer};
+//!
+//! app.use_middleware(layer(TraceLayer::new_for_http()));
+//! app.group("/api").use_middleware(layer(CorsLayer::permissive()));
+//! app.add_route("GET", "/legacy/*path", service(legacy_axum_router));
+//!
+//! let router = axum::Router::new().layer(MiddlewareLayer::new(access_log));
+//! ```
+//!
+//! [`EngineService`], from `Engine::build`, is itself a tower `Service`, so
+//! tower middleware can wrap the whole application.
+//!
+//! The request context (params, state, remote address) travels through the
+//! tower service with the request, so the rest of the chain sees it
+//! unchanged. Bodies are passed on as they are unless a layer replaces them;
+//! a replaced request body is buffered up to the body limit. Service errors
+//! answer `500`, and a response body failing midway ends the response early
+//! (the error is logged).
+
+use std::{
+    any::Any,
+    convert::Infallible,
+    future::{Future, poll_fn},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures_util::{StreamExt, future};
+use http_body_util::{BodyExt, BodyStream, LengthLimitError, Limited};
+use hyper::body::{Body, Bytes, Frame, Incoming, SizeHint};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    BoxError, DEFAULT_BODY_LIMIT, EngineService, Error, IntoResponse, Middleware, Next, PayloadTooLarge, RequestCtx,
+    Response, StatusCode, log, response::frame_body,
+};
+
+/// Request body handed to tower services: the body stream, or its bytes if a
+/// middleware already read it.
+#[derive(Debug, Default)]
+pub struct TowerBody {
+    buffered: Option<Bytes>,
+    stream: Option<Incoming>,
+}
+
+impl Body for TowerBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        let this = self.get_mut();
+        if let Some(bytes) = this.buffered.take() {
+            return Poll::Ready(Some(Ok(Frame::data(bytes))));
+        }
+        match &mut this.stream {
+            Some(stream) => Pin::new(stream).poll_frame(cx),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.buffered.is_none() && self.stream.as_ref().is_none_or(Body::is_end_stream)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match (&self.buffered, &self.stream) {
+            (Some(bytes), _) => SizeHint::with_exact(bytes.len() as u64),
+            (None, Some(stream)) => stream.size_hint(),
+            (None, None) => SizeHint::with_exact(0),
+        }
+    }
+}
+
+/// The context and chain continuation riding along in the request extensions
+#[derive(Clone)]
+struct Suspended(Arc<Mutex<Option<(RequestCtx, Next)>>>);
+
+/// Turn `ctx` into a plain request for a tower service
+fn to_request(mut ctx: RequestCtx) -> (hyper::Request<TowerBody>, RequestCtx) {
+    let (buffered, stream) = ctx.take_raw_body();
+    let (parts, ()) = std::mem::take(&mut ctx.request).into_parts();
+    (hyper::Request::from_parts(parts, TowerBody { buffered, stream }), ctx)
+}
+
+/// Body of a request coming back from tower: our own body as it was, or a
+/// replaced one read into memory
+async fn raw_body<B>(body: B, limit: usize) -> Result<(Option<Bytes>, Option<Incoming>), Response>
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    let mut slot = Some(body);
+    let any = &mut slot as &mut dyn Any;
+    if let Some(body) = any.downcast_mut::<Option<TowerBody>>().and_then(Option::take) {
+        return Ok((body.buffered, body.stream));
+    }
+    if let Some(stream) = any.downcast_mut::<Option<Incoming>>().and_then(Option::take) {
+        return Ok((None, Some(stream)));
+    }
+    let Some(body) = slot else {
+        unreachable!("the body is only taken when its type matched");
+    };
+    match Limited::new(body, limit).collect().await {
+        Ok(collected) => Ok((Some(collected.to_bytes()), None)),
+        Err(e) if e.is::<LengthLimitError>() => Err(Error::from(PayloadTooLarge { limit }).into_response()),
+        Err(e) => Err(Error::new(StatusCode::BAD_REQUEST, "failed to read request body")
+            .with_source(e)
+            .into_response()),
+    }
+}
+
+/// Response from a tower service, keeping s_web bodies as they are
+fn from_response<B>(response: hyper::Response<B>) -> Response
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    let (parts, body) = response.into_parts();
+    let mut slot = Some(body);
+    if let Some(body) = (&mut slot as &mut dyn Any)
+        .downcast_mut::<Option<crate::response::Body>>()
+        .and_then(Option::take)
+    {
+        return Response::from_parts(parts, body);
+    }
+    let Some(body) = slot else {
+        unreachable!("the body is only taken when its type matched");
+    };
+    // Our body's error type can't be constructed, so a failing body ends early
+    let frames = BodyStream::new(body).scan((), |_, frame| {
+        future::ready(match frame {
+            Ok(frame) => Some(Ok(frame)),
+            Err(e) => {
+                log::warn!("tower response body failed: {}", e.into());
+                None
+            }
+        })
+    });
+    Response::from_parts(parts, frame_body(frames))
+}
+
+/// Wait until `service` is ready, call it and convert its response
+async fn call<S, B>(mut service: S, request: hyper::Request<TowerBody>) -> Response
+where
+    S: Service<hyper::Request<TowerBody>, Response = hyper::Response<B>>,
+    S::Error: Into<BoxError>,
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    if let Err(e) = poll_fn(|cx| service.poll_ready(cx)).await {
+        return Error::internal(e).into_response();
+    }
+    match service.call(request).await {
+        Ok(response) => from_response(response),
+        Err(e) => Error::internal(e).into_response(),
+    }
+}
+
+/// The rest of the s_web chain as a tower service: what [`layer`] wraps.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NextService;
+
+impl<B> Service<hyper::Request<B>> for NextService
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: hyper::Request<B>) -> Self::Future {
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            let suspended = parts
+                .extensions
+                .remove::<Suspended>()
+                .and_then(|s| s.0.lock().unwrap_or_else(|e| e.into_inner()).take());
+            let Some((mut ctx, next)) = suspended else {
+                return Ok(Error::internal("a tower layer dropped the request extensions").into_response());
+            };
+            match raw_body(body, ctx.body_limit()).await {
+                Ok((buffered, stream)) => ctx.restore_body(buffered, stream),
+                Err(response) => return Ok(response),
+            }
+            ctx.request = hyper::Request::from_parts(parts, ());
+            Ok(next(ctx).await)
+        })
+    }
+}
+
+/// Run a tower layer as middleware, e.g.
+/// `app.use_middleware(layer(TraceLayer::new_for_http()))`.
+///
+/// The service is built once and cloned per request, so layers with shared
+/// state (concurrency or rate limits) apply across requests.
+pub fn layer<L, S, B>(
+    layer: L,
+) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>> + Send + Sync + 'static
+where
+    L: Layer<NextService, Service = S>,
+    S: Service<hyper::Request<TowerBody>, Response = hyper::Response<B>> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    // The mutex only makes the service `Sync`; it is held just to clone it
+    let service = Mutex::new(layer.layer(NextService));
+    move |ctx, next| {
+        let service = service.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let (mut request, ctx) = to_request(ctx);
+        request
+            .extensions_mut()
+            .insert(Suspended(Arc::new(Mutex::new(Some((ctx, next))))));
+        Box::pin(call(service, request))
+    }
+}
+
+/// Mount a tower service as a handler, e.g. an axum router during a migration.
+///
+/// The service sees the request's full path, not the part after the route prefix.
+pub fn service<S, B>(
+    service: S,
+) -> impl Fn(RequestCtx) -> Pin<Box<dyn Future<Output = Response> + Send>> + Send + Sync + 'static
+where
+    S: Service<hyper::Request<TowerBody>, Response = hyper::Response<B>> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    let service = Mutex::new(service);
+    move |ctx| {
+        let service = service.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let (request, _) = to_request(ctx);
+        Box::pin(call(service, request))
+    }
+}
+
+/// s_web middleware as a tower layer, for axum or any other tower stack.
+///
+/// The middleware's `next` calls the wrapped service. Requests built outside
+/// s_web have no path params, state or remote address.
+#[derive(Clone)]
+pub struct MiddlewareLayer {
+    middleware: Middleware,
+}
+
+impl MiddlewareLayer {
+    pub fn new<F, Fut>(middleware: F) -> Self
+    where
+        F: Fn(RequestCtx, Next) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        let middleware: Middleware = Arc::new(move |ctx, next| {
+            let fut = middleware(ctx, next);
+            Box::pin(fut) as Pin<Box<dyn Future<Output = Response> + Send>>
+        });
+        Self { middleware }
+    }
+}
+
+impl<S> Layer<S> for MiddlewareLayer {
+    type Service = MiddlewareService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MiddlewareService {
+            middleware: self.middleware.clone(),
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+}
+
+/// Service built by [`MiddlewareLayer`].
+pub struct MiddlewareService<S> {
+    middleware: Middleware,
+    inner: Arc<Mutex<S>>,
+}
+
+impl<S> Clone for MiddlewareService<S> {
+    fn clone(&self) -> Self {
+        Self {
+            middleware: self.middleware.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<hyper::Request<ReqBody>> for MiddlewareService<S>
+where
+    S: Service<hyper::Request<TowerBody>, Response = hyper::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    ReqBody: Body<Data = Bytes> + Send + 'static,
+    ReqBody::Error: Into<BoxError>,
+    ResBody: Body<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    /// Readiness of the inner service is awaited when `next` calls it
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: hyper::Request<ReqBody>) -> Self::Future {
+        let middleware = self.middleware.clone();
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let mut ctx = RequestCtx::from_parts(parts);
+            match raw_body(body, DEFAULT_BODY_LIMIT).await {
+                Ok((buffered, stream)) => ctx.restore_body(buffered, stream),
+                Err(response) => return Ok(response),
+            }
+            let next: Next = Arc::new(move |ctx| {
+                let service = inner.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                let (request, _) = to_request(ctx);
+                Box::pin(call(service, request))
+            });
+            Ok(middleware(ctx, next).await)
+        })
+    }
+}
+
+/// The whole application as a tower service. Bodies other than hyper's own
+/// are read into memory first, up to the engine's body limit.
+impl<B> Service<hyper::Request<B>> for EngineService
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: hyper::Request<B>) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let mut ctx = RequestCtx::from_parts(parts);
+            match raw_body(body, service.body_limit()).await {
+                Ok((buffered, stream)) => ctx.restore_body(buffered, stream),
+                Err(response) => return Ok(response),
+            }
+            Ok(service.respond(ctx).await)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResponseBuilder;
+    use http_body_util::Full;
+
+    /// Adds a response header and counts requests, like a typical tower-http layer
+    #[derive(Clone)]
+    struct StampLayer;
+
+    #[derive(Clone)]
+    struct Stamp<S>(S);
+
+    impl<S> Layer<S> for StampLayer {
+        type Service = Stamp<S>;
+
+        fn layer(&self, inner: S) -> Stamp<S> {
+            Stamp(inner)
+        }
+    }
+
+    impl<S, B> Service<hyper::Request<B>> for Stamp<S>
+    where
+        S: Service<hyper::Request<B>, Response = Response> + Send + 'static,
+        S::Future: Send + 'static,
+    {
+        type Response = Response;
+        type Error = S::Error;
+        type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+            self.0.poll_ready(cx)
+        }
+
+        fn call(&mut self, mut request: hyper::Request<B>) -> Self::Future {
+            request.headers_mut().insert("x-from-layer", "1".parse().unwrap());
+            let fut = self.0.call(request);
+            Box::pin(async move {
+                let mut response = fut.await?;
+                response.headers_mut().insert("x-stamped", "yes".parse().unwrap());
+                Ok(response)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_layer_keeps_context_and_body() {
+        let mw = layer(StampLayer);
+        let mut ctx = RequestCtx::from_parts(
+            hyper::Request::post("/users/7").body(()).unwrap().into_parts().0,
+        );
+        ctx.restore_body(Some(Bytes::from_static(b"hello")), None);
+        ctx.add_param("id".into(), "7".into());
+        let next: Next = Arc::new(|mut ctx: RequestCtx| {
+            Box::pin(async move {
+                let body = ctx.body_string().await.unwrap().unwrap_or_default();
+                let seen = format!(
+                    "{} {} {}",
+                    ctx.get_param("id").unwrap(),
+                    ctx.header("x-from-layer").unwrap(),
+                    body
+                );
+                ResponseBuilder::new().build(seen)
+            })
+        });
+        let response = mw(ctx, next).await;
+        assert_eq!(response.headers()["x-stamped"], "yes");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "7 1 hello");
+    }
+
+    #[tokio::test]
+    async fn test_middleware_layer_wraps_tower_service() {
+        let inner = tower_service_fn(|request: hyper::Request<TowerBody>| async move {
+            let body = request.into_body().collect().await?.to_bytes();
+            Ok::<_, hyper::Error>(hyper::Response::new(Full::new(body)))
+        });
+        let mut service = MiddlewareLayer::new(|mut ctx: RequestCtx, next: Next| async move {
+            if ctx.header("authorization").is_none() {
+                return ResponseBuilder::new().status(StatusCode::UNAUTHORIZED).build("");
+            }
+            ctx.set_header("x-user", "alice").unwrap();
+            next(ctx).await
+        })
+        .layer(inner);
+
+        let request = hyper::Request::new(Full::new(Bytes::from_static(b"ping")));
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let request = hyper::Request::builder()
+            .header("authorization", "Bearer t")
+            .body(Full::new(Bytes::from_static(b"ping")))
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "ping");
+    }
+
+    #[tokio::test]
+    async fn test_service_as_handler() {
+        let handler = service(tower_service_fn(|request: hyper::Request<TowerBody>| async move {
+            let path = request.uri().path().to_string();
+            Ok::<_, Infallible>(hyper::Response::new(Full::new(Bytes::from(path))))
+        }));
+        let ctx = RequestCtx::from_parts(hyper::Request::get("/legacy/a/b").body(()).unwrap().into_parts().0);
+        let body = handler(ctx).await.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "/legacy/a/b");
+    }
+
+    #[tokio::test]
+    async fn test_built_engine_under_tower_layer() {
+        let mut engine = crate::Engine::new();
+        engine.post("/users/:id", |mut ctx: RequestCtx| async move {
+            let body = ctx.body_string().await?.unwrap_or_default();
+            let seen = format!("{} {} {body}", ctx.get_param("id").unwrap(), ctx.header("x-from-layer").unwrap());
+            Ok::<_, Error>(seen)
+        });
+        let mut app = StampLayer.layer(engine.build());
+
+        poll_fn(|cx| Service::<hyper::Request<Full<Bytes>>>::poll_ready(&mut app, cx)).await.unwrap();
+        let request = hyper::Request::post("/users/7").body(Full::new(Bytes::from_static(b"hello"))).unwrap();
+        let response = app.call(request).await.unwrap();
+        assert_eq!(response.headers()["x-stamped"], "yes");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "7 1 hello");
+
+        let request = hyper::Request::get("/missing").body(Full::new(Bytes::new())).unwrap();
+        assert_eq!(app.call(request).await.unwrap().status(), StatusCode::NOT_FOUND);
+    }
+
+    /// `tower::service_fn` without depending on tower
+    fn tower_service_fn<F, Fut, T, E>(f: F) -> ServiceFn<F>
+    where
+        F: FnMut(hyper::Request<TowerBody>) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        ServiceFn(f)
+    }
+
+    #[derive(Clone)]
+    struct ServiceFn<F>(F);
+
+    impl<F, Fut, T, E> Service<hyper::Request<TowerBody>> for ServiceFn<F>
+    where
+        F: FnMut(hyper::Request<TowerBody>) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        type Response = T;
+        type Error = E;
+        type Future = Fut;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: hyper::Request<TowerBody>) -> Fut {
+            (self.0)(request)
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Request tracing with [`tracing`] spans.
+//!
+//! With the `tracing` feature the engine runs every request inside a
+//! `request` span carrying `method`, `path`, `request_id` (from
+//! `X-Request-Id`), `status` and `latency_ms`, and the framework's own log
+//! lines become `tracing` events. Install any subscriber to see them:
+//!
+//! ```rust,ignore
+//! tracing_subscriber::fmt().with_env_filter("info").init();
+//! ```
+//!
+//! [`Trace`] adds events (or a span of its own) around part of the chain,
+//! with callbacks for the start and the end of each request:
+//!
+//! ```rust,ignore
+//! use s_web::trace::Trace;
+//!
+//! app.use_middleware(
+//!     Trace::new()
+//!         .on_request(|ctx, _span| tracing::info!(user_agent = ctx.header("user-agent"), "started"))
+//!         .on_response(|response, latency, _span| {
+//!             tracing::info!(status = response.status().as_u16(), ?latency, "finished")
+//!         })
+//!         .middleware(),
+//! );
+//! app.group("/api").use_middleware(
+//!     Trace::new()
+//!         .make_span(|ctx| tracing::info_span!("api", tenant = ctx.header("x-tenant")))
+//!         .middleware(),
+//! );
+//! ```
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tracing::{Instrument, Span, field::Empty};
+
+use crate::{Next, RequestCtx, Response};
+
+type MakeSpan = Arc<dyn Fn(&RequestCtx) -> Span + Send + Sync>;
+type OnRequest = Arc<dyn Fn(&RequestCtx, &Span) + Send + Sync>;
+type OnResponse = Arc<dyn Fn(&Response, Duration, &Span) + Send + Sync>;
+
+/// Tracing middleware configuration.
+#[derive(Clone)]
+pub struct Trace {
+    make_span: MakeSpan,
+    on_request: OnRequest,
+    on_response: OnResponse,
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Trace {
+    /// Log a debug event when a request arrives and an info event with its
+    /// status and latency when it is answered, inside the engine's span.
+    pub fn new() -> Self {
+        Self {
+            make_span: Arc::new(|_| Span::current()),
+            on_request: Arc::new(|_, _| tracing::debug!("started processing request")),
+            on_response: Arc::new(|response, latency, _| {
+                tracing::info!(
+                    status = response.status().as_u16(),
+                    latency_ms = latency.as_secs_f64() * 1000.0,
+                    "finished processing request"
+                )
+            }),
+        }
+    }
+
+    /// Span the rest of the chain runs in (default: the current one, i.e.
+    /// the engine's `request` span).
+    pub fn make_span(mut self, f: impl Fn(&RequestCtx) -> Span + Send + Sync + 'static) -> Self {
+        self.make_span = Arc::new(f);
+        self
+    }
+
+    /// Called inside the span before the request is passed on.
+    pub fn on_request(mut self, f: impl Fn(&RequestCtx, &Span) + Send + Sync + 'static) -> Self {
+        self.on_request = Arc::new(f);
+        self
+    }
+
+    /// Called inside the span with the response and the time it took.
+    pub fn on_response(
+        mut self,
+        f: impl Fn(&Response, Duration, &Span) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_response = Arc::new(f);
+        self
+    }
+
+    /// Build the middleware function for `use_middleware` or `with_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let trace = Arc::new(self);
+        move |ctx, next| {
+            let trace = trace.clone();
+            Box::pin(async move {
+                let span = (trace.make_span)(&ctx);
+                span.in_scope(|| (trace.on_request)(&ctx, &span));
+                let start = Instant::now();
+                let response = next(ctx).instrument(span.clone()).await;
+                span.in_scope(|| (trace.on_response)(&response, start.elapsed(), &span));
+                response
+            })
+        }
+    }
+}
+
+/// Span and timing of one request, from arrival to response
+pub(crate) struct RequestTrace {
+    pub(crate) span: Span,
+    started: Instant,
+    #[cfg(feature = "otel")]
+    propagation: Option<crate::otel::Propagation>,
+}
+
+impl RequestTrace {
+    /// Open the `request` span; `status` and `latency_ms` are recorded by [`Self::finish`]
+    pub(crate) fn start<B>(req: &mut hyper::Request<B>) -> Self {
+        let request_id = req.headers().get("x-request-id").and_then(|v| v.to_str().ok());
+        #[cfg(not(feature = "otel"))]
+        let span = tracing::info_span!(
+            "request",
+            method = %req.method(),
+            path = req.uri().path(),
+            request_id,
+            status = Empty,
+            latency_ms = Empty,
+        );
+        #[cfg(feature = "otel")]
+        let span = tracing::info_span!(
+            "request",
+            otel.name = %req.method(),
+            otel.kind = "server",
+            method = %req.method(),
+            path = req.uri().path(),
+            http.route = Empty,
+            request_id,
+            status = Empty,
+            latency_ms = Empty,
+        );
+
+        #[cfg(feature = "otel")]
+        let propagation = {
+            let remote = crate::otel::extract(req.headers());
+            crate::otel::set_parent(&span, remote.as_ref());
+            let propagation = remote.as_ref().map(|remote| remote.propagation);
+            if let Some(remote) = remote {
+                req.extensions_mut().insert(remote);
+            }
+            req.extensions_mut().insert(crate::otel::RequestSpan(span.clone()));
+            propagation
+        };
+        Self {
+            span,
+            started: Instant::now(),
+            #[cfg(feature = "otel")]
+            propagation,
+        }
+    }
+
+    pub(crate) fn finish(&self, response: &mut Response) {
+        self.span.record("status", response.status().as_u16());
+        self.span.record("latency_ms", self.started.elapsed().as_secs_f64() * 1000.0);
+        #[cfg(feature = "otel")]
+        crate::otel::inject_response(&self.span, self.propagation, response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{ResponseBuilder, StatusCode};
+
+    #[tokio::test]
+    async fn test_callbacks_see_request_and_response() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let (on_request, on_response) = (seen.clone(), seen.clone());
+        let mw = Trace::new()
+            .make_span(|ctx| tracing::info_span!("test", path = ctx.request.uri().path()))
+            .on_request(move |ctx, _| {
+                on_request.lock().unwrap().push(format!("{} {}", ctx.request.method(), ctx.request.uri()))
+            })
+            .on_response(move |response, latency, _| {
+                assert!(latency >= Duration::from_millis(20));
+                on_response.lock().unwrap().push(response.status().to_string())
+            })
+            .middleware();
+
+        let ctx = RequestCtx::from_parts(hyper::Request::post("/orders").body(()).unwrap().into_parts().0);
+        let next: Next = Arc::new(|_| {
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                ResponseBuilder::new().status(StatusCode::CREATED).build("")
+            })
+        });
+        let response = mw(ctx, next).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(*seen.lock().unwrap(), ["POST /orders", "201 Created"]);
+    }
+}
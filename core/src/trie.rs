@@ -1,23 +1,179 @@
 //! Trie data structure for efficient route matching.
+//!
+//! Static children are found through a hash map and only `:param` and
+//! `*wildcard` children are scanned, so a lookup costs one map probe per
+//! path segment however many routes share a prefix.
 
+use std::{collections::HashMap, sync::Arc};
+
+use regex::Regex;
+
+use crate::log;
+
+/// One segment of a route pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Segment<'a> {
+    Static(&'a str),
+    /// `:name`, `:name<regex>`, `{name}` or `{name:type}`
+    Param { name: &'a str, constraint: Option<ConstraintSpec<'a>> },
+    /// `*name`, capturing the rest of the path
+    Wildcard(&'a str),
+}
+
+/// Constraint as written in the pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ConstraintSpec<'a> {
+    Regex(&'a str),
+    Type(&'a str),
+}
+
+/// Types usable in `{name:type}` segments
+const PARAM_TYPES: [&str; 19] = [
+    "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize", "f32",
+    "f64", "bool", "uuid", "alpha", "alnum", "str",
+];
+
+pub(crate) fn parse_segment(part: &str) -> Segment<'_> {
+    if let Some(name) = part.strip_prefix('*') {
+        return Segment::Wildcard(name);
+    }
+    if let Some(param) = part.strip_prefix(':') {
+        if let Some((name, rest)) = param.split_once('<')
+            && let Some(regex) = rest.strip_suffix('>')
+        {
+            return Segment::Param {
+                name,
+                constraint: Some(ConstraintSpec::Regex(regex)),
+            };
+        }
+        return Segment::Param {
+            name: param,
+            constraint: None,
+        };
+    }
+    if let Some(inner) = part.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+        return match inner.split_once(':') {
+            Some((name, ty)) => Segment::Param {
+                name,
+                constraint: Some(ConstraintSpec::Type(ty)),
+            },
+            None => Segment::Param {
+                name: inner,
+                constraint: None,
+            },
+        };
+    }
+    Segment::Static(part)
+}
+
+/// Compiled parameter constraint; segments failing it fall through to other routes
+#[derive(Debug, Clone)]
+enum Constraint {
+    Regex(Regex),
+    Type(&'static str),
+    /// Invalid constraint: reported at registration, never matches
+    Invalid,
+}
+
+impl Constraint {
+    fn compile(pattern: &str, spec: ConstraintSpec<'_>) -> Self {
+        match spec {
+            ConstraintSpec::Regex(regex) => match Regex::new(&format!("^(?:{regex})$")) {
+                Ok(regex) => Constraint::Regex(regex),
+                Err(e) => {
+                    log::warn!("route \"{pattern}\": invalid parameter regex <{regex}>: {e}");
+                    Constraint::Invalid
+                }
+            },
+            ConstraintSpec::Type(ty) => match PARAM_TYPES.iter().find(|t| **t == ty) {
+                Some(ty) => Constraint::Type(ty),
+                None => {
+                    log::warn!(
+                        "route \"{pattern}\": unknown parameter type `{ty}` (expected one of {})",
+                        PARAM_TYPES.join(", ")
+                    );
+                    Constraint::Invalid
+                }
+            },
+        }
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Constraint::Regex(regex) => regex.is_match(value),
+            Constraint::Type(ty) => value_has_type(ty, value),
+            Constraint::Invalid => false,
+        }
+    }
+}
+
+fn value_has_type(ty: &str, value: &str) -> bool {
+    match ty {
+        "u8" => value.parse::<u8>().is_ok(),
+        "u16" => value.parse::<u16>().is_ok(),
+        "u32" => value.parse::<u32>().is_ok(),
+        "u64" => value.parse::<u64>().is_ok(),
+        "u128" => value.parse::<u128>().is_ok(),
+        "usize" => value.parse::<usize>().is_ok(),
+        "i8" => value.parse::<i8>().is_ok(),
+        "i16" => value.parse::<i16>().is_ok(),
+        "i32" => value.parse::<i32>().is_ok(),
+        "i64" => value.parse::<i64>().is_ok(),
+        "i128" => value.parse::<i128>().is_ok(),
+        "isize" => value.parse::<isize>().is_ok(),
+        "f32" => value.parse::<f32>().is_ok(),
+        "f64" => value.parse::<f64>().is_ok(),
+        "bool" => value.parse::<bool>().is_ok(),
+        "uuid" => is_uuid(value),
+        "alpha" => !value.is_empty() && value.chars().all(char::is_alphabetic),
+        "alnum" => !value.is_empty() && value.chars().all(char::is_alphanumeric),
+        _ => true,
+    }
+}
+
+/// Hyphenated 8-4-4-4-12 hex form
+fn is_uuid(value: &str) -> bool {
+    let groups: Vec<&str> = value.split('-').collect();
+    groups.len() == 5
+        && groups.iter().map(|g| g.len()).eq([8, 4, 4, 4, 12])
+        && groups.iter().all(|g| g.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+#[derive(Clone)]
 pub struct Node<T> {
-    pattern: String,
+    /// Shared so a matched route can be named without copying it per request
+    pattern: Arc<str>,
     part: String,
     children: Vec<Node<T>>,
+    /// Index into `children` of each static child, by segment
+    statics: HashMap<String, usize>,
+    /// Indices into `children` of `:param` and `*wildcard` children
+    dynamic: Vec<usize>,
     iswild: bool,
+    constraint: Option<Constraint>,
     value: Option<T>,
-    params: Vec<(usize, String)>,
+    /// Segment index and name of each parameter in `pattern`
+    params: Vec<(usize, Arc<str>)>,
+    /// Segment index of the `*wildcard` in `pattern`
+    wildcard: Option<usize>,
+    /// Number of segments in `pattern`
+    segments: usize,
 }
 
 impl<T> Default for Node<T> {
     fn default() -> Self {
         Self {
-            pattern: String::new(),
+            pattern: Arc::default(),
             part: String::new(),
             children: Vec::new(),
+            statics: HashMap::new(),
+            dynamic: Vec::new(),
             iswild: false,
+            constraint: None,
             value: None,
             params: Vec::new(),
+            wildcard: None,
+            segments: 0,
         }
     }
 }
@@ -29,6 +185,7 @@ impl<T> std::fmt::Debug for Node<T> {
             .field("part", &self.part)
             .field("children", &self.children)
             .field("iswild", &self.iswild)
+            .field("constraint", &self.constraint)
             .field("params", &self.params)
             .finish()
     }
@@ -42,29 +199,42 @@ impl<T> Node<T> {
 
     #[allow(dead_code)]
     fn match_child(&self, path: &str) -> Option<&Node<T>> {
-        self.children
-            .iter()
-            .find(|child| child.part == path || child.iswild)
+        self.match_children(path).next()
     }
 
     fn match_child_mut(&mut self, path: &str) -> Option<&mut Node<T>> {
-        self.children.iter_mut().find(|child| child.part == path)
+        let index = match self.statics.get(path) {
+            Some(index) => *index,
+            None => *self.dynamic.iter().find(|index| self.children[**index].part == path)?,
+        };
+        Some(&mut self.children[index])
     }
 
-    fn match_children(&self, path: &str) -> Vec<&Node<T>> {
-        self.children
-            .iter()
-            .filter(|&child| child.part == path || child.iswild)
-            .collect()
+    /// Children that can match `path`, in priority order: the exact segment,
+    /// then constrained params that accept it, then plain params, then
+    /// `*wildcards`, whatever order they were registered in
+    fn match_children<'a>(&'a self, path: &str) -> impl Iterator<Item = &'a Node<T>> {
+        let exact = self.statics.get(path).map(|index| &self.children[*index]);
+        let dynamic = || self.dynamic.iter().map(|index| &self.children[*index]);
+        let is_param = |child: &&Node<T>| !child.part.starts_with('*');
+        let constrained = dynamic()
+            .filter(is_param)
+            .filter(move |child| child.constraint.as_ref().is_some_and(|c| c.matches(path)));
+        let params = dynamic().filter(is_param).filter(|child| child.constraint.is_none());
+        let wildcards = dynamic().filter(|child| child.part.starts_with('*'));
+        exact.into_iter().chain(constrained).chain(params).chain(wildcards)
     }
 
     // --- Getters for encapsulated fields ---
 
-    #[allow(dead_code)]
     pub fn pattern(&self) -> &str {
         &self.pattern
     }
 
+    pub fn shared_pattern(&self) -> &Arc<str> {
+        &self.pattern
+    }
+
     #[allow(dead_code)]
     pub fn part(&self) -> &str {
         &self.part
@@ -79,10 +249,24 @@ impl<T> Node<T> {
         self.value.as_ref()
     }
 
-    pub fn params(&self) -> &[(usize, String)] {
+    pub fn value_mut(&mut self) -> Option<&mut T> {
+        self.value.as_mut()
+    }
+
+    pub fn params(&self) -> &[(usize, Arc<str>)] {
         &self.params
     }
 
+    pub fn wildcard(&self) -> Option<usize> {
+        self.wildcard
+    }
+
+    /// Number of segments in the registered pattern; a path matched through a
+    /// `*wildcard` has more when the wildcard spans several segments
+    pub fn segments(&self) -> usize {
+        self.segments
+    }
+
     #[allow(dead_code)]
     pub fn children(&self) -> &[Node<T>] {
         &self.children
@@ -92,30 +276,29 @@ impl<T> Node<T> {
         if height == parts.len() {
             // Warn on route conflict (e.g. registering the same pattern twice,
             // or two dynamic patterns that share the same structure like /:a and /:b).
-            if !self.pattern.is_empty() && self.pattern != pattern {
-                eprintln!(
-                    "[s_web] route conflict: \"{}\" overwrites \"{}\"",
+            if !self.pattern.is_empty() && &*self.pattern != pattern {
+                log::warn!(
+                    "route conflict: \"{}\" overwrites \"{}\"",
                     pattern, self.pattern
                 );
             } else if self.value.is_some() {
-                eprintln!(
-                    "[s_web] route conflict: \"{}\" registered more than once",
+                log::warn!(
+                    "route conflict: \"{}\" registered more than once",
                     pattern
                 );
             }
-            self.pattern = pattern.to_string();
+            self.pattern = Arc::from(pattern);
             self.value = Some(handler);
+            self.segments = parts.len();
             self.params = parts
                 .iter()
                 .enumerate()
-                .filter_map(|(i, part)| {
-                    if part.starts_with(':') || part.starts_with('*') {
-                        Some((i, part.to_string()))
-                    } else {
-                        None
-                    }
+                .filter_map(|(i, part)| match parse_segment(part) {
+                    Segment::Static(_) => None,
+                    Segment::Param { name, .. } | Segment::Wildcard(name) => Some((i, name.into())),
                 })
                 .collect();
+            self.wildcard = parts.iter().position(|part| matches!(parse_segment(part), Segment::Wildcard(_)));
             return;
         }
 
@@ -123,41 +306,110 @@ impl<T> Node<T> {
         if let Some(child) = self.match_child_mut(part) {
             child.insert(pattern, parts, height + 1, handler);
         } else {
+            let segment = parse_segment(part);
+            let constraint = match segment {
+                Segment::Param {
+                    constraint: Some(spec),
+                    ..
+                } => Some(Constraint::compile(pattern, spec)),
+                _ => None,
+            };
             let mut new_node = Node {
-                pattern: String::new(),
+                pattern: Arc::default(),
                 part: part.to_string(),
                 children: Vec::new(),
-                iswild: part.starts_with(':') || part.starts_with('*'),
+                statics: HashMap::new(),
+                dynamic: Vec::new(),
+                iswild: !matches!(segment, Segment::Static(_)),
+                constraint,
                 value: None,
                 params: Vec::new(),
+                wildcard: None,
+                segments: 0,
             };
             new_node.insert(pattern, parts, height + 1, handler);
+            if new_node.iswild {
+                self.dynamic.push(self.children.len());
+            } else {
+                self.statics.insert(new_node.part.clone(), self.children.len());
+            }
             self.children.push(new_node);
         }
     }
 
     pub fn search(&self, parts: &[&str], height: usize) -> Option<&Node<T>> {
-        if height == parts.len() || self.part.starts_with('*') {
-            return if self.pattern.is_empty() {
-                None
-            } else {
-                Some(self)
-            };
+        if self.part.starts_with('*') {
+            // The wildcard has taken one segment; it swallows as many more as
+            // a suffix like `/*path/meta` allows, or the whole rest of the path
+            return (height..parts.len())
+                .rev()
+                .find_map(|end| self.search_children(parts, end))
+                .or_else(|| self.registered());
+        }
+        if height == parts.len() {
+            return self.registered();
         }
+        self.search_children(parts, height)
+    }
 
-        let part = parts[height];
-        for child in self.match_children(part) {
-            if let Some(result) = child.search(parts, height + 1) {
-                return Some(result);
-            }
+    fn search_children(&self, parts: &[&str], height: usize) -> Option<&Node<T>> {
+        self.match_children(parts[height])
+            .find_map(|child| child.search(parts, height + 1))
+    }
+
+    fn registered(&self) -> Option<&Node<T>> {
+        (!self.pattern.is_empty()).then_some(self)
+    }
+
+    /// Find the node registered for exactly these pattern parts (no wildcard matching)
+    pub fn find_mut(&mut self, parts: &[&str], height: usize) -> Option<&mut Node<T>> {
+        if height == parts.len() {
+            return Some(self);
+        }
+        self.match_child_mut(parts[height])?.find_mut(parts, height + 1)
+    }
+
+    /// Unregister the route held by this node; its children stay
+    pub fn take_value(&mut self) -> Option<T> {
+        if self.pattern.is_empty() {
+            return None;
+        }
+        self.pattern = Arc::default();
+        self.params.clear();
+        self.wildcard = None;
+        self.segments = 0;
+        self.value.take()
+    }
+
+    /// Collect every node that holds a registered route
+    pub fn collect_nodes<'a>(&'a self, nodes: &mut Vec<&'a Node<T>>) {
+        if !self.pattern.is_empty() {
+            nodes.push(self);
+        }
+
+        for child in &self.children {
+            child.collect_nodes(nodes);
+        }
+    }
+
+    /// Take every registered `(pattern, value)` out of this node and its children
+    pub fn into_values(self, values: &mut Vec<(String, T)>) {
+        if let Some(value) = self.value
+            && !self.pattern.is_empty()
+        {
+            values.push((self.pattern.to_string(), value));
+        }
+
+        for child in self.children {
+            child.into_values(values);
         }
-        None
     }
 
     /// Collect all patterns from this node and its children
+    #[allow(dead_code)]
     pub fn collect_patterns(&self, patterns: &mut Vec<String>) {
         if !self.pattern.is_empty() {
-            patterns.push(self.pattern.clone());
+            patterns.push(self.pattern.to_string());
         }
 
         for child in &self.children {
@@ -213,6 +465,83 @@ mod tests {
 
         let result = root.search(&["static", "js", "app.js"], 0);
         assert!(result.is_some());
-        assert_eq!(result.unwrap().pattern, "/static/*filepath");
+        assert_eq!(&*result.unwrap().pattern, "/static/*filepath");
+    }
+
+    fn root_with(patterns: &[&str]) -> Node<()> {
+        let mut root = Node::<()>::new();
+        for pattern in patterns {
+            let parts: Vec<&str> = pattern.split('/').filter(|p| !p.is_empty()).collect();
+            root.insert(pattern, &parts, 0, ());
+        }
+        root
+    }
+
+    #[test]
+    fn test_static_beats_param_beats_wildcard_in_any_order() {
+        let patterns = ["/users/*rest", "/users/:id", "/users/new"];
+        for order in [[0, 1, 2], [2, 1, 0], [1, 0, 2], [1, 2, 0]] {
+            let root = root_with(&order.map(|i| patterns[i]));
+            let found = |path: &[&str]| root.search(path, 0).map(|n| n.pattern().to_string());
+            assert_eq!(found(&["users", "new"]).as_deref(), Some("/users/new"), "{order:?}");
+            assert_eq!(found(&["users", "42"]).as_deref(), Some("/users/:id"), "{order:?}");
+            assert_eq!(found(&["users", "42", "x"]).as_deref(), Some("/users/*rest"), "{order:?}");
+        }
+    }
+
+    #[test]
+    fn test_constrained_params_fall_through() {
+        let root = root_with(&["/users/:slug", r"/users/:id<\d+>", "/files/{id:uuid}", "/files/{n:u8}"]);
+        let found = |path: &[&str]| root.search(path, 0).map(|n| n.pattern().to_string());
+        assert_eq!(found(&["users", "42"]).as_deref(), Some(r"/users/:id<\d+>"));
+        assert_eq!(found(&["users", "bob"]).as_deref(), Some("/users/:slug"));
+        assert_eq!(found(&["files", "255"]).as_deref(), Some("/files/{n:u8}"));
+        assert_eq!(found(&["files", "256"]), None);
+        assert_eq!(
+            found(&["files", "67e55044-10b1-426f-9247-bb680e5fe0c8"]).as_deref(),
+            Some("/files/{id:uuid}")
+        );
+        assert_eq!(root.search(&["users", "42"], 0).unwrap().params(), [(1, "id".into())]);
+        assert_eq!(
+            parse_segment("{id:u32}"),
+            Segment::Param { name: "id", constraint: Some(ConstraintSpec::Type("u32")) }
+        );
+    }
+
+    #[test]
+    fn test_mid_path_wildcard() {
+        let root = root_with(&["/files/*path", "/files/*path/meta", "/files/*path/v/:version"]);
+        let found = |path: &[&str]| root.search(path, 0).map(|n| n.pattern().to_string());
+        assert_eq!(found(&["files", "a", "b", "meta"]).as_deref(), Some("/files/*path/meta"));
+        assert_eq!(found(&["files", "meta", "meta"]).as_deref(), Some("/files/*path/meta"));
+        assert_eq!(found(&["files", "a", "v", "2"]).as_deref(), Some("/files/*path/v/:version"));
+        assert_eq!(found(&["files", "a", "meta", "b"]).as_deref(), Some("/files/*path"));
+        assert_eq!(found(&["files", "meta"]).as_deref(), Some("/files/*path"));
+        assert_eq!(found(&["files"]), None);
+    }
+
+    #[test]
+    fn test_falls_back_when_static_branch_dead_ends() {
+        let root = root_with(&["/users/new", "/users/:id/posts"]);
+        let found = root.search(&["users", "new", "posts"], 0).map(|n| n.pattern());
+        assert_eq!(found, Some("/users/:id/posts"));
+    }
+
+    #[test]
+    fn test_large_route_table() {
+        let patterns: Vec<String> = (0..5000)
+            .flat_map(|i| [format!("/api/res{i}"), format!("/api/res{i}/:id"), format!("/api/res{i}/:id/*rest")])
+            .collect();
+        let patterns: Vec<&str> = patterns.iter().map(String::as_str).collect();
+        let mut root = root_with(&patterns);
+        root.insert("/api/:name", &["api", ":name"], 0, ());
+
+        let found = |path: &[&str]| root.search(path, 0).map(|n| n.pattern().to_string());
+        assert_eq!(found(&["api", "res4999"]).as_deref(), Some("/api/res4999"));
+        assert_eq!(found(&["api", "res17", "9"]).as_deref(), Some("/api/res17/:id"));
+        assert_eq!(found(&["api", "res17", "9", "a", "b"]).as_deref(), Some("/api/res17/:id/*rest"));
+        assert_eq!(found(&["api", "other"]).as_deref(), Some("/api/:name"));
+        assert_eq!(found(&["api", "other", "9"]), None);
+        assert!(root.find_mut(&["api", "res42", ":id"], 0).is_some());
     }
 }
@@ -0,0 +1,231 @@
+//! Request-scoped sqlx transactions (`sqlx` feature).
+//!
+//! Install [`transaction`] on the routes that write, register the pool with
+//! `Engine::with_state`, and ask for the transaction inside the handler. It is
+//! only opened on first use, committed when the response is 2xx and rolled
+//! back otherwise.
+//!
+//! ```rust,ignore
+//! use s_web::tx::transaction;
+//! use sqlx::Sqlite;
+//!
+//! let mut app = Engine::new().with_state(pool);
+//! app.post("/products", create_product)
+//!     .with_middleware(transaction::<Sqlite>());
+//!
+//! async fn create_product(ctx: RequestCtx) -> Response {
+//!     let Ok(mut tx) = ctx.tx::<Sqlite>().await else {
+//!         return "500 Internal Server Error".into_response();
+//!     };
+//!     sqlx::query("INSERT INTO products (name) VALUES (?)")
+//!         .bind("widget")
+//!         .execute(&mut **tx)
+//!         .await
+//!         .ok();
+//!     // A non-2xx response here would roll the insert back.
+//!     "created".into_response()
+//! }
+//! ```
+
+use std::{
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::Arc,
+};
+
+use sqlx::{Database, Pool, Transaction};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::{Next, RequestCtx, Response, ResponseBuilder, StatusCode, log};
+
+type Slot<DB> = Arc<Mutex<Option<Transaction<'static, DB>>>>;
+
+/// Stored in the request extensions by [`transaction`].
+struct TxSlot<DB: Database> {
+    pool: Pool<DB>,
+    tx: Slot<DB>,
+}
+
+impl<DB: Database> Clone for TxSlot<DB> {
+    fn clone(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+/// The request's open transaction.
+///
+/// Dereferences to [`sqlx::Transaction`], so queries run with `&mut **tx`.
+/// Commit and rollback are handled by the middleware; just drop it when done.
+pub struct Tx<DB: Database> {
+    guard: OwnedMutexGuard<Option<Transaction<'static, DB>>>,
+}
+
+impl<DB: Database> Deref for Tx<DB> {
+    type Target = Transaction<'static, DB>;
+
+    fn deref(&self) -> &Self::Target {
+        self.guard.as_ref().expect("transaction is open while Tx is alive")
+    }
+}
+
+impl<DB: Database> DerefMut for Tx<DB> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.guard.as_mut().expect("transaction is open while Tx is alive")
+    }
+}
+
+impl RequestCtx {
+    /// The request-scoped transaction, begun from the registered `Pool<DB>` on first use.
+    ///
+    /// Fails if the route has no [`transaction`] middleware or the pool was
+    /// not registered as state. Hold at most one `Tx` at a time; a second call
+    /// waits until the first is dropped.
+    pub async fn tx<DB: Database>(&self) -> Result<Tx<DB>, sqlx::Error> {
+        let slot = self
+            .request
+            .extensions()
+            .get::<TxSlot<DB>>()
+            .cloned()
+            .ok_or_else(|| {
+                sqlx::Error::Configuration(
+                    "no transaction on this route; add the `transaction` middleware".into(),
+                )
+            })?;
+        let mut guard = slot.tx.lock_owned().await;
+        if guard.is_none() {
+            *guard = Some(slot.pool.begin().await?);
+        }
+        Ok(Tx { guard })
+    }
+}
+
+/// Middleware that scopes a lazily opened transaction to the request.
+///
+/// The transaction commits when the handler answers 2xx and rolls back
+/// otherwise. A failed commit turns the response into a 500. Requests pass
+/// through untouched (with a warning) if no `Pool<DB>` is registered.
+pub fn transaction<DB: Database>()
+-> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>> + Send + Sync + 'static
+{
+    move |mut ctx, next| {
+        Box::pin(async move {
+            let Some(pool) = ctx.state::<Pool<DB>>().cloned() else {
+                log::warn!("transaction middleware: no Pool registered with Engine::with_state");
+                return next(ctx).await;
+            };
+            let tx: Slot<DB> = Arc::new(Mutex::new(None));
+            ctx.request.extensions_mut().insert(TxSlot {
+                pool,
+                tx: tx.clone(),
+            });
+
+            let response = next(ctx).await;
+
+            let Some(open) = tx.lock().await.take() else {
+                return response;
+            };
+            if response.status().is_success() {
+                if let Err(e) = open.commit().await {
+                    log::warn!("transaction commit failed: {e}");
+                    return ResponseBuilder::new()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .content_type("text/plain; charset=utf-8")
+                        .build("500 Internal Server Error");
+                }
+            } else if let Err(e) = open.rollback().await {
+                log::warn!("transaction rollback failed: {e}");
+            }
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{Sqlite, SqlitePool, sqlite::SqlitePoolOptions};
+
+    use super::*;
+    use crate::{Engine, StatusCode};
+
+    /// One connection, so every query sees the same in-memory database
+    async fn pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE items (name TEXT NOT NULL)").execute(&pool).await.unwrap();
+        pool
+    }
+
+    async fn insert(ctx: &RequestCtx, name: &str) {
+        let mut tx = ctx.tx::<Sqlite>().await.unwrap();
+        sqlx::query("INSERT INTO items (name) VALUES (?)").bind(name).execute(&mut **tx).await.unwrap();
+    }
+
+    async fn names(pool: &SqlitePool) -> Vec<String> {
+        sqlx::query_scalar("SELECT name FROM items ORDER BY name").fetch_all(pool).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_commits_on_success_and_rolls_back_otherwise() {
+        let pool = pool().await;
+        let mut app = Engine::new().with_state(pool.clone());
+        app.post("/ok", |ctx: RequestCtx| async move {
+            insert(&ctx, "kept").await;
+            "created"
+        })
+        .with_middleware(transaction::<Sqlite>());
+        app.post("/rejected", |ctx: RequestCtx| async move {
+            insert(&ctx, "rejected").await;
+            crate::Error::from_status(StatusCode::UNPROCESSABLE_ENTITY)
+        })
+        .with_middleware(transaction::<Sqlite>());
+        app.post("/panics", |ctx: RequestCtx| async move {
+            insert(&ctx, "panicked").await;
+            panic!("handler failed mid-transaction");
+            #[allow(unreachable_code)]
+            "unreachable"
+        })
+        .with_middleware(transaction::<Sqlite>());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(app.run_with_listener(listener));
+
+        let client = crate::client::Client::new();
+        let response = client.post(&format!("{base}/ok")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = client.post(&format!("{base}/rejected")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        // The connection dies with the handler; dropping the transaction rolls it back
+        assert!(client.post(&format!("{base}/panics")).send().await.is_err());
+
+        assert_eq!(names(&pool).await, ["kept"]);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_tx_needs_the_middleware() {
+        let mut app = Engine::new().with_state(pool().await);
+        app.post("/noop", |_| async { "nothing to write" }).with_middleware(transaction::<Sqlite>());
+        app.get("/outside", |ctx: RequestCtx| async move {
+            ctx.tx::<Sqlite>().await.err().map_or_else(String::new, |e| e.to_string())
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(app.run_with_listener(listener));
+
+        let client = crate::client::Client::new();
+        let response = client.post(&format!("{base}/noop")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = client.get(&format!("{base}/outside")).send().await.unwrap();
+        assert!(response.text().await.unwrap().contains("add the `transaction` middleware"));
+        server.abort();
+    }
+}
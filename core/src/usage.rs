@@ -0,0 +1,345 @@
+//! Usage/billing event emission.
+//!
+//! [`UsageReporter`] runs after every response and hands a [`UsageRecord`] to a
+//! pluggable [`UsageSink`] in a background task, so metered APIs can bill
+//! without wrapping each handler.
+//!
+//! ```rust,ignore
+//! use s_web::usage::{FileSink, UsageReporter};
+//!
+//! app.use_middleware(
+//!     UsageReporter::new(FileSink::new("usage.jsonl"))
+//!         .principal(|ctx| ctx.header("x-api-key").map(str::to_owned))
+//!         .middleware(),
+//! );
+//! ```
+//!
+//! Middleware and handlers running after the reporter, such as an auth
+//! middleware that only knows the caller once it has checked credentials,
+//! name the caller by putting a [`Principal`] in the response extensions.
+
+use std::{
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper_util::{
+    client::legacy::{Client, connect::HttpConnector},
+    rt::TokioExecutor,
+};
+use serde::Serialize;
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+
+use crate::{MatchedRoute, Next, RequestCtx, Response, log};
+
+/// A single metered request.
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageRecord {
+    /// Caller the usage is billed to, if known.
+    pub principal: Option<String>,
+    pub method: String,
+    /// Matched route pattern, falling back to the raw path.
+    pub route: String,
+    pub status: u16,
+    pub units: u64,
+    pub latency_ms: f64,
+    /// Unix timestamp in milliseconds when the request started.
+    pub timestamp_ms: u64,
+}
+
+/// Destination for usage records (file, message queue, billing service...).
+pub trait UsageSink: Send + Sync + 'static {
+    fn emit(&self, record: UsageRecord) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+}
+
+/// Caller a request's usage is billed to, set in the response extensions by
+/// middleware or handlers running after the [`UsageReporter`]. Takes
+/// precedence over [`UsageReporter::principal`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal(pub String);
+
+/// Appends records as JSON lines to a file.
+///
+/// One writer task, started with the first record, owns the file and
+/// flushes after each batch of queued records.
+pub struct FileSink {
+    path: PathBuf,
+    writer: OnceLock<mpsc::Sender<Vec<u8>>>,
+}
+
+impl FileSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            writer: OnceLock::new(),
+        }
+    }
+
+    fn writer(&self) -> &mpsc::Sender<Vec<u8>> {
+        self.writer.get_or_init(|| {
+            let (tx, rx) = mpsc::channel(1024);
+            tokio::spawn(write_lines(self.path.clone(), rx));
+            tx
+        })
+    }
+}
+
+async fn write_lines(path: PathBuf, mut rx: mpsc::Receiver<Vec<u8>>) {
+    let file = match tokio::fs::OpenOptions::new().create(true).append(true).open(&path).await {
+        Ok(file) => file,
+        Err(e) => {
+            log::warn!("usage sink: cannot open {path:?}: {e}");
+            return;
+        }
+    };
+    let mut file = tokio::io::BufWriter::new(file);
+    let mut lines = Vec::new();
+    while rx.recv_many(&mut lines, 256).await > 0 {
+        for line in lines.drain(..) {
+            if let Err(e) = file.write_all(&line).await {
+                log::warn!("usage sink: write failed: {e}");
+            }
+        }
+        if let Err(e) = file.flush().await {
+            log::warn!("usage sink: flush failed: {e}");
+        }
+    }
+}
+
+impl UsageSink for FileSink {
+    fn emit(&self, record: UsageRecord) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(async move {
+            let Ok(mut line) = serde_json::to_vec(&record) else {
+                return;
+            };
+            line.push(b'\n');
+            if self.writer().send(line).await.is_err() {
+                log::warn!("usage sink: {:?} is not writable, record dropped", self.path);
+            }
+        })
+    }
+}
+
+/// POSTs each record as JSON to an HTTP collector.
+pub struct HttpSink {
+    uri: hyper::Uri,
+    client: Client<HttpConnector, Full<Bytes>>,
+}
+
+impl HttpSink {
+    pub fn new(uri: hyper::Uri) -> Self {
+        Self {
+            uri,
+            client: Client::builder(TokioExecutor::new()).build_http(),
+        }
+    }
+}
+
+impl UsageSink for HttpSink {
+    fn emit(&self, record: UsageRecord) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(async move {
+            let Ok(body) = serde_json::to_vec(&record) else {
+                return;
+            };
+            let req = hyper::Request::post(self.uri.clone())
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(Full::new(Bytes::from(body)));
+            if let Ok(req) = req
+                && let Err(e) = self.client.request(req).await
+            {
+                log::warn!("usage sink: POST {} failed: {e}", self.uri);
+            }
+        })
+    }
+}
+
+/// Forwards records into a channel, e.g. for a task batching them to Kafka.
+pub struct ChannelSink {
+    tx: tokio::sync::mpsc::Sender<UsageRecord>,
+}
+
+impl ChannelSink {
+    pub fn new(tx: tokio::sync::mpsc::Sender<UsageRecord>) -> Self {
+        Self { tx }
+    }
+}
+
+impl UsageSink for ChannelSink {
+    fn emit(&self, record: UsageRecord) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(async move {
+            if self.tx.send(record).await.is_err() {
+                log::warn!("usage sink: channel closed, record dropped");
+            }
+        })
+    }
+}
+
+type PrincipalFn = Arc<dyn Fn(&RequestCtx) -> Option<String> + Send + Sync>;
+type UnitsFn = Arc<dyn Fn(&Response) -> u64 + Send + Sync>;
+
+/// Post-response middleware emitting a [`UsageRecord`] per request.
+#[derive(Clone)]
+pub struct UsageReporter {
+    sink: Arc<dyn UsageSink>,
+    principal: PrincipalFn,
+    units: UnitsFn,
+}
+
+impl UsageReporter {
+    /// Report every request as one unit, without a principal.
+    pub fn new(sink: impl UsageSink) -> Self {
+        Self {
+            sink: Arc::new(sink),
+            principal: Arc::new(|_| None),
+            units: Arc::new(|_| 1),
+        }
+    }
+
+    /// Identify the caller the usage is billed to from the request. A
+    /// [`Principal`] in the response extensions overrides it.
+    pub fn principal<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&RequestCtx) -> Option<String> + Send + Sync + 'static,
+    {
+        self.principal = Arc::new(f);
+        self
+    }
+
+    /// Compute billable units from the response (default 1 per request).
+    pub fn units<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Response) -> u64 + Send + Sync + 'static,
+    {
+        self.units = Arc::new(f);
+        self
+    }
+
+    /// Build the middleware function for `use_middleware`.
+    pub fn middleware(
+        self,
+    ) -> impl Fn(RequestCtx, Next) -> Pin<Box<dyn Future<Output = Response> + Send>>
+    + Send
+    + Sync
+    + 'static {
+        let reporter = Arc::new(self);
+        move |ctx, next| {
+            let reporter = reporter.clone();
+            Box::pin(async move { reporter.handle(ctx, next).await })
+        }
+    }
+
+    async fn handle(&self, ctx: RequestCtx, next: Next) -> Response {
+        let principal = (self.principal)(&ctx);
+        let method = ctx.request.method().to_string();
+        let path = ctx.request.uri().path().to_string();
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let start = Instant::now();
+
+        let response = next(ctx).await;
+
+        let record = UsageRecord {
+            principal: response.extensions().get::<Principal>().map(|p| p.0.clone()).or(principal),
+            method,
+            route: response
+                .extensions()
+                .get::<MatchedRoute>()
+                .map(|r| r.0.to_string())
+                .unwrap_or(path),
+            status: response.status().as_u16(),
+            units: (self.units)(&response),
+            latency_ms: start.elapsed().as_secs_f64() * 1000.0,
+            timestamp_ms,
+        };
+        let sink = self.sink.clone();
+        tokio::spawn(async move { sink.emit(record).await });
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ResponseBuilder, StatusCode};
+
+    fn record(status: u16) -> UsageRecord {
+        UsageRecord {
+            principal: None,
+            method: "GET".to_string(),
+            route: "/".to_string(),
+            status,
+            units: 1,
+            latency_ms: 0.0,
+            timestamp_ms: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reporter_records_requests() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+        let reporter = UsageReporter::new(ChannelSink::new(tx))
+            .principal(|ctx| ctx.header("x-api-key").map(str::to_owned))
+            .units(|response| response.headers().get("x-units").map_or(1, |v| v.to_str().unwrap().parse().unwrap()))
+            .middleware();
+        // Stands in for an auth middleware that names the caller after the reporter has run
+        let next: Next = Arc::new(|ctx| {
+            Box::pin(async move {
+                let mut response = ResponseBuilder::new().status(StatusCode::CREATED).with_header("x-units", "3").build("");
+                response.extensions_mut().insert(MatchedRoute("/users/:id".into()));
+                if ctx.header("x-user").is_some() {
+                    response.extensions_mut().insert(Principal("alice".to_string()));
+                }
+                response
+            })
+        });
+        let request = |headers: &[(&str, &str)]| {
+            let mut request = hyper::Request::post("/users/7");
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            RequestCtx::from_parts(request.body(()).unwrap().into_parts().0)
+        };
+
+        reporter(request(&[("x-api-key", "key-1")]), next.clone()).await;
+        let record = rx.recv().await.unwrap();
+        assert_eq!(record.principal.as_deref(), Some("key-1"));
+        assert_eq!((record.method.as_str(), record.route.as_str()), ("POST", "/users/:id"));
+        assert_eq!((record.status, record.units), (201, 3));
+        assert!(record.timestamp_ms > 0);
+
+        reporter(request(&[("x-api-key", "key-1"), ("x-user", "")]), next).await;
+        assert_eq!(rx.recv().await.unwrap().principal.as_deref(), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_file_sink_appends_lines() {
+        let path = std::env::temp_dir().join(format!("s_web-usage-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sink = FileSink::new(&path);
+        for status in [200, 404, 500] {
+            sink.emit(record(status)).await;
+        }
+
+        let mut contents = String::new();
+        for _ in 0..100 {
+            contents = std::fs::read_to_string(&path).unwrap_or_default();
+            if contents.lines().count() == 3 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let statuses: Vec<u64> = contents
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["status"].as_u64().unwrap())
+            .collect();
+        assert_eq!(statuses, [200, 404, 500]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
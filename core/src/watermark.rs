@@ -0,0 +1,140 @@
+//! Tagging responses with the instance that served them.
+
+use futures_util::{StreamExt, stream};
+use http_body_util::{BodyExt, BodyStream, StreamBody};
+use hyper::{
+    body::{Body as _, Bytes, Frame},
+    header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, HeaderName, HeaderValue},
+};
+
+use crate::Response;
+use crate::log;
+
+/// Per-deployment identifier added to every response by
+/// [`Engine::watermark`](crate::Engine::watermark), so operators can tell
+/// which instance and version served a request during incident triage.
+///
+/// ```rust,ignore
+/// let id = format!("{}@{}", hostname, env!("CARGO_PKG_VERSION"));
+/// app.watermark(Watermark::new(id).html_comment(true));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Watermark {
+    id: String,
+    header: HeaderName,
+    html_comment: bool,
+}
+
+impl Watermark {
+    /// Send `id` in the `X-Served-By` header.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            header: HeaderName::from_static("x-served-by"),
+            html_comment: false,
+        }
+    }
+
+    /// Use a different header name. Invalid names are ignored with a warning.
+    pub fn header(mut self, name: &str) -> Self {
+        match HeaderName::from_bytes(name.as_bytes()) {
+            Ok(name) => self.header = name,
+            Err(e) => log::warn!("watermark: invalid header name {name:?}: {e}"),
+        }
+        self
+    }
+
+    /// Also append `<!-- served-by: <id> -->` to uncompressed HTML bodies,
+    /// where it also shows up in saved or copy-pasted page sources.
+    pub fn html_comment(mut self, enabled: bool) -> Self {
+        self.html_comment = enabled;
+        self
+    }
+
+    /// Tag a response; `head` marks answers to `HEAD`, which only get the
+    /// `Content-Length` the matching `GET` would have.
+    pub(crate) fn apply(&self, mut response: Response, head: bool) -> Response {
+        match HeaderValue::from_str(&self.id) {
+            Ok(value) => {
+                response.headers_mut().insert(self.header.clone(), value);
+            }
+            Err(e) => log::warn!("watermark: invalid id {:?}: {e}", self.id),
+        }
+        if !self.html_comment || !is_plain_html(&response) {
+            return response;
+        }
+        let comment = self.comment();
+        if !head {
+            return append(response, comment);
+        }
+        let len = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+        if let Some(len) = len {
+            response
+                .headers_mut()
+                .insert(CONTENT_LENGTH, HeaderValue::from(len + comment.len() as u64));
+        }
+        response
+    }
+
+    fn comment(&self) -> Bytes {
+        // `--` would end the comment early
+        Bytes::from(format!(
+            "\n<!-- served-by: {} -->\n",
+            self.id.replace("--", "- -")
+        ))
+    }
+}
+
+fn append(response: Response, comment: Bytes) -> Response {
+    let (mut parts, body) = response.into_parts();
+    if let Some(len) = body.size_hint().exact() {
+        parts
+            .headers
+            .insert(CONTENT_LENGTH, HeaderValue::from(len + comment.len() as u64));
+    }
+    let tail = stream::iter([Ok(Frame::data(comment))]);
+    let body = BodyExt::boxed(StreamBody::new(BodyStream::new(body).chain(tail)));
+    Response::from_parts(parts, body)
+}
+
+fn is_plain_html(response: &Response) -> bool {
+    let status = response.status();
+    if status.is_informational()
+        || status == hyper::StatusCode::NO_CONTENT
+        || status == hyper::StatusCode::NOT_MODIFIED
+        || response.headers().contains_key(CONTENT_ENCODING)
+    {
+        return false;
+    }
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.trim_start().to_ascii_lowercase().starts_with("text/html"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResponseBuilder;
+
+    #[tokio::test]
+    async fn test_header_and_html_comment() {
+        let mark = Watermark::new("web-3@1.2.0").html_comment(true);
+
+        let html = mark.apply(ResponseBuilder::html("<p>hi</p>"), false);
+        assert_eq!(html.headers()["x-served-by"], "web-3@1.2.0");
+        let expected = "<p>hi</p>\n<!-- served-by: web-3@1.2.0 -->\n";
+        assert_eq!(html.headers()[CONTENT_LENGTH], expected.len().to_string().as_str());
+        let body = html.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, expected);
+
+        let text = mark.header("X-Instance").apply(ResponseBuilder::new().build("plain"), false);
+        assert_eq!(text.headers()["x-instance"], "web-3@1.2.0");
+        let body = text.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "plain");
+    }
+}
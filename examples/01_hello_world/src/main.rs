@@ -25,10 +25,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // 路径参数：`:name` 会被捕获到 ctx.params 中
     app.get("/hello/:name", |ctx: RequestCtx| async move {
-        let name = ctx
-            .get_param("name")
-            .map(|s| s.as_str())
-            .unwrap_or("stranger");
+        let name = ctx.get_param("name").unwrap_or("stranger");
         format!("Hello, {}! 👋", name)
     });
 
@@ -55,5 +52,5 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("   http://127.0.0.1:3000/hello/Rustacean");
     println!("   http://127.0.0.1:3000/about");
 
-    app.run("127.0.0.1:3000").await
+    Ok(app.run("127.0.0.1:3000").await?)
 }
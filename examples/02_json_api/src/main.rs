@@ -48,7 +48,8 @@ fn json_error(status: StatusCode, msg: &str) -> Response {
     ResponseBuilder::new()
         .status(status)
         .content_type("application/json; charset=utf-8")
-        .body(json!({ "error": msg }).to_string())
+        .try_body(json!({ "error": msg }).to_string())
+        .into_response()
 }
 
 // ──────────────────────────────────────────
@@ -107,7 +108,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         ResponseBuilder::new()
             .status(StatusCode::CREATED)
             .content_type("application/json; charset=utf-8")
-            .body(json!({ "message": "user created", "user": created }).to_string())
+            .try_body(json!({ "message": "user created", "user": created }).to_string())
+            .into_response()
     });
 
     println!("🚀 Example 2 · JSON API  →  http://127.0.0.1:3000");
@@ -121,5 +123,5 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
        -d '{{\"name\":\"Dave\",\"email\":\"dave@example.com\"}}'"
     );
 
-    app.run("127.0.0.1:3000").await
+    Ok(app.run("127.0.0.1:3000").await?)
 }
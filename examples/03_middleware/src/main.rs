@@ -10,14 +10,14 @@
 //!
 //! 接口（公开）：
 //!   GET  /          → 欢迎页
-//!   GET  /health    → 健康检查
+//!   GET  /health    → 健康检查（另有 /health/ready、/health/live）
 //!
 //! 接口（需要 Header: X-API-Key: secret）：
 //!   GET  /api/v1/users        → 用户列表
 //!   GET  /api/v1/users/:id    → 单个用户
 //!   POST /api/v1/users        → 创建用户
 
-use s_web::{Engine, IntoResponse, Next, RequestCtx, Response, ResponseBuilder, StatusCode};
+use s_web::{Engine, IntoResponse, Next, RequestCtx, RequireAuth, Response, ResponseBuilder, StatusCode};
 use serde_json::json;
 use std::time::Instant;
 
@@ -43,15 +43,17 @@ async fn logging_middleware(ctx: RequestCtx, next: Next) -> Response {
     response
 }
 
+/// 通过认证的调用方，由认证中间件放入请求上下文，处理函数用 `ctx.get::<ApiClient>()` 读取
+#[derive(Clone)]
+struct ApiClient {
+    name: &'static str,
+}
+
 /// 认证中间件：校验 `X-API-Key` 请求头，不匹配则直接返回 401
-async fn auth_middleware(ctx: RequestCtx, next: Next) -> Response {
-    match ctx.header("x-api-key") {
-        Some("secret") => next(ctx).await,
-        _ => ResponseBuilder::new()
-            .status(StatusCode::UNAUTHORIZED)
-            .content_type("application/json; charset=utf-8")
-            .body(json!({ "error": "missing or invalid X-API-Key" }).to_string()),
-    }
+fn auth_middleware() -> RequireAuth {
+    RequireAuth::api_key("x-api-key", |key| async move {
+        (key == "secret").then_some(ApiClient { name: "demo-client" })
+    })
 }
 
 // ──────────────────────────────────────────
@@ -70,15 +72,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         ResponseBuilder::html("<h1>Welcome!</h1><p>Protected API is under <code>/api/v1</code>.</p>")
     });
 
-    app.get("/health", |_ctx: RequestCtx| async {
-        json!({ "status": "ok" })
-    });
+    // 内置健康检查：/health、/health/ready（依赖探针汇总）与 /health/live
+    app.health_check("/health");
 
     // ── 受保护的路由分组 ──────────────────────────
     // 所有 /api/v1/* 路由均需通过 auth_middleware
     {
         let g = app.group("/api/v1");
-        g.use_middleware(auth_middleware);
+        g.use_middleware(auth_middleware().middleware());
 
         g.get("/users", |_ctx: RequestCtx| async {
             json!([
@@ -88,14 +89,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         });
 
         g.get("/users/:id", |ctx: RequestCtx| async move {
-            let id = ctx.get_param("id").cloned().unwrap_or_default();
-            match id.as_str() {
+            let id = ctx.get_param("id").unwrap_or_default();
+            match id {
                 "1" => json!({ "id": 1, "name": "Alice", "role": "admin" }).into_response(),
                 "2" => json!({ "id": 2, "name": "Bob",   "role": "user"  }).into_response(),
                 _   => ResponseBuilder::new()
                     .status(StatusCode::NOT_FOUND)
                     .content_type("application/json; charset=utf-8")
-                    .body(json!({ "error": "not found" }).to_string()),
+                    .try_body(json!({ "error": "not found" }).to_string())
+                    .into_response(),
             }
         });
 
@@ -105,12 +107,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 Err(_) => return ResponseBuilder::new()
                     .status(StatusCode::BAD_REQUEST)
                     .content_type("application/json; charset=utf-8")
-                    .body(json!({ "error": "invalid JSON body" }).to_string()),
+                    .try_body(json!({ "error": "invalid JSON body" }).to_string())
+                    .into_response(),
             };
+            let created_by = ctx.get::<ApiClient>().map(|client| client.name);
             ResponseBuilder::new()
                 .status(StatusCode::CREATED)
                 .content_type("application/json; charset=utf-8")
-                .body(json!({ "message": "created", "data": body }).to_string())
+                .try_body(json!({ "message": "created", "created_by": created_by, "data": body }).to_string())
+                .into_response()
         });
     }
 
@@ -126,5 +131,5 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("       -H 'X-API-Key: secret' -H 'Content-Type: application/json' \\");
     println!("       -d '{{\"name\":\"Charlie\"}}'");
 
-    app.run("127.0.0.1:3000").await
+    Ok(app.run("127.0.0.1:3000").await?)
 }
@@ -109,7 +109,8 @@ fn json_err(status: StatusCode, msg: &str) -> Response {
     ResponseBuilder::new()
         .status(status)
         .content_type("application/json; charset=utf-8")
-        .body(json!({ "error": msg }).to_string())
+        .try_body(json!({ "error": msg }).to_string())
+        .into_response()
 }
 
 fn parse_id(ctx: &RequestCtx) -> Option<u32> {
@@ -194,7 +195,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 ResponseBuilder::new()
                     .status(StatusCode::CREATED)
                     .content_type("application/json; charset=utf-8")
-                    .body(json!(todo).to_string())
+                    .try_body(json!(todo).to_string())
+                    .into_response()
             }
         });
 
@@ -285,5 +287,5 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("  curl -X DELETE http://127.0.0.1:3000/todos/2");
     println!("  curl 'http://127.0.0.1:3000/todos/?done=false'");
 
-    app.run("127.0.0.1:3000").await
+    Ok(app.run("127.0.0.1:3000").await?)
 }
@@ -5,6 +5,7 @@
 //!   - `swagger()` builder 配置摘要、标签、参数、响应
 //!   - `get_with_swagger` / `post_with_swagger` 等带注解的路由注册
 //!   - 路径参数、查询参数、请求体、响应码文档化
+//!   - `#[derive(ToSchema)]` 从 Rust 类型生成请求体/响应的字段级 schema
 //!   - Bearer token 安全方案标注
 //!
 //! 运行：
@@ -13,7 +14,7 @@
 //! 然后打开 http://127.0.0.1:3000/docs/ 查看 Swagger UI
 
 use s_web::{
-    Engine, IntoResponse, RequestCtx, Response, ResponseBuilder, StatusCode,
+    Engine, IntoResponse, RequestCtx, Response, ResponseBuilder, StatusCode, ToSchema,
     swagger,
 };
 use serde::{Deserialize, Serialize};
@@ -23,7 +24,7 @@ use serde_json::json;
 // 数据模型（内存演示数据）
 // ──────────────────────────────────────────
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 struct Article {
     id: u32,
     title: String,
@@ -31,6 +32,17 @@ struct Article {
     author: String,
 }
 
+/// 创建文章的请求体
+#[derive(Deserialize, ToSchema)]
+struct NewArticle {
+    /// 标题
+    title: String,
+    /// 正文
+    body: String,
+    /// 作者
+    author: String,
+}
+
 fn demo_articles() -> Vec<Article> {
     vec![
         Article { id: 1, title: "Hello Rust".into(),      body: "Rust is awesome.".into(),        author: "Alice".into() },
@@ -43,7 +55,8 @@ fn json_err(status: StatusCode, msg: &str) -> Response {
     ResponseBuilder::new()
         .status(status)
         .content_type("application/json; charset=utf-8")
-        .body(json!({ "error": msg }).to_string())
+        .try_body(json!({ "error": msg }).to_string())
+        .into_response()
 }
 
 // ──────────────────────────────────────────
@@ -105,10 +118,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     app.post_with_swagger(
         "/articles",
         |mut ctx: RequestCtx| async move {
-            #[derive(Deserialize)]
-            struct Payload { title: String, body: String, author: String }
-
-            let p: Payload = match ctx.json().await {
+            let p: NewArticle = match ctx.json().await {
                 Ok(v)  => v,
                 Err(_) => return json_err(StatusCode::BAD_REQUEST, "invalid JSON body"),
             };
@@ -117,18 +127,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             ResponseBuilder::new()
                 .status(StatusCode::CREATED)
                 .content_type("application/json; charset=utf-8")
-                .body(json!(created).to_string())
+                .try_body(json!(created).to_string())
+                .into_response()
         },
         swagger()
             .summary("创建文章")
             .description("提交 JSON 请求体创建一篇新文章，需要 Bearer Token 认证")
             .tag("Articles")
-            .request_body(json!({ "title": "My Title", "body": "Content here.", "author": "Dave" }))
-            .json_response(
-                "201",
-                "创建成功，返回新文章",
-                Some(json!({ "id": 100, "title": "My Title", "body": "Content here.", "author": "Dave" })),
-            )
+            .request_body_of::<NewArticle>()
+            .json_response_of::<Article>("201", "创建成功，返回新文章")
             .response("400", "请求体格式错误")
             .bearer_auth()
             .build(),
@@ -191,5 +198,5 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("🚀 Example 5 · Swagger UI  →  http://127.0.0.1:3000");
     println!("📖 Swagger UI             →  http://127.0.0.1:3000/docs/");
 
-    app.run("127.0.0.1:3000").await
+    Ok(app.run("127.0.0.1:3000").await?)
 }
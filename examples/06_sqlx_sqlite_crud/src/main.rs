@@ -3,7 +3,8 @@
 //! 演示框架与 sqlx ORM 的完整集成：
 //!   - 启动时建立 SQLitePool 连接池
 //!   - 在 on_startup 钩子中执行数据库迁移（建表）
-//!   - Arc<SqlitePool> 作为共享状态注入每个处理器
+//!   - `Engine::with_state` 注册连接池，处理器通过 `ctx.state()` 读取
+//!   - `transaction` 中间件 + `ctx.tx()`：请求级事务，自动提交/回滚
 //!   - 完整 REST CRUD 对接真实数据库
 //!   - 统一错误处理
 //!
@@ -19,11 +20,10 @@
 //!   PUT    /products/:id       → 整体更新
 //!   DELETE /products/:id       → 删除
 
-use s_web::{Engine, IntoResponse, Next, RequestCtx, Response, ResponseBuilder, StatusCode};
+use s_web::{Engine, IntoResponse, Next, RequestCtx, Response, ResponseBuilder, StatusCode, tx::transaction};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use sqlx::{FromRow, SqlitePool};
-use std::sync::Arc;
+use sqlx::{FromRow, Sqlite, SqlitePool};
 
 // ──────────────────────────────────────────
 // 数据模型
@@ -45,10 +45,18 @@ fn json_err(status: StatusCode, msg: &str) -> Response {
     ResponseBuilder::new()
         .status(status)
         .content_type("application/json; charset=utf-8")
-        .body(json!({ "error": msg }).to_string())
+        .try_body(json!({ "error": msg }).to_string())
+        .into_response()
 }
 
-/// 注入 pool 的日志中间件
+/// 从共享状态中取出连接池（SqlitePool 内部是 Arc，clone 代价很低）
+fn db(ctx: &RequestCtx) -> SqlitePool {
+    ctx.state::<SqlitePool>()
+        .cloned()
+        .expect("SqlitePool is registered with Engine::with_state")
+}
+
+/// 日志中间件
 async fn log_middleware(ctx: RequestCtx, next: Next) -> Response {
     let method = ctx.request.method().to_string();
     let path   = ctx.request.uri().path().to_string();
@@ -106,199 +114,183 @@ async fn migrate(pool: &SqlitePool) -> Result<(), sqlx::Error> {
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 建立 SQLite 连接池
-    let pool = Arc::new(
-        SqlitePool::connect("sqlite:./products.db?mode=rwc").await?,
-    );
+    let pool = SqlitePool::connect("sqlite:./products.db?mode=rwc").await?;
 
     // 迁移 & 种子数据
     migrate(&pool).await?;
 
-    let mut app = Engine::new();
+    // 连接池作为共享状态注册到 Engine，处理器中通过 ctx.state() 读取
+    let mut app = Engine::new().with_state(pool);
 
     // ── 全局日志中间件 ───────────────────────────────
     app.use_middleware(log_middleware);
 
     // ── GET /products?name=xx ────────────────────────
-    {
-        let pool = pool.clone();
-        app.get("/products", move |ctx: RequestCtx| {
-            let pool = pool.clone();
-            async move {
-                let name_filter = ctx.query_param("name").unwrap_or_default();
-
-                let products: Vec<Product> = if name_filter.is_empty() {
-                    sqlx::query_as("SELECT id, name, price, stock FROM products ORDER BY id")
-                        .fetch_all(pool.as_ref())
-                        .await
-                        .unwrap_or_default()
-                } else {
-                    let pattern = format!("%{}%", name_filter);
-                    sqlx::query_as(
-                        "SELECT id, name, price, stock FROM products WHERE name LIKE ? ORDER BY id",
-                    )
-                    .bind(pattern)
-                    .fetch_all(pool.as_ref())
-                    .await
-                    .unwrap_or_default()
-                };
-
-                json!({ "count": products.len(), "products": products }).into_response()
-            }
-        });
-    }
+    app.get("/products", |ctx: RequestCtx| async move {
+        let pool = db(&ctx);
+        let name_filter = ctx.query_param("name").unwrap_or_default();
 
-    // ── POST /products ───────────────────────────────
-    {
-        let pool = pool.clone();
-        app.post("/products", move |mut ctx: RequestCtx| {
-            let pool = pool.clone();
-            async move {
-                #[derive(Deserialize)]
-                struct Payload { name: String, price: f64, stock: i64 }
-
-                let p: Payload = match ctx.json().await {
-                    Ok(v)  => v,
-                    Err(_) => return json_err(StatusCode::BAD_REQUEST, "invalid JSON body"),
-                };
-
-                if p.name.trim().is_empty() {
-                    return json_err(StatusCode::BAD_REQUEST, "name must not be empty");
-                }
-                if p.price < 0.0 {
-                    return json_err(StatusCode::BAD_REQUEST, "price must be non-negative");
-                }
-
-                let row: (i64,) = match sqlx::query_as(
-                    "INSERT INTO products (name, price, stock) VALUES (?, ?, ?) RETURNING id",
-                )
-                .bind(&p.name)
-                .bind(p.price)
-                .bind(p.stock)
-                .fetch_one(pool.as_ref())
+        let products: Vec<Product> = if name_filter.is_empty() {
+            sqlx::query_as("SELECT id, name, price, stock FROM products ORDER BY id")
+                .fetch_all(&pool)
                 .await
-                {
-                    Ok(r)  => r,
-                    Err(e) => {
-                        eprintln!("DB insert error: {e}");
-                        return json_err(StatusCode::INTERNAL_SERVER_ERROR, "database error");
-                    }
-                };
-
-                let product = Product { id: row.0, name: p.name, price: p.price, stock: p.stock };
-                ResponseBuilder::new()
-                    .status(StatusCode::CREATED)
-                    .content_type("application/json; charset=utf-8")
-                    .body(json!(product).to_string())
+                .unwrap_or_default()
+        } else {
+            let pattern = format!("%{}%", name_filter);
+            sqlx::query_as(
+                "SELECT id, name, price, stock FROM products WHERE name LIKE ? ORDER BY id",
+            )
+            .bind(pattern)
+            .fetch_all(&pool)
+            .await
+            .unwrap_or_default()
+        };
+
+        json!({ "count": products.len(), "products": products }).into_response()
+    });
+
+    // ── POST /products ───────────────────────────────
+    app.post("/products", |mut ctx: RequestCtx| async move {
+        #[derive(Deserialize)]
+        struct Payload { name: String, price: f64, stock: i64 }
+
+        let p: Payload = match ctx.json().await {
+            Ok(v)  => v,
+            Err(_) => return json_err(StatusCode::BAD_REQUEST, "invalid JSON body"),
+        };
+
+        if p.name.trim().is_empty() {
+            return json_err(StatusCode::BAD_REQUEST, "name must not be empty");
+        }
+        if p.price < 0.0 {
+            return json_err(StatusCode::BAD_REQUEST, "price must be non-negative");
+        }
+
+        // 事务在首次使用时开启；返回 2xx 时由 transaction 中间件提交，否则回滚
+        let mut tx = match ctx.tx::<Sqlite>().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                eprintln!("DB begin error: {e}");
+                return json_err(StatusCode::INTERNAL_SERVER_ERROR, "database error");
             }
-        });
-    }
+        };
+
+        let row: (i64,) = match sqlx::query_as(
+            "INSERT INTO products (name, price, stock) VALUES (?, ?, ?) RETURNING id",
+        )
+        .bind(&p.name)
+        .bind(p.price)
+        .bind(p.stock)
+        .fetch_one(&mut **tx)
+        .await
+        {
+            Ok(r)  => r,
+            Err(e) => {
+                eprintln!("DB insert error: {e}");
+                return json_err(StatusCode::INTERNAL_SERVER_ERROR, "database error");
+            }
+        };
+
+        let product = Product { id: row.0, name: p.name, price: p.price, stock: p.stock };
+        ResponseBuilder::new()
+            .status(StatusCode::CREATED)
+            .content_type("application/json; charset=utf-8")
+            .try_body(json!(product).to_string())
+            .into_response()
+    })
+    .with_middleware(transaction::<Sqlite>());
 
     // ── GET /products/:id ────────────────────────────
-    {
-        let pool = pool.clone();
-        app.get("/products/:id", move |ctx: RequestCtx| {
-            let pool = pool.clone();
-            async move {
-                let id: i64 = match ctx.get_param("id").and_then(|s| s.parse().ok()) {
-                    Some(v) => v,
-                    None    => return json_err(StatusCode::BAD_REQUEST, "id must be a positive integer"),
-                };
-
-                match sqlx::query_as::<_, Product>(
-                    "SELECT id, name, price, stock FROM products WHERE id = ?",
-                )
-                .bind(id)
-                .fetch_optional(pool.as_ref())
-                .await
-                {
-                    Ok(Some(p)) => json!(p).into_response(),
-                    Ok(None)    => json_err(StatusCode::NOT_FOUND, "product not found"),
-                    Err(e)      => {
-                        eprintln!("DB query error: {e}");
-                        json_err(StatusCode::INTERNAL_SERVER_ERROR, "database error")
-                    }
-                }
+    app.get("/products/:id", |ctx: RequestCtx| async move {
+        let pool = db(&ctx);
+        let id: i64 = match ctx.get_param("id").and_then(|s| s.parse().ok()) {
+            Some(v) => v,
+            None    => return json_err(StatusCode::BAD_REQUEST, "id must be a positive integer"),
+        };
+
+        match sqlx::query_as::<_, Product>(
+            "SELECT id, name, price, stock FROM products WHERE id = ?",
+        )
+        .bind(id)
+        .fetch_optional(&pool)
+        .await
+        {
+            Ok(Some(p)) => json!(p).into_response(),
+            Ok(None)    => json_err(StatusCode::NOT_FOUND, "product not found"),
+            Err(e)      => {
+                eprintln!("DB query error: {e}");
+                json_err(StatusCode::INTERNAL_SERVER_ERROR, "database error")
             }
-        });
-    }
+        }
+    });
 
     // ── PUT /products/:id ────────────────────────────
-    {
-        let pool = pool.clone();
-        app.put("/products/:id", move |mut ctx: RequestCtx| {
-            let pool = pool.clone();
-            async move {
-                let id: i64 = match ctx.get_param("id").and_then(|s| s.parse().ok()) {
-                    Some(v) => v,
-                    None    => return json_err(StatusCode::BAD_REQUEST, "id must be a positive integer"),
-                };
-
-                #[derive(Deserialize)]
-                struct Payload { name: String, price: f64, stock: i64 }
-
-                let p: Payload = match ctx.json().await {
-                    Ok(v)  => v,
-                    Err(_) => return json_err(StatusCode::BAD_REQUEST, "invalid JSON body"),
-                };
-
-                let result = sqlx::query(
-                    "UPDATE products SET name = ?, price = ?, stock = ? WHERE id = ?",
-                )
-                .bind(&p.name)
-                .bind(p.price)
-                .bind(p.stock)
-                .bind(id)
-                .execute(pool.as_ref())
-                .await;
-
-                match result {
-                    Ok(r) if r.rows_affected() == 0 => {
-                        json_err(StatusCode::NOT_FOUND, "product not found")
-                    }
-                    Ok(_) => {
-                        let product = Product { id, name: p.name, price: p.price, stock: p.stock };
-                        json!(product).into_response()
-                    }
-                    Err(e) => {
-                        eprintln!("DB update error: {e}");
-                        json_err(StatusCode::INTERNAL_SERVER_ERROR, "database error")
-                    }
-                }
+    app.put("/products/:id", |mut ctx: RequestCtx| async move {
+        let pool = db(&ctx);
+        let id: i64 = match ctx.get_param("id").and_then(|s| s.parse().ok()) {
+            Some(v) => v,
+            None    => return json_err(StatusCode::BAD_REQUEST, "id must be a positive integer"),
+        };
+
+        #[derive(Deserialize)]
+        struct Payload { name: String, price: f64, stock: i64 }
+
+        let p: Payload = match ctx.json().await {
+            Ok(v)  => v,
+            Err(_) => return json_err(StatusCode::BAD_REQUEST, "invalid JSON body"),
+        };
+
+        let result = sqlx::query(
+            "UPDATE products SET name = ?, price = ?, stock = ? WHERE id = ?",
+        )
+        .bind(&p.name)
+        .bind(p.price)
+        .bind(p.stock)
+        .bind(id)
+        .execute(&pool)
+        .await;
+
+        match result {
+            Ok(r) if r.rows_affected() == 0 => {
+                json_err(StatusCode::NOT_FOUND, "product not found")
             }
-        });
-    }
+            Ok(_) => {
+                let product = Product { id, name: p.name, price: p.price, stock: p.stock };
+                json!(product).into_response()
+            }
+            Err(e) => {
+                eprintln!("DB update error: {e}");
+                json_err(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+            }
+        }
+    });
 
     // ── DELETE /products/:id ─────────────────────────
-    {
-        let pool = pool.clone();
-        app.delete("/products/:id", move |ctx: RequestCtx| {
-            let pool = pool.clone();
-            async move {
-                let id: i64 = match ctx.get_param("id").and_then(|s| s.parse().ok()) {
-                    Some(v) => v,
-                    None    => return json_err(StatusCode::BAD_REQUEST, "id must be a positive integer"),
-                };
-
-                let result = sqlx::query("DELETE FROM products WHERE id = ?")
-                    .bind(id)
-                    .execute(pool.as_ref())
-                    .await;
-
-                match result {
-                    Ok(r) if r.rows_affected() == 0 => {
-                        json_err(StatusCode::NOT_FOUND, "product not found")
-                    }
-                    Ok(_) => ResponseBuilder::new()
-                        .status(StatusCode::NO_CONTENT)
-                        .empty_body(),
-                    Err(e) => {
-                        eprintln!("DB delete error: {e}");
-                        json_err(StatusCode::INTERNAL_SERVER_ERROR, "database error")
-                    }
-                }
+    app.delete("/products/:id", |ctx: RequestCtx| async move {
+        let pool = db(&ctx);
+        let id: i64 = match ctx.get_param("id").and_then(|s| s.parse().ok()) {
+            Some(v) => v,
+            None    => return json_err(StatusCode::BAD_REQUEST, "id must be a positive integer"),
+        };
+
+        let result = sqlx::query("DELETE FROM products WHERE id = ?")
+            .bind(id)
+            .execute(&pool)
+            .await;
+
+        match result {
+            Ok(r) if r.rows_affected() == 0 => {
+                json_err(StatusCode::NOT_FOUND, "product not found")
             }
-        });
-    }
+            Ok(_) => ResponseBuilder::new()
+                .status(StatusCode::NO_CONTENT)
+                .empty_body(),
+            Err(e) => {
+                eprintln!("DB delete error: {e}");
+                json_err(StatusCode::INTERNAL_SERVER_ERROR, "database error")
+            }
+        }
+    });
 
     println!("🚀 Example 6 · SQLite CRUD  →  http://127.0.0.1:3000");
     println!("💾 Database file: ./products.db");
@@ -314,5 +306,5 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("       -d '{{\"name\":\"Rust Book v2\",\"price\":34.99,\"stock\":30}}'");
     println!("  curl -X DELETE http://127.0.0.1:3000/products/3");
 
-    app.run("127.0.0.1:3000").await
+    Ok(app.run("127.0.0.1:3000").await?)
 }
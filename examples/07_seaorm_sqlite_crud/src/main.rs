@@ -38,7 +38,8 @@ fn json_err(status: StatusCode, msg: &str) -> Response {
     ResponseBuilder::new()
         .status(status)
         .content_type("application/json; charset=utf-8")
-        .body(json!({ "error": msg }).to_string())
+        .try_body(json!({ "error": msg }).to_string())
+        .into_response()
 }
 
 async fn log_middleware(ctx: RequestCtx, next: Next) -> Response {
@@ -161,7 +162,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     Ok(m) => ResponseBuilder::new()
                         .status(StatusCode::CREATED)
                         .content_type("application/json; charset=utf-8")
-                        .body(json!(m).to_string()),
+                        .try_body(json!(m).to_string())
+                        .into_response(),
                     Err(e) => {
                         eprintln!("DB insert error: {e}");
                         json_err(StatusCode::INTERNAL_SERVER_ERROR, "database error")
@@ -282,5 +284,5 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("       -d '{{\"name\":\"Rust Book v2\",\"price\":34.99,\"stock\":30}}'");
     println!("  curl -X DELETE http://127.0.0.1:3000/products/3");
 
-    app.run("127.0.0.1:3000").await
+    Ok(app.run("127.0.0.1:3000").await?)
 }
@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use s_web::App;
 use sqlx::SqlitePool;
 
 use crate::{
@@ -8,10 +9,10 @@ use crate::{
     repository::BlogRepository,
 };
 
-pub fn register_routes(app: &mut s_web::Engine, db: Arc<SqlitePool>) {
-    app.use_middleware(middleware::access_log);
-
-    let api = app.group("/api");
+/// Blog API routes, mounted by `main` under `/api`
+pub fn api(db: Arc<SqlitePool>) -> App {
+    let mut api = App::new();
+    api.use_middleware(middleware::access_log);
     let repo = BlogRepository::new(db);
 
     {
@@ -101,4 +102,6 @@ pub fn register_routes(app: &mut s_web::Engine, db: Arc<SqlitePool>) {
             async move { examples::quick_publish(ctx, repo).await }
         });
     }
+
+    api
 }
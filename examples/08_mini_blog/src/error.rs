@@ -1,24 +1,35 @@
-use s_web::{Response, ResponseBuilder, StatusCode};
-use serde_json::json;
+use std::fmt;
 
+use s_web::{HttpError, StatusCode};
+
+#[derive(Debug)]
 pub enum AppError {
     BadRequest(String),
     NotFound(String),
     Database(String),
 }
 
-impl AppError {
-    pub fn to_response(self) -> Response {
-        let (status, msg) = match self {
-            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
-            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
-            AppError::Database(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
-        };
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::BadRequest(msg) | AppError::NotFound(msg) | AppError::Database(msg) => {
+                f.write_str(msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
 
-        ResponseBuilder::new()
-            .status(status)
-            .content_type("application/json; charset=utf-8")
-            .body(json!({ "error": msg }).to_string())
+/// 处理函数直接返回 `AppResult<_>`，错误按各自的状态码响应；
+/// 数据库错误只记录日志，不把细节返回给客户端
+impl HttpError for AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
     }
 }
 
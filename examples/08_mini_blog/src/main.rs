@@ -7,6 +7,8 @@ mod models;
 mod repository;
 mod handlers;
 
+use s_web::IntoResponse;
+use serde_json::json;
 use sqlx::SqlitePool;
 use std::sync::Arc;
 
@@ -24,6 +26,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("🛑 mini_blog shutdown");
         });
 
-    app::register_routes(&mut app, db);
-    app.run("127.0.0.1:3008").await
+    // AppError 等处理函数错误统一渲染为 {"error": "..."}
+    app.error_handler(|err| {
+        let msg = err.message().unwrap_or("服务器内部错误");
+        (err.status(), json!({ "error": msg })).into_response()
+    });
+    app.mount("/api", app::api(db));
+    app.print_routes();
+    Ok(app.run("127.0.0.1:3008").await?)
 }
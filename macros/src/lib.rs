@@ -0,0 +1,268 @@
+//! Derive macros for s_web, re-exported from the `s_web` crate.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    Attribute, Data, DataEnum, DeriveInput, Fields, FieldsNamed, LitStr, ext::IdentExt, meta::ParseNestedMeta,
+    parse_macro_input, parse_quote,
+};
+
+/// Implement `s_web::ToSchema` for a struct with named fields, a newtype
+/// struct or an enum whose variants have no fields.
+///
+/// The schema follows the type's serde attributes (`rename`, `rename_all`,
+/// `skip`, `default`, `flatten`), fields are required unless they are an
+/// `Option` or have a default, and doc comments become descriptions.
+#[proc_macro_derive(ToSchema, attributes(serde))]
+pub fn derive_to_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+fn expand(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let container = serde_attrs(&input.attrs)?;
+    let body = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => named_fields(fields, &container)?,
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                let ty = &fields.unnamed[0].ty;
+                quote! { <#ty as ::s_web::ToSchema>::schema() }
+            }
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "ToSchema needs a struct with named fields or a newtype struct",
+                ));
+            }
+        },
+        Data::Enum(data) => unit_variants(data, &container)?,
+        Data::Union(_) => return Err(syn::Error::new_spanned(&input.ident, "ToSchema does not support unions")),
+    };
+    let schema = match doc(&input.attrs) {
+        Some(description) => quote! {
+            ::s_web::Schema {
+                description: ::std::option::Option::Some(::std::string::String::from(#description)),
+                ..#body
+            }
+        },
+        None => body,
+    };
+
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::s_web::ToSchema));
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::s_web::ToSchema for #name #ty_generics #where_clause {
+            fn schema() -> ::s_web::Schema {
+                #schema
+            }
+        }
+    })
+}
+
+fn named_fields(fields: &FieldsNamed, container: &Serde) -> syn::Result<TokenStream2> {
+    let mut steps = Vec::new();
+    for field in &fields.named {
+        let serde = serde_attrs(&field.attrs)?;
+        let ty = &field.ty;
+        if serde.skip {
+            continue;
+        }
+        if serde.flatten {
+            steps.push(quote! {
+                let inner = <#ty as ::s_web::ToSchema>::schema();
+                properties.extend(inner.properties.unwrap_or_default());
+                required.extend(inner.required.unwrap_or_default());
+            });
+            continue;
+        }
+
+        let ident = field.ident.as_ref().map(|ident| ident.unraw().to_string()).unwrap_or_default();
+        let name = serde.rename.unwrap_or_else(|| rename_field(&ident, container.rename_all.as_deref()));
+        let property = match doc(&field.attrs) {
+            Some(description) => quote! {
+                ::s_web::Schema {
+                    description: ::std::option::Option::Some(::std::string::String::from(#description)),
+                    ..<#ty as ::s_web::ToSchema>::schema()
+                }
+            },
+            None => quote! { <#ty as ::s_web::ToSchema>::schema() },
+        };
+        steps.push(quote! {
+            properties.insert(::std::string::String::from(#name), #property);
+        });
+        if !serde.default && !container.default {
+            steps.push(quote! {
+                if <#ty as ::s_web::ToSchema>::required() {
+                    required.push(::std::string::String::from(#name));
+                }
+            });
+        }
+    }
+
+    Ok(quote! {{
+        let mut properties = ::std::collections::HashMap::new();
+        let mut required = ::std::vec::Vec::<::std::string::String>::new();
+        #(#steps)*
+        let mut schema = ::s_web::Schema::object();
+        schema.properties = ::std::option::Option::Some(properties);
+        if !required.is_empty() {
+            schema.required = ::std::option::Option::Some(required);
+        }
+        schema
+    }})
+}
+
+fn unit_variants(data: &DataEnum, container: &Serde) -> syn::Result<TokenStream2> {
+    let mut names = Vec::new();
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new_spanned(
+                variant,
+                "ToSchema only supports enums whose variants have no fields",
+            ));
+        }
+        let serde = serde_attrs(&variant.attrs)?;
+        if serde.skip {
+            continue;
+        }
+        let ident = variant.ident.unraw().to_string();
+        names.push(serde.rename.unwrap_or_else(|| rename_variant(&ident, container.rename_all.as_deref())));
+    }
+
+    Ok(quote! {{
+        let mut schema = ::s_web::Schema::string();
+        schema.enum_values = ::std::option::Option::Some(::std::vec![#(::std::string::String::from(#names)),*]);
+        schema
+    }})
+}
+
+/// The serde attributes that change a type's JSON shape
+#[derive(Default)]
+struct Serde {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    skip: bool,
+    default: bool,
+    flatten: bool,
+}
+
+fn serde_attrs(attrs: &[Attribute]) -> syn::Result<Serde> {
+    let mut serde = Serde::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                serde.rename = renamed(&meta)?.or(serde.rename.take());
+            } else if meta.path.is_ident("rename_all") {
+                serde.rename_all = renamed(&meta)?.or(serde.rename_all.take());
+            } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_serializing") {
+                serde.skip = true;
+            } else if meta.path.is_ident("flatten") {
+                serde.flatten = true;
+            } else if meta.path.is_ident("default") {
+                serde.default = true;
+                skip_value(&meta)?;
+            } else {
+                skip_value(&meta)?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(serde)
+}
+
+/// Serialized name of `rename = ".."` or `rename(serialize = "..")`
+fn renamed(meta: &ParseNestedMeta) -> syn::Result<Option<String>> {
+    if meta.input.peek(syn::Token![=]) {
+        return Ok(Some(meta.value()?.parse::<LitStr>()?.value()));
+    }
+    let mut name = None;
+    meta.parse_nested_meta(|inner| {
+        let value = inner.value()?.parse::<LitStr>()?.value();
+        if inner.path.is_ident("serialize") {
+            name = Some(value);
+        }
+        Ok(())
+    })?;
+    Ok(name)
+}
+
+/// Consume the value of a serde attribute that does not affect the schema
+fn skip_value(meta: &ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(syn::Token![=]) {
+        meta.value()?.parse::<syn::Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|inner| skip_value(&inner))?;
+    }
+    Ok(())
+}
+
+/// Doc comment lines joined into one paragraph
+fn doc(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            syn::Meta::NameValue(syn::MetaNameValue {
+                value: syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(line), .. }),
+                ..
+            }) => Some(line.value().trim().to_string()),
+            _ => None,
+        })
+        .filter(|line| !line.is_empty())
+        .collect();
+    (!lines.is_empty()).then(|| lines.join(" "))
+}
+
+/// Apply `rename_all` to a snake_case field name
+fn rename_field(name: &str, rule: Option<&str>) -> String {
+    match rule {
+        Some("UPPERCASE" | "SCREAMING_SNAKE_CASE") => name.to_ascii_uppercase(),
+        Some("PascalCase") => pascal(name),
+        Some("camelCase") => lower_first(&pascal(name)),
+        Some("kebab-case") => name.replace('_', "-"),
+        Some("SCREAMING-KEBAB-CASE") => name.replace('_', "-").to_ascii_uppercase(),
+        _ => name.to_string(),
+    }
+}
+
+/// Apply `rename_all` to a PascalCase variant name
+fn rename_variant(name: &str, rule: Option<&str>) -> String {
+    let snake = || {
+        let mut snake = String::new();
+        for (i, c) in name.char_indices() {
+            if c.is_uppercase() && i > 0 {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+        }
+        snake
+    };
+    match rule {
+        Some("lowercase") => name.to_ascii_lowercase(),
+        Some("UPPERCASE") => name.to_ascii_uppercase(),
+        Some("camelCase") => lower_first(name),
+        Some("snake_case") => snake(),
+        Some("SCREAMING_SNAKE_CASE") => snake().to_ascii_uppercase(),
+        Some("kebab-case") => snake().replace('_', "-"),
+        Some("SCREAMING-KEBAB-CASE") => snake().replace('_', "-").to_ascii_uppercase(),
+        _ => name.to_string(),
+    }
+}
+
+fn pascal(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|first| first.to_ascii_uppercase().to_string() + chars.as_str()).unwrap_or_default()
+        })
+        .collect()
+}
+
+fn lower_first(name: &str) -> String {
+    let mut chars = name.chars();
+    chars.next().map(|first| first.to_ascii_lowercase().to_string() + chars.as_str()).unwrap_or_default()
+}